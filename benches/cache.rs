@@ -0,0 +1,53 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use llmdig::utils::cache::Cache;
+use std::sync::Arc;
+use std::time::Duration;
+
+const CAPACITY: usize = 10_000;
+const OPS_PER_TASK: usize = 1_000;
+
+/// Mixed get/set traffic from concurrent tasks over a key space twice the
+/// cache capacity, so that most writes trigger an eviction
+async fn concurrent_load(cache: Arc<Cache<String>>, tasks: usize) {
+    let handles: Vec<_> = (0..tasks)
+        .map(|task| {
+            let cache = cache.clone();
+            tokio::spawn(async move {
+                for i in 0..OPS_PER_TASK {
+                    let key = format!("question-{}", (task * 7919 + i * 31) % (CAPACITY * 2));
+                    if cache.get(&key).await.is_none() {
+                        cache.set(key, "answer".to_string()).await;
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+fn bench_cache(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    let mut group = c.benchmark_group("cache_concurrent_load");
+
+    for tasks in [1, 4, 16] {
+        // Start from a full cache so every run measures steady-state eviction
+        let cache = Arc::new(Cache::new(CAPACITY, Duration::from_secs(300)));
+        runtime.block_on(async {
+            for i in 0..CAPACITY {
+                cache.set(format!("warm-{}", i), "answer".to_string()).await;
+            }
+        });
+
+        group.bench_with_input(BenchmarkId::from_parameter(tasks), &tasks, |b, &tasks| {
+            b.to_async(&runtime).iter(|| concurrent_load(cache.clone(), tasks));
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_cache);
+criterion_main!(benches);
\ No newline at end of file
@@ -0,0 +1,79 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use llmdig::utils::cache::Cache;
+use llmdig::utils::rate_limiter::RateLimiter;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+const TASKS: usize = 32;
+const OPS_PER_TASK: usize = 2_000;
+
+/// Each task reads and fills its own 500 keys, so tasks only contend for
+/// the locks and never for entries
+async fn cache_load(cache: Arc<Cache<String>>) {
+    let handles: Vec<_> = (0..TASKS)
+        .map(|task| {
+            let cache = cache.clone();
+            tokio::spawn(async move {
+                for i in 0..OPS_PER_TASK {
+                    let key = format!("question-{}-{}", task, i % 500);
+                    if cache.get(&key).await.is_none() {
+                        cache.set(key, "answer".to_string()).await;
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+/// Each task asks on behalf of its own 250 clients
+async fn rate_limiter_load(limiter: Arc<RateLimiter>) {
+    let handles: Vec<_> = (0..TASKS)
+        .map(|task| {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                for i in 0..OPS_PER_TASK {
+                    let addr = SocketAddr::new(IpAddr::from([10, task as u8, (i % 250) as u8, 1]), 53);
+                    limiter.allow_request(addr).await;
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+fn bench_sharding(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(8)
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("cache_shards");
+    for shards in [1, 16] {
+        let cache = Arc::new(Cache::with_shards(100_000, Duration::from_secs(300), shards));
+        group.bench_with_input(BenchmarkId::from_parameter(shards), &shards, |b, _| {
+            b.to_async(&runtime).iter(|| cache_load(cache.clone()));
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("rate_limiter_shards");
+    for shards in [1, 16] {
+        let limiter = Arc::new(RateLimiter::with_shards(60, 1_000_000, shards));
+        group.bench_with_input(BenchmarkId::from_parameter(shards), &shards, |b, _| {
+            b.to_async(&runtime).iter(|| rate_limiter_load(limiter.clone()));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_sharding);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+//! Answer from an inference service LLMdig has no built-in backend for.
+//!
+//!     cargo run --example custom_backend
+//!     dig @127.0.0.1 -p 9000 TXT what.is.rust.com
+
+use async_trait::async_trait;
+use llmdig::config::LlmBackendType;
+use llmdig::{Config, DnsServerBuilder, LlmBackend};
+
+/// Replace the body with a call to your own service
+struct InHouseBackend {
+    model: String,
+}
+
+#[async_trait]
+impl LlmBackend for InHouseBackend {
+    async fn generate_response(&self, prompt: &str) -> anyhow::Result<String> {
+        Ok(format!("{} says: you asked {:?}", self.model, prompt))
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // Registered backends are picked by name, so config files can select
+    // them with `backend = { registered = "in-house" }`
+    let server = DnsServerBuilder::new()
+        .register_backend("in-house", |config: &Config| {
+            Ok(Box::new(InHouseBackend {
+                model: config.llm.model.clone(),
+            }) as Box<dyn LlmBackend>)
+        })
+        .backend(LlmBackendType::Registered("in-house".to_string()))
+        .listen("127.0.0.1:9000".parse()?)
+        .start()?;
+    println!("Serving on {}, press Ctrl-C to stop", server.local_addrs()[0]);
+
+    tokio::signal::ctrl_c().await?;
+    server.shutdown().await
+}
\ No newline at end of file
@@ -0,0 +1,37 @@
+//! Run LLMdig inside another program: start a server on a free port, ask it
+//! one question over UDP and shut it down.
+//!
+//!     cargo run --example embedded
+
+use llmdig::config::LlmBackendType;
+use llmdig::server::DnsServerBuilder;
+use tokio::net::UdpSocket;
+use trust_dns_proto::op::{Message, Query};
+use trust_dns_proto::rr::{Name, RecordType};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // The mock backend answers offline; use e.g. `LlmBackendType::OpenAI`
+    // with `.api_key(...)` for real answers
+    let server = DnsServerBuilder::new()
+        .backend(LlmBackendType::Mock)
+        .listen("127.0.0.1:0".parse()?)
+        .start()?;
+    let addr = server.local_addrs()[0];
+    println!("Serving on {}", addr);
+
+    let mut query = Message::new();
+    query.set_id(1).set_recursion_desired(true);
+    query.add_query(Query::query(Name::from_ascii("what.is.rust.com.")?, RecordType::TXT));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    socket.send_to(&query.to_vec()?, addr).await?;
+    let mut buf = vec![0u8; 4096];
+    let len = socket.recv(&mut buf).await?;
+    for record in Message::from_vec(&buf[..len])?.answers() {
+        println!("{}", record);
+    }
+
+    println!("Requests served: {}", server.metrics().get_stats().await.total_requests);
+    server.shutdown().await
+}
\ No newline at end of file
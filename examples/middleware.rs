@@ -0,0 +1,58 @@
+//! Add custom logic to the request pipeline: answer greetings without asking
+//! the backend, and refuse questions about one topic.
+//!
+//!     cargo run --example middleware
+//!     dig @127.0.0.1 -p 9000 TXT hello.com
+
+use llmdig::config::LlmBackendType;
+use llmdig::dns::Answer;
+use llmdig::middleware::{Exchange, Middleware, Next, Stage};
+use llmdig::server::DnsServerBuilder;
+use std::sync::Arc;
+use trust_dns_proto::op::ResponseCode;
+
+struct Greetings;
+
+#[async_trait::async_trait]
+impl Middleware for Greetings {
+    async fn handle(&self, exchange: &mut Exchange<'_>, next: Next<'_>) -> anyhow::Result<()> {
+        for question in exchange.questions.iter_mut().filter(|question| question.is_pending()) {
+            if question.text.as_deref() == Some("hello") {
+                question.answer = Some(Answer::Txt("Hello from LLMdig".to_string()));
+            }
+        }
+        next.run(exchange).await
+    }
+}
+
+struct NoWeather;
+
+#[async_trait::async_trait]
+impl Middleware for NoWeather {
+    async fn handle(&self, exchange: &mut Exchange<'_>, next: Next<'_>) -> anyhow::Result<()> {
+        let asks_weather = exchange
+            .questions
+            .iter()
+            .any(|question| question.text.as_deref().is_some_and(|text| text.contains("weather")));
+        if asks_weather {
+            // Answer the whole message without running the later stages
+            exchange.respond(ResponseCode::Refused);
+            return Ok(());
+        }
+        next.run(exchange).await
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let server = DnsServerBuilder::new()
+        .backend(LlmBackendType::Mock)
+        .listen("127.0.0.1:9000".parse()?)
+        .middleware_after(Stage::Sanitize, Arc::new(NoWeather))
+        .middleware_before(Stage::Cache, Arc::new(Greetings))
+        .start()?;
+    println!("Serving on {}, press Ctrl-C to stop", server.local_addrs()[0]);
+
+    tokio::signal::ctrl_c().await?;
+    server.shutdown().await
+}
\ No newline at end of file
@@ -0,0 +1,59 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use llmdig::config::LlmBackendType;
+use llmdig::server::handle_datagram;
+use llmdig::utils::network::DnsNetworkUtils;
+use llmdig::{Config, DnsHandler};
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use tokio::runtime::Runtime;
+use trust_dns_proto::op::Message;
+use trust_dns_proto::serialize::binary::BinDecodable;
+use trust_dns_server::server::ResponseHandler;
+
+/// Fails the run when the server answers with bytes that do not parse
+struct CheckedResponse;
+
+#[async_trait::async_trait]
+impl ResponseHandler for CheckedResponse {
+    async fn send_response(&self, response_bytes: Vec<u8>) -> Result<(), std::io::Error> {
+        Message::from_bytes(&response_bytes).expect("server sent a malformed response");
+        Ok(())
+    }
+}
+
+/// One runtime and handler for the whole run, answering with the mock backend
+fn harness() -> &'static (Runtime, DnsHandler) {
+    static HARNESS: OnceLock<(Runtime, DnsHandler)> = OnceLock::new();
+    HARNESS.get_or_init(|| {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        let mut config = Config::default();
+        config.llm.backend = LlmBackendType::Mock;
+        // Every input comes from the same address
+        config.rate_limit.enabled = false;
+        let handler = {
+            let _guard = runtime.enter();
+            DnsHandler::new(config).unwrap()
+        };
+
+        (runtime, handler)
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    // The header helpers must cope with packets of any length
+    DnsNetworkUtils::validate_dns_packet(data);
+    DnsNetworkUtils::get_query_count(data);
+    DnsNetworkUtils::get_answer_count(data);
+    DnsNetworkUtils::is_dns_query(data);
+    DnsNetworkUtils::is_dns_response(data);
+    DnsNetworkUtils::get_dns_id(data);
+    DnsNetworkUtils::set_dns_id(&mut data.to_vec(), 0xbeef);
+
+    // Errors are expected for most inputs; panics and hangs are not
+    let (runtime, handler) = harness();
+    let src: SocketAddr = "192.0.2.1:5353".parse().unwrap();
+    let _ = runtime.block_on(handle_datagram(handler, data, src, Box::new(CheckedResponse)));
+});
\ No newline at end of file
@@ -0,0 +1,385 @@
+use crate::config::{AcmeConfig, TlsConfig};
+use crate::utils::encryption::write_private_file;
+use crate::Error;
+use anyhow::Result;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount, NewOrder, OrderStatus,
+};
+use rcgen::{CertificateParams, DnType, KeyPair};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tracing::{info, warn};
+use trust_dns_proto::rr::rdata::TXT;
+use trust_dns_proto::rr::{LowerName, Name, RData, Record};
+
+/// First label of the names DNS-01 challenges are answered under
+const CHALLENGE_LABEL: &str = "_acme-challenge";
+
+/// TTL of challenge records, short so a retried order is not answered from
+/// a resolver's cache
+const CHALLENGE_TTL: u32 = 60;
+
+/// Longest wait for the certificate authority to check the challenges or
+/// issue the certificate
+const ORDER_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Wait before trying again after a failed renewal
+const RETRY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// TXT values published for DNS-01 challenges while an order is pending,
+/// answered by the DNS handler ahead of everything else
+#[derive(Debug, Default)]
+pub struct Dns01Challenges {
+    enabled: bool,
+    values: RwLock<HashMap<LowerName, Vec<String>>>,
+}
+
+impl Dns01Challenges {
+    pub fn new(config: &AcmeConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            values: RwLock::default(),
+        }
+    }
+
+    /// With ACME on, names under `_acme-challenge` are never questions,
+    /// pending or not
+    pub fn serves(&self, name: &Name) -> bool {
+        self.enabled
+            && name.iter().next().is_some_and(|label| label.eq_ignore_ascii_case(CHALLENGE_LABEL.as_bytes()))
+    }
+
+    /// The challenge records for `name`, empty when none is pending
+    pub fn lookup(&self, name: &Name) -> Vec<Record> {
+        let values = self.values.read().unwrap();
+        values
+            .get(&LowerName::new(name))
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|value| Record::from_rdata(name.clone(), CHALLENGE_TTL, RData::TXT(TXT::new(vec![value.clone()]))))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Answer `value` for the challenge of `domain`, besides any already
+    /// published; a name and its wildcard share one challenge name
+    pub fn publish(&self, domain: &str, value: String) -> Result<()> {
+        let name = challenge_name(domain)?;
+        self.values.write().unwrap().entry(name).or_default().push(value);
+        Ok(())
+    }
+
+    /// Stop answering every challenge
+    pub fn clear(&self) {
+        self.values.write().unwrap().clear();
+    }
+}
+
+fn challenge_name(domain: &str) -> Result<LowerName> {
+    let name = Name::from_ascii(format!("{}.{}.", CHALLENGE_LABEL, domain.trim_end_matches('.')))
+        .map_err(|e| Error::Configuration(format!("Invalid ACME host name {}: {}", domain, e)))?;
+    Ok(LowerName::new(&name))
+}
+
+/// A certificate and its private key, both PEM encoded
+#[derive(Debug, Clone)]
+pub struct TlsCertificate {
+    pub cert_pem: String,
+    pub key_pem: String,
+    /// When the certificate expires
+    pub not_after: SystemTime,
+}
+
+impl TlsCertificate {
+    pub fn new(cert_pem: String, key_pem: String) -> Result<Self> {
+        let not_after = not_after(&cert_pem)?;
+        Ok(Self {
+            cert_pem,
+            key_pem,
+            not_after,
+        })
+    }
+
+    /// Read `tls.cert_path` and `tls.key_path`, or `None` if either is
+    /// missing or the certificate cannot be read, so that ACME replaces it
+    pub fn load(config: &TlsConfig) -> Option<Self> {
+        let cert_pem = std::fs::read_to_string(&config.cert_path).ok()?;
+        let key_pem = std::fs::read_to_string(&config.key_path).ok()?;
+        match Self::new(cert_pem, key_pem) {
+            Ok(certificate) => Some(certificate),
+            Err(e) => {
+                warn!("Ignoring certificate {}: {}", config.cert_path, e);
+                None
+            }
+        }
+    }
+
+    /// Whether the certificate expires within `days`
+    pub fn expires_within(&self, days: u32) -> bool {
+        SystemTime::now() + Duration::from_secs(u64::from(days) * 86400) >= self.not_after
+    }
+}
+
+/// Expiry of the first certificate in `cert_pem`
+fn not_after(cert_pem: &str) -> Result<SystemTime> {
+    let (_, pem) = x509_parser::pem::parse_x509_pem(cert_pem.as_bytes())
+        .map_err(|e| Error::Configuration(format!("Invalid PEM certificate: {}", e)))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|e| Error::Configuration(format!("Invalid certificate: {}", e)))?;
+    let timestamp = cert.validity().not_after.timestamp();
+    Ok(UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64))
+}
+
+/// The certificate being served. TLS listeners subscribe and switch to a
+/// renewed certificate as soon as it is stored, without a restart.
+#[derive(Debug)]
+pub struct CertificateStore {
+    current: watch::Sender<Option<Arc<TlsCertificate>>>,
+}
+
+impl CertificateStore {
+    /// A store holding the certificate in `tls.cert_path`, if there is one
+    pub fn load(config: &TlsConfig) -> Self {
+        let (current, _) = watch::channel(TlsCertificate::load(config).map(Arc::new));
+        Self { current }
+    }
+
+    pub fn current(&self) -> Option<Arc<TlsCertificate>> {
+        self.current.borrow().clone()
+    }
+
+    /// Notified whenever the certificate is replaced
+    pub fn subscribe(&self) -> watch::Receiver<Option<Arc<TlsCertificate>>> {
+        self.current.subscribe()
+    }
+
+    pub fn replace(&self, certificate: TlsCertificate) {
+        self.current.send_replace(Some(Arc::new(certificate)));
+    }
+}
+
+/// Keeps the certificate in `[tls]` issued by the ACME certificate authority
+pub struct AcmeManager {
+    tls: TlsConfig,
+    challenges: Arc<Dns01Challenges>,
+    store: Arc<CertificateStore>,
+}
+
+impl AcmeManager {
+    pub fn new(tls: &TlsConfig, challenges: Arc<Dns01Challenges>, store: Arc<CertificateStore>) -> Result<Self> {
+        if tls.hostnames.is_empty() {
+            return Err(Error::Configuration("tls.hostnames: ACME needs at least one host name".to_string()).into());
+        }
+        if let Some(ip) = tls.hostnames.iter().find(|name| name.parse::<IpAddr>().is_ok()) {
+            return Err(Error::Configuration(format!(
+                "tls.hostnames: ACME certificates cannot be issued for the IP address {}",
+                ip
+            ))
+            .into());
+        }
+        for hostname in &tls.hostnames {
+            challenge_name(hostname.trim_start_matches("*."))?;
+        }
+
+        Ok(Self {
+            tls: tls.clone(),
+            challenges,
+            store,
+        })
+    }
+
+    /// Renew whenever the certificate is missing or close to expiry, until
+    /// the task is dropped
+    pub async fn run(self) {
+        let config = &self.tls.acme;
+        let check_interval = Duration::from_secs(config.check_interval_hours.max(1) * 3600);
+        loop {
+            let due = match self.store.current() {
+                Some(certificate) => certificate.expires_within(config.renew_before_days),
+                None => true,
+            };
+            if !due {
+                tokio::time::sleep(check_interval).await;
+                continue;
+            }
+
+            let result = self.renew().await;
+            self.challenges.clear();
+            match result {
+                Ok(certificate) => {
+                    info!("Obtained a certificate for {} from {}", self.tls.hostnames.join(", "), config.directory_url);
+                    self.store.replace(certificate);
+                    tokio::time::sleep(check_interval).await;
+                }
+                Err(e) => {
+                    warn!("Could not obtain a certificate from {}: {:#}", config.directory_url, e);
+                    tokio::time::sleep(RETRY_INTERVAL.min(check_interval)).await;
+                }
+            }
+        }
+    }
+
+    /// Order a certificate, answer its challenges and store what is issued
+    async fn renew(&self) -> Result<TlsCertificate> {
+        let account = self.account().await?;
+        let identifiers: Vec<Identifier> = self.tls.hostnames.iter().map(|name| Identifier::Dns(name.clone())).collect();
+        let mut order = account
+            .new_order(&NewOrder {
+                identifiers: &identifiers,
+            })
+            .await?;
+
+        let mut ready = Vec::new();
+        for authorization in order.authorizations().await? {
+            match authorization.status {
+                AuthorizationStatus::Pending => {}
+                AuthorizationStatus::Valid => continue,
+                status => {
+                    return Err(Error::Network(format!("Authorization is {:?}", status)).into());
+                }
+            }
+            let challenge = authorization
+                .challenges
+                .iter()
+                .find(|challenge| challenge.r#type == ChallengeType::Dns01)
+                .ok_or_else(|| Error::Network("The certificate authority offered no DNS-01 challenge".to_string()))?;
+            let Identifier::Dns(domain) = &authorization.identifier;
+            self.challenges.publish(domain, order.key_authorization(challenge).dns_value())?;
+            ready.push(challenge.url.clone());
+        }
+        for url in &ready {
+            order.set_challenge_ready(url).await?;
+        }
+
+        let key_pair = KeyPair::generate()?;
+        let mut params = CertificateParams::new(self.tls.hostnames.clone())?;
+        params.distinguished_name.push(DnType::CommonName, self.tls.hostnames[0].as_str());
+        let csr = params.serialize_request(&key_pair)?;
+
+        let deadline = tokio::time::Instant::now() + ORDER_TIMEOUT;
+        let mut delay = Duration::from_secs(1);
+        let cert_pem = loop {
+            let state = order.refresh().await?;
+            let (status, reason) = (state.status, state.error.as_ref().and_then(|problem| problem.detail.clone()));
+            match status {
+                OrderStatus::Ready => order.finalize(csr.der()).await?,
+                OrderStatus::Valid => {
+                    if let Some(cert_pem) = order.certificate().await? {
+                        break cert_pem;
+                    }
+                }
+                OrderStatus::Invalid => {
+                    return Err(Error::Network(format!(
+                        "Order failed: {}",
+                        reason.unwrap_or_else(|| "no reason given".to_string())
+                    ))
+                    .into());
+                }
+                OrderStatus::Pending | OrderStatus::Processing => {}
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(Error::Network("Timed out waiting for the order to complete".to_string()).into());
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(Duration::from_secs(10));
+        };
+
+        // The key first, so the certificate never names a key not yet written
+        let certificate = TlsCertificate::new(cert_pem, key_pair.serialize_pem())?;
+        write_private_file(Path::new(&self.tls.key_path), certificate.key_pem.as_bytes())
+            .map_err(|e| Error::Configuration(format!("Could not write {}: {}", self.tls.key_path, e)))?;
+        let cert_path = Path::new(&self.tls.cert_path);
+        if let Some(parent) = cert_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(cert_path, &certificate.cert_pem)
+            .map_err(|e| Error::Configuration(format!("Could not write {}: {}", self.tls.cert_path, e)))?;
+        Ok(certificate)
+    }
+
+    /// The account kept in `account_path`, created on first use
+    async fn account(&self) -> Result<Account> {
+        let config: &AcmeConfig = &self.tls.acme;
+        if let Ok(json) = std::fs::read_to_string(&config.account_path) {
+            let credentials: AccountCredentials = serde_json::from_str(&json)
+                .map_err(|e| Error::Configuration(format!("Invalid ACME account {}: {}", config.account_path, e)))?;
+            return Ok(Account::from_credentials(credentials).await?);
+        }
+
+        let contact: Vec<&str> = config.contact.iter().map(String::as_str).collect();
+        let (account, credentials) = Account::create(
+            &NewAccount {
+                contact: &contact,
+                terms_of_service_agreed: true,
+                only_return_existing: false,
+            },
+            &config.directory_url,
+            None,
+        )
+        .await?;
+        write_private_file(Path::new(&config.account_path), serde_json::to_string(&credentials)?.as_bytes())
+            .map_err(|e| Error::Configuration(format!("Could not write {}: {}", config.account_path, e)))?;
+        info!("Created ACME account at {}, kept in {}", config.directory_url, config.account_path);
+        Ok(account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, LlmBackendType};
+    use crate::dns::DnsHandler;
+    use crate::dry_run;
+    use crate::utils::encryption::CertificateUtils;
+    use trust_dns_proto::rr::RecordType;
+    use trust_dns_proto::xfer::Protocol;
+
+    #[tokio::test]
+    async fn test_challenges_are_answered() {
+        let mut config = Config::default();
+        config.llm.backend = LlmBackendType::Mock;
+        config.server.served_zones = vec!["example.com".to_string()];
+        config.tls.acme.enabled = true;
+        let handler = DnsHandler::new(config).unwrap();
+        handler.acme_challenges().publish("dns.example.com", "token-digest".to_string()).unwrap();
+
+        let name = Name::from_ascii("_ACME-challenge.dns.example.com.").unwrap();
+        let dry_run = dry_run::query(&handler, name, RecordType::TXT, Protocol::Udp).await.unwrap();
+        assert_eq!(dry_run.response.answer_count(), 1);
+        assert!(dry_run.render().contains("\"token-digest\""));
+
+        // Never asked of the backend, even without a pending challenge
+        handler.acme_challenges().clear();
+        let name = Name::from_ascii("_acme-challenge.other.example.com.").unwrap();
+        let dry_run = dry_run::query(&handler, name, RecordType::TXT, Protocol::Udp).await.unwrap();
+        assert_eq!(dry_run.response.answer_count(), 0);
+    }
+
+    #[test]
+    fn test_certificate_expiry() {
+        let cert = CertificateUtils::generate_self_signed_cert(&["dns.example.com".to_string()], 20).unwrap();
+        let certificate = TlsCertificate::new(cert.cert_pem, cert.key_pem).unwrap();
+        assert!(certificate.expires_within(30));
+        assert!(!certificate.expires_within(10));
+        assert!(TlsCertificate::new("not a certificate".to_string(), String::new()).is_err());
+
+        let tls = TlsConfig {
+            hostnames: vec!["dns.example.com".to_string(), "192.0.2.1".to_string()],
+            ..TlsConfig::default()
+        };
+        let store = Arc::new(CertificateStore::load(&tls));
+        let updates = store.subscribe();
+        assert!(AcmeManager::new(&tls, Arc::default(), store.clone()).is_err());
+
+        store.replace(certificate);
+        assert!(updates.has_changed().unwrap());
+        assert!(store.current().is_some());
+    }
+}
@@ -0,0 +1,675 @@
+use crate::config::{AdminConfig, PasswordHashConfig};
+use crate::dns::DnsHandler;
+use crate::https::{ClientIdentity, HttpsAcceptor};
+use crate::utils::abuse::Ban;
+use crate::utils::audit::{Actor, AuditQuery};
+use crate::utils::encryption::HashUtils;
+use crate::utils::secret::SecretString;
+use crate::Error;
+use anyhow::Result;
+use argon2::password_hash::PasswordHash;
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+
+#[derive(Deserialize)]
+struct SecondaryKey {
+    key: SecretString,
+}
+
+#[derive(Clone)]
+struct AdminState {
+    handler: Arc<DnsHandler>,
+    token: AdminToken,
+}
+
+/// The bearer token requests must present
+#[derive(Debug, Clone, Default)]
+pub enum AdminToken {
+    /// Anyone who can reach the listener may use the API
+    #[default]
+    None,
+    Plain(SecretString),
+    /// PHC hash of the token, see [`HashUtils::hash_password`]
+    Hashed(SecretString),
+}
+
+impl AdminToken {
+    /// `admin.token` or `admin.token_hash`, whichever is set. A hash made
+    /// with weaker settings than `[password_hashing]` is still accepted,
+    /// with a warning to regenerate it.
+    pub fn from_config(config: &AdminConfig, hashing: &PasswordHashConfig) -> Result<Self> {
+        match (&config.token, &config.token_hash) {
+            (Some(_), Some(_)) => Err(Error::Configuration(
+                "admin.token and admin.token_hash cannot both be set".to_string(),
+            )
+            .into()),
+            (Some(token), None) => Ok(Self::Plain(token.clone())),
+            (None, Some(hash)) => {
+                PasswordHash::new(hash.expose())
+                    .map_err(|e| Error::Configuration(format!("admin.token_hash is not a PHC string: {}", e)))?;
+                if HashUtils::needs_rehash(hash.expose(), hashing) {
+                    warn!("admin.token_hash is weaker than [password_hashing] asks for; regenerate it with `llmdig hash-password`");
+                }
+                Ok(Self::Hashed(hash.clone()))
+            }
+            (None, None) => Ok(Self::None),
+        }
+    }
+}
+
+/// Serve the admin API until the listener fails.
+///
+/// `GET /admin/bans` lists the clients banned for abuse and
+/// `DELETE /admin/bans/{ip}` lifts a ban. `DELETE /admin/clients/{ip}`
+/// purges the data kept about a client, `GET /admin/tenants` reports what
+/// each tenant has used and `GET /admin/threats` lists the attackers the
+/// honeypot has seen. `PUT /admin/maintenance` enters maintenance mode,
+/// `DELETE /admin/maintenance` leaves it and `GET` tells whether it is on.
+/// `DELETE /admin/cache` drops every cached answer.
+/// `GET /admin/cache/fingerprints` counts the cached answers per generation
+/// fingerprint, along with the current one, and
+/// `DELETE /admin/cache/fingerprints/{fingerprint}` drops those generated
+/// under a fingerprint. `GET /admin/llm/keys`
+/// shows the LLM provider's API keys, masked; `PUT /admin/llm/keys/secondary`
+/// sets the secondary key from `{"key": "..."}` and
+/// `POST /admin/llm/keys/promote` makes it the primary. Each of these
+/// changes is recorded in the audit log, which `GET /admin/audit` queries.
+/// When a token is configured it is required as `Authorization: Bearer <token>`;
+/// without one, the keys cannot be changed.
+/// With `tls`, the API is served over HTTPS instead, and may require client
+/// certificates on top of the token.
+pub async fn serve(
+    listener: TcpListener,
+    handler: Arc<DnsHandler>,
+    token: AdminToken,
+    tls: Option<HttpsAcceptor>,
+) -> Result<()> {
+    let app = Router::new()
+        .route("/admin/bans", get(list_bans))
+        .route("/admin/bans/:ip", delete(unban))
+        .route("/admin/clients/:ip", delete(purge_client))
+        .route("/admin/tenants", get(tenant_usage))
+        .route("/admin/threats", get(list_attackers))
+        .route(
+            "/admin/maintenance",
+            get(maintenance).put(start_maintenance).delete(end_maintenance),
+        )
+        .route("/admin/cache", delete(flush_cache))
+        .route("/admin/cache/fingerprints", get(cache_fingerprints))
+        .route("/admin/cache/fingerprints/:fingerprint", delete(invalidate_fingerprint))
+        .route("/admin/llm/keys", get(llm_keys))
+        .route("/admin/llm/keys/secondary", put(set_secondary_key))
+        .route("/admin/llm/keys/promote", post(promote_secondary_key))
+        .route("/admin/audit", get(audit_log))
+        .with_state(AdminState { handler, token });
+
+    if matches!(token, AdminToken::None) {
+        warn!("The admin API has no token: anyone who can reach it may use it");
+    }
+    info!("Admin API listening on {}", listener.local_addr()?);
+    match tls {
+        Some(tls) => tls.serve(listener, app).await,
+        None => Ok(axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?),
+    }
+}
+
+async fn authorized(state: &AdminState, headers: &HeaderMap) -> bool {
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match (&state.token, presented) {
+        (AdminToken::None, _) => true,
+        (_, None) => false,
+        (AdminToken::Plain(token), Some(presented)) => {
+            HashUtils::constant_time_eq(token.expose().as_bytes(), presented.as_bytes())
+        }
+        // Hashing is slow by design, so it is kept off the runtime's threads
+        (AdminToken::Hashed(hash), Some(presented)) => {
+            let (hash, presented) = (hash.clone(), presented.to_string());
+            tokio::task::spawn_blocking(move || HashUtils::verify_password(&presented, hash.expose()))
+                .await
+                .unwrap_or(false)
+        }
+    }
+}
+
+/// Whether requests must present a token. The provider's keys can only be
+/// changed when they must.
+fn has_token(state: &AdminState) -> bool {
+    !matches!(state.token, AdminToken::None)
+}
+
+/// The client making a request, for the audit log
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Actor {
+            address: parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(address)| address.ip()),
+            common_name: parts
+                .extensions
+                .get::<ClientIdentity>()
+                .and_then(|identity| identity.common_name.clone()),
+        })
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+async fn list_bans(State(state): State<AdminState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    let bans: Vec<Ban> = state.handler.abuse().bans().await;
+    Json(bans).into_response()
+}
+
+async fn unban(State(state): State<AdminState>, actor: Actor, headers: HeaderMap, Path(ip): Path<String>) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    let Ok(ip) = ip.parse::<IpAddr>() else {
+        return error(StatusCode::BAD_REQUEST, "not an IP address");
+    };
+    let abuse = state.handler.abuse();
+    let ban = abuse.ban(ip).await;
+    if !abuse.unban(ip).await {
+        return error(StatusCode::NOT_FOUND, "not banned");
+    }
+    let before = serde_json::to_value(ban).unwrap_or_default();
+    state.handler.audit().record(actor, "unban", Some(ip.to_string()), before, Value::Null).await;
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn tenant_usage(State(state): State<AdminState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    Json(state.handler.tenant_usage().await).into_response()
+}
+
+async fn list_attackers(State(state): State<AdminState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    match state.handler.threats() {
+        Some(threats) => Json(threats.attackers().await).into_response(),
+        None => error(StatusCode::NOT_FOUND, "honeypot is disabled"),
+    }
+}
+
+async fn maintenance(State(state): State<AdminState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    Json(json!({ "enabled": state.handler.in_maintenance() })).into_response()
+}
+
+async fn start_maintenance(State(state): State<AdminState>, actor: Actor, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    switch_maintenance(&state, actor, true).await
+}
+
+async fn end_maintenance(State(state): State<AdminState>, actor: Actor, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    switch_maintenance(&state, actor, false).await
+}
+
+async fn switch_maintenance(state: &AdminState, actor: Actor, enabled: bool) -> Response {
+    let before = json!({ "enabled": state.handler.in_maintenance() });
+    state.handler.set_maintenance(enabled);
+    let after = json!({ "enabled": enabled });
+    state.handler.audit().record(actor, "maintenance", None, before, after.clone()).await;
+    Json(after).into_response()
+}
+
+async fn flush_cache(State(state): State<AdminState>, actor: Actor, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    let removed = state.handler.flush_cache().await;
+    let (before, after) = (json!({ "cached_answers": removed }), json!({ "cached_answers": 0 }));
+    state.handler.audit().record(actor, "flush_cache", None, before, after).await;
+    Json(json!({ "cached_answers": removed })).into_response()
+}
+
+async fn cache_fingerprints(State(state): State<AdminState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    Json(json!({
+        "current": state.handler.generation_fingerprint(),
+        "fingerprints": state.handler.cache_fingerprints().await,
+    }))
+    .into_response()
+}
+
+async fn invalidate_fingerprint(
+    State(state): State<AdminState>,
+    actor: Actor,
+    Path(fingerprint): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    let removed = state.handler.invalidate_fingerprint(&fingerprint).await;
+    let (before, after) = (json!({ "cached_answers": removed }), json!({ "cached_answers": 0 }));
+    state.handler.audit().record(actor, "invalidate_fingerprint", Some(fingerprint), before, after).await;
+    Json(json!({ "cached_answers": removed })).into_response()
+}
+
+async fn llm_keys(State(state): State<AdminState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    Json(state.handler.llm_keys().status()).into_response()
+}
+
+async fn set_secondary_key(
+    State(state): State<AdminState>,
+    actor: Actor,
+    headers: HeaderMap,
+    Json(request): Json<SecondaryKey>,
+) -> Response {
+    if !has_token(&state) {
+        return error(StatusCode::FORBIDDEN, "key rotation needs admin.token or admin.token_hash");
+    }
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    if request.key.expose().trim().is_empty() {
+        return error(StatusCode::BAD_REQUEST, "empty key");
+    }
+    let keys = state.handler.llm_keys();
+    let before = json!(keys.status());
+    let after = keys.set_secondary(request.key);
+    state.handler.audit().record(actor, "set_secondary_key", None, before, json!(after)).await;
+    Json(after).into_response()
+}
+
+async fn promote_secondary_key(State(state): State<AdminState>, actor: Actor, headers: HeaderMap) -> Response {
+    if !has_token(&state) {
+        return error(StatusCode::FORBIDDEN, "key rotation needs admin.token or admin.token_hash");
+    }
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    let keys = state.handler.llm_keys();
+    let before = json!(keys.status());
+    match keys.promote_secondary() {
+        Ok(after) => {
+            state.handler.audit().record(actor, "promote_key", None, before, json!(after)).await;
+            Json(after).into_response()
+        }
+        Err(_) => error(StatusCode::CONFLICT, "no secondary key to promote"),
+    }
+}
+
+async fn audit_log(State(state): State<AdminState>, headers: HeaderMap, Query(query): Query<AuditQuery>) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    match state.handler.audit().entries(&query).await {
+        Ok(entries) => Json(entries).into_response(),
+        Err(e) => {
+            error!("Could not read the audit log: {}", e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, "audit log unavailable")
+        }
+    }
+}
+
+async fn purge_client(
+    State(state): State<AdminState>,
+    actor: Actor,
+    headers: HeaderMap,
+    Path(ip): Path<String>,
+) -> Response {
+    if !authorized(&state, &headers).await {
+        return error(StatusCode::UNAUTHORIZED, "invalid or missing admin token");
+    }
+    let Ok(ip) = ip.parse::<IpAddr>() else {
+        return error(StatusCode::BAD_REQUEST, "not an IP address");
+    };
+    match state.handler.purge_client(ip).await {
+        Ok(report) => {
+            let after = serde_json::to_value(report).unwrap_or_default();
+            state.handler.audit().record(actor, "purge_client", Some(ip.to_string()), Value::Null, after).await;
+            Json(report).into_response()
+        }
+        Err(e) => {
+            error!("Could not purge the data of {}: {}", ip, e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, "purge failed")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AbuseConfig, Config, HoneypotConfig, LlmBackendType, TenantConfig};
+    use crate::dns::CacheStatus;
+    use crate::utils::abuse::Offence;
+    use crate::utils::threats::{ThreatKind, ThreatLogEntry};
+
+    async fn start(handler: Arc<DnsHandler>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, handler, AdminToken::Plain("secret".into()), None));
+        format!("http://{}/admin", addr)
+    }
+
+    fn mock_handler(config: Config) -> Arc<DnsHandler> {
+        let mut config = config;
+        config.llm.backend = LlmBackendType::Mock;
+        Arc::new(DnsHandler::new(config).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_bans() {
+        let handler = mock_handler(Config {
+            abuse: AbuseConfig {
+                enabled: true,
+                max_malformed: 1,
+                ..Default::default()
+            },
+            ..Config::default()
+        });
+        let abuse = handler.abuse();
+        let ip: IpAddr = "203.0.113.9".parse().unwrap();
+        abuse.record(ip, Offence::Malformed).await;
+
+        let url = format!("{}/bans", start(handler).await);
+        let client = reqwest::Client::new();
+
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+
+        let bans: serde_json::Value = client.get(&url).bearer_auth("secret").send().await.unwrap().json().await.unwrap();
+        assert_eq!(bans[0]["ip"], "203.0.113.9");
+        assert_eq!(bans[0]["reason"], "malformed");
+
+        let unban = |ip: &str| client.delete(format!("{}/{}", url, ip)).bearer_auth("secret").send();
+        assert_eq!(unban("203.0.113.9").await.unwrap().status().as_u16(), 204);
+        assert_eq!(unban("203.0.113.9").await.unwrap().status().as_u16(), 404);
+        assert_eq!(unban("nonsense").await.unwrap().status().as_u16(), 400);
+        assert!(!abuse.is_banned(ip).await);
+    }
+
+    #[tokio::test]
+    async fn test_hashed_token() {
+        // Cheap settings, so the test runs quickly
+        let hashing = PasswordHashConfig {
+            memory_kib: 1024,
+            iterations: 1,
+            ..PasswordHashConfig::default()
+        };
+        let config = AdminConfig {
+            token_hash: Some(HashUtils::hash_password("secret", &hashing).unwrap().into()),
+            ..AdminConfig::default()
+        };
+        let token = AdminToken::from_config(&config, &hashing).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/admin/maintenance", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, mock_handler(Config::default()), token, None));
+        let client = reqwest::Client::new();
+        assert_eq!(client.get(&url).bearer_auth("secret").send().await.unwrap().status().as_u16(), 200);
+        assert_eq!(client.get(&url).bearer_auth("wrong").send().await.unwrap().status().as_u16(), 401);
+        assert_eq!(client.get(&url).send().await.unwrap().status().as_u16(), 401);
+
+        let both = AdminConfig {
+            token: Some("secret".into()),
+            ..config
+        };
+        assert!(AdminToken::from_config(&both, &hashing).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_purge_client() {
+        let handler = mock_handler(Config::default());
+        let ip: IpAddr = "203.0.113.10".parse().unwrap();
+        handler.abuse().record(ip, Offence::Error).await;
+
+        let url = format!("{}/clients", start(handler.clone()).await);
+        let client = reqwest::Client::new();
+        let purge = |ip: &str| client.delete(format!("{}/{}", url, ip)).bearer_auth("secret").send();
+
+        let response = client.delete(format!("{}/203.0.113.10", url)).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+
+        let report: serde_json::Value = purge("203.0.113.10").await.unwrap().json().await.unwrap();
+        assert_eq!(report["client_records"], 1);
+        assert_eq!(report["query_log_entries"], 0);
+        let report: serde_json::Value = purge("203.0.113.10").await.unwrap().json().await.unwrap();
+        assert_eq!(report["client_records"], 0);
+        assert_eq!(purge("nonsense").await.unwrap().status().as_u16(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_usage() {
+        let handler = mock_handler(Config {
+            tenants: vec![TenantConfig {
+                name: "research".to_string(),
+                zones: vec!["ask.research.com".to_string()],
+                ..TenantConfig::default()
+            }],
+            ..Config::default()
+        });
+
+        let url = format!("{}/tenants", start(handler).await);
+        let client = reqwest::Client::new();
+        assert_eq!(client.get(&url).send().await.unwrap().status().as_u16(), 401);
+
+        let usage: serde_json::Value = client.get(&url).bearer_auth("secret").send().await.unwrap().json().await.unwrap();
+        assert_eq!(usage[0]["name"], "research");
+        assert_eq!(usage[0]["queries"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_threats() {
+        let path = std::env::temp_dir().join(format!("llmdig-admin-threats-{}.jsonl", std::process::id()));
+        let handler = mock_handler(Config {
+            honeypot: HoneypotConfig {
+                enabled: true,
+                threat_log_path: path.to_string_lossy().to_string(),
+                ..HoneypotConfig::default()
+            },
+            ..Config::default()
+        });
+        handler
+            .threats()
+            .unwrap()
+            .record(ThreatLogEntry {
+                timestamp: 1_700_000_000_000,
+                client_ip: "203.0.113.11".parse().unwrap(),
+                kind: ThreatKind::VersionProbe,
+                name: "version.bind.".to_string(),
+                query_type: "TXT".to_string(),
+                query_class: "CH".to_string(),
+                fingerprint: "udp noedns".to_string(),
+            })
+            .await;
+
+        let url = format!("{}/threats", start(handler).await);
+        let client = reqwest::Client::new();
+        assert_eq!(client.get(&url).send().await.unwrap().status().as_u16(), 401);
+
+        let attackers: serde_json::Value = client.get(&url).bearer_auth("secret").send().await.unwrap().json().await.unwrap();
+        assert_eq!(attackers[0]["ip"], "203.0.113.11");
+        assert_eq!(attackers[0]["queries"], 1);
+        assert_eq!(attackers[0]["kinds"]["version_probe"], 1);
+
+        // Without the honeypot there is nothing to list
+        let url = format!("{}/threats", start(mock_handler(Config::default())).await);
+        assert_eq!(client.get(&url).bearer_auth("secret").send().await.unwrap().status().as_u16(), 404);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance() {
+        let handler = mock_handler(Config::default());
+        let url = format!("{}/maintenance", start(handler.clone()).await);
+        let client = reqwest::Client::new();
+        assert_eq!(client.put(&url).send().await.unwrap().status().as_u16(), 401);
+        assert!(!handler.in_maintenance());
+
+        let status: serde_json::Value = client.put(&url).bearer_auth("secret").send().await.unwrap().json().await.unwrap();
+        assert_eq!(status["enabled"], true);
+        assert!(handler.in_maintenance());
+        let status: serde_json::Value = client.get(&url).bearer_auth("secret").send().await.unwrap().json().await.unwrap();
+        assert_eq!(status["enabled"], true);
+
+        client.delete(&url).bearer_auth("secret").send().await.unwrap();
+        assert!(!handler.in_maintenance());
+        assert!(handler.toggle_maintenance());
+    }
+
+    #[tokio::test]
+    async fn test_key_rotation() {
+        let mut config = Config::default();
+        config.llm.api_key = Some("sk-admin-rotation-0001".into());
+        let handler = mock_handler(config);
+        let url = format!("{}/llm/keys", start(handler.clone()).await);
+        let client = reqwest::Client::new();
+
+        let promote = || client.post(format!("{}/promote", url)).bearer_auth("secret").send();
+        assert_eq!(promote().await.unwrap().status().as_u16(), 409);
+        let secondary = json!({ "key": "sk-admin-rotation-0002" });
+        let response = client.put(format!("{}/secondary", url)).json(&secondary).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+        let status: serde_json::Value =
+            client.put(format!("{}/secondary", url)).bearer_auth("secret").json(&secondary).send().await.unwrap().json().await.unwrap();
+        assert_eq!(status["primary"], "****0001");
+        assert_eq!(status["secondary"], "****0002");
+
+        let status: serde_json::Value = promote().await.unwrap().json().await.unwrap();
+        assert_eq!(status["primary"], "****0002");
+        assert!(status["secondary"].is_null());
+        let candidates = handler.llm_keys().candidates();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].expose(), "sk-admin-rotation-0002");
+
+        // The audit log shows which keys were in use, never the keys
+        let entries = handler.audit().entries(&AuditQuery::default()).await.unwrap();
+        assert_eq!(entries[1].action, "promote_key");
+        assert_eq!(entries[1].before["primary"], "****0001");
+        assert!(!serde_json::to_string(&entries).unwrap().contains("sk-admin"));
+
+        // Keys cannot be changed through an API anyone can reach
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/admin/llm/keys", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, handler.clone(), AdminToken::None, None));
+        let response = client.put(format!("{}/secondary", url)).json(&secondary).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 403);
+        assert_eq!(client.post(format!("{}/promote", url)).send().await.unwrap().status().as_u16(), 403);
+        assert_eq!(client.get(&url).send().await.unwrap().status().as_u16(), 200);
+        assert!(handler.llm_keys().status().secondary.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_fingerprint() {
+        let handler = mock_handler(Config::default());
+        let client_addr: SocketAddr = "127.0.0.1:5300".parse().unwrap();
+        handler.ask(client_addr, None, "what is rust", None).await.unwrap();
+        handler.ask(client_addr, None, "what is dns", None).await.unwrap();
+        let answer = handler.ask(client_addr, None, "what is rust", None).await.unwrap();
+        assert_eq!(answer.cache, Some(CacheStatus::Hit));
+
+        let url = start(handler.clone()).await;
+        let client = reqwest::Client::new();
+        let listed: serde_json::Value =
+            client.get(format!("{}/cache/fingerprints", url)).bearer_auth("secret").send().await.unwrap().json().await.unwrap();
+        let current = listed["current"].as_str().unwrap().to_string();
+        assert_eq!(listed["fingerprints"][&current], 2);
+
+        let invalidated: serde_json::Value = client
+            .delete(format!("{}/cache/fingerprints/{}", url, current))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(invalidated["cached_answers"], 2);
+        let answer = handler.ask(client_addr, None, "what is rust", None).await.unwrap();
+        assert_eq!(answer.cache, Some(CacheStatus::Miss));
+
+        let entries = handler.audit().entries(&AuditQuery::default()).await.unwrap();
+        assert_eq!(entries[0].action, "invalidate_fingerprint");
+        assert_eq!(entries[0].target.as_deref(), Some(current.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let handler = mock_handler(Config {
+            abuse: AbuseConfig {
+                enabled: true,
+                max_malformed: 1,
+                ..Default::default()
+            },
+            ..Config::default()
+        });
+        let ip: IpAddr = "203.0.113.12".parse().unwrap();
+        handler.abuse().record(ip, Offence::Malformed).await;
+
+        let url = start(handler).await;
+        let client = reqwest::Client::new();
+        client.put(format!("{}/maintenance", url)).bearer_auth("secret").send().await.unwrap();
+        client.delete(format!("{}/bans/203.0.113.12", url)).bearer_auth("secret").send().await.unwrap();
+        let flushed: serde_json::Value =
+            client.delete(format!("{}/cache", url)).bearer_auth("secret").send().await.unwrap().json().await.unwrap();
+        assert_eq!(flushed["cached_answers"], 0);
+        // Refused requests change nothing and are not recorded
+        client.delete(format!("{}/cache", url)).send().await.unwrap();
+        assert_eq!(client.get(format!("{}/audit", url)).send().await.unwrap().status().as_u16(), 401);
+
+        let entries: serde_json::Value =
+            client.get(format!("{}/audit", url)).bearer_auth("secret").send().await.unwrap().json().await.unwrap();
+        let actions: Vec<&str> = entries.as_array().unwrap().iter().map(|entry| entry["action"].as_str().unwrap()).collect();
+        assert_eq!(actions, vec!["maintenance", "unban", "flush_cache"]);
+        assert_eq!(entries[0]["before"]["enabled"], false);
+        assert_eq!(entries[0]["after"]["enabled"], true);
+        assert_eq!(entries[0]["actor"]["address"], "127.0.0.1");
+        assert_eq!(entries[1]["target"], "203.0.113.12");
+        assert_eq!(entries[1]["before"]["reason"], "malformed");
+        assert!(entries[1]["after"].is_null());
+
+        let query = format!("{}/audit?action=unban&actor=127.0.0.1&limit=5", url);
+        let entries: serde_json::Value = client.get(query).bearer_auth("secret").send().await.unwrap().json().await.unwrap();
+        assert_eq!(entries.as_array().unwrap().len(), 1);
+
+        // Without a token, changes are still recorded with the client's address
+        let handler = mock_handler(Config::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/admin/maintenance", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, handler.clone(), AdminToken::None, None));
+        assert_eq!(client.put(&url).send().await.unwrap().status().as_u16(), 200);
+        assert!(handler.in_maintenance());
+        let entries = handler.audit().entries(&AuditQuery::default()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, "maintenance");
+        assert_eq!(entries[0].actor.address, Some("127.0.0.1".parse().unwrap()));
+    }
+}
\ No newline at end of file
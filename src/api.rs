@@ -0,0 +1,132 @@
+use crate::dns::{AskError, DnsHandler};
+use crate::https::HttpsAcceptor;
+use anyhow::Result;
+use axum::extract::{ConnectInfo, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::info;
+
+#[derive(Debug, Deserialize)]
+struct AskRequest {
+    question: String,
+    /// Name of a configured persona, like a leading persona label in DNS
+    persona: Option<String>,
+}
+
+/// Serve the HTTP API until the listener fails.
+///
+/// `POST /v1/ask` answers a question the way a TXT query would, but returns
+/// the whole answer along with its cache status and token usage. An access
+/// token is passed as `Authorization: Bearer <token>`. With `tls`, the API
+/// is served over HTTPS instead.
+pub async fn serve(listener: TcpListener, handler: Arc<DnsHandler>, tls: Option<HttpsAcceptor>) -> Result<()> {
+    let app = Router::new().route("/v1/ask", post(ask)).with_state(handler);
+
+    info!("HTTP API listening on {}", listener.local_addr()?);
+    match tls {
+        Some(tls) => tls.serve(listener, app).await,
+        None => Ok(axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?),
+    }
+}
+
+async fn ask(
+    State(handler): State<Arc<DnsHandler>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<AskRequest>,
+) -> Response {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match handler
+        .ask(client, token, &request.question, request.persona.as_deref())
+        .await
+    {
+        Ok(answer) => Json(answer).into_response(),
+        Err(e) => {
+            let (status, message) = match e {
+                AskError::Forbidden => (StatusCode::FORBIDDEN, "forbidden".to_string()),
+                AskError::Unauthorized => (StatusCode::UNAUTHORIZED, "invalid or missing access token".to_string()),
+                AskError::RateLimited => (StatusCode::TOO_MANY_REQUESTS, "rate limit exceeded".to_string()),
+                AskError::InvalidQuestion(reason) => (StatusCode::BAD_REQUEST, reason),
+                AskError::Unavailable => (StatusCode::BAD_GATEWAY, "the backend could not answer".to_string()),
+            };
+            (status, Json(json!({ "error": message }))).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LlmBackendType;
+    use crate::Config;
+
+    async fn spawn_api(config: Config) -> SocketAddr {
+        let handler = Arc::new(DnsHandler::new(config).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, handler, None));
+        addr
+    }
+
+    async fn post_ask(addr: SocketAddr, body: serde_json::Value) -> (u16, serde_json::Value) {
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/v1/ask", addr))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        (response.status().as_u16(), response.json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_ask() {
+        let mut config = Config::default();
+        config.llm.backend = LlmBackendType::Mock;
+        let addr = spawn_api(config).await;
+
+        let (status, body) = post_ask(addr, json!({ "question": "what is dns" })).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["question"], "what is dns");
+        assert_eq!(body["cache"], "miss");
+        assert_eq!(body["truncated"], false);
+        assert!(!body["answer"].as_str().unwrap().is_empty());
+
+        let (_, body) = post_ask(addr, json!({ "question": "What is DNS?" })).await;
+        assert_eq!(body["cache"], "hit");
+        assert!(body["usage"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_ask_errors() {
+        let mut config = Config::default();
+        config.llm.backend = LlmBackendType::Mock;
+        let addr = spawn_api(config.clone()).await;
+
+        // Nothing is left of the question once it is sanitized
+        let (status, _) = post_ask(addr, json!({ "question": "<>" })).await;
+        assert_eq!(status, 400);
+
+        let (status, body) = post_ask(addr, json!({ "question": "what is dns", "persona": "pirate" })).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"], "Unknown persona \"pirate\"");
+
+        config.rate_limit.requests_per_minute = 1;
+        config.rate_limit.burst_size = 1;
+        let addr = spawn_api(config).await;
+        post_ask(addr, json!({ "question": "what is dns" })).await;
+        let (status, body) = post_ask(addr, json!({ "question": "what is dns" })).await;
+        assert_eq!(status, 429);
+        assert_eq!(body["error"], "rate limit exceeded");
+    }
+}
\ No newline at end of file
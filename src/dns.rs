@@ -1,167 +1,2055 @@
-use crate::config::Config;
-use crate::llm::LlmClient;
-use crate::utils::rate_limiter::RateLimiter;
+use crate::acme::Dns01Challenges;
+use crate::config::{Config, UnauthenticatedPolicy};
+use crate::middleware::{new_response, Exchange, Next, Pipeline, Question, Reply, Stage};
+use crate::llm::{truncate_for_txt, GenerationOptions, LlmClient, TokenUsage, MAX_TXT_ANSWER};
+use crate::utils::abuse::{AbuseDetector, Offence};
+use crate::utils::acl::AccessControl;
+use crate::utils::answer_ttl::AnswerTtl;
+use crate::utils::api_keys::{self, ApiKey, ApiKeyStore, Authentication};
+use crate::utils::audit::AuditLog;
+use crate::utils::cache::{Cache, SemanticCache, SemanticLookup};
+use crate::utils::cache_key::{generation_fingerprint, CacheKeyNormalizer};
+use crate::utils::cookies::{CookieVerdict, DnsCookies};
+use crate::utils::faq::FaqTable;
+use crate::utils::forwarder::Forwarder;
+use crate::utils::injection::InjectionGuard;
+use crate::utils::load_control::{LoadController, Rung};
+use crate::utils::llm_keys::LlmKeyRing;
+use crate::utils::local_answers::{LocalAnswer, LocalAnswers};
+use crate::utils::log_policy::{question_hash, LogPolicy};
+use crate::utils::metering::{estimate_tokens, Pricing, UsageMeter};
+use crate::utils::metrics::{Metrics, QueryLabels};
+use crate::utils::personas::{Persona, Personas};
+use crate::utils::question_policy::{PolicyDecision, QuestionPolicy};
+use crate::utils::query_log::{QueryLogEntry, QueryLogger};
+use crate::utils::rate_limiter::ClientRateLimiter;
+use crate::utils::retention::{PurgeFilter, PurgeReport};
+use crate::utils::reverse::{self, ReverseLookups};
+use crate::utils::sanitizer::Sanitizer;
+use crate::utils::shadow::ShadowBackend;
+use crate::utils::static_records::StaticRecords;
+use crate::utils::storage;
+use crate::utils::text;
+use crate::utils::tenants::{Tenant, TenantUsage, Tenants};
+use crate::utils::threats::{self, ThreatKind, ThreatLogEntry, ThreatMonitor};
+use crate::utils::tsig::{TsigKeyring, TsigSession, TsigVerification};
+use crate::utils::zone_profiles::{normalize_zone, ZoneProfile, ZoneProfiles, DEFAULT_CACHE_TTL};
+use crate::utils::zones::ServedZones;
 use crate::Error;
 use anyhow::Result;
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
-use tracing::{debug, error, info, warn};
-use trust_dns_proto::op::{Message, MessageType, ResponseCode};
+use tracing::{debug, error, field, info, instrument, warn, Span};
+use trust_dns_proto::op::{Edns, Message, MessageType, OpCode, ResponseCode};
+use trust_dns_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use trust_dns_proto::rr::rdata::{PTR, TXT};
 use trust_dns_proto::rr::{DNSClass, Name, Record, RecordType};
 use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable};
+use trust_dns_proto::xfer::Protocol;
 use trust_dns_server::authority::{Authority, Catalog};
 use trust_dns_server::server::{Request, ResponseHandler, ResponseInfo};
 
 pub struct DnsHandler {
     llm_client: LlmClient,
     config: Config,
-    rate_limiter: Arc<RateLimiter>,
-    cache: Arc<RwLock<HashMap<String, (String, std::time::Instant)>>>,
+    metrics: Arc<Metrics>,
+    rate_limiter: Arc<ClientRateLimiter>,
+    acl: Arc<AccessControl>,
+    tsig: TsigKeyring,
+    cookies: Option<DnsCookies>,
+    api_keys: ApiKeyStore,
+    personas: Personas,
+    question_policy: QuestionPolicy,
+    injection: InjectionGuard,
+    /// Cleans up questions asked over the HTTP API
+    sanitizer: Sanitizer,
+    faq: FaqTable,
+    local_answers: LocalAnswers,
+    reverse: ReverseLookups,
+    zones: ServedZones,
+    zone_profiles: ZoneProfiles,
+    tenants: Tenants,
+    answer_ttl: AnswerTtl,
+    static_records: StaticRecords,
+    /// DNS-01 challenges of the server's own certificate
+    acme_challenges: Arc<Dns01Challenges>,
+    cache_keys: CacheKeyNormalizer,
+    /// Exact answers, bounded by `cache.max_entries` and sharded so that
+    /// concurrent queries rarely wait on one another's lock
+    cache: Cache<CachedAnswer>,
+    semantic_cache: Option<SemanticCache>,
+    negative_cache: Arc<RwLock<HashMap<String, (NegativeEntry, Option<IpAddr>, Instant)>>>,
+    query_logger: Option<QueryLogger>,
+    pricing: Pricing,
+    load_control: LoadController,
+    shadow: Option<Arc<ShadowBackend>>,
+    usage_meter: Option<UsageMeter>,
+    /// Name answered with the server's statistics
+    stats_name: Option<Name>,
+    log_policy: LogPolicy,
+    forwarder: Option<Forwarder>,
+    abuse: Arc<AbuseDetector>,
+    audit: Arc<AuditLog>,
+    /// API keys of `llm.backend`, shared with its backends
+    llm_keys: Arc<LlmKeyRing>,
+    threats: Option<ThreatMonitor>,
+    /// Only cached and static answers are served while set
+    maintenance: AtomicBool,
+    pipeline: Pipeline,
+}
+
+/// An answer in the exact cache
+#[derive(Clone)]
+struct CachedAnswer {
+    answer: String,
+    provenance: Provenance,
+    /// Client that asked the question, so its data can be purged on request
+    client: Option<IpAddr>,
+    stored_at: Instant,
+}
+
+/// Remembered outcome of a question that could not be answered, kept with
+/// the client that asked until the paired expiry instant
+#[derive(Debug, Clone)]
+enum NegativeEntry {
+    /// The backend failed
+    Error,
+    /// The question was refused; carries the refusal text
+    Refusal(String),
+}
+
+/// Longest character-string a TXT record can hold
+const MAX_TXT_STRING: usize = 255;
+
+/// First string of the TXT record carrying an answer's provenance
+const METADATA_MARKER: &str = "llmdig-metadata";
+
+/// Outcome of a single question within a message
+#[derive(Debug, Clone)]
+pub enum Answer {
+    /// Answer text, split across TXT records when it is sent
+    Txt(String),
+    /// Ready-made records; empty means the name exists without this type
+    Records(Vec<Record>),
+    /// Fail the question with this code
+    Error(ResponseCode),
+}
+
+/// Per-request bookkeeping filled in while a query is being answered
+#[derive(Debug, Default)]
+pub(crate) struct QueryContext {
+    /// Address of the client asking
+    client: Option<IpAddr>,
+    question: Option<String>,
+    /// Where the last question's answer came from
+    cache: Option<CacheStatus>,
+    /// Whether this query writes informational log lines
+    verbose: bool,
+    /// Served zone of the last question
+    zone: Option<String>,
+    persona: Option<String>,
+    /// Backend asked for an answer, when the cache could not provide one
+    backend: Option<String>,
+    response_size: usize,
+    response_code: Option<ResponseCode>,
+    tsig: Option<TsigSession>,
+    /// COOKIE option data to send back with the response
+    cookie: Option<Vec<u8>>,
+    api_key: Option<String>,
+    /// Tenant the query is answered for
+    tenant: Option<String>,
+    generation: GenerationOptions,
+    /// Estimated cost in US dollars of the last answer, when its model has a price
+    cost: Option<f64>,
+    /// Tokens spent on the last question, when the backend reports them
+    usage: Option<TokenUsage>,
+    /// The backend's answer before it was cut to fit TXT records
+    full_answer: Option<String>,
+}
+
+impl QueryContext {
+    fn cache_hit(&self) -> bool {
+        matches!(
+            self.cache,
+            Some(CacheStatus::Hit | CacheStatus::SemanticHit | CacheStatus::NegativeHit)
+        )
+    }
+}
+
+/// How a question was answered with respect to the caches
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum CacheStatus {
+    #[serde(rename = "hit")]
+    Hit,
+    #[serde(rename = "semantic_hit")]
+    SemanticHit,
+    /// A remembered failure or refusal
+    #[serde(rename = "negative_hit")]
+    NegativeHit,
+    /// The backend was asked
+    #[serde(rename = "miss")]
+    Miss,
+}
+
+impl CacheStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "hit",
+            CacheStatus::SemanticHit => "semantic_hit",
+            CacheStatus::NegativeHit => "negative_hit",
+            CacheStatus::Miss => "miss",
+        }
+    }
+}
+
+/// Where a backend answer came from, sent in a TXT record of its own when
+/// `response.include_metadata` is set
+#[derive(Debug, Clone)]
+pub(crate) struct Provenance {
+    cache: CacheStatus,
+    /// What generated the answer; unknown for semantic cache hits
+    model: Option<String>,
+    backend: Option<String>,
+    /// Unix time the backend answered
+    generated_at: Option<u64>,
+    tokens: Option<u64>,
+}
+
+impl Provenance {
+    /// The record's strings: the marker, then `key=value` attributes as in
+    /// RFC 1464, leaving out what is not known
+    fn txt_strings(&self) -> Vec<String> {
+        let mut strings = vec![METADATA_MARKER.to_string()];
+        if let Some(model) = &self.model {
+            strings.push(format!("model={}", model));
+        }
+        if let Some(backend) = &self.backend {
+            strings.push(format!("backend={}", backend));
+        }
+        strings.push(format!("cache={}", self.cache.as_str()));
+        if let Some(generated_at) = self.generated_at {
+            strings.push(format!("generated_at={}", generated_at));
+        }
+        if let Some(tokens) = self.tokens {
+            strings.push(format!("tokens={}", tokens));
+        }
+        // A string too long for a TXT record is cut short
+        strings
+            .iter()
+            .map(|string| text::truncate_to_bytes(string, MAX_TXT_STRING).to_string())
+            .collect()
+    }
+}
+
+/// Answer to a question asked over the HTTP API
+#[derive(Debug, Clone, Serialize)]
+pub struct AskResponse {
+    /// The question as it was answered, after sanitizing
+    pub question: String,
+    /// The full answer, before it is cut down to fit TXT records
+    pub answer: String,
+    /// Whether a DNS client would get a shortened answer
+    pub truncated: bool,
+    /// `None` when the question was refused by policy
+    pub cache: Option<CacheStatus>,
+    /// Tokens spent on this request; `None` for cached answers and
+    /// backends that do not report usage
+    pub usage: Option<TokenUsage>,
+}
+
+/// Why a question asked over the HTTP API was not answered
+#[derive(Debug, Clone, PartialEq)]
+pub enum AskError {
+    /// Refused by the ACL
+    Forbidden,
+    /// Missing or unknown access token when one is required
+    Unauthorized,
+    RateLimited,
+    InvalidQuestion(String),
+    /// The backend failed or is overloaded
+    Unavailable,
 }
 
 impl DnsHandler {
     pub fn new(config: Config) -> Result<Self> {
-        let llm_client = LlmClient::new(config.clone())?;
-        let rate_limiter = Arc::new(RateLimiter::new(
-            config.rate_limit.requests_per_minute,
-            config.rate_limit.burst_size,
-        ));
+        let llm_client = LlmClient::with_keys(config.clone(), LlmKeyRing::for_config(&config))?;
+        Self::with_llm_client(config, llm_client)
+    }
+
+    /// A handler asking `llm_client` instead of the configured backend
+    ///
+    /// Moderation, embeddings and the zone, tenant and shadow backends
+    /// without a key of their own share `llm_client`'s key ring
+    pub fn with_llm_client(config: Config, llm_client: LlmClient) -> Result<Self> {
+        let llm_keys = llm_client.keys();
+        let metrics = Arc::new(Metrics::new());
+        let llm_client = llm_client.with_metrics(metrics.clone());
+        let rate_limiter = Arc::new(ClientRateLimiter::new(&config)?);
+
+        let acl = Arc::new(AccessControl::new(&config.acl)?);
+        if config.acl.enabled {
+            if let Some(path) = &config.acl.lists_file {
+                acl.spawn_reloader(
+                    path.clone(),
+                    Duration::from_secs(config.acl.reload_interval_seconds.max(1)),
+                );
+            }
+        }
+
+        let tsig = TsigKeyring::new(&config.tsig)?;
+        let cookies = if config.cookies.enabled {
+            Some(DnsCookies::new(&config.cookies)?)
+        } else {
+            None
+        };
+        // Bans and usage counters live in the store `[storage]` picks
+        let storage = storage::open(&config.storage)?;
+        let api_keys = ApiKeyStore::new(&config.api_keys, storage.clone())?;
+        let personas = Personas::new(&config.personas)?;
+        let question_policy = QuestionPolicy::new(&config.question_policy)?;
+        let injection = InjectionGuard::new(&config.injection)?;
+        let sanitizer = Sanitizer::new(&config.sanitizer);
+        let faq = FaqTable::new(&config.faq)?;
+        let local_answers = LocalAnswers::new(&config.local_answers);
+        let reverse = ReverseLookups::new(&config.reverse)?;
+        // Zones with settings of their own are served like any other
+        let mut served_zones = config.server.served_zones.clone();
+        served_zones.extend(config.zones.iter().map(|zone| zone.name.clone()));
+        served_zones.extend(config.tenants.iter().flat_map(|tenant| tenant.zones.iter().cloned()));
+        let zones = ServedZones::new(&served_zones, &config.authority)?;
+        let zone_profiles = ZoneProfiles::new(&config, metrics.clone(), llm_keys.clone())?;
+        let tenants = Tenants::new(&config, metrics.clone(), llm_keys.clone(), storage.clone())?;
+        let answer_ttl = AnswerTtl::new(&config.answer_ttl)?;
+        let static_records = StaticRecords::new(&config.static_records)?;
+        let acme_challenges = Arc::new(Dns01Challenges::new(&config.tls.acme));
+        let cache_keys = CacheKeyNormalizer::new(&config.cache);
+        // Entries are checked against their zone's TTL when looked up, and
+        // none is kept longer than the longest of them
+        let longest_ttl = config
+            .zones
+            .iter()
+            .filter_map(|zone| zone.cache_ttl_seconds)
+            .map(Duration::from_secs)
+            .fold(DEFAULT_CACHE_TTL, Duration::max);
+        let cache = Cache::new(config.cache.max_entries, longest_ttl);
+        let semantic_cache = if config.semantic_cache.enabled {
+            Some(SemanticCache::new(&config, llm_keys.clone())?)
+        } else {
+            None
+        };
+
+        let log_policy = LogPolicy::new(&config.logging);
+        let query_logger = if config.logging.query_log_enabled {
+            Some(QueryLogger::new(&config.logging))
+        } else {
+            None
+        };
+        let usage_meter = if config.metering.enabled {
+            Some(UsageMeter::new(&config.metering)?)
+        } else {
+            None
+        };
+        let load_control = LoadController::new(&config.llm.downgrade)?;
+        let shadow = if config.shadow.enabled {
+            Some(Arc::new(ShadowBackend::new(&config, llm_keys.clone())?))
+        } else {
+            None
+        };
+        let stats_name = match &config.response.stats_name {
+            Some(name) => Some(Name::from_ascii(normalize_zone(name)?)?),
+            None => None,
+        };
+
+        let audit = Arc::new(AuditLog::new(storage.clone()));
+        let abuse = Arc::new(AbuseDetector::new(&config.abuse, storage)?);
+        let threats = if config.honeypot.enabled {
+            Some(ThreatMonitor::new(&config.honeypot))
+        } else {
+            None
+        };
+
+        let forwarder = if config.forwarding.enabled {
+            Some(Forwarder::new(&config.forwarding)?)
+        } else {
+            None
+        };
+
+        let mut pipeline = Pipeline::default();
+        if !config.plugins.wasm.is_empty() {
+            #[cfg(feature = "wasm-plugins")]
+            crate::wasm_plugins::WasmPlugins::new(&config.plugins)?.install(&mut pipeline);
+            #[cfg(not(feature = "wasm-plugins"))]
+            return Err(Error::Configuration(
+                "WASM plugins need LLMdig built with the wasm-plugins feature".to_string(),
+            )
+            .into());
+        }
+        if !config.lua.hooks.is_empty() {
+            #[cfg(feature = "lua-hooks")]
+            crate::lua_hooks::LuaHooks::new(&config.lua)?.install(&mut pipeline);
+            #[cfg(not(feature = "lua-hooks"))]
+            return Err(Error::Configuration(
+                "Lua hooks need LLMdig built with the lua-hooks feature".to_string(),
+            )
+            .into());
+        }
 
         Ok(Self {
             llm_client,
             config,
+            metrics,
             rate_limiter,
-            cache: Arc::new(RwLock::new(HashMap::new())),
+            acl,
+            tsig,
+            cookies,
+            api_keys,
+            personas,
+            question_policy,
+            injection,
+            sanitizer,
+            faq,
+            local_answers,
+            reverse,
+            zones,
+            zone_profiles,
+            tenants,
+            answer_ttl,
+            static_records,
+            acme_challenges,
+            cache_keys,
+            cache,
+            semantic_cache,
+            negative_cache: Arc::new(RwLock::new(HashMap::new())),
+            query_logger,
+            pricing: Pricing::new(&config.llm.prices),
+            load_control,
+            shadow,
+            usage_meter,
+            stats_name,
+            log_policy,
+            forwarder,
+            abuse,
+            audit,
+            llm_keys,
+            threats,
+            maintenance: AtomicBool::new(config.maintenance.enabled),
+            pipeline,
         })
     }
 
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// The stages every DNS message runs through, for adding custom
+    /// middleware before the server starts
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+
+    /// Answerers tried before the cache and the backend, for adding
+    /// custom ones before the server starts
+    pub fn local_answers_mut(&mut self) -> &mut LocalAnswers {
+        &mut self.local_answers
+    }
+
+    /// Where ACME publishes the DNS-01 challenges this handler answers
+    pub fn acme_challenges(&self) -> Arc<Dns01Challenges> {
+        self.acme_challenges.clone()
+    }
+
+    /// Offence counts and bans, shared with the admin API
+    pub fn abuse(&self) -> Arc<AbuseDetector> {
+        self.abuse.clone()
+    }
+
+    /// Administrative actions taken through the admin API
+    pub fn audit(&self) -> Arc<AuditLog> {
+        self.audit.clone()
+    }
+
+    /// Primary and secondary API keys of the LLM provider, for rotating them
+    pub fn llm_keys(&self) -> Arc<LlmKeyRing> {
+        self.llm_keys.clone()
+    }
+
+    /// Queries logged as threats and what is known of their senders, when
+    /// the honeypot is on
+    pub fn threats(&self) -> Option<&ThreatMonitor> {
+        self.threats.as_ref()
+    }
+
+    /// Whether only cached and static answers are served, with the
+    /// maintenance banner for questions that would need the backend
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    /// Enter or leave maintenance mode
+    pub fn set_maintenance(&self, enabled: bool) {
+        if self.maintenance.swap(enabled, Ordering::Relaxed) != enabled {
+            Self::log_maintenance(enabled);
+        }
+    }
+
+    /// Enter maintenance mode if the server is not in it, or leave it,
+    /// returning whether it is on now
+    pub fn toggle_maintenance(&self) -> bool {
+        let enabled = !self.maintenance.fetch_xor(true, Ordering::Relaxed);
+        Self::log_maintenance(enabled);
+        enabled
+    }
+
+    fn log_maintenance(enabled: bool) {
+        if enabled {
+            warn!("Maintenance mode on: serving cached and static answers only");
+        } else {
+            info!("Maintenance mode off");
+        }
+    }
+
+    /// Count a packet from `src` that could not be parsed, returning how
+    /// many that client has sent recently
+    pub async fn record_malformed(&self, src: SocketAddr) -> u64 {
+        self.metrics.increment_malformed_packets();
+        self.abuse.record(src.ip(), Offence::Malformed).await
+    }
+
+    /// Drop query log entries, cached answers and client history older
+    /// than the `[retention]` limits
+    pub async fn purge_expired(&self) -> PurgeReport {
+        let retention = &self.config.retention;
+        let query_log = PurgeFilter::older_than_hours(retention.query_log_max_age_hours);
+        let report = PurgeReport {
+            query_log_entries: self.purge_query_log(&query_log).await.unwrap_or_else(|e| {
+                error!("Could not purge the query log: {}", e);
+                0
+            }),
+            cached_answers: self
+                .purge_cache(&PurgeFilter::older_than_hours(retention.cache_max_age_hours))
+                .await,
+            client_records: self
+                .purge_client_history(&PurgeFilter::older_than_hours(retention.client_history_max_age_hours))
+                .await,
+        };
+        if report.total() > 0 {
+            info!("Retention purge removed {:?}", report);
+        }
+        report
+    }
+
+    /// Forget everything kept about `client`: its query log entries, the
+    /// cached answers to its questions and its rate-limit, offence and
+    /// threat history. Bans stay in force.
+    pub async fn purge_client(&self, client: IpAddr) -> Result<PurgeReport> {
+        let filter = PurgeFilter::client(client);
+        let report = PurgeReport {
+            query_log_entries: self.purge_query_log(&filter).await?,
+            cached_answers: self.purge_cache(&filter).await,
+            client_records: self.purge_client_history(&filter).await,
+        };
+        info!("Purged the data of {}: {:?}", client, report);
+        Ok(report)
+    }
+
+    /// Drop every cached answer, exact, semantic and negative, returning
+    /// how many went
+    pub async fn flush_cache(&self) -> usize {
+        let removed = self
+            .purge_cache(&PurgeFilter {
+                max_age: Some(Duration::ZERO),
+                client: None,
+            })
+            .await;
+        info!("Flushed {} cached answers", removed);
+        removed
+    }
+
+    /// Generation fingerprint of questions asked with the configured
+    /// settings, with no persona, zone or tenant of their own
+    pub fn generation_fingerprint(&self) -> String {
+        let mut generation = GenerationOptions::default();
+        self.injection.guard(&mut generation);
+        generation_fingerprint(&self.config, &generation)
+    }
+
+    /// How many answers are cached under each generation fingerprint,
+    /// exact, semantic and negative together
+    pub async fn cache_fingerprints(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        let fingerprint_of = |key: &str| key.split_once(':').map_or(key, |(fingerprint, _)| fingerprint).to_string();
+        for key in self.cache.keys().await {
+            *counts.entry(fingerprint_of(&key)).or_default() += 1;
+        }
+        for key in self.negative_cache.read().await.keys() {
+            *counts.entry(fingerprint_of(key)).or_default() += 1;
+        }
+        if let Some(semantic_cache) = &self.semantic_cache {
+            for (fingerprint, count) in semantic_cache.fingerprints().await {
+                *counts.entry(fingerprint).or_default() += count;
+            }
+        }
+        counts
+    }
+
+    /// Drop the cached answers generated under `fingerprint`, exact,
+    /// semantic and negative, returning how many went
+    pub async fn invalidate_fingerprint(&self, fingerprint: &str) -> usize {
+        let prefix = format!("{}:", fingerprint);
+        let mut removed = self.cache.retain(|key, _| !key.starts_with(&prefix)).await;
+        {
+            let mut cache = self.negative_cache.write().await;
+            let before = cache.len();
+            cache.retain(|key, _| !key.starts_with(&prefix));
+            removed += before - cache.len();
+        }
+        if let Some(semantic_cache) = &self.semantic_cache {
+            removed += semantic_cache.invalidate(fingerprint).await;
+        }
+        info!("Invalidated {} cached answers with fingerprint {}", removed, fingerprint);
+        removed
+    }
+
+    async fn purge_query_log(&self, filter: &PurgeFilter) -> std::io::Result<usize> {
+        match &self.query_logger {
+            Some(logger) if !filter.is_empty() => logger.purge(*filter).await,
+            _ => Ok(0),
+        }
+    }
+
+    async fn purge_cache(&self, filter: &PurgeFilter) -> usize {
+        if filter.is_empty() {
+            return 0;
+        }
+
+        let mut removed = self
+            .cache
+            .retain(|_, entry| !filter.matches(entry.client, entry.stored_at.elapsed()))
+            .await;
+        {
+            let now = Instant::now();
+            let mut cache = self.negative_cache.write().await;
+            let before = cache.len();
+            cache.retain(|_, (entry, client, expires_at)| {
+                let age = self.negative_cache_ttl(entry).saturating_sub(expires_at.saturating_duration_since(now));
+                !filter.matches(*client, age)
+            });
+            removed += before - cache.len();
+        }
+        if let Some(semantic_cache) = &self.semantic_cache {
+            removed += semantic_cache.purge(filter).await;
+        }
+        removed
+    }
+
+    async fn purge_client_history(&self, filter: &PurgeFilter) -> usize {
+        if filter.is_empty() {
+            return 0;
+        }
+
+        let mut removed = self
+            .rate_limiter
+            .forget(|client, idle| filter.matches(Some(client.ip()), idle))
+            .await;
+        removed += self.zone_profiles.forget(filter).await;
+        removed += self.tenants.forget(filter).await;
+        if let Some(cookies) = &self.cookies {
+            removed += cookies.forget(filter).await;
+        }
+        if let Some(threats) = &self.threats {
+            removed += threats.forget(filter).await;
+        }
+        removed + self.abuse.forget(filter).await
+    }
+
+    /// Check that the LLM backend can be reached
+    pub async fn check_backend(&self) -> Result<()> {
+        self.llm_client.check_backend().await
+    }
+
+    /// Run the startup checks of the LLM backend and of the zones' and
+    /// tenants' own backends
+    pub async fn prepare_backend(&self) -> Result<()> {
+        self.llm_client.prepare_backend().await?;
+        self.zone_profiles.prepare_backends().await?;
+        self.tenants.prepare_backends().await
+    }
+
+    /// What each tenant has used
+    pub async fn tenant_usage(&self) -> Vec<TenantUsage> {
+        self.tenants.usage().await
+    }
+
+    /// Move down or up the model ladder as the load since the last check
+    /// calls for
+    pub async fn check_load(&self) {
+        self.load_control.check(&self.metrics).await;
+    }
+
+    /// Export the usage metered since the last export, returning how many
+    /// records it made
+    pub async fn export_usage(&self) -> Result<usize> {
+        match &self.usage_meter {
+            Some(meter) => meter.export().await,
+            None => Ok(0),
+        }
+    }
+
     pub async fn handle_request(
         &self,
         request: &Request,
         response_handle: Box<dyn ResponseHandler>,
     ) -> Result<ResponseInfo> {
-        let client_addr = request.src();
-        let query = request.query();
+        self.handle(request, None, response_handle).await
+    }
+
+    /// Like `handle_request`, but with the query's wire bytes so that TSIG
+    /// signatures can be verified
+    pub async fn handle_wire_request(
+        &self,
+        request: &Request,
+        wire: &[u8],
+        response_handle: Box<dyn ResponseHandler>,
+    ) -> Result<ResponseInfo> {
+        self.handle(request, Some(wire), response_handle).await
+    }
+
+    /// Answer a question asked over the HTTP API. The question passes the
+    /// same ACL, access tokens, rate limits, sanitizer and caches as a DNS
+    /// query, but the answer is returned in full.
+    #[instrument(name = "api.ask", skip_all, fields(client = %client_addr, question_hash = field::Empty, backend = field::Empty))]
+    pub async fn ask(
+        &self,
+        client_addr: SocketAddr,
+        token: Option<&str>,
+        question: &str,
+        persona: Option<&str>,
+    ) -> std::result::Result<AskResponse, AskError> {
+        let mut ctx = QueryContext {
+            client: Some(client_addr.ip()),
+            verbose: self.log_policy.sample(),
+            ..Default::default()
+        };
+        self.metrics.increment_total_requests();
+
+        if !self.acl.is_allowed(client_addr.ip()).await {
+            warn!("Refusing API request from {} (ACL)", client_addr);
+            return Err(AskError::Forbidden);
+        }
+        if self.abuse.is_banned(client_addr.ip()).await {
+            debug!("Refusing API request from banned {}", client_addr);
+            return Err(AskError::Forbidden);
+        }
+        let api_key = self
+            .authenticate(client_addr, token, &mut ctx)
+            .await
+            .map_err(|()| AskError::Unauthorized)?;
+        // Every HTTP connection comes from a new port, so clients are
+        // limited by address alone
+        let client_key = SocketAddr::new(client_addr.ip(), 0);
+        if !self.within_rate_limit(client_key, api_key, None, &ctx).await {
+            warn!("Rate limit exceeded for {}", client_addr);
+            return Err(AskError::RateLimited);
+        }
+
+        let persona = match persona {
+            Some(name) => Some(
+                self.personas
+                    .get(name)
+                    .ok_or_else(|| AskError::InvalidQuestion(format!("Unknown persona {:?}", name)))?,
+            ),
+            None => None,
+        };
+        let mut generation = ctx.generation.clone();
+        if let Some(tenant) = self.tenants.get(ctx.tenant.as_deref()) {
+            tenant.apply(&mut generation);
+        }
+        if let Some(persona) = persona {
+            persona.apply(&mut generation);
+            ctx.persona = Some(persona.name.clone());
+        }
+
+        let question = self.sanitizer.sanitize(question);
+        if question.is_empty() {
+            return Err(AskError::InvalidQuestion("Question is empty".to_string()));
+        }
+        // Left in unless sanitizing is strict, as they are mostly plain English
+        let flagged = self.sanitizer.flagged_words(&question);
+        if !flagged.is_empty() {
+            warn!("API question from {} has words common in injection: {}", client_addr, flagged.join(", "));
+        }
+        Span::current().record("question_hash", question_hash(&question).as_str());
+        if ctx.verbose {
+            info!("API question from {}: {}", client_addr, self.log_policy.question(&question));
+        }
+
+        let answer = self.answer_text(&question, persona, &generation, &mut ctx).await;
+        self.meter(&ctx).await;
+        match answer {
+            Answer::Txt(answer) => {
+                // Cached answers were truncated when they were stored
+                let answer = ctx.full_answer.take().unwrap_or(answer);
+                Ok(AskResponse {
+                    truncated: answer.len() > MAX_TXT_ANSWER,
+                    question,
+                    answer,
+                    cache: ctx.cache,
+                    usage: ctx.usage,
+                })
+            }
+            Answer::Records(_) | Answer::Error(_) => Err(AskError::Unavailable),
+        }
+    }
+
+    #[instrument(
+        name = "dns.request",
+        skip_all,
+        fields(
+            query_id = request.id(),
+            client = %request.src(),
+            qtype = ?request.query().query_type(),
+            question_hash = field::Empty,
+            backend = field::Empty,
+            latency_ms = field::Empty,
+        )
+    )]
+    async fn handle(
+        &self,
+        request: &Request,
+        wire: Option<&[u8]>,
+        response_handle: Box<dyn ResponseHandler>,
+    ) -> Result<ResponseInfo> {
+        let start = Instant::now();
+        let mut ctx = QueryContext {
+            client: Some(request.src().ip()),
+            verbose: self.log_policy.sample(),
+            ..Default::default()
+        };
+        self.metrics.increment_total_requests();
+
+        let tsig = match wire {
+            Some(wire) => self.tsig.verify(wire),
+            None => TsigVerification::Unsigned,
+        };
+
+        let result = self.process_request(request, wire, tsig, response_handle, &mut ctx).await;
+        if matches!(
+            ctx.response_code,
+            Some(ResponseCode::FormErr | ResponseCode::Refused | ResponseCode::NotAuth)
+        ) {
+            self.abuse.record(request.src().ip(), Offence::Error).await;
+        }
+        let latency = start.elapsed();
+        self.metrics.record_response_time(latency).await;
+        self.record_query(request, &ctx).await;
+
+        Span::current().record("latency_ms", latency.as_millis() as u64);
+        if ctx.verbose {
+            info!(
+                response_code = ctx.response_code.map(|code| format!("{:?}", code)).as_deref().unwrap_or("Error"),
+                cache_hit = ctx.cache_hit(),
+                "Query complete"
+            );
+        }
+
+        if let Some(logger) = &self.query_logger {
+            self.log_query(logger, request, ctx, latency.as_millis() as u64);
+        }
+
+        result
+    }
+
+    async fn record_query(&self, request: &Request, ctx: &QueryContext) {
+        self.meter(ctx).await;
+        let cache = match ctx.cache {
+            Some(CacheStatus::Miss) => "miss",
+            Some(_) => "hit",
+            None => "",
+        };
+
+        self.metrics
+            .record_query(QueryLabels {
+                query_type: &format!("{:?}", request.query().query_type()),
+                zone: ctx.zone.as_deref().unwrap_or_default(),
+                cache,
+                persona: ctx.persona.as_deref().unwrap_or_default(),
+                backend: ctx.backend.as_deref().unwrap_or_default(),
+            })
+            .await;
+    }
 
-        info!(
-            "DNS query from {}: {:?} {:?}",
-            client_addr, query.name(), query.query_type()
+    /// Count a query towards its tenant's usage and the billing meter
+    async fn meter(&self, ctx: &QueryContext) {
+        if let Some(tenant) = self.tenants.get(ctx.tenant.as_deref()) {
+            tenant.record_query(ctx.cache_hit(), ctx.usage).await;
+        }
+        if let Some(meter) = &self.usage_meter {
+            meter.record(
+                ctx.tenant.as_deref(),
+                ctx.api_key.as_deref(),
+                ctx.cache_hit(),
+                ctx.usage,
+                ctx.cost,
+            );
+        }
+    }
+
+    fn log_query(&self, logger: &QueryLogger, request: &Request, ctx: QueryContext, latency_ms: u64) {
+        let mut entry = QueryLogEntry::new(
+            request.src().ip(),
+            format!("{:?}", request.query().query_type()),
+            ctx.backend
+                .clone()
+                .unwrap_or_else(|| self.config.llm.backend.name().to_string()),
         );
+        entry.question = ctx.question;
+        entry.latency_ms = latency_ms;
+        entry.cache_hit = ctx.cache_hit();
+        entry.response_size = ctx.response_size;
+        entry.response_code = ctx
+            .response_code
+            .map(|code| format!("{:?}", code))
+            .unwrap_or_else(|| "Error".to_string());
+
+        logger.log(entry);
+    }
+
+    async fn process_request(
+        &self,
+        request: &Request,
+        wire: Option<&[u8]>,
+        tsig: TsigVerification,
+        response_handle: Box<dyn ResponseHandler>,
+        ctx: &mut QueryContext,
+    ) -> Result<ResponseInfo> {
+        let query = request.query();
+        if ctx.verbose {
+            info!(
+                "DNS query from {}: {} {:?}",
+                request.src(),
+                self.log_policy.question(&query.name().to_string()),
+                query.query_type()
+            );
+        }
+
+        let mut exchange = Exchange::new(request, wire, tsig, std::mem::take(ctx));
+        let result = self.pipeline.start(self).run(&mut exchange).await;
+        *ctx = exchange.ctx;
+        result?;
+
+        match exchange.reply {
+            Some(Reply::Message(response)) => self.finish_response(request, response, response_handle, ctx).await,
+            Some(Reply::Relayed(response_bytes)) => {
+                let response_code = ctx.response_code.unwrap_or(ResponseCode::NoError);
+                ctx.response_size = response_bytes.len();
+                response_handle.send_response(response_bytes).await?;
+                Ok(ResponseInfo::new(request.id(), response_code, false))
+            }
+            None => self.send_error_response(request, ResponseCode::ServFail, response_handle, ctx).await,
+        }
+    }
+
+    /// Run one built-in stage of the pipeline
+    pub(crate) async fn run_stage(&self, stage: Stage, exchange: &mut Exchange<'_>, next: Next<'_>) -> Result<()> {
+        let client_addr = exchange.request.src();
+        match stage {
+            Stage::Acl => {
+                if !self.acl.is_allowed(client_addr.ip()).await {
+                    warn!("Refusing query from {} (ACL)", client_addr);
+                    exchange.respond(ResponseCode::Refused);
+                    return Ok(());
+                }
+            }
+            Stage::Cookies => {
+                // Turn away floods from spoofed addresses before they cost anything
+                if let Some(cookies) = &self.cookies {
+                    let request = exchange.request;
+                    let option = request.edns().and_then(|edns| match edns.option(EdnsCode::Cookie) {
+                        Some(EdnsOption::Unknown(_, data)) => Some(data.as_slice()),
+                        _ => None,
+                    });
+                    match cookies.verify(option, client_addr.ip(), request.protocol() == Protocol::Tcp).await {
+                        CookieVerdict::Accept(reply) => exchange.ctx.cookie = reply,
+                        CookieVerdict::FormErr => {
+                            exchange.respond(ResponseCode::FormErr);
+                            return Ok(());
+                        }
+                        CookieVerdict::BadCookie(reply) => {
+                            debug!("Missing or stale server cookie from {}", client_addr);
+                            exchange.ctx.cookie = Some(reply);
+                            exchange.respond(ResponseCode::BADCOOKIE);
+                            return Ok(());
+                        }
+                        CookieVerdict::Truncate => {
+                            debug!("Too many queries without cookies from {}, asking for TCP", client_addr);
+                            let mut response = new_response(request, ResponseCode::NoError);
+                            response.set_truncated(true);
+                            exchange.reply = Some(Reply::Message(response));
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            Stage::Tsig => match exchange.tsig.take() {
+                Some(TsigVerification::Verified(session)) => exchange.ctx.tsig = Some(session),
+                // Signed with the error, so the client can correct its clock
+                Some(TsigVerification::BadTime(session)) => {
+                    warn!("Refusing query from {} signed outside the TSIG time window", client_addr);
+                    exchange.ctx.tsig = Some(session);
+                    exchange.respond(ResponseCode::NotAuth);
+                    return Ok(());
+                }
+                Some(TsigVerification::Failed(reason)) => {
+                    warn!("TSIG check failed for {}: {}", client_addr, reason);
+                    exchange.respond(ResponseCode::NotAuth);
+                    return Ok(());
+                }
+                Some(TsigVerification::Unsigned) | None if self.tsig.is_required() => {
+                    warn!("Refusing unsigned query from {}", client_addr);
+                    exchange.respond(ResponseCode::Refused);
+                    return Ok(());
+                }
+                Some(TsigVerification::Unsigned) | None => {}
+            },
+            Stage::Forward => {
+                // Scans and attacks are logged and answered here, so they
+                // reach neither the forwarder nor the backend
+                if let Some(monitor) = &self.threats {
+                    if let Some(kind) = self.classify_threat(monitor, exchange.request) {
+                        debug!("Logging {:?} query from {} as a threat", kind, client_addr);
+                        self.metrics.increment_threat_queries();
+                        monitor.record(ThreatLogEntry::new(exchange.request, kind)).await;
+                        exchange.respond(Self::threat_response_code(kind));
+                        return Ok(());
+                    }
+                }
+                // Only standard queries may reach the forwarder or the backend
+                if let Some(response_code) = Self::refuse_op_code(exchange.request.op_code()) {
+                    debug!("Answering {} from {} with {:?}", exchange.request.op_code(), client_addr, response_code);
+                    exchange.respond(response_code);
+                    return Ok(());
+                }
+                // Relay queries LLMdig does not answer itself, so it can stand
+                // in for the LAN resolver. Signed queries are always meant for us.
+                if let Some(forwarder) = &self.forwarder {
+                    if exchange.ctx.tsig.is_none() && self.should_forward(exchange.request) {
+                        self.forward_request(forwarder, exchange).await;
+                        return Ok(());
+                    }
+                }
+            }
+            Stage::RateLimit => {
+                // Resolve an access token embedded as the first label
+                let (token, _) = self.split_token(exchange.request.query().name());
+                let api_key = match self.authenticate(client_addr, token.as_deref(), &mut exchange.ctx).await {
+                    Ok(api_key) => api_key,
+                    Err(()) => {
+                        exchange.respond(ResponseCode::Refused);
+                        return Ok(());
+                    }
+                };
+
+                let zone = self.zones.find(exchange.request.query().name()).map(|zone| zone.to_string());
+                if exchange.ctx.tenant.is_none() {
+                    exchange.ctx.tenant = self.tenants.resolve(None, zone.as_deref()).map(|tenant| tenant.name.clone());
+                }
+                let profile = self.zone_profiles.get(zone.as_deref());
+                if !self.within_rate_limit(client_addr, api_key, profile, &exchange.ctx).await {
+                    warn!("Rate limit exceeded for {}", client_addr);
+                    exchange.respond(ResponseCode::ServFail);
+                    return Ok(());
+                }
+            }
+            Stage::Sanitize => {
+                // Multiple questions per message are rare and optional in practice
+                let count = exchange.questions.len();
+                if count > 1 {
+                    if !self.config.server.multi_question {
+                        debug!("Rejecting message with {} questions", count);
+                        exchange.respond(ResponseCode::FormErr);
+                        return Ok(());
+                    }
+                    if count > self.config.server.max_questions {
+                        warn!("Too many questions from {}: {}", client_addr, count);
+                        exchange.respond(ResponseCode::FormErr);
+                        return Ok(());
+                    }
+                }
+
+                for question in &mut exchange.questions {
+                    let (_, name) = self.split_token(&question.name);
+                    question.name = name;
+                    self.prepare_question(question, &mut exchange.ctx);
+                }
+            }
+            Stage::Injection => {
+                if self.injection.is_enabled() {
+                    for question in exchange.questions.iter_mut().filter(|question| question.is_pending()) {
+                        self.screen_question(question, &exchange.ctx).await;
+                    }
+                }
+            }
+            Stage::Cache => {
+                for question in exchange.questions.iter_mut().filter(|question| question.is_pending()) {
+                    self.lookup_answer(question, &mut exchange.ctx).await;
+                }
+                next.run(exchange).await?;
+                for question in &exchange.questions {
+                    self.store_answer(question, &exchange.ctx).await;
+                }
+                return Ok(());
+            }
+            Stage::Llm => {
+                for question in exchange.questions.iter_mut().filter(|question| question.is_pending()) {
+                    self.generate_answer(question, &mut exchange.ctx).await;
+                }
+            }
+            Stage::PostProcess => {
+                for question in &mut exchange.questions {
+                    Self::fit_answer(question, &mut exchange.ctx);
+                }
+            }
+            Stage::Encode => {
+                let response = self.build_response(exchange.request, &exchange.questions);
+                exchange.reply = Some(Reply::Message(response));
+            }
+        }
 
-        // Check rate limiting
-        if self.config.rate_limit.enabled {
-            if !self.rate_limiter.allow_request(client_addr).await {
-                warn!("Rate limit exceeded for {}", client_addr);
-                return self.send_error_response(request, ResponseCode::ServFail, response_handle).await;
+        next.run(exchange).await
+    }
+
+    /// Apply the access token policy and the key's settings to the query.
+    /// Fails when the query must be refused.
+    async fn authenticate(
+        &self,
+        client_addr: SocketAddr,
+        token: Option<&str>,
+        ctx: &mut QueryContext,
+    ) -> std::result::Result<Option<&ApiKey>, ()> {
+        // A tenant's token names the tenant rather than an API key
+        if let Some(tenant) = token.and_then(|token| self.tenants.resolve(Some(token), None)) {
+            ctx.tenant = Some(tenant.name.clone());
+            return Ok(None);
+        }
+
+        match self.api_keys.authenticate(token) {
+            Authentication::Key(key) => {
+                key.record_request().await;
+                ctx.api_key = Some(key.name.clone());
+                ctx.generation.model = key.model.clone();
+                Ok(Some(key))
+            }
+            Authentication::Invalid => {
+                warn!("Invalid access token from {}", client_addr);
+                Err(())
+            }
+            Authentication::Anonymous => {
+                if self.api_keys.is_enabled() {
+                    match self.api_keys.unauthenticated_policy() {
+                        UnauthenticatedPolicy::Allow => {}
+                        UnauthenticatedPolicy::Reject => {
+                            warn!("Refusing query without access token from {}", client_addr);
+                            return Err(());
+                        }
+                        UnauthenticatedPolicy::Fallback => {
+                            ctx.generation.model = self.api_keys.fallback_model().map(str::to_string);
+                        }
+                    }
+                }
+                Ok(None)
             }
         }
+    }
+
+    /// Answer to the stats query: request counts and the LLM spend so far
+    fn stats_text(&self) -> String {
+        let stats = self.metrics.load_counters();
+        format!(
+            "requests={} cache_hit_rate={:.1}% llm_calls={} spend_usd={:.4} over_budget={} uptime={}s",
+            stats.total_requests,
+            stats.cache_hit_rate(),
+            stats.llm_api_calls,
+            stats.llm_spend,
+            stats.over_budget_queries,
+            stats.uptime.as_secs()
+        )
+    }
 
-        // Only handle TXT queries
-        if query.query_type() != RecordType::TXT {
-            debug!("Ignoring non-TXT query: {:?}", query.query_type());
-            return self.send_error_response(request, ResponseCode::NotImp, response_handle).await;
+    /// Split off a leading access token, when API keys or tenants use them
+    fn split_token(&self, name: &Name) -> (Option<String>, Name) {
+        if self.tenants.has_tokens() {
+            api_keys::split_token(name)
+        } else {
+            self.api_keys.split_token(name)
+        }
+    }
+
+    /// Check rate limiting, preferring a per-key limit for signed or
+    /// token-authenticated queries, then the tenant's and the zone's own limits
+    async fn within_rate_limit(
+        &self,
+        client_addr: SocketAddr,
+        api_key: Option<&ApiKey>,
+        zone: Option<&ZoneProfile>,
+        ctx: &QueryContext,
+    ) -> bool {
+        let mut key_limit = match &ctx.tsig {
+            Some(session) => self.tsig.check_rate_limit(&session.key_name).await,
+            None => None,
+        };
+        if key_limit.is_none() {
+            if let Some(key) = api_key {
+                key_limit = key.check_rate_limit().await;
+            }
+        }
+        if key_limit.is_none() {
+            if let Some(tenant) = self.tenants.get(ctx.tenant.as_deref()) {
+                key_limit = tenant.allow_request(client_addr).await;
+            }
+        }
+        if key_limit.is_none() {
+            if let Some(zone) = zone {
+                key_limit = zone.allow_request(client_addr).await;
+            }
+        }
+        let allowed = match key_limit {
+            Some(allowed) => allowed,
+            None => !self.config.rate_limit.enabled || self.rate_limiter.allow_request(client_addr).await,
+        };
+        if !allowed {
+            self.abuse.record(client_addr.ip(), Offence::RateLimited).await;
+        }
+        allowed
+    }
+
+    /// How to answer a message with `op_code`, or `None` for a standard query
+    fn refuse_op_code(op_code: OpCode) -> Option<ResponseCode> {
+        match op_code {
+            OpCode::Query => None,
+            // LLMdig is nobody's secondary, so change notifications are not welcome
+            OpCode::Notify => Some(ResponseCode::Refused),
+            // Served zones are generated, not stored, so there is nothing to update
+            OpCode::Update | OpCode::Status => Some(ResponseCode::NotImp),
+        }
+    }
+
+    /// What sort of scan or attack `request` looks like, if any
+    fn classify_threat(&self, monitor: &ThreatMonitor, request: &Request) -> Option<ThreatKind> {
+        let query = request.query();
+        let query_type = query.query_type();
+        if query.query_class() == DNSClass::CH || threats::is_version_probe(query.name()) {
+            return Some(ThreatKind::VersionProbe);
+        }
+        match query_type {
+            RecordType::ANY => return Some(ThreatKind::Any),
+            RecordType::AXFR | RecordType::IXFR => return Some(ThreatKind::ZoneTransfer),
+            _ => {}
+        }
+        // Whatever the forwarder relays is ordinary resolver traffic
+        if self.forwarder.is_some() && self.should_forward(request) {
+            return None;
+        }
+
+        let (_, name) = self.split_token(query.name());
+        if self.static_records.contains(&name)
+            || self.stats_name.as_ref() == Some(&name)
+            || self.acme_challenges.serves(&name)
+        {
+            return None;
+        }
+        if self.reverse.serves(&name) {
+            return (!matches!(query_type, RecordType::PTR | RecordType::TXT)).then_some(ThreatKind::UnexpectedType);
+        }
+        if !self.zones.is_empty() && self.zones.find(&name).is_none() {
+            return Some(ThreatKind::OutsideZone);
+        }
+        // Resolvers ask for SOA and NS on their way down to a question
+        if !matches!(query_type, RecordType::TXT | RecordType::SOA | RecordType::NS) {
+            return Some(ThreatKind::UnexpectedType);
+        }
+        (query_type == RecordType::TXT && monitor.has_random_label(&name)).then_some(ThreatKind::RandomSubdomain)
+    }
+
+    /// Probes for the server and its zones are refused, random names do not
+    /// exist and other queries find no data
+    fn threat_response_code(kind: ThreatKind) -> ResponseCode {
+        match kind {
+            ThreatKind::VersionProbe | ThreatKind::ZoneTransfer | ThreatKind::OutsideZone => ResponseCode::Refused,
+            ThreatKind::RandomSubdomain => ResponseCode::NXDomain,
+            ThreatKind::Any | ThreatKind::UnexpectedType => ResponseCode::NoError,
+        }
+    }
+
+    /// Whether a message is outside what LLMdig answers itself
+    fn should_forward(&self, request: &Request) -> bool {
+        request
+            .queries()
+            .iter()
+            .all(|query| !self.is_served(query.name(), query.query_type()))
+    }
+
+    /// Static records and the reverse zones are always ours. With served
+    /// zones, everything under them is too; without, only TXT.
+    fn is_served(&self, name: &Name, query_type: RecordType) -> bool {
+        if self.static_records.contains(name) || self.reverse.serves(name) || self.acme_challenges.serves(name) {
+            true
+        } else if self.zones.is_empty() {
+            query_type == RecordType::TXT
+        } else {
+            self.zones.find(name).is_some()
+        }
+    }
+
+    /// Relay the message upstream and answer with whatever comes back
+    async fn forward_request(&self, forwarder: &Forwarder, exchange: &mut Exchange<'_>) {
+        let request = exchange.request;
+        let query = match exchange.wire {
+            Some(wire) => Ok(wire.to_vec()),
+            None => {
+                let mut message = Message::new();
+                message.set_id(request.id());
+                message.set_message_type(MessageType::Query);
+                message.set_op_code(request.op_code());
+                message.set_recursion_desired(request.recursion_desired());
+                message.add_queries(request.queries().iter().cloned());
+                message.to_bytes().map_err(anyhow::Error::from)
+            }
+        };
+
+        let result = match query {
+            Ok(query) => forwarder.forward(&query).await.and_then(|response_bytes| {
+                let response_code = Message::from_bytes(&response_bytes)?.response_code();
+                Ok((response_bytes, response_code))
+            }),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok((response_bytes, response_code)) => {
+                debug!("Relayed {:?} answer from upstream", response_code);
+                exchange.ctx.response_code = Some(response_code);
+                exchange.reply = Some(Reply::Relayed(response_bytes));
+            }
+            Err(e) => {
+                warn!("Forwarding failed: {}", e);
+                exchange.respond(ResponseCode::ServFail);
+            }
+        }
+    }
+
+    /// Find the question in a query name, or answer names that hold none
+    fn prepare_question(&self, question: &mut Question, ctx: &mut QueryContext) {
+        let name = &question.name;
+        let query_type = question.query_type;
+
+        // Zones cannot be transferred: their content is made up on demand
+        if matches!(query_type, RecordType::AXFR | RecordType::IXFR) {
+            debug!("Refusing zone transfer of {}", name);
+            question.answer = Some(Answer::Error(ResponseCode::Refused));
+            return;
+        }
+
+        // Certificate authorities checking a DNS-01 challenge
+        if self.acme_challenges.serves(name) {
+            debug!("Answering ACME challenge {} {:?}", name, query_type);
+            question.answer = Some(Answer::Records(match query_type {
+                RecordType::TXT => self.acme_challenges.lookup(name),
+                _ => Vec::new(),
+            }));
+            return;
+        }
+
+        // Operator-defined records take precedence over the LLM
+        if let Some(records) = self.static_records.lookup(name, query_type) {
+            debug!("Answering {} {:?} from static records", name, query_type);
+            question.answer = Some(Answer::Records(records));
+            return;
+        }
+        // Names with static records are never questions
+        if self.static_records.contains(name) {
+            question.answer = Some(Answer::Records(Vec::new()));
+            return;
+        }
+        if self.stats_name.as_ref() == Some(name) {
+            question.answer = Some(match query_type {
+                RecordType::TXT => Answer::Txt(self.stats_text()),
+                _ => Answer::Records(Vec::new()),
+            });
+            return;
+        }
+        // Reverse names are answered with a description of their address
+        if self.reverse.serves(name) {
+            self.prepare_reverse(question, ctx);
+            return;
+        }
+
+        // Names outside the served zones are not ours to answer
+        if !self.zones.is_empty() && self.zones.find(name).is_none() {
+            debug!("Refusing query outside served zones: {}", name);
+            question.answer = Some(Answer::Error(ResponseCode::Refused));
+            return;
+        }
+        ctx.zone = self.zones.find(name).map(|zone| zone.to_string());
+        question.zone = ctx.zone.clone();
+
+        // Delegation needs SOA and NS answers at the zone apex
+        if let Some(records) = self.zones.apex_records(name, query_type) {
+            question.answer = Some(Answer::Records(records));
+            return;
+        }
+
+        // Only TXT carries answers; every question name exists, so other
+        // types get NODATA rather than an error
+        if query_type != RecordType::TXT {
+            debug!("No data for non-TXT query: {:?}", query_type);
+            question.answer = Some(Answer::Records(Vec::new()));
+            return;
+        }
+
+        // A leading persona label selects a system-prompt preset
+        let (persona, name) = self.personas.split(name);
+        question.generation = ctx.generation.clone();
+        if let Some(tenant) = self.tenants.get(ctx.tenant.as_deref()) {
+            tenant.apply(&mut question.generation);
+        }
+        if let Some(profile) = self.zone_profiles.get(question.zone.as_deref()) {
+            profile.apply(&mut question.generation);
+        }
+        if let Some(persona) = persona {
+            persona.apply(&mut question.generation);
+            question.persona = Some(persona.name.clone());
+            ctx.persona = Some(persona.name.clone());
         }
 
         // Extract question from domain name
-        let question = self.extract_question_from_domain(query.name())?;
-        
-        if question.is_empty() {
-            warn!("Empty question extracted from domain");
-            return self.send_error_response(request, ResponseCode::FormErr, response_handle).await;
+        let text = match self.extract_question_from_domain(&name) {
+            Ok(text) => text,
+            Err(e) => {
+                // Tell the asker how to get an answer instead of failing outright
+                if let Some(Error::QuestionTooLong(advice)) = e.downcast_ref::<Error>() {
+                    debug!("Question too long in {}", name);
+                    question.answer = Some(Answer::Txt(advice.clone()));
+                    return;
+                }
+                debug!("Could not extract question from {}: {}", name, e);
+                question.answer = Some(Answer::Error(ResponseCode::NXDomain));
+                return;
+            }
+        };
+
+        if ctx.question.is_none() {
+            Span::current().record("question_hash", question_hash(&text).as_str());
+            ctx.question = Some(text.clone());
+        }
+
+        // The zone apex exists but is not a question; any other name
+        // without one does not exist
+        if text.trim().is_empty() {
+            if self.zones.is_apex(&name) {
+                question.answer = Some(Answer::Records(Vec::new()));
+            } else {
+                debug!("Empty question extracted from {}", name);
+                question.answer = Some(Answer::Error(ResponseCode::NXDomain));
+            }
+            return;
+        }
+
+        if let Some(answer) = self.answer_from_faq(&text, ctx) {
+            question.answer = Some(answer);
+            return;
+        }
+        if let Some(answer) = self.answer_locally(&text, ctx) {
+            question.ttl = answer.ttl;
+            question.answer = Some(Answer::Txt(answer.text));
+            return;
+        }
+        question.text = Some(text);
+    }
+
+    /// Describe the address a reverse name stands for from the template,
+    /// or leave a question about it for the LLM
+    fn prepare_reverse(&self, question: &mut Question, ctx: &QueryContext) {
+        let address = match (question.query_type, reverse::address(&question.name)) {
+            (RecordType::PTR | RecordType::TXT, Some(address)) => address,
+            // Names short of a whole address exist, without data
+            _ => {
+                question.answer = Some(Answer::Records(Vec::new()));
+                return;
+            }
+        };
+
+        match self.reverse.prompt(address) {
+            Some(prompt) => {
+                question.generation = ctx.generation.clone();
+                question.text = Some(prompt);
+            }
+            None => question.answer = Some(Answer::Txt(self.reverse.describe(address))),
+        }
+    }
+
+    /// The operator's answer to a question in the FAQ table, which needs
+    /// neither the cache nor the backend
+    fn answer_from_faq(&self, text: &str, ctx: &QueryContext) -> Option<Answer> {
+        let answer = self.faq.lookup(text)?;
+        self.metrics.increment_faq_hits();
+        if ctx.verbose {
+            info!("Answered from the FAQ table: {}", self.log_policy.question(text));
+        }
+        Some(Answer::Txt(answer.to_string()))
+    }
+
+    /// A local answerer's answer to a question about the time, units or
+    /// arithmetic, which needs neither the cache nor the backend
+    fn answer_locally(&self, text: &str, ctx: &QueryContext) -> Option<LocalAnswer> {
+        let answer = self.local_answers.answer(text)?;
+        self.metrics.increment_local_answers();
+        if ctx.verbose {
+            info!("Answered by the {} answerer: {}", answer.answerer, self.log_policy.question(text));
+        }
+        Some(answer)
+    }
+
+    /// Answer a question from the caches or the backend, applying the
+    /// question policy on the way. Used by the HTTP API, which does not go
+    /// through the pipeline.
+    async fn answer_text(
+        &self,
+        text: &str,
+        persona: Option<&Persona>,
+        generation: &GenerationOptions,
+        ctx: &mut QueryContext,
+    ) -> Answer {
+        if let Some(answer) = self.answer_from_faq(text, ctx) {
+            return answer;
+        }
+        if let Some(answer) = self.answer_locally(text, ctx) {
+            return Answer::Txt(answer.text);
+        }
+        let mut question = Question::new(Name::root(), RecordType::TXT);
+        question.text = Some(text.to_string());
+        question.persona = persona.map(|persona| persona.name.clone());
+        question.generation = generation.clone();
+
+        self.screen_question(&mut question, ctx).await;
+        if question.is_pending() {
+            self.lookup_answer(&mut question, ctx).await;
+        }
+        if question.is_pending() {
+            self.generate_answer(&mut question, ctx).await;
+        }
+        Self::fit_answer(&mut question, ctx);
+        self.store_answer(&question, ctx).await;
+
+        question.answer.unwrap_or(Answer::Error(ResponseCode::ServFail))
+    }
+
+    /// Refuse a question that looks like prompt injection, and put the
+    /// guard prompt in front of the system prompt of any other
+    async fn screen_question(&self, question: &mut Question, ctx: &QueryContext) {
+        let Some(text) = question.text.as_deref() else {
+            return;
+        };
+        // Nothing is generated in maintenance, and the classifier may be
+        // unavailable
+        if self.in_maintenance() {
+            return;
+        }
+
+        if self.injection.detect(text, &self.llm_client).await {
+            self.metrics.increment_injection_detections();
+            if ctx.verbose {
+                info!("Question refused as prompt injection: {}", self.log_policy.question(text));
+            }
+            question.answer = Some(Answer::Txt(self.injection.refusal_message().to_string()));
+            return;
+        }
+        self.injection.guard(&mut question.generation);
+    }
+
+    /// Answer from the negative cache, the question policy, or the exact
+    /// and semantic caches, in that order
+    async fn lookup_answer(&self, question: &mut Question, ctx: &mut QueryContext) {
+        let Some(text) = question.text.clone() else {
+            return;
+        };
+
+        // Equivalent spellings of a question share cache entries, but each
+        // persona, and each zone with settings of its own, answers differently
+        let profile = self.zone_profiles.get(question.zone.as_deref());
+        question.cache_key = match &question.persona {
+            Some(persona) => format!("{}:{}", persona, self.cache_keys.normalize(&text)),
+            None => self.cache_keys.normalize(&text),
+        };
+        if let Some(profile) = profile {
+            question.cache_key = format!("{}:{}", profile.name, question.cache_key);
+        }
+        // Tenants never see each other's answers
+        if let Some(tenant) = &ctx.tenant {
+            question.cache_key = format!("tenant:{}:{}", tenant, question.cache_key);
+        }
+        // Answers are only served to the settings that generated them, so
+        // changing the model or the prompt stops the old ones being served
+        let semantic_key = question.cache_key.clone();
+        question.fingerprint = generation_fingerprint(&self.config, &question.generation);
+        question.cache_key = format!("{}:{}", question.fingerprint, question.cache_key);
+
+        // Repeat offenders are answered from the negative cache so they
+        // do not reach the classifier or the backend again
+        if let Some(entry) = self.negative_cache_lookup(&question.cache_key).await {
+            debug!("Negative cache hit for: {}", text);
+            self.metrics.increment_negative_cache_hits();
+            ctx.cache = Some(CacheStatus::NegativeHit);
+            question.answer = Some(match entry {
+                NegativeEntry::Error => Answer::Error(ResponseCode::ServFail),
+                NegativeEntry::Refusal(message) => Answer::Txt(message),
+            });
+            return;
+        }
+
+        // Refuse out-of-policy questions before spending any tokens. None
+        // are spent in maintenance, when the classifier may be unavailable.
+        if !self.in_maintenance() {
+            if let PolicyDecision::Refuse { category } = self.question_policy.evaluate(&text, &self.llm_client).await {
+                if ctx.verbose {
+                    info!("Question refused by policy ({}): {}", category, self.log_policy.question(&text));
+                }
+                let message = self.question_policy.refusal_message().to_string();
+                self.negative_cache_insert(&question.cache_key, NegativeEntry::Refusal(message.clone()), ctx)
+                    .await;
+                question.answer = Some(Answer::Txt(message));
+                return;
+            }
         }
 
         // Check cache first
-        if let Some((cached_response, timestamp)) = self.cache.read().await.get(&question) {
-            if timestamp.elapsed().as_secs() < 300 { // 5 minute cache
-                info!("Returning cached response for: {}", question);
-                return self.send_txt_response(request, cached_response, response_handle).await;
+        let ttl = profile.map_or(DEFAULT_CACHE_TTL, |profile| profile.cache_ttl);
+        if let Some((cached_response, provenance)) = self.cache_lookup(&question.cache_key, ttl).await {
+            if ctx.verbose {
+                info!("Returning cached response for: {}", self.log_policy.question(&text));
             }
+            self.metrics.increment_cache_hits();
+            ctx.cache = Some(CacheStatus::Hit);
+            question.answer = Some(Answer::Txt(cached_response));
+            question.provenance = Some(Provenance {
+                cache: CacheStatus::Hit,
+                ..provenance
+            });
+            return;
         }
 
-        // Generate LLM response
-        match self.llm_client.query(&question).await {
-            Ok(response) => {
-                // Cache the response
-                self.cache.write().await.insert(
-                    question.clone(),
-                    (response.clone(), std::time::Instant::now()),
-                );
+        // Fall back to the answer of a similar enough earlier question. The
+        // semantic cache does not tell personas, zones or tenants apart, so
+        // they skip it.
+        if let (Some(semantic_cache), None, None, None) =
+            (&self.semantic_cache, &question.persona, profile, &ctx.tenant)
+        {
+            match semantic_cache.lookup(&semantic_key, &question.fingerprint).await {
+                Ok(SemanticLookup::Hit { answer, similarity }) => {
+                    if ctx.verbose {
+                        info!(
+                            "Returning semantically cached response ({:.3}) for: {}",
+                            similarity,
+                            self.log_policy.question(&text)
+                        );
+                    }
+                    self.metrics.increment_semantic_cache_hits();
+                    self.metrics.increment_cache_hits();
+                    ctx.cache = Some(CacheStatus::SemanticHit);
+                    question.answer = Some(Answer::Txt(answer));
+                    question.provenance = Some(Provenance {
+                        cache: CacheStatus::SemanticHit,
+                        model: None,
+                        backend: None,
+                        generated_at: None,
+                        tokens: None,
+                    });
+                }
+                Ok(SemanticLookup::Miss(vector)) => question.embedding = Some(vector),
+                // Fail open: exact caching and the backend still work without embeddings
+                Err(e) => warn!("Semantic cache lookup failed: {}", e),
+            }
+        }
+    }
+
+    /// Ask the backend, remembering failures in the negative cache
+    async fn generate_answer(&self, question: &mut Question, ctx: &mut QueryContext) {
+        let Some(text) = question.text.as_deref() else {
+            return;
+        };
+
+        // The backend is left alone in maintenance; the banner is not cached
+        if self.in_maintenance() {
+            debug!("Answering with the maintenance banner: {}", self.log_policy.question(text));
+            question.ttl = Some(self.config.maintenance.banner_ttl);
+            question.answer = Some(Answer::Txt(self.config.maintenance.banner.clone()));
+            return;
+        }
+
+        // Tenants and zones with a backend of their own are answered by it
+        let own_client = self
+            .tenants
+            .get(ctx.tenant.as_deref())
+            .and_then(Tenant::llm_client)
+            .or_else(|| self.zone_profiles.get(question.zone.as_deref()).and_then(ZoneProfile::llm_client));
+        let llm_client = own_client.unwrap_or(&self.llm_client);
+        let backend = llm_client.backend_name();
 
-                info!("Generated response for: {}", question);
-                self.send_txt_response(request, &response, response_handle).await
+        // Under load the shared backend is asked for a faster model, or not at all
+        if own_client.is_none() {
+            match self.load_control.rung() {
+                Some(Rung::Model(model)) => question.generation.model = Some(model.clone()),
+                Some(Rung::CacheOnly) => {
+                    debug!("Not asking the backend under load: {}", self.log_policy.question(text));
+                    question.answer = Some(Answer::Error(ResponseCode::ServFail));
+                    return;
+                }
+                None => {}
+            }
+        }
+
+        // The question is fenced in so it reads as data, not instructions
+        let prompt = self.injection.wrap(text);
+        if !self.within_budget(&mut question.generation, &prompt) {
+            if ctx.verbose {
+                info!("Question refused as over budget: {}", self.log_policy.question(text));
+            }
+            question.answer = Some(Answer::Error(ResponseCode::Refused));
+            return;
+        }
+
+        self.metrics.increment_cache_misses();
+        self.metrics.increment_llm_api_calls();
+        ctx.cache = Some(CacheStatus::Miss);
+        ctx.backend = Some(backend.to_string());
+        Span::current().record("backend", backend);
+        match llm_client.query_detailed(&prompt, &question.generation).await {
+            Ok(generation) => {
+                let model = question.generation.model_or(&self.config.llm.model).to_string();
+                ctx.usage = generation.usage;
+                ctx.cost = generation.usage.and_then(|usage| self.pricing.cost(&model, usage));
+                if let Some(cost) = ctx.cost {
+                    self.metrics.add_llm_cost(cost);
+                }
+                if ctx.verbose {
+                    info!("Generated response for: {}", self.log_policy.question(text));
+                }
+                if let Some(shadow) = self.shadow.as_ref().filter(|shadow| own_client.is_none() && shadow.sample()) {
+                    // The client is answered without waiting for the shadow
+                    let shadow = shadow.clone();
+                    let logged = self.log_policy.question(text).into_owned();
+                    let (prompt, options) = (prompt.to_string(), question.generation.clone());
+                    let (model, answer) = (model.clone(), generation.text.clone());
+                    tokio::spawn(async move {
+                        shadow.compare(logged, prompt, options, model, answer).await;
+                    });
+                }
+                question.provenance = Some(Provenance {
+                    cache: CacheStatus::Miss,
+                    model: Some(model),
+                    backend: Some(backend.to_string()),
+                    generated_at: SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|now| now.as_secs()),
+                    tokens: generation.usage.map(|usage| usage.total_tokens()),
+                });
+                question.answer = Some(Answer::Txt(generation.text));
+                question.fresh = true;
             }
             Err(e) => {
-                error!("LLM query failed: {}", e);
-                self.send_error_response(request, ResponseCode::ServFail, response_handle).await
+                error!("LLM query failed for {:?}: {}", text, e);
+                // Overload is transient, so the question is not remembered as failing
+                if !matches!(e.downcast_ref::<Error>(), Some(Error::Overloaded(_))) {
+                    self.negative_cache_insert(&question.cache_key, NegativeEntry::Error, ctx).await;
+                }
+                question.answer = Some(Answer::Error(ResponseCode::ServFail));
+            }
+        }
+    }
+
+    /// Keep the estimated cost of answering `prompt` within
+    /// `llm.max_cost_per_query`, switching to `llm.over_budget_model` if need
+    /// be. False when the question is over budget even so.
+    fn within_budget(&self, generation: &mut GenerationOptions, prompt: &str) -> bool {
+        let llm = &self.config.llm;
+        if llm.max_cost_per_query <= 0.0 {
+            return true;
+        }
+
+        let system_prompt = generation.system_prompt.as_deref().unwrap_or_default();
+        let usage = TokenUsage {
+            prompt_tokens: estimate_tokens(system_prompt) + estimate_tokens(prompt),
+            completion_tokens: llm.max_tokens as u64,
+        };
+        let affordable = |model: &str| {
+            self.pricing
+                .cost(model, usage)
+                .map_or(true, |cost| cost <= llm.max_cost_per_query)
+        };
+        if affordable(generation.model_or(&llm.model)) {
+            return true;
+        }
+
+        self.metrics.increment_over_budget_queries();
+        match &llm.over_budget_model {
+            Some(model) if affordable(model) => {
+                debug!("Answering with {} to stay within the budget", model);
+                generation.model = Some(model.clone());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Cut a fresh answer down to what fits in TXT records, keeping the
+    /// whole text for the HTTP API. Cached answers were cut when stored.
+    fn fit_answer(question: &mut Question, ctx: &mut QueryContext) {
+        if let (true, Some(Answer::Txt(text))) = (question.fresh, &mut question.answer) {
+            let full = std::mem::take(text);
+            *text = truncate_for_txt(full.clone());
+            ctx.full_answer = Some(full);
+        }
+    }
+
+    /// File a fresh answer in the exact and semantic caches
+    async fn store_answer(&self, question: &Question, ctx: &QueryContext) {
+        let (true, Some(Answer::Txt(text)), Some(provenance)) = (question.fresh, &question.answer, &question.provenance)
+        else {
+            return;
+        };
+
+        let entry = CachedAnswer {
+            answer: text.clone(),
+            provenance: provenance.clone(),
+            client: ctx.client,
+            stored_at: Instant::now(),
+        };
+        self.cache.set(question.cache_key.clone(), entry).await;
+        if let (Some(semantic_cache), Some(embedding)) = (&self.semantic_cache, &question.embedding) {
+            semantic_cache
+                .insert(embedding.clone(), text.clone(), ctx.client, &question.fingerprint)
+                .await;
+        }
+    }
+
+    #[instrument(name = "cache.lookup", skip_all)]
+    async fn cache_lookup(&self, question: &str, ttl: Duration) -> Option<(String, Provenance)> {
+        match self.cache.get(question).await {
+            Some(entry) if entry.stored_at.elapsed() < ttl => Some((entry.answer, entry.provenance)),
+            _ => None,
+        }
+    }
+
+    async fn negative_cache_lookup(&self, question: &str) -> Option<NegativeEntry> {
+        if !self.config.negative_cache.enabled {
+            return None;
+        }
+
+        let cache = self.negative_cache.read().await;
+        match cache.get(question) {
+            Some((entry, _, expires_at)) if *expires_at > Instant::now() => Some(entry.clone()),
+            _ => None,
+        }
+    }
+
+    async fn negative_cache_insert(&self, question: &str, entry: NegativeEntry, ctx: &QueryContext) {
+        let config = &self.config.negative_cache;
+        if !config.enabled {
+            return;
+        }
+
+        let ttl = self.negative_cache_ttl(&entry);
+        if ttl.is_zero() {
+            return;
+        }
+
+        let mut cache = self.negative_cache.write().await;
+        let now = Instant::now();
+        if cache.len() >= config.max_entries {
+            cache.retain(|_, (_, _, expires_at)| *expires_at > now);
+            if cache.len() >= config.max_entries {
+                return;
             }
         }
+        cache.insert(question.to_string(), (entry, ctx.client, now + ttl));
+    }
+
+    fn negative_cache_ttl(&self, entry: &NegativeEntry) -> Duration {
+        let config = &self.config.negative_cache;
+        Duration::from_secs(match entry {
+            NegativeEntry::Error => config.error_ttl_seconds,
+            NegativeEntry::Refusal(_) => config.refusal_ttl_seconds,
+        })
     }
 
     fn extract_question_from_domain(&self, domain: &Name) -> Result<String> {
-        let domain_str = domain.to_string();
-        
-        // Remove trailing dot if present
-        let domain_str = domain_str.trim_end_matches('.');
-        
-        // Split by dots and reverse to get the question
-        let parts: Vec<&str> = domain_str.split('.').collect();
-        
-        if parts.len() < 2 {
-            return Err(Error::InvalidQuery("Domain must have at least 2 parts".to_string()).into());
+        let labels = if self.zones.is_empty() {
+            let domain_str = domain.to_string();
+
+            // Remove trailing dot if present
+            let domain_str = domain_str.trim_end_matches('.');
+
+            // Split by dots and reverse to get the question
+            let parts: Vec<&str> = domain_str.split('.').collect();
+
+            if parts.len() < 2 {
+                return Err(Error::InvalidQuery("Domain must have at least 2 parts".to_string()).into());
+            }
+
+            // The question is everything except the last part (which is the TLD)
+            parts[..parts.len() - 1].iter().map(|part| part.to_string()).collect()
+        } else {
+            // The question is everything in front of the served zone
+            self.zones
+                .strip(domain)
+                .ok_or_else(|| Error::InvalidQuery(format!("{} is outside the served zones", domain)))?
+        };
+
+        // A leading `b32` label marks a base32-encoded question, which keeps
+        // the punctuation and case that plain labels cannot carry
+        if labels.len() > 1 && labels[0].eq_ignore_ascii_case("b32") {
+            let question = Sanitizer::decode_base32(&labels[1..])
+                .ok_or_else(|| Error::InvalidQuery("Invalid base32 question".to_string()))?;
+            return self.check_question_length(question);
+        }
+
+        // Every label is a word or two, so long names make for expensive prompts
+        let max_labels = self.config.server.max_question_labels;
+        if max_labels > 0 && labels.len() > max_labels {
+            return Err(Error::QuestionTooLong(format!(
+                "Questions can span at most {} labels, this one has {}. Ask in fewer words, \
+                 or send the question base32-encoded after a b32 label.",
+                max_labels,
+                labels.len()
+            ))
+            .into());
         }
 
-        // The question is everything except the last part (which is the TLD)
-        let question_parts = &parts[..parts.len() - 1];
+        // International questions arrive punycode-encoded, so decode each label
+        // before the hyphens used by punycode are turned into spaces.
+        let question_parts: Vec<String> = labels
+            .iter()
+            .map(|label| Sanitizer::decode_label(label))
+            .collect();
         let question = question_parts.join(" ");
         
         // Clean up the question
         let question = question.replace('-', " ").replace('_', " ");
         
+        self.check_question_length(question)
+    }
+
+    /// Fail questions longer than `server.max_question_length` characters
+    fn check_question_length(&self, question: String) -> Result<String> {
+        let max_length = self.config.server.max_question_length;
+        let length = question.chars().count();
+        if max_length > 0 && length > max_length {
+            return Err(Error::QuestionTooLong(format!(
+                "Questions can be at most {} characters long, this one has {}. Please shorten it.",
+                max_length, length
+            ))
+            .into());
+        }
         Ok(question)
     }
 
-    async fn send_txt_response(
-        &self,
-        request: &Request,
-        response_text: &str,
-        response_handle: Box<dyn ResponseHandler>,
-    ) -> Result<ResponseInfo> {
-        let query = request.query();
-        let mut response = Message::new();
-        
-        response.set_id(request.id());
-        response.set_message_type(MessageType::Response);
-        response.set_op_code(request.op_code());
-        response.set_response_code(ResponseCode::NoError);
-        response.set_authoritative(true);
-        response.set_recursion_desired(request.recursion_desired());
-        response.set_recursion_available(false);
-        response.set_authentic_data(false);
-        response.set_checking_disabled(false);
-        response.set_query(query.clone());
-
-        // Split response into chunks that fit in TXT records (255 bytes max per string)
-        let chunks = self.chunk_response(response_text);
-        
-        for chunk in chunks {
-            let record = Record::from_rdata(
-                query.name().clone(),
-                300, // TTL
-                trust_dns_proto::rr::RData::TXT(chunk),
-            );
-            response.add_answer(record);
+    /// Build the response carrying the answers to every question
+    fn build_response(&self, request: &Request, questions: &[Question]) -> Message {
+        let answers: Vec<Answer> = questions
+            .iter()
+            .map(|question| question.answer.clone().unwrap_or(Answer::Error(ResponseCode::ServFail)))
+            .collect();
+        // Resolvers keep answers about changing things for less time
+        let ttls: Vec<u32> = questions
+            .iter()
+            .map(|question| {
+                if let Some(ttl) = question.ttl {
+                    return ttl;
+                }
+                let zone_default = self
+                    .zone_profiles
+                    .get(question.zone.as_deref())
+                    .and_then(|profile| profile.answer_ttl);
+                self.answer_ttl.ttl(question.text.as_deref(), zone_default)
+            })
+            .collect();
+
+        // A lone question keeps its own error code; with several questions the
+        // message only fails when none of them could be answered
+        let response_code = if answers
+            .iter()
+            .any(|answer| matches!(answer, Answer::Txt(_) | Answer::Records(_)))
+        {
+            ResponseCode::NoError
+        } else {
+            answers
+                .iter()
+                .find_map(|answer| match answer {
+                    Answer::Error(code) => Some(*code),
+                    Answer::Txt(_) | Answer::Records(_) => None,
+                })
+                .unwrap_or(ResponseCode::ServFail)
+        };
+
+        let mut response = new_response(request, response_code);
+
+        for (((query, answer), ttl), question) in request.queries().iter().zip(answers).zip(ttls).zip(questions) {
+            match answer {
+                // PTR records carry a name, so the answer is spelled out as one
+                Answer::Txt(text) if query.query_type() == RecordType::PTR => {
+                    if let Some(target) = self.reverse.ptr_name(&text) {
+                        response.add_answer(Record::from_rdata(
+                            query.name().clone(),
+                            ttl,
+                            trust_dns_proto::rr::RData::PTR(PTR(target)),
+                        ));
+                    }
+                }
+                Answer::Txt(text) => {
+                    // Split response into chunks that fit in TXT records (255 bytes max per string)
+                    for chunk in chunk_response(&text) {
+                        let record = Record::from_rdata(
+                            query.name().clone(),
+                            ttl,
+                            trust_dns_proto::rr::RData::TXT(TXT::new(vec![chunk])),
+                        );
+                        response.add_answer(record);
+                    }
+                    if let (true, Some(provenance)) = (self.config.response.include_metadata, &question.provenance) {
+                        response.add_answer(Record::from_rdata(
+                            query.name().clone(),
+                            ttl,
+                            trust_dns_proto::rr::RData::TXT(TXT::new(provenance.txt_strings())),
+                        ));
+                    }
+                }
+                Answer::Records(records) => {
+                    response.add_answers(records);
+                }
+                Answer::Error(_) => {}
+            }
         }
 
-        let response_bytes = response.to_bytes()?;
-        response_handle.send_response(response_bytes).await?;
-        
-        Ok(ResponseInfo::new(
-            request.id(),
-            ResponseCode::NoError,
-            false,
-        ))
+        // Negative answers carry the zone's SOA so resolvers know how long
+        // to cache them (RFC 2308)
+        let negative_ttl = match response_code {
+            ResponseCode::NXDomain => Some(self.config.authority.nxdomain_ttl),
+            ResponseCode::NoError if response.answers().is_empty() => Some(self.config.authority.nodata_ttl),
+            _ => None,
+        };
+        if let Some(ttl) = negative_ttl {
+            if let Some(soa) = request
+                .queries()
+                .first()
+                .and_then(|query| self.zones.negative_soa(query.name(), ttl))
+            {
+                response.add_name_server(soa);
+            }
+        }
+
+        response
     }
 
     async fn send_error_response(
@@ -169,47 +2057,114 @@ impl DnsHandler {
         request: &Request,
         response_code: ResponseCode,
         response_handle: Box<dyn ResponseHandler>,
+        ctx: &mut QueryContext,
     ) -> Result<ResponseInfo> {
-        let query = request.query();
-        let mut response = Message::new();
-        
-        response.set_id(request.id());
-        response.set_message_type(MessageType::Response);
-        response.set_op_code(request.op_code());
-        response.set_response_code(response_code);
-        response.set_authoritative(true);
-        response.set_recursion_desired(request.recursion_desired());
-        response.set_recursion_available(false);
-        response.set_authentic_data(false);
-        response.set_checking_disabled(false);
-        response.set_query(query.clone());
+        let response = new_response(request, response_code);
+        self.finish_response(request, response, response_handle, ctx).await
+    }
+
+    async fn finish_response(
+        &self,
+        request: &Request,
+        mut response: Message,
+        response_handle: Box<dyn ResponseHandler>,
+        ctx: &mut QueryContext,
+    ) -> Result<ResponseInfo> {
+        if let Some(cookie) = ctx.cookie.take() {
+            let mut edns = Edns::new();
+            edns.set_option(EdnsOption::Unknown(u16::from(EdnsCode::Cookie), cookie));
+            response.set_edns(edns);
+        }
 
+        if let Some(session) = &ctx.tsig {
+            self.tsig.sign(session, &mut response)?;
+        }
+
+        let response_code = response.response_code();
         let response_bytes = response.to_bytes()?;
+        ctx.response_size = response_bytes.len();
+        ctx.response_code = Some(response_code);
         response_handle.send_response(response_bytes).await?;
         
         Ok(ResponseInfo::new(request.id(), response_code, false))
     }
+}
 
-    fn chunk_response(&self, response: &str) -> Vec<Vec<u8>> {
-        let mut chunks = Vec::new();
-        let mut current_chunk = Vec::new();
-        
-        for byte in response.bytes() {
-            if current_chunk.len() >= 255 {
-                chunks.push(current_chunk);
-                current_chunk = Vec::new();
-            }
-            current_chunk.push(byte);
+/// Split a response into TXT strings of at most 255 bytes without
+/// cutting UTF-8 sequences. Resolvers may reorder the records, so with
+/// more than one chunk each is prefixed with its position (`1/3:`).
+fn chunk_response(response: &str) -> Vec<String> {
+    if response.is_empty() {
+        return vec!["No response".to_string()];
+    }
+
+    let mut chunks = split_utf8(response, MAX_TXT_STRING);
+    if chunks.len() == 1 {
+        return chunks;
+    }
+
+    // The prefix eats into each chunk, which can raise the chunk count
+    // and so the prefix length; repeat until the count settles
+    let mut count = chunks.len();
+    loop {
+        let prefix_len = format!("{}/{}:", count, count).len();
+        chunks = split_utf8(response, MAX_TXT_STRING - prefix_len);
+        if chunks.len() <= count {
+            break;
         }
-        
-        if !current_chunk.is_empty() {
-            chunks.push(current_chunk);
+        count = chunks.len();
+    }
+
+    let total = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| format!("{}/{}:{}", index + 1, total, chunk))
+        .collect()
+}
+
+/// Split `response` into pieces of at most `max_bytes`, only at character boundaries
+fn split_utf8(response: &str, max_bytes: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = response;
+
+    while !rest.is_empty() {
+        let mut chunk = text::truncate_to_bytes(rest, max_bytes);
+        if chunk.is_empty() {
+            // A character wider than `max_bytes` still goes out whole
+            chunk = &rest[..rest.chars().next().map_or(0, char::len_utf8)];
         }
-        
-        if chunks.is_empty() {
-            chunks.push(b"No response".to_vec());
+        chunks.push(chunk.to_string());
+        rest = &rest[chunk.len()..];
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_respect_utf8_boundaries() {
+        // 'ğ' is two bytes, so a byte-based split would cut one in half
+        let text = "ğ".repeat(300);
+        let chunks = chunk_response(&text);
+
+        assert_eq!(chunks.len(), 3);
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert!(chunk.len() <= MAX_TXT_STRING);
+            assert!(chunk.starts_with(&format!("{}/3:", index + 1)));
         }
-        
-        chunks
+
+        let joined: String = chunks.iter().map(|chunk| chunk.split_once(':').unwrap().1).collect();
+        assert_eq!(joined, text);
+    }
+
+    #[test]
+    fn test_short_answers_are_not_prefixed() {
+        assert_eq!(chunk_response("forty-two"), vec!["forty-two"]);
+        assert_eq!(chunk_response(""), vec!["No response"]);
+        assert_eq!(split_utf8(&"a".repeat(510), 255).len(), 2);
     }
-} 
\ No newline at end of file
+}
\ No newline at end of file
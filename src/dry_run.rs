@@ -0,0 +1,204 @@
+use crate::dns::DnsHandler;
+use crate::Error;
+use anyhow::Result;
+use idna::punycode;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query};
+use trust_dns_proto::rr::{Name, RData, RecordType};
+use trust_dns_proto::serialize::binary::BinDecodable;
+use trust_dns_proto::xfer::Protocol;
+use trust_dns_server::server::{Request, ResponseHandler};
+
+/// Where dry-run queries appear to come from
+const DRY_RUN_CLIENT: &str = "127.0.0.1:53053";
+
+/// Largest response a client without EDNS accepts over UDP
+const MAX_PLAIN_UDP: usize = 512;
+
+/// The response to a query sent through the handler without a socket, as
+/// a resolver would receive it
+pub struct DryRun {
+    pub name: Name,
+    pub query_type: RecordType,
+    pub protocol: Protocol,
+    pub response: Message,
+    /// Size of the response on the wire
+    pub size: usize,
+}
+
+/// Keeps the response instead of sending it
+#[derive(Clone, Default)]
+struct Capture {
+    response: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+#[async_trait::async_trait]
+impl ResponseHandler for Capture {
+    async fn send_response(&self, response_bytes: Vec<u8>) -> Result<(), std::io::Error> {
+        *self.response.lock().unwrap() = Some(response_bytes);
+        Ok(())
+    }
+}
+
+/// The query name a question is asked with under `zone`: one label per
+/// word, lowercase and without punctuation, as the `dns-client` tool sends it.
+/// Words outside ASCII are punycode encoded, which the server decodes.
+pub fn question_name(question: &str, zone: Option<&str>) -> Result<Name> {
+    let mut labels: Vec<String> = Vec::new();
+    for word in question.split_whitespace() {
+        let word: String = word.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect();
+        if word.is_empty() {
+            continue;
+        }
+        if word.is_ascii() {
+            labels.push(word);
+        } else {
+            let encoded = punycode::encode_str(&word)
+                .ok_or_else(|| Error::InvalidQuery(format!("Could not encode {:?}", word)))?;
+            labels.push(format!("xn--{}", encoded));
+        }
+    }
+    if labels.is_empty() {
+        return Err(Error::InvalidQuery("Question has no words".to_string()).into());
+    }
+    if let Some(zone) = zone.map(|zone| zone.trim_matches('.')).filter(|zone| !zone.is_empty()) {
+        labels.push(zone.to_string());
+    }
+
+    let mut name = Name::from_ascii(labels.join("."))
+        .map_err(|e| Error::InvalidQuery(format!("Question does not fit a DNS name: {}", e)))?;
+    name.set_fqdn(true);
+    Ok(name)
+}
+
+/// Send a query for `name` through the whole pipeline, as though it had
+/// arrived over UDP or TCP, and return the response
+pub async fn query(handler: &DnsHandler, name: Name, query_type: RecordType, protocol: Protocol) -> Result<DryRun> {
+    let mut message = Message::new();
+    message.set_id(rand::random());
+    message.set_message_type(MessageType::Query);
+    message.set_op_code(OpCode::Query);
+    message.set_recursion_desired(true);
+    message.add_query(Query::query(name.clone(), query_type));
+    let request = Request::new(message, DRY_RUN_CLIENT.parse::<SocketAddr>()?, protocol);
+
+    let capture = Capture::default();
+    handler.handle_request(&request, Box::new(capture.clone())).await?;
+    let bytes = capture
+        .response
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or_else(|| Error::Dns("The query was dropped without a response".to_string()))?;
+
+    Ok(DryRun {
+        name,
+        query_type,
+        protocol,
+        response: Message::from_bytes(&bytes)?,
+        size: bytes.len(),
+    })
+}
+
+impl DryRun {
+    /// The response in the style of dig, with every TXT string quoted
+    /// byte for byte and its length, so encoding and truncation show
+    pub fn render(&self) -> String {
+        let response = &self.response;
+        let protocol = format!("{:?}", self.protocol).to_uppercase();
+        let mut out = String::new();
+        let _ = writeln!(out, ";; Query: {} {} over {}", self.name.to_ascii(), self.query_type, protocol);
+        let _ = writeln!(
+            out,
+            ";; Status: {}, {} answers, {} bytes",
+            response.response_code(),
+            response.answer_count(),
+            self.size
+        );
+        if self.protocol == Protocol::Udp && self.size > MAX_PLAIN_UDP {
+            let _ = writeln!(
+                out,
+                ";; Over {} bytes: clients without EDNS would need to retry over TCP",
+                MAX_PLAIN_UDP
+            );
+        }
+
+        for record in response.answers().iter().chain(response.name_servers()) {
+            match record.data() {
+                Some(RData::TXT(txt)) => {
+                    let strings: Vec<&[u8]> = txt.txt_data().iter().map(|data| &data[..]).collect();
+                    let quoted: Vec<String> = strings.iter().map(|data| quote(data)).collect();
+                    let _ = writeln!(
+                        out,
+                        "{} {} {} TXT {}",
+                        record.name().to_ascii(),
+                        record.ttl(),
+                        record.dns_class(),
+                        quoted.join(" ")
+                    );
+                    let lengths: Vec<String> = strings.iter().map(|data| data.len().to_string()).collect();
+                    let _ = writeln!(out, ";;   {} strings of {} bytes", strings.len(), lengths.join(" + "));
+                }
+                _ => {
+                    let _ = writeln!(out, "{}", record);
+                }
+            }
+        }
+        out
+    }
+}
+
+/// A character string as it appears in zone files: printable ASCII as is,
+/// quotes and backslashes escaped, and every other byte as `\DDD`
+fn quote(data: &[u8]) -> String {
+    let mut quoted = String::with_capacity(data.len() + 2);
+    quoted.push('"');
+    for &byte in data {
+        match byte {
+            b'"' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(byte as char);
+            }
+            0x20..=0x7e => quoted.push(byte as char),
+            _ => {
+                let _ = write!(quoted, "\\{:03}", byte);
+            }
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, LlmBackendType};
+
+    #[test]
+    fn test_question_name() {
+        assert_eq!(
+            question_name("What is DNS?", Some("ask.example.com.")).unwrap().to_string(),
+            "what.is.dns.ask.example.com."
+        );
+        assert_eq!(question_name("où est Zürich", None).unwrap().to_ascii(), "xn--o-7ga.est.xn--zrich-kva.");
+        assert!(question_name("?!", None).is_err());
+        assert_eq!(quote("say \"hi\" ü".as_bytes()), "\"say \\\"hi\\\" \\195\\188\"");
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let mut config = Config::default();
+        config.llm.backend = LlmBackendType::Mock;
+        let handler = DnsHandler::new(config).unwrap();
+
+        let name = question_name("what is dns", None).unwrap();
+        let dry_run = query(&handler, name, RecordType::TXT, Protocol::Udp).await.unwrap();
+        assert_eq!(dry_run.response.answer_count(), 1);
+        let rendered = dry_run.render();
+        assert!(rendered.starts_with(";; Query: what.is.dns. TXT over UDP\n;; Status: No Error, 1 answers"));
+        assert!(rendered.contains(" IN TXT \""));
+        assert!(rendered.contains(" strings of "));
+    }
+}
\ No newline at end of file
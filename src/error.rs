@@ -20,9 +20,16 @@ pub enum Error {
     #[error("Rate limit exceeded")]
     RateLimitExceeded,
 
+    #[error("Overloaded: {0}")]
+    Overloaded(String),
+
     #[error("Sanitization error: {0}")]
     Sanitization(String),
 
+    /// Carries advice on asking a shorter question
+    #[error("Question too long: {0}")]
+    QuestionTooLong(String),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
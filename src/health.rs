@@ -0,0 +1,113 @@
+use crate::dns::DnsHandler;
+use crate::utils::metrics::Metrics;
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Startup milestones reported by the readiness probe
+#[derive(Debug, Default)]
+pub struct HealthState {
+    socket_bound: AtomicBool,
+    backend_reachable: AtomicBool,
+}
+
+impl HealthState {
+    pub fn set_socket_bound(&self) {
+        self.socket_bound.store(true, Ordering::Relaxed);
+    }
+
+    pub fn set_backend_reachable(&self) {
+        self.backend_reachable.store(true, Ordering::Relaxed);
+    }
+
+    /// Ready once the DNS socket is bound and the backend has answered once
+    pub fn is_ready(&self) -> bool {
+        self.socket_bound.load(Ordering::Relaxed) && self.backend_reachable.load(Ordering::Relaxed)
+    }
+}
+
+/// Serve `/healthz`, `/readyz` and Prometheus `/metrics` until the
+/// listener fails.
+///
+/// Liveness is answered by the same runtime as DNS traffic, so it only
+/// succeeds while the event loop is making progress.
+pub async fn serve(listener: TcpListener, state: Arc<HealthState>, metrics: Arc<Metrics>) -> Result<()> {
+    let app = Router::new()
+        .route("/healthz", get(|| async { "ok" }))
+        .route("/readyz", get(readyz))
+        .with_state(state)
+        .route(
+            "/metrics",
+            get(move || {
+                let metrics = metrics.clone();
+                async move { metrics.get_detailed_stats().await.to_prometheus() }
+            }),
+        );
+
+    info!("Health probes listening on {}", listener.local_addr()?);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn readyz(State(state): State<Arc<HealthState>>) -> (StatusCode, &'static str) {
+    if state.is_ready() {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    }
+}
+
+/// Probe the LLM backend until it answers, then mark it reachable
+pub async fn wait_for_backend(handler: Arc<DnsHandler>, state: Arc<HealthState>, retry_interval: Duration) {
+    loop {
+        match handler.check_backend().await {
+            Ok(()) => {
+                info!("LLM backend is reachable");
+                state.set_backend_reachable();
+                return;
+            }
+            Err(e) => {
+                warn!("LLM backend not reachable yet: {}", e);
+                tokio::time::sleep(retry_interval).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn status(addr: std::net::SocketAddr, path: &str) -> u16 {
+        reqwest::get(format!("http://{}{}", addr, path))
+            .await
+            .unwrap()
+            .status()
+            .as_u16()
+    }
+
+    #[tokio::test]
+    async fn test_probes() {
+        let state = Arc::new(HealthState::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, state.clone(), Arc::new(Metrics::new())));
+
+        assert_eq!(status(addr, "/healthz").await, 200);
+        assert_eq!(status(addr, "/readyz").await, 503);
+
+        state.set_socket_bound();
+        assert_eq!(status(addr, "/readyz").await, 503);
+
+        state.set_backend_reachable();
+        assert_eq!(status(addr, "/readyz").await, 200);
+        assert_eq!(status(addr, "/metrics").await, 200);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,301 @@
+use crate::acme::{CertificateStore, TlsCertificate};
+use crate::config::HttpTlsConfig;
+use crate::Error;
+use anyhow::Result;
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode};
+use axum::response::IntoResponse;
+use axum::{Json, Router};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::CertificateDer;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+use tracing::{debug, warn};
+
+/// Longest wait for a client to finish the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Who is at the other end of a connection, added to every request on it
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    pub address: SocketAddr,
+    /// Common name of the client certificate, if one was presented
+    pub common_name: Option<String>,
+}
+
+/// Serves a router over HTTPS with the `[tls]` certificate, optionally
+/// requiring client certificates
+#[derive(Clone)]
+pub struct HttpsAcceptor {
+    acceptor: TlsAcceptor,
+    allowed_names: Arc<[String]>,
+}
+
+impl HttpsAcceptor {
+    /// `setting` names the section of `config` in errors
+    pub fn new(setting: &str, config: &HttpTlsConfig, certificates: Arc<CertificateStore>) -> Result<Self> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::Configuration(format!("{}: {}", setting, e)))?;
+
+        let builder = match &config.client_ca_path {
+            Some(path) => {
+                let roots = load_roots(path)
+                    .map_err(|e| Error::Configuration(format!("{}.client_ca_path: {}: {}", setting, path, e)))?;
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                    .build()
+                    .map_err(|e| Error::Configuration(format!("{}.client_ca_path: {}: {}", setting, path, e)))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None if !config.allowed_client_names.is_empty() => {
+                return Err(Error::Configuration(format!(
+                    "{}.allowed_client_names: needs client_ca_path to be set",
+                    setting
+                ))
+                .into());
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut server_config = builder.with_cert_resolver(Arc::new(StoreResolver {
+            store: certificates,
+            provider,
+            cached: Mutex::default(),
+        }));
+        server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            allowed_names: config.allowed_client_names.clone().into(),
+        })
+    }
+
+    /// Whether a client with this certificate common name may use the listener
+    pub fn allows(&self, common_name: Option<&str>) -> bool {
+        self.allowed_names.is_empty() || common_name.is_some_and(|name| self.allowed_names.iter().any(|allowed| allowed == name))
+    }
+
+    /// Serve `app` until the listener fails. Requests carry the client's
+    /// [`ClientIdentity`] and `ConnectInfo<SocketAddr>`; clients whose
+    /// certificate is not among `allowed_client_names` get 403 for every
+    /// request.
+    pub async fn serve(self, listener: TcpListener, app: Router) -> Result<()> {
+        loop {
+            let (stream, address) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Out of file descriptors and the like; wait for some to free up
+                    debug!("Failed to accept a connection: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            let this = self.clone();
+            let app = app.clone();
+            tokio::spawn(async move {
+                let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, this.acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        debug!("TLS handshake with {} failed: {}", address, e);
+                        return;
+                    }
+                    Err(_) => {
+                        debug!("TLS handshake with {} timed out", address);
+                        return;
+                    }
+                };
+
+                let common_name = stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|chain| chain.first())
+                    .and_then(common_name);
+                let app = if this.allows(common_name.as_deref()) {
+                    app
+                } else {
+                    warn!(
+                        "Refusing {}: client certificate {} is not allowed",
+                        address,
+                        common_name.as_deref().unwrap_or("without a common name")
+                    );
+                    Router::new().fallback(forbidden)
+                };
+
+                let identity = ClientIdentity { address, common_name };
+                let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                    request.extensions_mut().insert(ConnectInfo(address));
+                    request.extensions_mut().insert(identity.clone());
+                    app.clone().call(request)
+                });
+                if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    debug!("HTTPS connection from {} failed: {}", address, e);
+                }
+            });
+        }
+    }
+}
+
+async fn forbidden() -> impl IntoResponse {
+    (StatusCode::FORBIDDEN, Json(json!({ "error": "client certificate not allowed" })))
+}
+
+fn load_roots(path: &str) -> Result<RootCertStore, Box<dyn std::error::Error + Send + Sync>> {
+    let pem = std::fs::read(path)?;
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+        roots.add(cert?)?;
+    }
+    if roots.is_empty() {
+        return Err("no certificates in the bundle".into());
+    }
+    Ok(roots)
+}
+
+/// Common name of the subject of `cert`
+fn common_name(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+    let name = cert.subject().iter_common_name().next()?.as_str().ok()?;
+    Some(name.to_string())
+}
+
+/// Hands out the certificate in the store, parsed again only once it has
+/// been replaced
+#[derive(Debug)]
+struct StoreResolver {
+    store: Arc<CertificateStore>,
+    provider: Arc<CryptoProvider>,
+    cached: Mutex<Option<(Arc<TlsCertificate>, Arc<CertifiedKey>)>>,
+}
+
+impl ResolvesServerCert for StoreResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let current = self.store.current()?;
+        let mut cached = self.cached.lock().unwrap();
+        if let Some((certificate, key)) = cached.as_ref() {
+            if Arc::ptr_eq(certificate, &current) {
+                return Some(key.clone());
+            }
+        }
+
+        match certified_key(&current, &self.provider) {
+            Ok(key) => {
+                let key = Arc::new(key);
+                *cached = Some((current, key.clone()));
+                Some(key)
+            }
+            Err(e) => {
+                warn!("Cannot serve the TLS certificate: {}", e);
+                None
+            }
+        }
+    }
+}
+
+fn certified_key(
+    certificate: &TlsCertificate,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, Box<dyn std::error::Error + Send + Sync>> {
+    let chain = rustls_pemfile::certs(&mut certificate.cert_pem.as_bytes()).collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut certificate.key_pem.as_bytes())?.ok_or("no private key in the key file")?;
+    Ok(CertifiedKey::new(chain, provider.key_provider.load_private_key(key)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TlsConfig;
+    use crate::utils::encryption::CertificateUtils;
+    use axum::routing::get;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+    /// A certificate authority for client certificates
+    fn client_ca() -> (rcgen::Certificate, KeyPair) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, "llmdig test CA");
+        (params.self_signed(&key).unwrap(), key)
+    }
+
+    /// A client certificate issued by `ca` for `common_name`, and its key
+    fn client_certificate(ca: &(rcgen::Certificate, KeyPair), common_name: &str) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.distinguished_name.push(DnType::CommonName, common_name);
+        let cert = params.signed_by(&key, &ca.0, &ca.1).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    #[tokio::test]
+    async fn test_client_certificates() {
+        let server = CertificateUtils::generate_self_signed_cert(&["localhost".to_string()], 1).unwrap();
+        let store = Arc::new(CertificateStore::load(&TlsConfig {
+            cert_path: String::new(),
+            ..TlsConfig::default()
+        }));
+        store.replace(TlsCertificate::new(server.cert_pem, server.key_pem).unwrap());
+
+        let ca = client_ca();
+        let ops = client_certificate(&ca, "ops");
+        let stranger = client_certificate(&ca, "stranger");
+        let forged = client_certificate(&client_ca(), "ops");
+        let ca_path = std::env::temp_dir().join(format!("llmdig-client-ca-{}.pem", std::process::id()));
+        std::fs::write(&ca_path, ca.0.pem()).unwrap();
+        let config = HttpTlsConfig {
+            enabled: true,
+            client_ca_path: Some(ca_path.to_string_lossy().to_string()),
+            allowed_client_names: vec!["ops".to_string()],
+        };
+        let acceptor = HttpsAcceptor::new("admin.tls", &config, store.clone()).unwrap();
+        let _ = std::fs::remove_file(&ca_path);
+        assert!(acceptor.allows(Some("ops")));
+        assert!(!acceptor.allows(None));
+
+        let app = Router::new().route(
+            "/",
+            get(|axum::Extension(identity): axum::Extension<ClientIdentity>| async move {
+                identity.common_name.unwrap_or_default()
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://localhost:{}/", listener.local_addr().unwrap().port());
+        tokio::spawn(acceptor.serve(listener, app));
+
+        let client = |identity: Option<&(String, String)>| {
+            let mut builder = reqwest::Client::builder().danger_accept_invalid_certs(true);
+            if let Some((cert, key)) = identity {
+                builder = builder.identity(reqwest::Identity::from_pkcs8_pem(cert.as_bytes(), key.as_bytes()).unwrap());
+            }
+            builder.build().unwrap()
+        };
+        let response = client(Some(&ops)).get(&url).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.text().await.unwrap(), "ops");
+        assert_eq!(client(Some(&stranger)).get(&url).send().await.unwrap().status().as_u16(), 403);
+        assert!(client(Some(&forged)).get(&url).send().await.is_err());
+        assert!(client(None).get(&url).send().await.is_err());
+
+        // Without a CA bundle there is nothing to check names against
+        let config = HttpTlsConfig {
+            client_ca_path: None,
+            ..config
+        };
+        assert!(HttpsAcceptor::new("admin.tls", &config, store).is_err());
+    }
+}
@@ -1,15 +1,36 @@
+pub mod acme;
+pub mod admin;
+pub mod api;
 pub mod config;
 pub mod dns;
+pub mod dry_run;
 pub mod error;
+pub mod health;
+pub mod https;
 pub mod llm;
+#[cfg(feature = "local-llm")]
+pub mod local_llm;
+#[cfg(feature = "lua-hooks")]
+pub mod lua_hooks;
+pub mod middleware;
+#[cfg(feature = "redis-storage")]
+pub mod redis_storage;
+pub mod self_check;
 pub mod server;
+pub mod service;
+#[cfg(feature = "sled-storage")]
+pub mod sled_storage;
+pub mod systemd;
+pub mod telemetry;
 pub mod utils;
+#[cfg(feature = "wasm-plugins")]
+pub mod wasm_plugins;
 
 pub use config::Config;
 pub use dns::DnsHandler;
 pub use error::Error;
-pub use llm::{LlmBackend, LlmClient};
-pub use server::DnsServer;
+pub use llm::{BackendRegistry, GenerationOptions, LlmBackend, LlmClient};
+pub use server::{DnsServer, DnsServerBuilder, ServerHandle};
 
 // Re-export common types
 pub use anyhow::Result; 
\ No newline at end of file
@@ -1,158 +1,1004 @@
-use crate::config::{Config, LlmBackendType};
+use crate::config::{Config, LlmBackendType, MockConfig, MockMode, PostProcessStep};
+use crate::utils::cassette::{CassetteRecorder, RecordingBackend, ReplayBackend};
+use crate::utils::concurrency::ConcurrencyLimiter;
+use crate::utils::llm_keys::LlmKeyRing;
+use crate::utils::load_balancer::LoadBalancer;
+use crate::utils::metrics::Metrics;
+use crate::utils::post_process;
+use crate::utils::text;
+use crate::utils::rag::DocumentStore;
+use crate::utils::secret::redact_url;
+use crate::utils::tools::ToolRegistry;
+use crate::utils::moderation::{ModerationVerdict, Moderator};
 use crate::Error;
 use anyhow::Result;
 use async_trait::async_trait;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
-use tracing::{debug, error, info};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, instrument, warn};
+
+/// Per-request overrides of the configured generation settings
+#[derive(Debug, Clone, Default)]
+pub struct GenerationOptions {
+    pub model: Option<String>,
+    pub system_prompt: Option<String>,
+    pub temperature: Option<f32>,
+    /// Sampling seed, for backends that accept one
+    pub seed: Option<u64>,
+}
+
+impl GenerationOptions {
+    pub fn model_or<'a>(&'a self, default: &'a str) -> &'a str {
+        self.model.as_deref().unwrap_or(default)
+    }
+
+    pub fn temperature_or(&self, default: f32) -> f32 {
+        self.temperature.unwrap_or(default)
+    }
+
+    /// Temperature 0 and a fixed seed, whatever was asked for
+    pub fn deterministic(&self, seed: u64) -> Self {
+        Self {
+            temperature: Some(0.0),
+            seed: Some(seed),
+            ..self.clone()
+        }
+    }
+}
+
+/// Tokens spent on one answer, as reported by the backend
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// Add a further call's usage to a running total, if it was reported
+fn add_usage(total: &mut Option<TokenUsage>, usage: Option<TokenUsage>) {
+    if let Some(usage) = usage {
+        *total.get_or_insert_with(TokenUsage::default) += usage;
+    }
+}
+
+/// An answer and what it cost
+#[derive(Debug, Clone)]
+pub struct Generation {
+    pub text: String,
+    /// `None` when the backend does not report usage
+    pub usage: Option<TokenUsage>,
+}
 
 #[async_trait]
 pub trait LlmBackend: Send + Sync {
     async fn generate_response(&self, prompt: &str) -> Result<String>;
+
+    /// Generate with per-request overrides. Backends that cannot honour the
+    /// overrides fall back to `generate_response`, with any system prompt
+    /// put in front of the question.
+    async fn generate_with_options(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        match &options.system_prompt {
+            Some(system_prompt) => {
+                self.generate_response(&format!("{}\n\n{}", system_prompt, prompt))
+                    .await
+            }
+            None => self.generate_response(prompt).await,
+        }
+    }
+
+    /// Generate with tools the model may call before answering. Backends
+    /// without function calling answer without them.
+    async fn generate_with_tools(
+        &self,
+        prompt: &str,
+        options: &GenerationOptions,
+        tools: &ToolRegistry,
+    ) -> Result<String> {
+        let _ = tools;
+        self.generate_with_options(prompt, options).await
+    }
+
+    /// Generate, with tools when given, and report the tokens spent.
+    /// Backends whose API does not report usage leave it out.
+    async fn generate_with_usage(
+        &self,
+        prompt: &str,
+        options: &GenerationOptions,
+        tools: Option<&ToolRegistry>,
+    ) -> Result<Generation> {
+        let text = match tools {
+            Some(tools) => self.generate_with_tools(prompt, options, tools).await?,
+            None => self.generate_with_options(prompt, options).await?,
+        };
+        Ok(Generation { text, usage: None })
+    }
+
+    /// Cheap reachability check used by the readiness probe
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// One-off checks run before the server starts answering, e.g. that the
+    /// configured model exists
+    async fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
-pub struct LlmClient {
+/// Creates a backend from the configuration, for [`BackendRegistry`]
+pub type BackendFactory = Arc<dyn Fn(&Config) -> Result<Box<dyn LlmBackend>> + Send + Sync>;
+
+/// Backends supplied by library users, selected with
+/// `backend = { registered = "<name>" }`
+#[derive(Clone, Default)]
+pub struct BackendRegistry {
+    factories: HashMap<String, BackendFactory>,
+}
+
+impl BackendRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make `factory` available as `name`, replacing any earlier backend of
+    /// that name
+    pub fn register<F>(&mut self, name: impl Into<String>, factory: F)
+    where
+        F: Fn(&Config) -> Result<Box<dyn LlmBackend>> + Send + Sync + 'static,
+    {
+        self.factories.insert(name.into(), Arc::new(factory));
+    }
+
+    fn create(&self, name: &str, config: &Config) -> Result<Box<dyn LlmBackend>> {
+        match self.factories.get(name) {
+            Some(factory) => factory(config),
+            None => Err(Error::Configuration(format!("No backend is registered as {:?}", name)).into()),
+        }
+    }
+}
+
+/// One endpoint of the backend pool
+struct PoolMember {
+    /// Key for the endpoint's `backend_stats`
+    name: String,
     backend: Box<dyn LlmBackend>,
+    limiter: ConcurrencyLimiter,
+}
+
+pub struct LlmClient {
+    backends: Vec<PoolMember>,
+    balancer: LoadBalancer,
+    /// Limit across all endpoints
+    limiter: ConcurrencyLimiter,
     config: Config,
+    moderator: Option<Moderator>,
+    tools: Option<ToolRegistry>,
+    documents: Option<DocumentStore>,
+    /// API keys of the provider, shared by the backends, moderation and embeddings
+    keys: Arc<LlmKeyRing>,
+    metrics: Arc<Metrics>,
 }
 
 impl LlmClient {
     pub fn new(config: Config) -> Result<Self> {
+        Self::with_registry(config, &BackendRegistry::default())
+    }
+
+    /// Like [`LlmClient::new`], creating `registered` backends from `registry`
+    pub fn with_registry(config: Config, registry: &BackendRegistry) -> Result<Self> {
+        let keys = LlmKeyRing::for_config(&config);
+        Self::build(config, registry, keys)
+    }
+
+    /// Like [`LlmClient::new`], calling the provider with the keys of `keys`
+    /// instead of those in `config`
+    pub fn with_keys(config: Config, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        Self::build(config, &BackendRegistry::default(), keys)
+    }
+
+    fn build(config: Config, registry: &BackendRegistry, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        if let Some(path) = &config.llm.replay_path {
+            if config.llm.record_path.is_some() {
+                return Err(Error::Configuration(
+                    "llm.record_path and llm.replay_path cannot both be set".to_string(),
+                )
+                .into());
+            }
+            let backend = Box::new(ReplayBackend::open(path)?);
+            let name = config.llm.backend.name().to_string();
+            return Self::single(config, name, backend, keys);
+        }
+
+        // One pooled client for every endpoint, so connections are reused
+        let client = http_client(&config)?;
+        let (backends, weights) = if config.llm.endpoints.is_empty() {
+            let name = config.llm.backend.name().to_string();
+            let member = PoolMember {
+                limiter: ConcurrencyLimiter::new(&name, 0, &config.llm.overflow),
+                backend: Self::create_backend(&config, &client, registry, &keys)?,
+                name,
+            };
+            (vec![member], vec![1])
+        } else {
+            let mut backends = Vec::with_capacity(config.llm.endpoints.len());
+            for endpoint in &config.llm.endpoints {
+                let endpoint_config = Self::endpoint_config(&config, &endpoint.url)?;
+                backends.push(PoolMember {
+                    name: endpoint.url.clone(),
+                    backend: Self::create_backend(&endpoint_config, &client, registry, &keys)?,
+                    limiter: ConcurrencyLimiter::new(
+                        &endpoint.url,
+                        endpoint.max_concurrent_requests,
+                        &config.llm.overflow,
+                    ),
+                });
+            }
+            let weights = config.llm.endpoints.iter().map(|endpoint| endpoint.weight).collect();
+            (backends, weights)
+        };
+        Self::from_pool(config, backends, weights, keys)
+    }
+
+    /// A client answering from `backend` alone, whatever `llm.backend` and
+    /// `llm.endpoints` say. `name` labels its metrics.
+    pub fn from_backend(config: Config, name: impl Into<String>, backend: Box<dyn LlmBackend>) -> Result<Self> {
+        let keys = LlmKeyRing::for_config(&config);
+        Self::single(config, name.into(), backend, keys)
+    }
+
+    fn single(config: Config, name: String, backend: Box<dyn LlmBackend>, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        let member = PoolMember {
+            limiter: ConcurrencyLimiter::new(&name, 0, &config.llm.overflow),
+            backend,
+            name,
+        };
+        Self::from_pool(config, vec![member], vec![1], keys)
+    }
+
+    fn from_pool(config: Config, backends: Vec<PoolMember>, weights: Vec<u32>, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        let backends = match &config.llm.record_path {
+            Some(path) => {
+                let recorder = Arc::new(CassetteRecorder::new(path));
+                backends
+                    .into_iter()
+                    .map(|member| PoolMember {
+                        backend: Box::new(RecordingBackend::new(member.backend, recorder.clone())),
+                        ..member
+                    })
+                    .collect()
+            }
+            None => backends,
+        };
+        let balancer = LoadBalancer::new(weights, config.llm.balancing.strategy.clone());
+        let limiter = ConcurrencyLimiter::new(
+            "all endpoints",
+            config.llm.max_concurrent_requests,
+            &config.llm.overflow,
+        );
+
+        let moderator = if config.moderation.enabled {
+            Some(Moderator::new(&config, keys.clone())?)
+        } else {
+            None
+        };
+
+        let tools = if config.llm.tools.enabled {
+            Some(ToolRegistry::new(&config)?)
+        } else {
+            None
+        };
+
+        let documents = if config.rag.enabled {
+            Some(DocumentStore::new(&config, keys.clone())?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            backends,
+            balancer,
+            limiter,
+            config,
+            moderator,
+            tools,
+            documents,
+            keys,
+            metrics: Arc::new(Metrics::new()),
+        })
+    }
+
+    fn create_backend(
+        config: &Config,
+        client: &Client,
+        registry: &BackendRegistry,
+        keys: &Arc<LlmKeyRing>,
+    ) -> Result<Box<dyn LlmBackend>> {
         let backend: Box<dyn LlmBackend> = match &config.llm.backend {
             LlmBackendType::OpenAI => {
-                Box::new(OpenAiBackend::new(config.clone())?)
+                Box::new(OpenAiBackend::new(config.clone(), client.clone(), keys.clone())?)
             }
             LlmBackendType::Ollama => {
-                Box::new(OllamaBackend::new(config.clone())?)
+                Box::new(OllamaBackend::new(config.clone(), client.clone())?)
             }
             LlmBackendType::Custom(url) => {
-                Box::new(CustomBackend::new(config.clone(), url.clone())?)
+                Box::new(CustomBackend::new(config.clone(), client.clone(), url.clone())?)
             }
+            LlmBackendType::Mock => {
+                Box::new(MockBackend::new(config.llm.mock.clone())?)
+            }
+            LlmBackendType::Registered(name) => registry.create(name, config)?,
+            #[cfg(feature = "local-llm")]
+            LlmBackendType::Local => {
+                Box::new(crate::local_llm::LocalBackend::new(config)?)
+            }
+            #[cfg(not(feature = "local-llm"))]
+            LlmBackendType::Local => {
+                return Err(Error::Configuration(
+                    "The local backend needs LLMdig built with the local-llm feature".to_string(),
+                )
+                .into());
+            }
+        };
+        Ok(backend)
+    }
+
+    /// The configuration with the backend's address replaced by `url`
+    fn endpoint_config(config: &Config, url: &str) -> Result<Config> {
+        let mut config = config.clone();
+        match &mut config.llm.backend {
+            LlmBackendType::OpenAI => config.llm.base_url = Some(url.to_string()),
+            LlmBackendType::Ollama => config.llm.ollama.host = url.to_string(),
+            LlmBackendType::Custom(endpoint) => *endpoint = url.to_string(),
+            backend => {
+                return Err(Error::Configuration(format!(
+                    "llm.endpoints is not supported by the {} backend",
+                    backend.name()
+                ))
+                .into());
+            }
+        }
+        Ok(config)
+    }
+
+    /// API keys the client calls the provider with
+    pub fn keys(&self) -> Arc<LlmKeyRing> {
+        self.keys.clone()
+    }
+
+    /// Report into a shared metrics registry instead of a private one
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Short name of the configured backend, for logs and metrics
+    pub fn backend_name(&self) -> &str {
+        self.config.llm.backend.name()
+    }
+
+    /// Check that the configured backend can be reached; with several
+    /// endpoints, one answering is enough
+    pub async fn check_backend(&self) -> Result<()> {
+        let mut last_error = None;
+        for member in &self.backends {
+            match member.backend.health_check().await {
+                Ok(()) => return Ok(()),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| Error::LlmApi("No LLM endpoints configured".to_string()).into()))
+    }
+
+    /// Run the backend's startup checks on every endpoint and index the
+    /// document corpus
+    pub async fn prepare_backend(&self) -> Result<()> {
+        for member in &self.backends {
+            member.backend.prepare().await?;
+        }
+        if let Some(documents) = &self.documents {
+            documents.index().await?;
+        }
+        Ok(())
+    }
+
+    /// Run the configured clean-up steps over an answer, then enforce the
+    /// character budget. Tokens spent summarizing are added to `usage`.
+    async fn post_process(&self, response: String, options: &GenerationOptions, usage: &mut Option<TokenUsage>) -> String {
+        let post_processing = &self.config.llm.post_processing;
+        let max_chars = post_processing.max_chars;
+        let mut response = response;
+
+        for step in &post_processing.steps {
+            response = match step {
+                PostProcessStep::Summarize if max_chars > 0 && response.chars().count() > max_chars => {
+                    let prompt = format!(
+                        "Shorten the following answer to at most {} characters of plain text, without markdown:\n\n{}",
+                        max_chars, response
+                    );
+                    match self.generate(&prompt, options).await {
+                        // The summary gets the same clean-up as the answer did
+                        Ok(summary) => {
+                            add_usage(usage, summary.usage);
+                            post_processing
+                                .steps
+                                .iter()
+                                .fold(summary.text, |text, step| Self::clean_up(step, &text))
+                        }
+                        Err(e) => {
+                            // The budget below still applies, by truncation
+                            warn!("Could not summarize over-long answer: {}", e);
+                            response
+                        }
+                    }
+                }
+                step => Self::clean_up(step, &response),
+            };
+        }
+
+        post_process::truncate_chars(response.trim(), max_chars)
+    }
+
+    /// Apply one of the text-only post-processing steps
+    fn clean_up(step: &PostProcessStep, text: &str) -> String {
+        match step {
+            PostProcessStep::StripMarkdown => post_process::strip_markdown(text),
+            PostProcessStep::StripPreamble => post_process::strip_preamble(text),
+            PostProcessStep::CollapseWhitespace => post_process::collapse_whitespace(text),
+            PostProcessStep::Summarize => text.to_string(),
+        }
+    }
+
+    /// Send the question to the endpoint chosen by the load balancer
+    async fn generate(&self, question: &str, options: &GenerationOptions) -> Result<Generation> {
+        let _slot = self.limiter.acquire(&self.metrics).await?;
+
+        let available = self.available_backends().await;
+        let index = self
+            .balancer
+            .pick(&available)
+            .ok_or_else(|| Error::LlmApi("No LLM endpoint available".to_string()))?;
+        let member = &self.backends[index];
+        let _endpoint_slot = member.limiter.acquire(&self.metrics).await?;
+
+        let _outstanding = self.balancer.start(index);
+        let started = Instant::now();
+        let result = member
+            .backend
+            .generate_with_usage(question, options, self.tools.as_ref())
+            .await;
+        self.metrics
+            .record_backend_call(member.name.clone(), result.is_ok(), started.elapsed())
+            .await;
+
+        if let Err(e) = &result {
+            warn!("LLM endpoint {} failed: {}", member.name, e);
+        }
+        result
+    }
+
+    /// Endpoints that are not sitting out a cooldown after repeated
+    /// failures. When every endpoint is failing all of them are tried,
+    /// rather than none. Endpoints at their concurrency limit are left out
+    /// while others still have room.
+    async fn available_backends(&self) -> Vec<bool> {
+        let balancing = &self.config.llm.balancing;
+        let cooldown = Duration::from_secs(balancing.cooldown_seconds);
+
+        let mut available = Vec::with_capacity(self.backends.len());
+        for member in &self.backends {
+            let cooling_down = self.metrics.backend_stats(&member.name).await.is_some_and(|stats| {
+                stats.consecutive_failures >= balancing.failure_threshold
+                    && stats.last_call.is_some_and(|last| last.elapsed() < cooldown)
+            });
+            available.push(!cooling_down);
+        }
+
+        let available = if available.contains(&true) {
+            available
+        } else {
+            vec![true; available.len()]
         };
 
-        Ok(Self { backend, config })
+        let with_room: Vec<bool> = available
+            .iter()
+            .zip(&self.backends)
+            .map(|(&available, member)| available && member.limiter.has_capacity())
+            .collect();
+        if with_room.contains(&true) {
+            with_room
+        } else {
+            available
+        }
     }
 
     pub async fn query(&self, question: &str) -> Result<String> {
-        info!("Processing LLM query: {}", question);
-        
-        let response = self.backend.generate_response(question).await?;
+        self.query_with_options(question, &GenerationOptions::default()).await
+    }
+
+    pub async fn query_with_options(&self, question: &str, options: &GenerationOptions) -> Result<String> {
+        let generation = self.query_detailed(question, options).await?;
+        Ok(truncate_for_txt(generation.text))
+    }
+
+    /// The full answer, before it is cut down to fit TXT records, and the
+    /// tokens it took
+    #[instrument(name = "llm.query", skip_all, fields(backend = %self.config.llm.backend.name()))]
+    pub async fn query_detailed(&self, question: &str, options: &GenerationOptions) -> Result<Generation> {
+        debug!("Processing LLM query: {}", question);
+
+        let prompt = match &self.documents {
+            Some(documents) => documents.augment(question).await.unwrap_or_else(|e| {
+                // Fail open: the model can still answer without the corpus
+                warn!("Document retrieval failed: {}", e);
+                question.to_string()
+            }),
+            None => question.to_string(),
+        };
         
-        // Truncate response to fit in DNS TXT record (255 bytes per string, max 16 strings)
-        let max_length = 255 * 16;
-        let truncated = if response.len() > max_length {
-            let truncated = &response[..max_length];
-            format!("{}...", truncated)
+        let deterministic;
+        let options = if self.config.llm.deterministic {
+            deterministic = options.deterministic(self.config.llm.seed);
+            &deterministic
         } else {
-            response
+            options
         };
 
-        debug!("LLM response ({} chars): {}", truncated.len(), truncated);
-        Ok(truncated)
+        let Generation { text, mut usage } = self.generate(&prompt, options).await?;
+        let mut response = self.post_process(text, options, &mut usage).await;
+
+        if let Some(moderator) = &self.moderator {
+            match moderator.moderate(&response).await {
+                ModerationVerdict::Allowed => {}
+                ModerationVerdict::Redacted(redacted) => {
+                    self.metrics.increment_moderated_responses();
+                    response = redacted;
+                }
+                ModerationVerdict::Blocked(_) => {
+                    self.metrics.increment_moderated_responses();
+                    response = moderator.blocked_message().to_string();
+                }
+            }
+        }
+
+        debug!("LLM response ({} chars): {}", response.len(), response);
+        Ok(Generation { text: response, usage })
+    }
+}
+
+/// Largest answer sent in TXT records (255 bytes per string, max 16 strings)
+pub const MAX_TXT_ANSWER: usize = 255 * 16;
+
+/// Cut an answer down to what fits in the TXT records of one response
+pub fn truncate_for_txt(response: String) -> String {
+    if response.len() > MAX_TXT_ANSWER {
+        format!("{}...", text::truncate_to_bytes(&response, MAX_TXT_ANSWER))
+    } else {
+        response
+    }
+}
+
+/// Build the HTTP client shared by the backends. Connections are pooled
+/// and kept alive between requests, which matters most for TLS endpoints
+/// where the handshake would otherwise dominate latency.
+pub fn http_client(config: &Config) -> Result<Client> {
+    let http = &config.llm.http;
+    let mut builder = Client::builder()
+        .timeout(Duration::from_secs(config.llm.timeout_seconds))
+        .pool_max_idle_per_host(http.pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(http.pool_idle_timeout_seconds))
+        .tcp_keepalive(Duration::from_secs(http.tcp_keepalive_seconds));
+    if http.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+
+    if let Some(proxy_url) = &config.llm.proxy_url {
+        let proxy = reqwest::Proxy::all(proxy_url)
+            .map_err(|e| Error::Configuration(format!("Invalid proxy URL {}: {}", redact_url(proxy_url), e)))?;
+        builder = builder.proxy(proxy);
     }
+    if let Some(path) = &config.llm.ca_bundle_path {
+        for certificate in load_ca_bundle(path)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Read every certificate from a PEM bundle, to be trusted on top of the
+/// system roots
+fn load_ca_bundle(path: &str) -> Result<Vec<reqwest::Certificate>> {
+    let invalid = |reason: String| Error::Configuration(format!("CA bundle {}: {}", path, reason));
+    let contents = std::fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+
+    const END: &str = "-----END CERTIFICATE-----";
+    let certificates = contents
+        .split_inclusive(END)
+        .filter(|block| block.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|block| reqwest::Certificate::from_pem(block.as_bytes()).map_err(|e| invalid(e.to_string())))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    if certificates.is_empty() {
+        return Err(invalid("no PEM certificates found".to_string()).into());
+    }
+    Ok(certificates)
 }
 
+/// Used when `llm.base_url` is not set
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
 pub struct OpenAiBackend {
     client: Client,
     config: Config,
+    /// API root that `/chat/completions` and `/models` are appended to
+    base_url: String,
+    keys: Arc<LlmKeyRing>,
 }
 
 impl OpenAiBackend {
-    pub fn new(config: Config) -> Result<Self> {
-        let api_key = config
-            .llm
-            .api_key
-            .as_ref()
-            .ok_or_else(|| Error::Configuration("OpenAI API key not found".to_string()))?;
+    pub fn new(config: Config, client: Client, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        let base_url = config.llm.base_url.clone().unwrap_or_else(|| OPENAI_BASE_URL.to_string());
+        Self::with_base_url(config, client, &base_url, keys)
+    }
 
-        let client = Client::builder()
-            .timeout(Duration::from_secs(config.llm.timeout_seconds))
-            .build()?;
+    /// Talk to an OpenAI-compatible API at `base_url` instead, e.g. a mock server
+    pub fn with_base_url(config: Config, client: Client, base_url: &str, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        // Self-hosted servers such as vLLM or LM Studio accept requests without a key
+        if config.llm.api_key.is_none() && config.llm.base_url.is_none() {
+            return Err(Error::Configuration("OpenAI API key not found".to_string()).into());
+        }
 
-        Ok(Self { client, config })
+        Ok(Self {
+            client,
+            config,
+            base_url: openai_api_root(base_url)?,
+            keys,
+        })
     }
-}
 
-#[async_trait]
-impl LlmBackend for OpenAiBackend {
-    async fn generate_response(&self, prompt: &str) -> Result<String> {
+    /// Send a request built by `build` with the provider's API keys
+    async fn send(&self, build: impl Fn() -> reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        Ok(self.keys.send(build).await?)
+    }
+
+    fn request(&self, method: reqwest::Method, endpoint: &str) -> reqwest::RequestBuilder {
+        self.client.request(method, format!("{}/{}", self.base_url, endpoint))
+    }
+
+    fn messages(prompt: &str, options: &GenerationOptions) -> Vec<OpenAiMessage> {
+        let mut messages = Vec::with_capacity(2);
+        if let Some(system_prompt) = &options.system_prompt {
+            messages.push(OpenAiMessage::new("system", system_prompt));
+        }
+        messages.push(OpenAiMessage::new("user", prompt));
+        messages
+    }
+
+    /// One chat completion; returns the first choice's message and the
+    /// tokens it took
+    async fn chat(
+        &self,
+        messages: Vec<OpenAiMessage>,
+        options: &GenerationOptions,
+        tools: Vec<OpenAiTool>,
+        tool_choice: Option<&str>,
+    ) -> Result<(OpenAiMessage, Option<TokenUsage>)> {
         let request = OpenAiRequest {
-            model: self.config.llm.model.clone(),
-            messages: vec![OpenAiMessage {
-                role: "user".to_string(),
-                content: prompt.to_string(),
-            }],
+            model: options.model_or(&self.config.llm.model).to_string(),
+            messages,
             max_tokens: self.config.llm.max_tokens,
-            temperature: self.config.llm.temperature,
+            temperature: options.temperature_or(self.config.llm.temperature),
+            seed: options.seed,
+            tools,
+            tool_choice: tool_choice.map(str::to_string),
         };
 
         let response = self
-            .client
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.config.llm.api_key.as_ref().unwrap()))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
+            .send(|| {
+                self.request(reqwest::Method::POST, "chat/completions")
+                    .header("Content-Type", "application/json")
+                    .json(&request)
+            })
             .await?;
 
         if !response.status().is_success() {
+            let status = response.status();
             let error_text = response.text().await?;
-            error!("OpenAI API error: {}", error_text);
-            return Err(Error::LlmApi(error_text).into());
+            error!("OpenAI API error ({}): {}", status, error_text);
+            return Err(Error::LlmApi(format!("{}: {}", status, error_text)).into());
         }
 
         let response: OpenAiResponse = response.json().await?;
-        
-        Ok(response
+        let usage = response.usage.map(|usage| TokenUsage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+        });
+        let message = response
             .choices
-            .first()
-            .and_then(|choice| choice.message.content.clone())
-            .unwrap_or_else(|| "No response generated".to_string()))
+            .into_iter()
+            .next()
+            .map(|choice| choice.message)
+            .unwrap_or_else(|| OpenAiMessage::new("assistant", "No response generated"));
+        Ok((message, usage))
+    }
+}
+
+/// Normalise a configured base URL to the API root. A bare host gets the
+/// `/v1` prefix OpenAI uses; any other path is taken as the provider's root
+/// (`https://api.groq.com/openai/v1`), and a full `/chat/completions` URL is
+/// accepted too.
+fn openai_api_root(base_url: &str) -> Result<String> {
+    let url = reqwest::Url::parse(base_url)
+        .map_err(|e| Error::Configuration(format!("Invalid LLM base URL {}: {}", base_url, e)))?;
+    let root = base_url.trim_end_matches('/');
+    let root = root.strip_suffix("/chat/completions").unwrap_or(root);
+
+    if url.path().trim_matches('/').is_empty() {
+        Ok(format!("{}/v1", root))
+    } else {
+        Ok(root.to_string())
+    }
+}
+
+#[async_trait]
+impl LlmBackend for OpenAiBackend {
+    async fn generate_response(&self, prompt: &str) -> Result<String> {
+        self.generate_with_options(prompt, &GenerationOptions::default()).await
+    }
+
+    async fn generate_with_options(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        Ok(self.generate_with_usage(prompt, options, None).await?.text)
+    }
+
+    async fn generate_with_tools(
+        &self,
+        prompt: &str,
+        options: &GenerationOptions,
+        tools: &ToolRegistry,
+    ) -> Result<String> {
+        Ok(self.generate_with_usage(prompt, options, Some(tools)).await?.text)
+    }
+
+    #[instrument(name = "llm.openai", skip_all, fields(model = %options.model_or(&self.config.llm.model)))]
+    async fn generate_with_usage(
+        &self,
+        prompt: &str,
+        options: &GenerationOptions,
+        tools: Option<&ToolRegistry>,
+    ) -> Result<Generation> {
+        let mut messages = Self::messages(prompt, options);
+        let Some(tools) = tools else {
+            let (message, usage) = self.chat(messages, options, Vec::new(), None).await?;
+            return Ok(Generation {
+                text: message.content.unwrap_or_else(|| "No response generated".to_string()),
+                usage,
+            });
+        };
+
+        let definitions: Vec<OpenAiTool> = tools
+            .tools()
+            .map(|tool| OpenAiTool {
+                kind: "function".to_string(),
+                function: OpenAiFunction {
+                    name: tool.name().to_string(),
+                    description: tool.description().to_string(),
+                    parameters: tool.parameters(),
+                },
+            })
+            .collect();
+        // Every round is billed, not just the one that answers
+        let mut total = None;
+
+        for round in 0..=tools.max_rounds() {
+            // Once the rounds are used up the model has to answer
+            let tool_choice = (round == tools.max_rounds()).then_some("none");
+            let (message, usage) = self
+                .chat(messages.clone(), options, definitions.clone(), tool_choice)
+                .await?;
+            add_usage(&mut total, usage);
+
+            if message.tool_calls.is_empty() || tool_choice.is_some() {
+                return Ok(Generation {
+                    text: message.content.unwrap_or_else(|| "No response generated".to_string()),
+                    usage: total,
+                });
+            }
+
+            let calls = message.tool_calls.clone();
+            messages.push(message);
+            for call in calls {
+                let output = tools.call(&call.function.name, &call.function.arguments).await;
+                messages.push(OpenAiMessage {
+                    role: "tool".to_string(),
+                    content: Some(output),
+                    tool_calls: Vec::new(),
+                    tool_call_id: Some(call.id),
+                });
+            }
+        }
+
+        unreachable!("the last round always answers")
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        let response = self.send(|| self.request(reqwest::Method::GET, "models")).await?;
+
+        if !response.status().is_success() {
+            return Err(Error::LlmApi(format!("OpenAI returned {}", response.status())).into());
+        }
+        Ok(())
     }
 }
 
 pub struct OllamaBackend {
     client: Client,
     config: Config,
+    base_url: String,
 }
 
 impl OllamaBackend {
-    pub fn new(config: Config) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(config.llm.timeout_seconds))
-            .build()?;
+    pub fn new(config: Config, client: Client) -> Result<Self> {
+        let host = config.llm.ollama.host.clone();
+        if host.contains("://") {
+            Self::with_base_url(config, client, &host)
+        } else {
+            // OLLAMA_HOST is commonly given as a bare `host:port`
+            Self::with_base_url(config, client, &format!("http://{}", host))
+        }
+    }
+
+    /// Talk to an Ollama server at `base_url` instead, e.g. a mock server
+    pub fn with_base_url(config: Config, client: Client, base_url: &str) -> Result<Self> {
+        Ok(Self {
+            client,
+            config,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Names of the models the server has available
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let response = self.client.get(format!("{}/api/tags", self.base_url)).send().await?;
 
-        Ok(Self { client, config })
+        if !response.status().is_success() {
+            return Err(Error::LlmApi(format!("Ollama returned {}", response.status())).into());
+        }
+
+        let tags: OllamaTags = response.json().await?;
+        Ok(tags.models.into_iter().map(|model| model.name).collect())
     }
 }
 
 #[async_trait]
 impl LlmBackend for OllamaBackend {
     async fn generate_response(&self, prompt: &str) -> Result<String> {
+        self.generate_with_options(prompt, &GenerationOptions::default()).await
+    }
+
+    async fn generate_with_options(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        Ok(self.generate_with_usage(prompt, options, None).await?.text)
+    }
+
+    /// Ollama has no function calling here, so tools are not offered
+    #[instrument(name = "llm.ollama", skip_all, fields(model = %options.model_or(&self.config.llm.model)))]
+    async fn generate_with_usage(
+        &self,
+        prompt: &str,
+        options: &GenerationOptions,
+        _tools: Option<&ToolRegistry>,
+    ) -> Result<Generation> {
         let request = OllamaRequest {
-            model: self.config.llm.model.clone(),
+            model: options.model_or(&self.config.llm.model).to_string(),
             prompt: prompt.to_string(),
+            system: options.system_prompt.clone(),
             stream: false,
+            options: (options.temperature.is_some() || options.seed.is_some()).then(|| OllamaOptions {
+                temperature: options.temperature,
+                seed: options.seed,
+            }),
         };
 
         let response = self
             .client
-            .post("http://localhost:11434/api/generate")
+            .post(format!("{}/api/generate", self.base_url))
             .header("Content-Type", "application/json")
             .json(&request)
             .send()
             .await?;
 
         if !response.status().is_success() {
+            let status = response.status();
             let error_text = response.text().await?;
-            error!("Ollama API error: {}", error_text);
-            return Err(Error::LlmApi(error_text).into());
+            error!("Ollama API error ({}): {}", status, error_text);
+            return Err(Error::LlmApi(format!("{}: {}", status, error_text)).into());
         }
 
         let response: OllamaResponse = response.json().await?;
-        Ok(response.response)
+        let usage = match (response.prompt_eval_count, response.eval_count) {
+            (None, None) => None,
+            (prompt_tokens, completion_tokens) => Some(TokenUsage {
+                prompt_tokens: prompt_tokens.unwrap_or(0),
+                completion_tokens: completion_tokens.unwrap_or(0),
+            }),
+        };
+        Ok(Generation {
+            text: response.response,
+            usage,
+        })
     }
+
+    async fn health_check(&self) -> Result<()> {
+        self.list_models().await.map(|_| ())
+    }
+
+    async fn prepare(&self) -> Result<()> {
+        let model = &self.config.llm.model;
+        let models = match self.list_models().await {
+            Ok(models) => models,
+            Err(e) => {
+                // The readiness probe keeps waiting for the server to come up
+                warn!("Could not list Ollama models at {}: {}", self.base_url, e);
+                return Ok(());
+            }
+        };
+
+        if models.iter().any(|name| model_matches(name, model)) {
+            return Ok(());
+        }
+
+        if !self.config.llm.ollama.pull {
+            return Err(Error::Configuration(format!(
+                "Ollama at {} does not have model {}; run `ollama pull {}` or set llm.ollama.pull = true",
+                self.base_url, model, model
+            ))
+            .into());
+        }
+
+        info!("Pulling Ollama model {}", model);
+        let response = self
+            .client
+            .post(format!("{}/api/pull", self.base_url))
+            .timeout(Duration::from_secs(self.config.llm.ollama.pull_timeout_seconds))
+            .json(&OllamaPullRequest {
+                model: model.clone(),
+                stream: false,
+            })
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await?;
+            return Err(Error::Configuration(format!(
+                "Could not pull Ollama model {}: {}: {}",
+                model, status, error_text
+            ))
+            .into());
+        }
+
+        info!("Pulled Ollama model {}", model);
+        Ok(())
+    }
+}
+
+/// Ollama reports untagged models as `name:latest`
+fn model_matches(available: &str, wanted: &str) -> bool {
+    available == wanted || (!wanted.contains(':') && available.strip_suffix(":latest") == Some(wanted))
 }
 
 pub struct CustomBackend {
@@ -162,11 +1008,7 @@ pub struct CustomBackend {
 }
 
 impl CustomBackend {
-    pub fn new(config: Config, url: String) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(config.llm.timeout_seconds))
-            .build()?;
-
+    pub fn new(config: Config, client: Client, url: String) -> Result<Self> {
         Ok(Self { client, config, url })
     }
 }
@@ -174,11 +1016,18 @@ impl CustomBackend {
 #[async_trait]
 impl LlmBackend for CustomBackend {
     async fn generate_response(&self, prompt: &str) -> Result<String> {
+        self.generate_with_options(prompt, &GenerationOptions::default()).await
+    }
+
+    #[instrument(name = "llm.custom", skip_all, fields(model = %options.model_or(&self.config.llm.model)))]
+    async fn generate_with_options(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
         let request = CustomRequest {
             prompt: prompt.to_string(),
-            model: self.config.llm.model.clone(),
+            system: options.system_prompt.clone(),
+            model: options.model_or(&self.config.llm.model).to_string(),
             max_tokens: self.config.llm.max_tokens,
-            temperature: self.config.llm.temperature,
+            temperature: options.temperature_or(self.config.llm.temperature),
+            seed: options.seed,
         };
 
         let response = self
@@ -190,14 +1039,70 @@ impl LlmBackend for CustomBackend {
             .await?;
 
         if !response.status().is_success() {
+            let status = response.status();
             let error_text = response.text().await?;
-            error!("Custom LLM API error: {}", error_text);
-            return Err(Error::LlmApi(error_text).into());
+            error!("Custom LLM API error ({}): {}", status, error_text);
+            return Err(Error::LlmApi(format!("{}: {}", status, error_text)).into());
         }
 
         let response: CustomResponse = response.json().await?;
         Ok(response.response)
     }
+
+    async fn health_check(&self) -> Result<()> {
+        // The endpoint may only accept POST; any HTTP answer proves it is up
+        self.client.get(&self.url).send().await?;
+        Ok(())
+    }
+}
+
+/// Deterministic backend that never touches the network
+pub struct MockBackend {
+    config: MockConfig,
+    script: HashMap<String, String>,
+}
+
+impl MockBackend {
+    pub fn new(config: MockConfig) -> Result<Self> {
+        let script = match (&config.mode, &config.fixture) {
+            (MockMode::Script, Some(path)) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| Error::Configuration(format!("Could not read mock fixture {}: {}", path, e)))?;
+                let script: HashMap<String, String> = serde_yaml::from_str(&contents)
+                    .map_err(|e| Error::Configuration(format!("Invalid mock fixture {}: {}", path, e)))?;
+                script
+                    .into_iter()
+                    .map(|(question, answer)| (Self::script_key(&question), answer))
+                    .collect()
+            }
+            (MockMode::Script, None) => {
+                return Err(Error::Configuration("Mock script mode needs a fixture file".to_string()).into());
+            }
+            _ => HashMap::new(),
+        };
+
+        Ok(Self { config, script })
+    }
+
+    fn script_key(question: &str) -> String {
+        question.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+    }
+}
+
+#[async_trait]
+impl LlmBackend for MockBackend {
+    async fn generate_response(&self, prompt: &str) -> Result<String> {
+        let response = match self.config.mode {
+            MockMode::Echo => prompt.to_string(),
+            MockMode::Fixed => self.config.response.clone(),
+            MockMode::Script => self
+                .script
+                .get(&Self::script_key(prompt))
+                .cloned()
+                .unwrap_or_else(|| self.config.response.clone()),
+        };
+        Ok(response)
+    }
 }
 
 // Request/Response structures for different backends
@@ -208,17 +1113,76 @@ struct OpenAiRequest {
     messages: Vec<OpenAiMessage>,
     max_tokens: usize,
     temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OpenAiTool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 struct OpenAiMessage {
     role: String,
-    content: String,
+    /// Empty when the model only calls tools
+    content: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OpenAiToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+impl OpenAiMessage {
+    fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: Some(content.to_string()),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct OpenAiTool {
+    #[serde(rename = "type")]
+    kind: String,
+    function: OpenAiFunction,
+}
+
+#[derive(Clone, Serialize)]
+struct OpenAiFunction {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct OpenAiToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    function: OpenAiFunctionCall,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct OpenAiFunctionCall {
+    name: String,
+    /// JSON object encoded as a string
+    arguments: String,
 }
 
 #[derive(Deserialize)]
 struct OpenAiResponse {
     choices: Vec<OpenAiChoice>,
+    #[serde(default)]
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
 }
 
 #[derive(Deserialize)]
@@ -230,20 +1194,57 @@ struct OpenAiChoice {
 struct OllamaRequest {
     model: String,
     prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
 }
 
 #[derive(Deserialize)]
 struct OllamaResponse {
     response: String,
+    /// Tokens in the prompt; left out when the prompt was cached
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    eval_count: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct OllamaTags {
+    models: Vec<OllamaModel>,
+}
+
+#[derive(Deserialize)]
+struct OllamaModel {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct OllamaPullRequest {
+    model: String,
+    stream: bool,
 }
 
 #[derive(Serialize)]
 struct CustomRequest {
     prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
     model: String,
     max_tokens: usize,
     temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
 }
 
 #[derive(Deserialize)]
@@ -0,0 +1,150 @@
+use crate::config::{Config, LocalLlmConfig};
+use crate::llm::LlmBackend;
+use crate::Error;
+use anyhow::Result;
+use async_trait::async_trait;
+use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::llama_backend::LlamaBackend;
+use llama_cpp_2::llama_batch::LlamaBatch;
+use llama_cpp_2::model::params::LlamaModelParams;
+use llama_cpp_2::model::{AddBos, LlamaChatMessage, LlamaModel, Special};
+use llama_cpp_2::sampling::LlamaSampler;
+use once_cell::sync::OnceCell;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use tracing::{info, instrument};
+
+/// llama.cpp may only be initialised once per process
+static LLAMA_BACKEND: OnceCell<LlamaBackend> = OnceCell::new();
+
+fn llama_backend() -> Result<&'static LlamaBackend> {
+    Ok(LLAMA_BACKEND.get_or_try_init(LlamaBackend::init)?)
+}
+
+/// Answers questions in-process from a GGUF model through llama.cpp, so
+/// LLMdig can run without network access or an Ollama server.
+pub struct LocalBackend {
+    model: Arc<LlamaModel>,
+    config: LocalLlmConfig,
+    max_tokens: usize,
+    temperature: f32,
+    /// Each context holds a full KV cache, so generate one answer at a time
+    busy: Arc<Mutex<()>>,
+}
+
+impl LocalBackend {
+    pub fn new(config: &Config) -> Result<Self> {
+        let local = &config.llm.local;
+        let path = local
+            .model_path
+            .as_ref()
+            .ok_or_else(|| Error::Configuration("llm.local.model_path is not set".to_string()))?;
+
+        let params = LlamaModelParams::default().with_n_gpu_layers(local.gpu_layers);
+        let model = LlamaModel::load_from_file(llama_backend()?, path, &params)
+            .map_err(|e| Error::Configuration(format!("Could not load model {}: {}", path, e)))?;
+        info!("Loaded local model {}", path);
+
+        Ok(Self {
+            model: Arc::new(model),
+            config: local.clone(),
+            max_tokens: config.llm.max_tokens,
+            // Greedy sampling, so the same prompt always gets the same answer
+            temperature: if config.llm.deterministic { 0.0 } else { config.llm.temperature },
+            busy: Arc::new(Mutex::new(())),
+        })
+    }
+}
+
+#[async_trait]
+impl LlmBackend for LocalBackend {
+    #[instrument(name = "llm.local", skip_all)]
+    async fn generate_response(&self, prompt: &str) -> Result<String> {
+        let model = self.model.clone();
+        let config = self.config.clone();
+        let busy = self.busy.clone();
+        let prompt = prompt.to_string();
+        let (max_tokens, temperature) = (self.max_tokens, self.temperature);
+
+        tokio::task::spawn_blocking(move || {
+            let _busy = busy.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            generate(&model, &config, &prompt, max_tokens, temperature)
+        })
+        .await?
+    }
+}
+
+fn generate(
+    model: &LlamaModel,
+    config: &LocalLlmConfig,
+    question: &str,
+    max_tokens: usize,
+    temperature: f32,
+) -> Result<String> {
+    let threads = config
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())) as i32;
+    let params = LlamaContextParams::default()
+        .with_n_ctx(NonZeroU32::new(config.context_size))
+        .with_n_batch(config.context_size)
+        .with_n_threads(threads)
+        .with_n_threads_batch(threads);
+    let mut context = model.new_context(llama_backend()?, params)?;
+
+    let tokens = model.str_to_token(&chat_prompt(model, question), AddBos::Always)?;
+    let context_size = config.context_size as usize;
+    if tokens.len() >= context_size {
+        return Err(Error::InvalidQuery(format!(
+            "Prompt of {} tokens does not fit the {} token context",
+            tokens.len(),
+            context_size
+        ))
+        .into());
+    }
+    let limit = (tokens.len() + max_tokens).min(context_size) as i32;
+
+    let mut batch = LlamaBatch::new(tokens.len(), 1);
+    let last = tokens.len() as i32 - 1;
+    for (position, token) in (0_i32..).zip(tokens) {
+        batch.add(token, position, &[0], position == last)?;
+    }
+    context.decode(&mut batch)?;
+
+    let mut sampler = if temperature <= 0.0 {
+        LlamaSampler::greedy()
+    } else {
+        LlamaSampler::chain_simple([LlamaSampler::temp(temperature), LlamaSampler::dist(rand::random())])
+    };
+
+    // Tokens can end mid-character, so decode the bytes once at the end
+    let mut answer = Vec::new();
+    let mut position = batch.n_tokens();
+    while position < limit {
+        let token = sampler.sample(&context, batch.n_tokens() - 1);
+        sampler.accept(token);
+        if model.is_eog_token(token) {
+            break;
+        }
+        answer.extend(model.token_to_bytes(token, Special::Tokenize)?);
+
+        batch.clear();
+        batch.add(token, position, &[0], true)?;
+        context.decode(&mut batch)?;
+        position += 1;
+    }
+
+    Ok(String::from_utf8_lossy(&answer).trim().to_string())
+}
+
+/// Wrap the question in the model's chat template, or send it as-is when
+/// the model does not ship one
+fn chat_prompt(model: &LlamaModel, question: &str) -> String {
+    model
+        .chat_template(None)
+        .ok()
+        .and_then(|template| {
+            let message = LlamaChatMessage::new("user".to_string(), question.to_string()).ok()?;
+            model.apply_chat_template(&template, &[message], true).ok()
+        })
+        .unwrap_or_else(|| question.to_string())
+}
\ No newline at end of file
@@ -0,0 +1,283 @@
+//! Lua hooks on questions and answers, a lighter alternative to WASM plugins.
+//!
+//! A script defines either or both of these globals:
+//!
+//! - `on_query(question, client_ip)` returns nothing or `true` to keep the
+//!   question, `false` to refuse it, or a string to ask instead
+//! - `on_response(answer)` returns nothing to keep a fresh answer, or a string
+//!   to send instead
+//!
+//! Scripts get only the `string`, `table`, `math` and `utf8` libraries, and each
+//! call runs on an instruction budget.
+
+use crate::config::LuaConfig;
+use crate::dns::Answer;
+use crate::middleware::{Exchange, Middleware, Next, Pipeline, Stage};
+use crate::Error;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use mlua::{Function, HookTriggers, IntoLuaMulti, Lua, LuaOptions, StdLib, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+use trust_dns_proto::op::ResponseCode;
+
+/// Instructions between budget checks
+const BUDGET_STEP: u32 = 1000;
+
+/// What `on_query` decided
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Keep,
+    Rewrite(String),
+    Refuse,
+}
+
+struct Script {
+    name: String,
+    /// Served zone the script applies to, as a lowercase FQDN; every zone when unset
+    zone: Option<String>,
+    lua: Mutex<Lua>,
+    /// Instructions left for the current call
+    budget: Arc<AtomicU64>,
+    instruction_limit: u64,
+}
+
+impl Script {
+    fn load(name: String, zone: Option<String>, source: &str, config: &LuaConfig) -> Result<Self> {
+        let invalid = |e: mlua::Error| Error::Configuration(format!("Invalid Lua hook {}: {}", name, e));
+        let lua = Lua::new_with(
+            StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8,
+            LuaOptions::new(),
+        )
+        .map_err(invalid)?;
+        // The base library is always loaded, including the functions that read files
+        for unsafe_function in ["dofile", "loadfile", "load"] {
+            lua.globals().set(unsafe_function, Value::Nil).map_err(invalid)?;
+        }
+        lua.set_memory_limit(config.max_memory_mb.saturating_mul(1024 * 1024))
+            .map_err(invalid)?;
+
+        let budget = Arc::new(AtomicU64::new(config.instruction_limit));
+        let remaining = budget.clone();
+        lua.set_hook(HookTriggers::new().every_nth_instruction(BUDGET_STEP), move |_, _| {
+            let left = remaining.load(Ordering::Relaxed);
+            if left < u64::from(BUDGET_STEP) {
+                return Err(mlua::Error::RuntimeError("instruction budget exceeded".to_string()));
+            }
+            remaining.store(left - u64::from(BUDGET_STEP), Ordering::Relaxed);
+            Ok(())
+        });
+
+        lua.load(source).set_name(name.as_str()).exec().map_err(invalid)?;
+        Ok(Self {
+            zone: zone.map(|zone| normalize_zone(&zone)),
+            lua: Mutex::new(lua),
+            budget,
+            instruction_limit: config.instruction_limit,
+            name,
+        })
+    }
+
+    fn applies_to(&self, zone: Option<&str>) -> bool {
+        match &self.zone {
+            Some(own) => zone.is_some_and(|zone| zone.eq_ignore_ascii_case(own)),
+            None => true,
+        }
+    }
+
+    /// Call a global function if the script defines it, with a fresh budget
+    fn call<A>(&self, function: &str, args: A) -> Result<Returned>
+    where
+        A: for<'lua> IntoLuaMulti<'lua>,
+    {
+        let lua = self.lua.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(function) = lua.globals().get::<_, Option<Function>>(function)? else {
+            return Ok(Returned::Nothing);
+        };
+        self.budget.store(self.instruction_limit, Ordering::Relaxed);
+        let returned = match function.call::<_, Value>(args)? {
+            Value::Nil => Returned::Nothing,
+            Value::Boolean(value) => Returned::Bool(value),
+            Value::String(text) => Returned::Text(text.to_str()?.to_string()),
+            other => return Err(anyhow!("returned a {}", other.type_name())),
+        };
+        Ok(returned)
+    }
+}
+
+/// What a hook function returned
+#[derive(Debug, PartialEq)]
+enum Returned {
+    Nothing,
+    Bool(bool),
+    Text(String),
+}
+
+/// Lowercase FQDN, the way served zones are compared
+fn normalize_zone(zone: &str) -> String {
+    let zone = zone.trim_end_matches('.').to_lowercase();
+    format!("{}.", zone)
+}
+
+/// The configured scripts
+pub struct LuaHooks {
+    scripts: Vec<Script>,
+}
+
+impl LuaHooks {
+    pub fn new(config: &LuaConfig) -> Result<Self> {
+        let mut scripts = Vec::with_capacity(config.hooks.len());
+        for hook in &config.hooks {
+            let source = std::fs::read_to_string(&hook.script)
+                .map_err(|e| Error::Configuration(format!("Could not read Lua hook {}: {}", hook.script, e)))?;
+            scripts.push(Script::load(hook.script.clone(), hook.zone.clone(), &source, config)?);
+        }
+        Ok(Self { scripts })
+    }
+
+    /// Slot the hooks into `pipeline`: questions after sanitizing, fresh
+    /// answers straight after the backend
+    pub fn install(self, pipeline: &mut Pipeline) {
+        let hooks = Arc::new(self);
+        pipeline.insert_after(Stage::Sanitize, Arc::new(QueryHooks(hooks.clone())));
+        pipeline.insert_after(Stage::Llm, Arc::new(ResponseHooks(hooks)));
+    }
+
+    /// Run `on_query` of the scripts for `zone` in order, each seeing the
+    /// previous rewrite, until one refuses the question. A failing script is
+    /// skipped.
+    fn on_query(&self, zone: Option<&str>, question: &str, client_ip: &str) -> Verdict {
+        let mut verdict = Verdict::Keep;
+        for script in self.scripts.iter().filter(|script| script.applies_to(zone)) {
+            let current = match &verdict {
+                Verdict::Rewrite(text) => text.as_str(),
+                _ => question,
+            };
+            match script.call("on_query", (current, client_ip)) {
+                Ok(Returned::Bool(false)) => {
+                    debug!("Lua hook {} refused a question", script.name);
+                    return Verdict::Refuse;
+                }
+                Ok(Returned::Text(text)) => verdict = Verdict::Rewrite(text),
+                Ok(Returned::Nothing | Returned::Bool(true)) => {}
+                Err(e) => warn!("Lua hook {} failed in on_query, skipping it: {}", script.name, e),
+            }
+        }
+        verdict
+    }
+
+    /// Run `on_response` of the scripts for `zone` in order, each seeing the
+    /// previous one's output
+    fn on_response(&self, zone: Option<&str>, answer: &str) -> Option<String> {
+        let mut transformed: Option<String> = None;
+        for script in self.scripts.iter().filter(|script| script.applies_to(zone)) {
+            let current = transformed.as_deref().unwrap_or(answer);
+            match script.call("on_response", current) {
+                Ok(Returned::Text(text)) => transformed = Some(text),
+                Ok(_) => {}
+                Err(e) => warn!("Lua hook {} failed in on_response, skipping it: {}", script.name, e),
+            }
+        }
+        transformed
+    }
+}
+
+/// Rewrites or refuses questions before the caches see them
+struct QueryHooks(Arc<LuaHooks>);
+
+#[async_trait]
+impl Middleware for QueryHooks {
+    async fn handle(&self, exchange: &mut Exchange<'_>, next: Next<'_>) -> Result<()> {
+        let client_ip = exchange.request.src().ip().to_string();
+        for question in exchange.questions.iter_mut().filter(|question| question.is_pending()) {
+            let Some(text) = question.text.as_deref() else {
+                continue;
+            };
+            match self.0.on_query(question.zone.as_deref(), text, &client_ip) {
+                Verdict::Keep => {}
+                Verdict::Rewrite(text) => question.text = Some(text),
+                Verdict::Refuse => question.answer = Some(Answer::Error(ResponseCode::Refused)),
+            }
+        }
+        next.run(exchange).await
+    }
+}
+
+/// Transforms fresh answers, so the caches keep the transformed text
+struct ResponseHooks(Arc<LuaHooks>);
+
+#[async_trait]
+impl Middleware for ResponseHooks {
+    async fn handle(&self, exchange: &mut Exchange<'_>, next: Next<'_>) -> Result<()> {
+        for question in exchange.questions.iter_mut().filter(|question| question.fresh) {
+            let zone = question.zone.clone();
+            if let Some(Answer::Txt(text)) = &mut question.answer {
+                if let Some(transformed) = self.0.on_response(zone.as_deref(), text) {
+                    *text = transformed;
+                }
+            }
+        }
+        next.run(exchange).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"
+        function on_query(question, client_ip)
+            if question:find("secret") then return false end
+            if client_ip == "192.0.2.1" then return question .. " briefly" end
+        end
+
+        function on_response(answer)
+            return answer:upper()
+        end
+
+        function spin()
+            while true do end
+        end
+    "#;
+
+    fn load_hooks(zone: Option<&str>) -> LuaHooks {
+        let script = Script::load("test.lua".to_string(), zone.map(str::to_string), SCRIPT, &LuaConfig::default());
+        LuaHooks {
+            scripts: vec![script.unwrap()],
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let hooks = load_hooks(None);
+        assert_eq!(hooks.on_query(None, "what is rust", "127.0.0.1"), Verdict::Keep);
+        assert_eq!(hooks.on_query(None, "tell me a secret", "127.0.0.1"), Verdict::Refuse);
+        assert_eq!(
+            hooks.on_query(None, "what is rust", "192.0.2.1"),
+            Verdict::Rewrite("what is rust briefly".to_string())
+        );
+        assert_eq!(hooks.on_response(None, "fast").as_deref(), Some("FAST"));
+
+        // Scripts for one zone leave the others alone
+        let hooks = load_hooks(Some("Q.example.com"));
+        assert_eq!(hooks.on_query(Some("q.example.com."), "a secret", "127.0.0.1"), Verdict::Refuse);
+        assert_eq!(hooks.on_query(Some("other.com."), "a secret", "127.0.0.1"), Verdict::Keep);
+        assert_eq!(hooks.on_query(None, "a secret", "127.0.0.1"), Verdict::Keep);
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let hooks = load_hooks(None);
+        let script = &hooks.scripts[0];
+        let error = script.call("spin", ()).unwrap_err();
+        assert!(error.to_string().contains("instruction budget exceeded"));
+        // The budget is refilled for the next call
+        assert_eq!(script.call("on_response", "again").unwrap(), Returned::Text("AGAIN".to_string()));
+
+        let config = LuaConfig::default();
+        assert!(Script::load("io.lua".to_string(), None, "io.open('/etc/passwd')", &config).is_err());
+        assert!(Script::load("os.lua".to_string(), None, "os.exit(1)", &config).is_err());
+        assert!(Script::load("dofile.lua".to_string(), None, "dofile('/etc/passwd')", &config).is_err());
+    }
+}
\ No newline at end of file
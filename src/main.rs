@@ -1,17 +1,32 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
-use tracing::{error, info, Level};
-use tracing_subscriber::FmtSubscriber;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use tracing::{error, info, warn, Level};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
+use trust_dns_proto::rr::{Name, RecordType};
+use trust_dns_proto::xfer::Protocol;
 
-use llmdig::config::Config;
+use llmdig::config::{Config, ConfigFormat, LogFormat};
+use llmdig::dns::DnsHandler;
+use llmdig::dry_run;
+use llmdig::self_check;
 use llmdig::server::DnsServer;
+use llmdig::service::{self, PidFile};
+use llmdig::telemetry;
+use llmdig::utils::encryption::{CertificateUtils, HashUtils};
+use llmdig::utils::validation::Validator;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Configuration file path
-    #[arg(short, long, default_value = "config.toml")]
+    /// Configuration file path (.toml, .yaml or .json), or `-` to read standard input
+    #[arg(short, long, default_value = "config.toml", global = true)]
     config: String,
 
     /// Log level
@@ -25,49 +40,358 @@ struct Args {
     /// Host to bind the DNS server to
     #[arg(long, default_value = "0.0.0.0")]
     host: String,
+
+    /// Address to listen on, as ip:port; repeat for several. Replaces
+    /// `server.listen`, `--host` and `--port`.
+    #[arg(long)]
+    listen: Vec<String>,
+
+    /// Network interface to listen on, at every address it has and the
+    /// configured port; repeat for several. Replaces `server.listen_interfaces`.
+    #[arg(long)]
+    listen_interface: Vec<String>,
+
+    /// Detach and run in the background (Unix)
+    #[arg(long)]
+    daemon: bool,
+
+    /// Run under the Windows service control manager
+    #[arg(long)]
+    service: bool,
+
+    /// Write the process id to this file while running
+    #[arg(long)]
+    pid_file: Option<PathBuf>,
+
+    /// Write logs to this file instead of standard output
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// Log output format (text or json), overriding `logging.format`
+    #[arg(long)]
+    log_format: Option<LogFormat>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Inspect the configuration without starting the server
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Check that the server could start and answer: validate the
+    /// configuration, ask the LLM backend a test prompt and bind every
+    /// listen address. Prints a PASS/FAIL report and exits with 1 on any
+    /// failure.
+    Check,
+    /// Answer a question in-process, through the same pipeline as a DNS
+    /// query but without binding a socket, and print the exact records a
+    /// resolver would get
+    Query(QueryArgs),
+    /// Generate a self-signed certificate and key for `tls.hostnames` at
+    /// `tls.cert_path` and `tls.key_path`, and print the fingerprints
+    /// clients pin
+    Cert(CertArgs),
+    /// Read a password or token from standard input and print its hash
+    /// under `[password_hashing]`, e.g. for `admin.token_hash`
+    HashPassword,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+#[derive(clap::Args, Debug, Clone)]
+struct QueryArgs {
+    /// The question, as typed; ignored with `--name`
+    #[arg(required_unless_present = "name")]
+    question: Option<String>,
+    /// Zone to ask under; defaults to the first served zone
+    #[arg(long)]
+    zone: Option<String>,
+    /// Query this name as is instead of one made from the question
+    #[arg(long)]
+    name: Option<String>,
+    /// Record type to ask for
+    #[arg(long = "type", default_value = "TXT")]
+    query_type: String,
+    /// Answer as though the query came over TCP rather than UDP
+    #[arg(long)]
+    tcp: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct CertArgs {
+    /// Name to issue the certificate for, a host name or IP address;
+    /// repeat for several. Replaces `tls.hostnames`.
+    #[arg(long)]
+    hostname: Vec<String>,
+    /// Days the certificate is valid for
+    #[arg(long, default_value = "365")]
+    days: u32,
+    /// Replace a certificate or key that already exists
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ConfigCommand {
+    /// Check the configuration, listing every error and warning
+    Validate,
+    /// Print the settings the server would run with, after merging the
+    /// file, environment variables and command line flags
+    PrintEffective {
+        /// Output format (toml, yaml or json)
+        #[arg(long, default_value = "toml")]
+        format: ConfigFormat,
+        /// Hide API keys, tokens and other secrets, e.g. to share the output
+        #[arg(long)]
+        redact: bool,
+    },
+}
+
+fn main() -> Result<()> {
     // Load environment variables from .env file
     dotenv().ok();
 
     // Parse command line arguments
     let args = Args::parse();
 
+    match &args.command {
+        Some(Command::Config { action }) => return run_config_command(&args, action),
+        Some(Command::Check) => return run_check(&args),
+        Some(Command::Query(query)) => return run_query(&args, query),
+        Some(Command::Cert(cert)) => return run_cert(&args, cert),
+        Some(Command::HashPassword) => return run_hash_password(&args),
+        None => {}
+    }
+
+    if args.service {
+        return run_service(args);
+    }
+
+    // Daemonize before the runtime spawns any threads
+    if args.daemon {
+        service::daemonize(args.log_file.as_deref())?;
+    }
+
+    let _pid_file = args.pid_file.as_deref().map(PidFile::create).transpose()?;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(serve(args, std::future::pending()))
+}
+
+fn run_config_command(args: &Args, action: &ConfigCommand) -> Result<()> {
+    let mut config = Config::load(&args.config)?;
+    apply_overrides(&mut config, args);
+
+    match action {
+        ConfigCommand::Validate => {
+            let result = Validator::validate_llmdig_config(&config);
+            for error in &result.errors {
+                println!("error: {}", error);
+            }
+            for warning in &result.warnings {
+                println!("warning: {}", warning);
+            }
+            if !result.is_valid {
+                std::process::exit(1);
+            }
+            println!("Configuration is valid");
+        }
+        ConfigCommand::PrintEffective { format, redact } => {
+            let config = if *redact { config.redacted() } else { config };
+            print!("{}", config.render(*format)?);
+        }
+    }
+    Ok(())
+}
+
+fn run_check(args: &Args) -> Result<()> {
+    let mut config = match Config::load(&args.config) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("FAIL  {:<24} {:#}", "configuration", e);
+            std::process::exit(1);
+        }
+    };
+    apply_overrides(&mut config, args);
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let results = runtime.block_on(self_check::run(&config));
+    for result in &results {
+        println!("{}", result);
+    }
+    let failed = results.iter().filter(|result| !result.passed).count();
+    if failed > 0 {
+        println!("{} of {} checks failed", failed, results.len());
+        std::process::exit(1);
+    }
+    println!("All {} checks passed", results.len());
+    Ok(())
+}
+
+fn run_query(args: &Args, query: &QueryArgs) -> Result<()> {
+    let mut config = Config::load(&args.config)?;
+    apply_overrides(&mut config, args);
+
+    let name = match &query.name {
+        Some(name) => Name::from_str(name)?,
+        None => {
+            let zone = query
+                .zone
+                .as_deref()
+                .or_else(|| config.server.served_zones.first().map(String::as_str))
+                .or_else(|| config.zones.first().map(|zone| zone.name.as_str()));
+            dry_run::question_name(query.question.as_deref().unwrap_or_default(), zone)?
+        }
+    };
+    let query_type = RecordType::from_str(&query.query_type.to_uppercase())?;
+    let protocol = if query.tcp { Protocol::Tcp } else { Protocol::Udp };
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let dry_run = runtime.block_on(async {
+        let handler = DnsHandler::new(config)?;
+        handler.prepare_backend().await?;
+        dry_run::query(&handler, name, query_type, protocol).await
+    })?;
+    print!("{}", dry_run.render());
+    Ok(())
+}
+
+fn run_cert(args: &Args, cert: &CertArgs) -> Result<()> {
+    let config = Config::load(&args.config)?;
+    let hostnames = if cert.hostname.is_empty() { &config.tls.hostnames } else { &cert.hostname };
+    let cert_path = Path::new(&config.tls.cert_path);
+    let key_path = Path::new(&config.tls.key_path);
+    if !cert.force {
+        if let Some(existing) = [cert_path, key_path].into_iter().find(|path| path.exists()) {
+            return Err(llmdig::Error::Configuration(format!(
+                "{} already exists; pass --force to replace it",
+                existing.display()
+            ))
+            .into());
+        }
+    }
+
+    let generated = CertificateUtils::generate_self_signed_cert(hostnames, cert.days)?;
+    generated.write(cert_path, key_path)?;
+    println!("Wrote {} and {} for {}", cert_path.display(), key_path.display(), hostnames.join(", "));
+    println!("SHA-256 fingerprint: {}", generated.fingerprint());
+    println!("SPKI pin (sha256):   {}", generated.spki_pin());
+    Ok(())
+}
+
+fn run_hash_password(args: &Args) -> Result<()> {
+    let config = Config::load(&args.config)?;
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err(llmdig::Error::Configuration("No password given on standard input".to_string()).into());
+    }
+    println!("{}", HashUtils::hash_password(password, &config.password_hashing)?);
+    Ok(())
+}
+
+/// Command line flags take precedence over the file and environment
+fn apply_overrides(config: &mut Config, args: &Args) {
+    if let Some(port) = args.port {
+        config.server.port = port;
+    }
+    config.server.host = args.host.clone();
+    if !args.listen.is_empty() {
+        config.server.listen = args.listen.clone();
+    }
+    if !args.listen_interface.is_empty() {
+        config.server.listen_interfaces = args.listen_interface.clone();
+    }
+}
+
+#[cfg(windows)]
+fn run_service(args: Args) -> Result<()> {
+    service::windows::run_as_service(move |stop| {
+        let _pid_file = args.pid_file.as_deref().map(PidFile::create).transpose()?;
+        let runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(serve(args, async {
+            let _ = stop.await;
+        }))
+    })
+}
+
+#[cfg(not(windows))]
+fn run_service(_args: Args) -> Result<()> {
+    Err(llmdig::Error::Configuration("--service is only supported on Windows; use --daemon instead".to_string()).into())
+}
+
+async fn serve(args: Args, shutdown: impl Future<Output = ()>) -> Result<()> {
+    // Load configuration (before logging so telemetry settings are available)
+    let mut config = Config::load(&args.config)?;
+
     // Initialize logging
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(args.log_level)
+    let writer = match &args.log_file {
+        Some(path) => BoxMakeWriter::new(Mutex::new(service::open_log_file(path)?)),
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
+
+    let fmt_layer = tracing_subscriber::fmt::layer()
         .with_target(false)
         .with_thread_ids(true)
         .with_thread_names(true)
         .with_file(true)
         .with_line_number(true)
+        .with_ansi(args.log_file.is_none())
+        .with_writer(writer);
+
+    // JSON events carry the fields of the request span they happen in,
+    // so every line can be tied to its query
+    let fmt_layer = match args.log_format.unwrap_or(config.logging.format) {
+        LogFormat::Text => fmt_layer.boxed(),
+        LogFormat::Json => fmt_layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::from_level(args.log_level))
+        .with(fmt_layer)
+        .with(telemetry::otel_layer(&config.telemetry)?)
         .init();
 
     info!("Starting LLMdig DNS server...");
 
-    // Load configuration
-    let mut config = Config::load(&args.config)?;
-    
     // Override config with command line arguments
-    if let Some(port) = args.port {
-        config.server.port = port;
+    apply_overrides(&mut config, &args);
+
+    // Refuse to start on settings that cannot work, naming all of them at once
+    for warning in config.validate()? {
+        warn!("Configuration: {}", warning);
     }
-    config.server.host = args.host;
 
-    info!("Configuration loaded: {:?}", config);
+    info!("Configuration loaded: {:?}", config.redacted());
 
     // Create and start DNS server
     let server = DnsServer::new(config)?;
-    
-    info!("DNS server starting on {}:{}", server.host(), server.port());
-    
-    // Run the server
-    if let Err(e) = server.run().await {
-        error!("Server error: {}", e);
-        std::process::exit(1);
+
+    for addr in server.local_addrs() {
+        info!("DNS server starting on {}", addr);
+    }
+
+    // Run the server until it fails or a stop is requested
+    tokio::select! {
+        result = server.run() => {
+            if let Err(e) = result {
+                error!("Server error: {}", e);
+                telemetry::shutdown();
+                std::process::exit(1);
+            }
+        }
+        _ = shutdown => info!("Stop requested, shutting down"),
     }
 
+    telemetry::shutdown();
+
     Ok(())
-} 
\ No newline at end of file
+}
\ No newline at end of file
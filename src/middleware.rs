@@ -0,0 +1,265 @@
+//! The DNS request pipeline as a chain of stages.
+//!
+//! Every message runs through the built-in stages in the order of
+//! [`Stage::ALL`]. Library users can slot their own [`Middleware`] in
+//! between them to inspect or change the exchange on its way in and out,
+//! or to answer it without going any further.
+
+use crate::dns::{Answer, DnsHandler, Provenance, QueryContext};
+use crate::llm::GenerationOptions;
+use crate::utils::tsig::TsigVerification;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use trust_dns_proto::op::{Message, MessageType, ResponseCode};
+use trust_dns_proto::rr::{Name, RecordType};
+use trust_dns_server::server::Request;
+
+/// Built-in stages of the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Refuse clients outside the ACL
+    Acl,
+    /// Check DNS cookies, pushing unproven clients to TCP
+    Cookies,
+    /// Verify TSIG signatures
+    Tsig,
+    /// Turn away operations other than QUERY and, with the honeypot on,
+    /// queries that look like scans or attacks; relay messages LLMdig does
+    /// not answer itself
+    Forward,
+    /// Resolve access tokens and apply rate limits
+    RateLimit,
+    /// Turn query names into questions, answering names that hold none
+    Sanitize,
+    /// Refuse likely prompt injection and guard the system prompt
+    Injection,
+    /// Answer from the negative, exact and semantic caches, and store fresh
+    /// answers once the later stages have run
+    Cache,
+    /// Ask the backend
+    Llm,
+    /// Fit fresh answers into TXT records
+    PostProcess,
+    /// Build the response message from the answers
+    Encode,
+}
+
+impl Stage {
+    /// Every built-in stage, in the order they run
+    pub const ALL: [Stage; 11] = [
+        Stage::Acl,
+        Stage::Cookies,
+        Stage::Tsig,
+        Stage::Forward,
+        Stage::RateLimit,
+        Stage::Sanitize,
+        Stage::Injection,
+        Stage::Cache,
+        Stage::Llm,
+        Stage::PostProcess,
+        Stage::Encode,
+    ];
+}
+
+/// A custom pipeline stage
+#[async_trait::async_trait]
+pub trait Middleware: Send + Sync {
+    /// Handle the exchange, calling `next.run(exchange)` to pass it on to
+    /// the rest of the pipeline. Returning without doing so answers the
+    /// message with whatever `exchange.reply` holds, or SERVFAIL.
+    async fn handle(&self, exchange: &mut Exchange<'_>, next: Next<'_>) -> Result<()>;
+}
+
+#[derive(Clone)]
+enum Link {
+    Builtin(Stage),
+    Custom(Arc<dyn Middleware>),
+}
+
+/// The built-in stages with any custom middleware slotted in
+#[derive(Clone)]
+pub struct Pipeline {
+    links: Vec<Link>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            links: Stage::ALL.into_iter().map(Link::Builtin).collect(),
+        }
+    }
+}
+
+impl Pipeline {
+    /// Run `middleware` just before `stage`
+    pub fn insert_before(&mut self, stage: Stage, middleware: Arc<dyn Middleware>) {
+        let index = self.position(stage);
+        self.links.insert(index, Link::Custom(middleware));
+    }
+
+    /// Run `middleware` just after `stage`, ahead of middleware already
+    /// inserted there
+    pub fn insert_after(&mut self, stage: Stage, middleware: Arc<dyn Middleware>) {
+        let index = self.position(stage) + 1;
+        self.links.insert(index, Link::Custom(middleware));
+    }
+
+    fn position(&self, stage: Stage) -> usize {
+        self.links
+            .iter()
+            .position(|link| matches!(link, Link::Builtin(builtin) if *builtin == stage))
+            .expect("every built-in stage is in the pipeline")
+    }
+
+    pub(crate) fn start<'a>(&'a self, handler: &'a DnsHandler) -> Next<'a> {
+        Next {
+            handler,
+            links: &self.links,
+        }
+    }
+}
+
+/// The rest of the pipeline after the current stage
+pub struct Next<'a> {
+    handler: &'a DnsHandler,
+    links: &'a [Link],
+}
+
+impl<'a> Next<'a> {
+    /// Pass the exchange on. Does nothing at the end of the pipeline.
+    pub fn run<'b, 'r: 'b>(self, exchange: &'b mut Exchange<'r>) -> BoxFuture<'b, Result<()>>
+    where
+        'a: 'b,
+    {
+        Box::pin(async move {
+            let Some((link, rest)) = self.links.split_first() else {
+                return Ok(());
+            };
+            let next = Next {
+                handler: self.handler,
+                links: rest,
+            };
+            match link {
+                Link::Builtin(stage) => self.handler.run_stage(*stage, exchange, next).await,
+                Link::Custom(middleware) => middleware.handle(exchange, next).await,
+            }
+        })
+    }
+}
+
+/// What will be sent back for a message
+#[derive(Debug)]
+pub enum Reply {
+    Message(Message),
+    /// A response relayed unchanged from an upstream server
+    Relayed(Vec<u8>),
+}
+
+/// One DNS message on its way through the pipeline
+pub struct Exchange<'r> {
+    pub request: &'r Request,
+    /// One per question in the message
+    pub questions: Vec<Question>,
+    /// Set by the stage that answers the message
+    pub reply: Option<Reply>,
+    pub(crate) wire: Option<&'r [u8]>,
+    pub(crate) tsig: Option<TsigVerification>,
+    pub(crate) ctx: QueryContext,
+}
+
+impl<'r> Exchange<'r> {
+    pub(crate) fn new(request: &'r Request, wire: Option<&'r [u8]>, tsig: TsigVerification, ctx: QueryContext) -> Self {
+        Self {
+            request,
+            questions: request
+                .queries()
+                .iter()
+                .map(|query| Question::new(query.name().clone(), query.query_type()))
+                .collect(),
+            reply: None,
+            wire,
+            tsig: Some(tsig),
+            ctx,
+        }
+    }
+
+    /// Answer the whole message with an empty response
+    pub fn respond(&mut self, response_code: ResponseCode) {
+        self.reply = Some(Reply::Message(new_response(self.request, response_code)));
+    }
+}
+
+/// One question of a message
+#[derive(Debug, Clone)]
+pub struct Question {
+    /// Query name, without an access token label once rate limiting ran
+    pub name: Name,
+    pub query_type: RecordType,
+    /// Served zone the name lies under
+    pub zone: Option<String>,
+    /// The question asked, once the sanitize stage found one in the name
+    pub text: Option<String>,
+    /// Persona selected by a leading label
+    pub persona: Option<String>,
+    /// Settings the backend is asked with
+    pub generation: GenerationOptions,
+    /// Set by the stage that answers the question; later stages pass over
+    /// answered questions
+    pub answer: Option<Answer>,
+    /// TTL of an answer that goes stale sooner than the configured TTL
+    /// allows
+    pub ttl: Option<u32>,
+    pub(crate) cache_key: String,
+    /// Generation fingerprint the cache key starts with
+    pub(crate) fingerprint: String,
+    /// Embedding to file a fresh answer under in the semantic cache
+    pub(crate) embedding: Option<Vec<f32>>,
+    /// Whether the backend answered during this exchange
+    pub(crate) fresh: bool,
+    /// Where a backend answer came from, fresh or cached
+    pub(crate) provenance: Option<Provenance>,
+}
+
+impl Question {
+    pub fn new(name: Name, query_type: RecordType) -> Self {
+        Self {
+            name,
+            query_type,
+            zone: None,
+            text: None,
+            persona: None,
+            generation: GenerationOptions::default(),
+            answer: None,
+            ttl: None,
+            cache_key: String::new(),
+            fingerprint: String::new(),
+            embedding: None,
+            fresh: false,
+            provenance: None,
+        }
+    }
+
+    /// A question with text that still needs an answer
+    pub fn is_pending(&self) -> bool {
+        self.answer.is_none() && self.text.is_some()
+    }
+}
+
+/// An empty response to `request`
+pub(crate) fn new_response(request: &Request, response_code: ResponseCode) -> Message {
+    let mut response = Message::new();
+
+    response.set_id(request.id());
+    response.set_message_type(MessageType::Response);
+    response.set_op_code(request.op_code());
+    response.set_response_code(response_code);
+    response.set_authoritative(true);
+    response.set_recursion_desired(request.recursion_desired());
+    response.set_recursion_available(false);
+    response.set_authentic_data(false);
+    response.set_checking_disabled(false);
+    response.add_queries(request.queries().iter().cloned());
+
+    response
+}
\ No newline at end of file
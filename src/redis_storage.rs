@@ -0,0 +1,186 @@
+use crate::utils::secret::redact_url;
+use crate::utils::storage::Storage;
+use crate::Error;
+use anyhow::Result;
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::info;
+
+/// Storage in Redis, shared by every instance of a deployment, so a client
+/// banned by one is banned by all and usage adds up across them.
+///
+/// The connection is made on first use and re-established after failures,
+/// so a Redis server that is down only fails the calls made meanwhile.
+pub struct RedisStorage {
+    client: redis::Client,
+    connection: OnceCell<ConnectionManager>,
+    /// Put in front of every key, so deployments can share a server
+    prefix: String,
+}
+
+impl RedisStorage {
+    pub fn new(url: &str, prefix: &str) -> Result<Self> {
+        let client = redis::Client::open(url)
+            .map_err(|e| Error::Configuration(format!("Invalid Redis URL {}: {}", redact_url(url), e)))?;
+        info!("Storage in Redis at {}", redact_url(url));
+        Ok(Self {
+            client,
+            connection: OnceCell::new(),
+            prefix: prefix.to_string(),
+        })
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await?;
+        Ok(connection.clone())
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+/// Refill a token bucket and take a token from it if there is one,
+/// returning 1 when a token was taken. The server's clock is used, so the
+/// clocks of the instances do not matter.
+const TAKE_TOKEN: &str = r#"
+local capacity = tonumber(ARGV[1])
+local refill_rate = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'refilled_at')
+local tokens = tonumber(bucket[1]) or capacity
+local refilled_at = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - refilled_at) * refill_rate)
+local taken = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    taken = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'refilled_at', now)
+redis.call('PEXPIRE', KEYS[1], ARGV[3])
+return taken
+"#;
+
+/// Token buckets in Redis, so that every instance of a deployment takes
+/// from the same bucket for a client
+pub struct RedisRateLimiter {
+    storage: RedisStorage,
+    script: redis::Script,
+    capacity: f64,
+    /// Tokens added per millisecond
+    refill_rate: f64,
+    /// How long an untouched bucket is kept, by when it is full again
+    idle_ms: u64,
+}
+
+impl RedisRateLimiter {
+    pub fn new(url: &str, prefix: &str, requests_per_minute: usize, burst_size: usize) -> Result<Self> {
+        let capacity = burst_size as f64;
+        let refill_rate = requests_per_minute as f64 / 60_000.0;
+        // Buckets that never refill are kept for 10 minutes, like local ones
+        let idle_ms = (capacity / refill_rate).min(600_000.0).ceil() as u64 + 1000;
+        Ok(Self {
+            storage: RedisStorage::new(url, prefix)?,
+            script: redis::Script::new(TAKE_TOKEN),
+            capacity,
+            refill_rate,
+            idle_ms,
+        })
+    }
+
+    /// Take a token from the bucket of `key`, returning whether there was one
+    pub async fn allow_request(&self, key: &str) -> Result<bool> {
+        let mut connection = self.storage.connection().await?;
+        let taken: i64 = self
+            .script
+            .key(self.storage.key(&format!("ratelimit:{}", key)))
+            .arg(self.capacity)
+            .arg(self.refill_rate)
+            .arg(self.idle_ms)
+            .invoke_async(&mut connection)
+            .await?;
+        Ok(taken == 1)
+    }
+}
+
+/// `text` with the characters special to SCAN patterns escaped
+fn escape_pattern(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[async_trait]
+impl Storage for RedisStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.connection().await?.get(self.key(key)).await?)
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()> {
+        let mut connection = self.connection().await?;
+        match ttl {
+            Some(ttl) => {
+                let millis = (ttl.as_millis() as u64).max(1);
+                connection.pset_ex::<_, _, ()>(self.key(key), value, millis).await?
+            }
+            None => connection.set::<_, _, ()>(self.key(key), value).await?,
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool> {
+        let removed: u64 = self.connection().await?.del(self.key(key)).await?;
+        Ok(removed > 0)
+    }
+
+    async fn scan(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut connection = self.connection().await?;
+        let pattern = format!("{}*", escape_pattern(&self.key(prefix)));
+        let mut keys: Vec<String> = Vec::new();
+        {
+            let mut iter = connection.scan_match::<_, String>(pattern).await?;
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+        }
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Keys that expired since the scan come back empty
+        let values: Vec<Option<Vec<u8>>> = connection.mget(&keys).await?;
+        Ok(keys
+            .into_iter()
+            .zip(values)
+            .filter_map(|(key, value)| Some((key.strip_prefix(&self.prefix)?.to_string(), value?)))
+            .collect())
+    }
+
+    async fn increment(&self, key: &str, by: u64) -> Result<u64> {
+        Ok(self.connection().await?.incr(self.key(key), by).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_pattern() {
+        assert_eq!(escape_pattern("llmdig:ban:"), "llmdig:ban:");
+        assert_eq!(escape_pattern("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+        assert!(RedisStorage::new("not a url", "llmdig:").is_err());
+    }
+}
\ No newline at end of file
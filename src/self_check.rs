@@ -0,0 +1,153 @@
+use crate::config::Config;
+use crate::llm::{GenerationOptions, LlmClient};
+use crate::server::{bind_tcp, bind_udp};
+use crate::utils::validation::Validator;
+use anyhow::Result;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// Prompt small enough to cost next to nothing on any backend
+const TEST_PROMPT: &str = "Reply with the single word OK.";
+
+/// One line of the `llmdig check` report
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: String,
+    pub passed: bool,
+    /// What was found, or why the check failed
+    pub detail: String,
+    /// Warnings that do not fail the check
+    pub notes: Vec<String>,
+}
+
+impl CheckResult {
+    fn new(name: impl Into<String>, result: Result<String>) -> Self {
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, format!("{:#}", e)),
+        };
+        Self {
+            name: name.into(),
+            passed,
+            detail,
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.passed { "PASS" } else { "FAIL" };
+        write!(f, "{}  {:<24} {}", status, self.name, self.detail)?;
+        for note in &self.notes {
+            write!(f, "\n      {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that a server could start with `config` and answer questions:
+/// the settings are valid, the LLM backend answers a test prompt and every
+/// address the server listens on can be bound. Every check runs, whatever
+/// the others found.
+pub async fn run(config: &Config) -> Vec<CheckResult> {
+    let mut results = vec![check_config(config)];
+    results.push(CheckResult::new(
+        format!("backend {}", config.llm.backend.name()),
+        check_backend(config).await,
+    ));
+
+    match config.server.listen_addrs() {
+        Ok(addrs) => {
+            for addr in addrs {
+                results.push(CheckResult::new(format!("listen {}", addr), check_dns_listener(addr)));
+            }
+        }
+        Err(e) => results.push(CheckResult::new("listen", Err(e))),
+    }
+    let http = [
+        ("api", config.api.enabled, &config.api.host, config.api.port),
+        ("admin", config.admin.enabled, &config.admin.host, config.admin.port),
+        (
+            "health",
+            config.observability.health_enabled,
+            &config.observability.health_host,
+            config.observability.health_port,
+        ),
+    ];
+    for (name, enabled, host, port) in http {
+        if enabled {
+            let addr = format!("{}:{}", host, port);
+            results.push(CheckResult::new(format!("{} {}", name, addr), check_http_listener(&addr)));
+        }
+    }
+    results
+}
+
+fn check_config(config: &Config) -> CheckResult {
+    let validation = Validator::validate_llmdig_config(config);
+    let mut result = CheckResult::new(
+        "configuration",
+        if validation.is_valid {
+            Ok(format!("{} warnings", validation.warnings.len()))
+        } else {
+            Err(anyhow::anyhow!("{} errors", validation.errors.len()))
+        },
+    );
+    result.notes.extend(validation.errors.iter().map(|error| format!("error: {}", error)));
+    result.notes.extend(validation.warnings.iter().map(|warning| format!("warning: {}", warning)));
+    result
+}
+
+/// Reach the backend, then have it answer the test prompt, which also
+/// proves the credentials and model name are accepted
+async fn check_backend(config: &Config) -> Result<String> {
+    let client = LlmClient::new(config.clone())?;
+    client.check_backend().await?;
+
+    let start = Instant::now();
+    let generation = client.query_detailed(TEST_PROMPT, &GenerationOptions::default()).await?;
+    if generation.text.trim().is_empty() {
+        anyhow::bail!("{} answered the test prompt with nothing", config.llm.model);
+    }
+    Ok(format!("{} answered in {} ms", config.llm.model, start.elapsed().as_millis()))
+}
+
+/// Bind UDP and TCP on `addr` the way the server does, then let go
+fn check_dns_listener(addr: SocketAddr) -> Result<String> {
+    let socket = bind_udp(addr)?;
+    bind_tcp(socket.local_addr()?)?;
+    Ok("UDP and TCP can be bound".to_string())
+}
+
+fn check_http_listener(addr: &str) -> Result<String> {
+    std::net::TcpListener::bind(addr).map_err(|e| anyhow::anyhow!("Could not bind {}: {}", addr, e))?;
+    Ok("can be bound".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LlmBackendType;
+
+    #[tokio::test]
+    async fn test_check() {
+        let mut config = Config::default();
+        config.llm.backend = LlmBackendType::Mock;
+        config.server.listen = vec!["127.0.0.1:0".to_string()];
+        let results = run(&config).await;
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| result.passed), "{:?}", results);
+        assert!(results[1].to_string().starts_with("PASS  backend mock"));
+
+        // An address in use fails its check alone
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        config.admin.enabled = true;
+        config.admin.host = "127.0.0.1".to_string();
+        config.admin.port = taken.local_addr().unwrap().port();
+        let results = run(&config).await;
+        assert_eq!(results.iter().filter(|result| !result.passed).count(), 1);
+        assert!(results[3].detail.contains("Could not bind"));
+    }
+}
\ No newline at end of file
@@ -1,106 +1,720 @@
-use crate::config::Config;
+use crate::acme::{AcmeManager, CertificateStore};
+use crate::admin::{self, AdminToken};
+use crate::api;
+use crate::config::{CacheConfig, Config, HttpTlsConfig, LlmBackendType, RateLimitConfig};
 use crate::dns::DnsHandler;
+use crate::health::{self, HealthState};
+use crate::https::HttpsAcceptor;
+use crate::llm::{BackendRegistry, LlmBackend, LlmClient};
+use crate::middleware::{Middleware, Pipeline, Stage};
+use crate::systemd;
+use crate::utils::local_answers::LocalAnswerer;
+use crate::utils::metrics::Metrics;
+use crate::utils::network::DnsNetworkUtils;
+use crate::utils::secret::SecretString;
+use crate::utils::work_queue::WorkQueue;
 use crate::Error;
 use anyhow::Result;
+use socket2::{Domain, Socket, Type};
+use std::future::Future;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::UdpSocket;
-use tracing::{error, info, warn};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, error, info, warn};
 use trust_dns_proto::op::Message;
 use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable};
+use trust_dns_proto::xfer::Protocol;
 use trust_dns_server::server::{Request, ResponseHandler, ResponseInfo};
 
+/// How long a TCP connection may sit between messages (RFC 7766 §6.2.3)
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct DnsServer {
     config: Config,
     handler: Arc<DnsHandler>,
-    socket: UdpSocket,
+    /// One socket per listen address, all served by the same handler
+    sockets: Vec<Arc<UdpSocket>>,
+    /// DNS over TCP, on the same addresses as `sockets`
+    listeners: Vec<Arc<TcpListener>>,
+    health: Arc<HealthState>,
+    /// Certificate for TLS listeners, renewed in place by ACME
+    certificates: Arc<CertificateStore>,
 }
 
 impl DnsServer {
     pub fn new(config: Config) -> Result<Self> {
-        let handler = Arc::new(DnsHandler::new(config.clone())?);
-        let addr = format!("{}:{}", config.server.host, config.server.port);
-        let socket = UdpSocket::bind(&addr)?;
-        
-        info!("DNS server bound to {}", addr);
+        let handler = DnsHandler::new(config.clone())?;
+        Self::with_handler(config, handler)
+    }
+
+    /// Bind the configured listeners, serving them with `handler`
+    fn with_handler(config: Config, handler: DnsHandler) -> Result<Self> {
+        let handler = Arc::new(handler);
+
+        // Prefer sockets passed in by systemd so that privileged ports can
+        // be used without running as root
+        let inherited = systemd::listen_fds();
+
+        let (sockets, listeners) = if inherited.udp.is_empty() {
+            let mut sockets = Vec::new();
+            let mut listeners = Vec::new();
+            for addr in config.server.listen_addrs()? {
+                let socket = bind_udp(addr)?;
+                // Same port for TCP, even when the OS picked it
+                listeners.push(bind_tcp(socket.local_addr()?)?);
+                sockets.push(socket);
+                info!("DNS server bound to {}", addr);
+            }
+            (sockets, listeners)
+        } else {
+            for socket in &inherited.udp {
+                info!("Using UDP socket {} passed by systemd", socket.local_addr()?);
+            }
+            for listener in &inherited.tcp {
+                info!("Using TCP socket {} passed by systemd", listener.local_addr()?);
+            }
+            if inherited.tcp.is_empty() {
+                warn!("systemd passed no TCP sockets, DNS over TCP is not served");
+            }
+            (inherited.udp, inherited.tcp)
+        };
+
+        let sockets = sockets
+            .into_iter()
+            .map(|socket| {
+                socket.set_nonblocking(true)?;
+                Ok(Arc::new(UdpSocket::from_std(socket)?))
+            })
+            .collect::<Result<_>>()?;
+
+        let listeners = listeners
+            .into_iter()
+            .map(|listener| {
+                listener.set_nonblocking(true)?;
+                Ok(Arc::new(TcpListener::from_std(listener)?))
+            })
+            .collect::<Result<_>>()?;
+
+        let health = Arc::new(HealthState::default());
+        health.set_socket_bound();
+        let certificates = Arc::new(CertificateStore::load(&config.tls));
 
         Ok(Self {
             config,
             handler,
-            socket,
+            sockets,
+            listeners,
+            health,
+            certificates,
         })
     }
 
-    pub fn host(&self) -> &str {
-        &self.config.server.host
+    /// Probe state shared with the `/readyz` endpoint
+    pub fn health(&self) -> Arc<HealthState> {
+        self.health.clone()
     }
 
-    pub fn port(&self) -> u16 {
-        self.config.server.port
+    /// The `[tls]` certificate, replaced whenever ACME renews it
+    pub fn certificates(&self) -> Arc<CertificateStore> {
+        self.certificates.clone()
+    }
+
+    /// Addresses the DNS sockets are bound to
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.sockets.iter().filter_map(|socket| socket.local_addr().ok()).collect()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.handler.metrics()
     }
 
     pub async fn run(&self) -> Result<()> {
-        info!("Starting DNS server on {}:{}", self.host(), self.port());
-        
-        let mut buf = vec![0u8; 512];
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Serve until `shutdown` completes, then stop the listeners and the
+    /// HTTP servers. Connections already accepted finish on their own.
+    pub async fn run_until(&self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        info!("Starting DNS server on {}", format_addrs(&self.local_addrs()));
+
+        self.handler.prepare_backend().await?;
+
+        // Aborted when dropped, so nothing outlives the server
+        let mut tasks = JoinSet::new();
+        self.start_health_probes(&mut tasks).await?;
+        self.start_api(&mut tasks).await?;
+        self.start_admin(&mut tasks).await?;
+        self.start_acme(&mut tasks)?;
+        self.start_retention(&mut tasks);
+        self.start_metering(&mut tasks);
+        self.start_load_control(&mut tasks);
+        self.start_maintenance_signal(&mut tasks)?;
+
+        for listener in &self.listeners {
+            tasks.spawn(Self::accept(listener.clone(), self.handler.clone()));
+        }
+
+        systemd::notify("READY=1");
+        systemd::spawn_watchdog();
+
         let handler = self.handler.clone();
+        let queue = WorkQueue::spawn(
+            self.config.server.queue_size,
+            self.config.server.workers,
+            self.handler.metrics(),
+            move |(data, src, socket): (Vec<u8>, SocketAddr, Arc<UdpSocket>)| {
+                let handler = handler.clone();
+                async move {
+                    if let Err(e) = Self::handle_packet(handler, socket, data, src).await {
+                        error!("Error handling packet from {}: {}", src, e);
+                    }
+                }
+            },
+        );
+
+        // Every socket feeds the same queue and workers
+        let receivers = futures::future::join_all(self.sockets.iter().map(|socket| Self::receive(socket.clone(), &queue)));
+        tokio::select! {
+            _ = receivers => {}
+            _ = shutdown => info!("Stopping DNS server on {}", format_addrs(&self.local_addrs())),
+        }
+        // Usage metered since the last export would otherwise be lost
+        if let Err(e) = self.handler.export_usage().await {
+            error!("Usage export failed: {:#}", e);
+        }
+        Ok(())
+    }
 
+    /// Serve in a background task until the returned handle is shut down
+    /// or dropped
+    pub fn start(self) -> ServerHandle {
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let local_addrs = self.local_addrs();
+        let metrics = self.metrics();
+        let health = self.health();
+        let task = tokio::spawn(async move {
+            self.run_until(async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        ServerHandle {
+            local_addrs,
+            metrics,
+            health,
+            shutdown,
+            task,
+        }
+    }
+
+    async fn receive(socket: Arc<UdpSocket>, queue: &WorkQueue<(Vec<u8>, SocketAddr, Arc<UdpSocket>)>) {
+        let mut buf = vec![0u8; 512];
         loop {
-            match self.socket.recv_from(&mut buf).await {
+            match socket.recv_from(&mut buf).await {
                 Ok((len, src)) => {
+                    if !queue.try_push((buf[..len].to_vec(), src, socket.clone())) {
+                        debug!("Request queue full, dropping packet from {}", src);
+                    }
+                }
+                Err(e) => {
+                    error!("Error receiving packet: {}", e);
+                }
+            }
+        }
+    }
+
+    async fn accept(listener: Arc<TcpListener>, handler: Arc<DnsHandler>) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, src)) => {
                     let handler = handler.clone();
-                    let data = buf[..len].to_vec();
-                    
                     tokio::spawn(async move {
-                        if let Err(e) = Self::handle_packet(handler, data, src).await {
-                            error!("Error handling packet from {}: {}", src, e);
+                        if let Err(e) = Self::serve_connection(handler, stream, src).await {
+                            debug!("Closing TCP connection from {}: {}", src, e);
                         }
                     });
                 }
                 Err(e) => {
-                    error!("Error receiving packet: {}", e);
+                    error!("Error accepting connection: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Answer length-prefixed messages on one connection, in order, until
+    /// the client closes it or leaves it idle
+    async fn serve_connection(handler: Arc<DnsHandler>, stream: TcpStream, src: SocketAddr) -> Result<()> {
+        let (mut reader, writer) = stream.into_split();
+        let writer = Arc::new(Mutex::new(writer));
+
+        loop {
+            let mut length = [0u8; 2];
+            match tokio::time::timeout(TCP_IDLE_TIMEOUT, reader.read_exact(&mut length)).await {
+                Ok(Ok(_)) => {}
+                // Closed by the client, or idle
+                Ok(Err(_)) | Err(_) => return Ok(()),
+            }
+
+            let mut data = vec![0u8; u16::from_be_bytes(length) as usize];
+            tokio::time::timeout(TCP_IDLE_TIMEOUT, reader.read_exact(&mut data)).await??;
+
+            let response_handler = Box::new(TcpResponseHandler { writer: writer.clone() });
+            handle_message(&handler, &data, src, Protocol::Tcp, response_handler).await?;
+        }
+    }
+
+    async fn start_health_probes(&self, tasks: &mut JoinSet<()>) -> Result<()> {
+        let observability = &self.config.observability;
+
+        tasks.spawn(health::wait_for_backend(
+            self.handler.clone(),
+            self.health.clone(),
+            Duration::from_secs(observability.backend_check_interval_seconds.max(1)),
+        ));
+
+        if observability.health_enabled {
+            let addr = format!("{}:{}", observability.health_host, observability.health_port);
+            let listener = TcpListener::bind(&addr).await?;
+            let state = self.health.clone();
+            let metrics = self.handler.metrics();
+
+            tasks.spawn(async move {
+                if let Err(e) = health::serve(listener, state, metrics).await {
+                    error!("Health probe server failed: {}", e);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn start_api(&self, tasks: &mut JoinSet<()>) -> Result<()> {
+        let config = &self.config.api;
+        if !config.enabled {
+            return Ok(());
+        }
+
+        let tls = self.https("api.tls", &config.tls)?;
+        let listener = TcpListener::bind(format!("{}:{}", config.host, config.port)).await?;
+        let handler = self.handler.clone();
+        tasks.spawn(async move {
+            if let Err(e) = api::serve(listener, handler, tls).await {
+                error!("HTTP API server failed: {}", e);
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn start_admin(&self, tasks: &mut JoinSet<()>) -> Result<()> {
+        let config = &self.config.admin;
+        if !config.enabled {
+            return Ok(());
+        }
+
+        let tls = self.https("admin.tls", &config.tls)?;
+        let listener = TcpListener::bind(format!("{}:{}", config.host, config.port)).await?;
+        let handler = self.handler.clone();
+        let token = AdminToken::from_config(config, &self.config.password_hashing)?;
+        tasks.spawn(async move {
+            if let Err(e) = admin::serve(listener, handler, token, tls).await {
+                error!("Admin API server failed: {}", e);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// HTTPS with the `[tls]` certificate for an HTTP listener, or `None`
+    /// when `config` leaves it plain HTTP
+    fn https(&self, setting: &str, config: &HttpTlsConfig) -> Result<Option<HttpsAcceptor>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        // Until ACME has issued one, handshakes fail rather than startup
+        if self.certificates.current().is_none() && !self.config.tls.acme.enabled {
+            return Err(Error::Configuration(format!(
+                "{}: no certificate in {}; create one with `llmdig cert` or turn on [tls.acme]",
+                setting, self.config.tls.cert_path
+            ))
+            .into());
+        }
+        Ok(Some(HttpsAcceptor::new(setting, config, self.certificates.clone())?))
+    }
+
+    /// Keep the `[tls]` certificate issued by the ACME certificate authority
+    fn start_acme(&self, tasks: &mut JoinSet<()>) -> Result<()> {
+        let config = &self.config.tls;
+        if !config.acme.enabled {
+            return Ok(());
+        }
+
+        let manager = AcmeManager::new(config, self.handler.acme_challenges(), self.certificates.clone())?;
+        tasks.spawn(manager.run());
+        Ok(())
+    }
+
+    /// Purge data past the `[retention]` limits now and then
+    fn start_retention(&self, tasks: &mut JoinSet<()>) {
+        let config = &self.config.retention;
+        if !config.enabled {
+            return;
+        }
+
+        let handler = self.handler.clone();
+        let mut interval = tokio::time::interval(Duration::from_secs(config.purge_interval_seconds.max(1)));
+        tasks.spawn(async move {
+            loop {
+                interval.tick().await;
+                handler.purge_expired().await;
+            }
+        });
+    }
+
+    fn start_metering(&self, tasks: &mut JoinSet<()>) {
+        let config = &self.config.metering;
+        if !config.enabled {
+            return;
+        }
+
+        let handler = self.handler.clone();
+        let period = Duration::from_secs(config.interval_seconds.max(1));
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        tasks.spawn(async move {
+            loop {
+                interval.tick().await;
+                match handler.export_usage().await {
+                    Ok(records) => debug!("Exported {} usage records", records),
+                    Err(e) => error!("Usage export failed: {:#}", e),
                 }
             }
+        });
+    }
+
+    fn start_load_control(&self, tasks: &mut JoinSet<()>) {
+        let config = &self.config.llm.downgrade;
+        if !config.enabled {
+            return;
         }
+
+        let handler = self.handler.clone();
+        let mut interval = tokio::time::interval(Duration::from_secs(config.check_interval_seconds.max(1)));
+        tasks.spawn(async move {
+            loop {
+                interval.tick().await;
+                handler.check_load().await;
+            }
+        });
+    }
+
+    /// Enter or leave maintenance mode on SIGUSR1
+    #[cfg(unix)]
+    fn start_maintenance_signal(&self, tasks: &mut JoinSet<()>) -> Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut signals = signal(SignalKind::user_defined1())?;
+        let handler = self.handler.clone();
+        tasks.spawn(async move {
+            while signals.recv().await.is_some() {
+                handler.toggle_maintenance();
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn start_maintenance_signal(&self, _tasks: &mut JoinSet<()>) -> Result<()> {
+        Ok(())
     }
 
     async fn handle_packet(
         handler: Arc<DnsHandler>,
+        socket: Arc<UdpSocket>,
         data: Vec<u8>,
         src: SocketAddr,
     ) -> Result<()> {
-        // Parse DNS message
-        let message = Message::from_bytes(&data)?;
-        
-        // Create request object
-        let request = Request::new(message, src);
-        
-        // Create response handler
-        let response_handler = Box::new(UdpResponseHandler::new(src));
-        
-        // Handle the request
-        let _response_info = handler.handle_request(&request, response_handler).await?;
-        
-        Ok(())
+        // Answer from the socket the query arrived on
+        let response_handler = Box::new(UdpResponseHandler::new(socket, src));
+        handle_datagram(&handler, &data, src, response_handler).await
     }
 }
 
+/// Sets up a [`DnsServer`] for embedding LLMdig in another program. Starts
+/// from the defaults, or from a loaded [`Config`], which the setters change.
+pub struct DnsServerBuilder {
+    config: Config,
+    middleware: Vec<Box<dyn FnOnce(&mut Pipeline) + Send>>,
+    registry: BackendRegistry,
+    backend: Option<(String, Box<dyn LlmBackend>)>,
+    local_answerers: Vec<Box<dyn LocalAnswerer>>,
+}
+
+impl Default for DnsServerBuilder {
+    fn default() -> Self {
+        Self::from_config(Config::default())
+    }
+}
+
+impl DnsServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: Config) -> Self {
+        Self {
+            config,
+            middleware: Vec::new(),
+            registry: BackendRegistry::new(),
+            backend: None,
+            local_answerers: Vec::new(),
+        }
+    }
+
+    pub fn backend(mut self, backend: LlmBackendType) -> Self {
+        self.config.llm.backend = backend;
+        self
+    }
+
+    /// Answer from `backend`, overriding the configured backend and endpoints.
+    /// `name` labels its metrics.
+    pub fn llm_backend(mut self, name: impl Into<String>, backend: Box<dyn LlmBackend>) -> Self {
+        self.backend = Some((name.into(), backend));
+        self
+    }
+
+    /// Make a backend available to the configuration as
+    /// `backend = { registered = "<name>" }`
+    pub fn register_backend<F>(mut self, name: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn(&Config) -> Result<Box<dyn LlmBackend>> + Send + Sync + 'static,
+    {
+        self.registry.register(name, factory);
+        self
+    }
+
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.config.llm.model = model.into();
+        self
+    }
+
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.config.llm.api_key = Some(SecretString::new(api_key.into()));
+        self
+    }
+
+    pub fn cache(mut self, cache: CacheConfig) -> Self {
+        self.config.cache = cache;
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: RateLimitConfig) -> Self {
+        self.config.rate_limit = rate_limit;
+        self
+    }
+
+    /// Listen on `addr` too; port 0 picks a free port. Replaces the
+    /// configured `host` and `port`.
+    pub fn listen(mut self, addr: SocketAddr) -> Self {
+        self.config.server.listen.push(addr.to_string());
+        self
+    }
+
+    /// Run `middleware` just before `stage`
+    pub fn middleware_before(mut self, stage: Stage, middleware: Arc<dyn Middleware>) -> Self {
+        self.middleware
+            .push(Box::new(move |pipeline| pipeline.insert_before(stage, middleware)));
+        self
+    }
+
+    /// Run `middleware` just after `stage`
+    pub fn middleware_after(mut self, stage: Stage, middleware: Arc<dyn Middleware>) -> Self {
+        self.middleware
+            .push(Box::new(move |pipeline| pipeline.insert_after(stage, middleware)));
+        self
+    }
+
+    /// Try `answerer` after the configured local answerers, before the
+    /// cache and the backend
+    pub fn local_answerer(mut self, answerer: Box<dyn LocalAnswerer>) -> Self {
+        self.local_answerers.push(answerer);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Create the handler and bind the listeners
+    pub fn build(self) -> Result<DnsServer> {
+        let llm_client = match self.backend {
+            Some((name, backend)) => LlmClient::from_backend(self.config.clone(), name, backend)?,
+            None => LlmClient::with_registry(self.config.clone(), &self.registry)?,
+        };
+        let mut handler = DnsHandler::with_llm_client(self.config.clone(), llm_client)?;
+        for insert in self.middleware {
+            insert(handler.pipeline_mut());
+        }
+        for answerer in self.local_answerers {
+            handler.local_answers_mut().register(answerer);
+        }
+        DnsServer::with_handler(self.config, handler)
+    }
+
+    /// Build the server and serve in a background task
+    pub fn start(self) -> Result<ServerHandle> {
+        Ok(self.build()?.start())
+    }
+}
+
+/// A server running in the background. Dropping the handle stops it too.
+pub struct ServerHandle {
+    local_addrs: Vec<SocketAddr>,
+    metrics: Arc<Metrics>,
+    health: Arc<HealthState>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl ServerHandle {
+    /// Addresses the DNS sockets are bound to
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    pub fn health(&self) -> Arc<HealthState> {
+        self.health.clone()
+    }
+
+    /// Stop serving and wait for the server to wind down, returning the
+    /// error it failed with, if any
+    pub async fn shutdown(self) -> Result<()> {
+        let _ = self.shutdown.send(());
+        self.task.await?
+    }
+}
+
+/// Answer one DNS message received as a datagram from `src`. Public so the
+/// fuzz targets can feed it arbitrary bytes.
+pub async fn handle_datagram(
+    handler: &DnsHandler,
+    data: &[u8],
+    src: SocketAddr,
+    response_handler: Box<dyn ResponseHandler>,
+) -> Result<()> {
+    handle_message(handler, data, src, Protocol::Udp, response_handler).await
+}
+
+/// Answer one DNS message received from `src` over `protocol`
+async fn handle_message(
+    handler: &DnsHandler,
+    data: &[u8],
+    src: SocketAddr,
+    protocol: Protocol,
+    response_handler: Box<dyn ResponseHandler>,
+) -> Result<()> {
+    // Banned clients get no answer at all
+    if handler.abuse().is_banned(src.ip()).await {
+        debug!("Dropping message from banned {}", src);
+        return Ok(());
+    }
+
+    // Parse DNS message, answering FORMERR if enough of the header survives
+    let message = match Message::from_bytes(data) {
+        Ok(message) => message,
+        Err(e) => {
+            let recent = handler.record_malformed(src).await;
+            debug!("Malformed packet from {} ({} recently): {}", src, recent, e);
+            if let Some(response) = DnsNetworkUtils::format_error_response(data) {
+                response_handler.send_response(response).await?;
+            }
+            return Ok(());
+        }
+    };
+    
+    // Create request object
+    let request = Request::new(message, src, protocol);
+    
+    // Handle the request
+    let _response_info = handler.handle_wire_request(&request, data, response_handler).await?;
+    
+    Ok(())
+}
+
+/// Bind a UDP socket. IPv6 sockets only take IPv6 traffic, so `0.0.0.0`
+/// and `[::]` can listen on the same port side by side.
+pub(crate) fn bind_udp(addr: SocketAddr) -> Result<std::net::UdpSocket> {
+    let bind = || -> std::io::Result<std::net::UdpSocket> {
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(socket2::Protocol::UDP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        socket.bind(&addr.into())?;
+        Ok(socket.into())
+    };
+    bind().map_err(|e| Error::Network(format!("Could not bind {}: {}", addr, e)).into())
+}
+
+/// Bind a TCP listener, IPv6-only like the UDP sockets
+pub(crate) fn bind_tcp(addr: SocketAddr) -> Result<std::net::TcpListener> {
+    let bind = || -> std::io::Result<std::net::TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(socket2::Protocol::TCP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        Ok(socket.into())
+    };
+    bind().map_err(|e| Error::Network(format!("Could not bind {} for TCP: {}", addr, e)).into())
+}
+
+fn format_addrs(addrs: &[SocketAddr]) -> String {
+    addrs.iter().map(SocketAddr::to_string).collect::<Vec<_>>().join(", ")
+}
+
 struct UdpResponseHandler {
+    socket: Arc<UdpSocket>,
     addr: SocketAddr,
 }
 
 impl UdpResponseHandler {
-    fn new(addr: SocketAddr) -> Self {
-        Self { addr }
+    fn new(socket: Arc<UdpSocket>, addr: SocketAddr) -> Self {
+        Self { socket, addr }
     }
 }
 
 #[async_trait::async_trait]
 impl ResponseHandler for UdpResponseHandler {
     async fn send_response(&self, response_bytes: Vec<u8>) -> Result<(), std::io::Error> {
-        // For now, we'll just log the response
-        // In a real implementation, you'd send it back via UDP
-        info!("Would send {} bytes to {}", response_bytes.len(), self.addr);
+        self.socket.send_to(&response_bytes, self.addr).await?;
         Ok(())
     }
-} 
\ No newline at end of file
+}
+
+/// Writes responses to a TCP connection, each prefixed with its length
+struct TcpResponseHandler {
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+}
+
+#[async_trait::async_trait]
+impl ResponseHandler for TcpResponseHandler {
+    async fn send_response(&self, response_bytes: Vec<u8>) -> Result<(), std::io::Error> {
+        let length = u16::try_from(response_bytes.len())
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "response too long for TCP"))?;
+        let mut framed = Vec::with_capacity(response_bytes.len() + 2);
+        framed.extend_from_slice(&length.to_be_bytes());
+        framed.extend_from_slice(&response_bytes);
+        self.writer.lock().await.write_all(&framed).await
+    }
+}
\ No newline at end of file
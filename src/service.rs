@@ -0,0 +1,209 @@
+//! Running LLMdig as a background service: Unix daemonization, PID files and
+//! the Windows service control manager.
+
+use crate::Error;
+use anyhow::Result;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Open a log file for appending, creating its directory if needed
+pub fn open_log_file(path: &Path) -> Result<fs::File> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+/// Detach from the controlling terminal and continue in the background.
+///
+/// Must be called before the Tokio runtime or any other thread is started,
+/// since only the calling thread survives the fork. Standard output and error
+/// are redirected to `log_file`, or discarded when none is given.
+#[cfg(unix)]
+pub fn daemonize(log_file: Option<&Path>) -> Result<()> {
+    let mut daemon = daemonize::Daemonize::new().working_directory(std::env::current_dir()?);
+
+    if let Some(path) = log_file {
+        let stdout = open_log_file(path)?;
+        let stderr = stdout.try_clone()?;
+        daemon = daemon.stdout(stdout).stderr(stderr);
+    }
+
+    daemon
+        .start()
+        .map_err(|e| Error::Configuration(format!("Failed to daemonize: {}", e)))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_log_file: Option<&Path>) -> Result<()> {
+    Err(Error::Configuration("--daemon is only supported on Unix; use --service on Windows".to_string()).into())
+}
+
+/// A PID file that is removed again when dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the current process id to `path`, refusing to start if the file
+    /// belongs to a process that is still running
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Ok(contents) = fs::read_to_string(path) {
+            match contents.trim().parse::<u32>() {
+                Ok(pid) if process_alive(pid) => {
+                    return Err(Error::Configuration(format!(
+                        "PID file {} belongs to running process {}",
+                        path.display(),
+                        pid
+                    ))
+                    .into());
+                }
+                _ => warn!("Replacing stale PID file {}", path.display()),
+            }
+        }
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, format!("{}\n", std::process::id()))?;
+
+        Ok(Self { path: path.to_path_buf() })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Could not remove PID file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+// Without a cheap portable check, assume the old process is gone
+#[cfg(not(target_os = "linux"))]
+fn process_alive(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(windows)]
+pub mod windows {
+    //! Windows service wrapper. Install with e.g.
+    //! `sc.exe create llmdig binPath= "C:\llmdig\llmdig.exe --service --config C:\llmdig\config.toml"`.
+
+    use anyhow::Result;
+    use std::ffi::OsString;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+    use tracing::error;
+    use windows_service::service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    pub const SERVICE_NAME: &str = "llmdig";
+
+    type ServiceBody = Box<dyn FnOnce(oneshot::Receiver<()>) -> Result<()> + Send>;
+
+    static BODY: Mutex<Option<ServiceBody>> = Mutex::new(None);
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Hand control to the service control manager. `body` runs the server
+    /// and must return once the receiver fires, which happens on Stop.
+    pub fn run_as_service(body: impl FnOnce(oneshot::Receiver<()>) -> Result<()> + Send + 'static) -> Result<()> {
+        *BODY.lock().unwrap() = Some(Box::new(body));
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+        Ok(())
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            error!("Windows service failed: {}", e);
+        }
+    }
+
+    fn run_service() -> Result<()> {
+        let body = BODY
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| crate::Error::Configuration("Service started twice".to_string()))?;
+
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let stop_tx = Mutex::new(Some(stop_tx));
+
+        let status_handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                if let Some(stop_tx) = stop_tx.lock().unwrap().take() {
+                    let _ = stop_tx.send(());
+                }
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+
+        let status = |state, controls_accepted, exit_code| ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        };
+
+        status_handle.set_service_status(status(
+            ServiceState::Running,
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            ServiceExitCode::Win32(0),
+        ))?;
+
+        let result = body(stop_rx);
+        let exit_code = if result.is_ok() { 0 } else { 1 };
+
+        status_handle.set_service_status(status(
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            ServiceExitCode::Win32(exit_code),
+        ))?;
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file_lifecycle() {
+        let path = std::env::temp_dir().join(format!("llmdig-test-{}.pid", std::process::id()));
+
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().trim(), std::process::id().to_string());
+
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pid_file_refuses_running_process() {
+        let path = std::env::temp_dir().join(format!("llmdig-live-{}.pid", std::process::id()));
+        fs::write(&path, std::process::id().to_string()).unwrap();
+
+        assert!(PidFile::create(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,134 @@
+use crate::utils::storage::{parse_counter, Storage};
+use crate::Error;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Storage in an embedded sled database, so a single instance keeps its
+/// bans and usage counters across restarts.
+///
+/// Each value is stored after its expiry time, as big-endian Unix
+/// milliseconds or 0 for none.
+pub struct SledStorage {
+    db: sled::Db,
+}
+
+impl SledStorage {
+    pub fn open(path: &str) -> Result<Self> {
+        let db = sled::open(path)
+            .map_err(|e| Error::Configuration(format!("Could not open sled database {}: {}", path, e)))?;
+        info!("Storage in sled database {}", path);
+        Ok(Self { db })
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn encode(value: &[u8], expires_at: u64) -> Vec<u8> {
+    let mut stored = expires_at.to_be_bytes().to_vec();
+    stored.extend_from_slice(value);
+    stored
+}
+
+/// The value and expiry time of a stored entry, or `None` once it expired
+fn decode(stored: &[u8], now: u64) -> Option<(&[u8], u64)> {
+    let (expires_at, value) = stored.split_at_checked(8)?;
+    let expires_at = u64::from_be_bytes(expires_at.try_into().ok()?);
+    (expires_at == 0 || expires_at > now).then_some((value, expires_at))
+}
+
+#[async_trait]
+impl Storage for SledStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let stored = self.db.get(key)?;
+        Ok(stored.and_then(|stored| decode(&stored, now_millis()).map(|(value, _)| value.to_vec())))
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()> {
+        let expires_at = ttl.map_or(0, |ttl| now_millis() + (ttl.as_millis() as u64).max(1));
+        self.db.insert(key, encode(&value, expires_at))?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool> {
+        let removed = self.db.remove(key)?;
+        Ok(removed.is_some_and(|stored| decode(&stored, now_millis()).is_some()))
+    }
+
+    async fn scan(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let now = now_millis();
+        let mut found = Vec::new();
+        for item in self.db.scan_prefix(prefix) {
+            let (key, stored) = item?;
+            match decode(&stored, now) {
+                Some((value, _)) => found.push((String::from_utf8_lossy(&key).into_owned(), value.to_vec())),
+                // Nothing else removes expired entries; one stored again
+                // since is left alone
+                None => {
+                    let _ = self.db.compare_and_swap(&key, Some(&stored), None as Option<&[u8]>)?;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    async fn increment(&self, key: &str, by: u64) -> Result<u64> {
+        let now = now_millis();
+        let mut invalid = None;
+        let updated = self.db.update_and_fetch(key, |stored| {
+            invalid = None;
+            let (count, expires_at) = match stored.and_then(|stored| decode(stored, now)) {
+                Some((value, expires_at)) => match parse_counter(value) {
+                    Ok(count) => (count, expires_at),
+                    Err(e) => {
+                        invalid = Some(e);
+                        return stored.map(<[u8]>::to_vec);
+                    }
+                },
+                None => (0, 0),
+            };
+            Some(encode((count + by).to_string().as_bytes(), expires_at))
+        })?;
+
+        if let Some(e) = invalid {
+            return Err(e);
+        }
+        let updated = updated.ok_or_else(|| anyhow!("Counter {} vanished", key))?;
+        let (value, _) = decode(&updated, now).ok_or_else(|| anyhow!("Counter {} expired", key))?;
+        parse_counter(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_sled_storage() {
+        let path = std::env::temp_dir().join(format!("llmdig-sled-{}", std::process::id()));
+        let storage: Arc<dyn Storage> = Arc::new(SledStorage::open(&path.to_string_lossy()).unwrap());
+
+        storage.set("ban:192.0.2.1", b"one".to_vec(), None).await.unwrap();
+        storage.set("ban:192.0.2.2", b"two".to_vec(), Some(Duration::from_millis(20))).await.unwrap();
+        assert_eq!(storage.get("ban:192.0.2.2").await.unwrap(), Some(b"two".to_vec()));
+        assert_eq!(storage.increment("usage:key:a", 2).await.unwrap(), 2);
+        assert_eq!(storage.increment("usage:key:a", 3).await.unwrap(), 5);
+        assert!(storage.increment("ban:192.0.2.1", 1).await.is_err());
+        tokio::time::sleep(Duration::from_millis(40)).await;
+
+        assert_eq!(storage.get("ban:192.0.2.2").await.unwrap(), None);
+        assert_eq!(storage.scan("ban:").await.unwrap(), vec![("ban:192.0.2.1".to_string(), b"one".to_vec())]);
+        assert!(storage.delete("ban:192.0.2.1").await.unwrap());
+        assert_eq!(storage.counter("usage:key:a").await.unwrap(), 5);
+
+        drop(storage);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,164 @@
+//! systemd integration: socket activation (`LISTEN_FDS`) and `sd_notify`.
+//!
+//! Everything here is a no-op when the process was not started by systemd,
+//! so callers do not need to check for it themselves.
+
+use std::env;
+use std::net::{TcpListener, UdpSocket};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Sockets handed over by systemd, split by type
+#[derive(Debug, Default)]
+pub struct InheritedSockets {
+    pub udp: Vec<UdpSocket>,
+    pub tcp: Vec<TcpListener>,
+}
+
+impl InheritedSockets {
+    pub fn is_empty(&self) -> bool {
+        self.udp.is_empty() && self.tcp.is_empty()
+    }
+}
+
+/// Take ownership of the sockets passed via `LISTEN_FDS`.
+///
+/// The environment variables are removed afterwards so that child processes
+/// do not try to claim the same descriptors.
+#[cfg(unix)]
+pub fn listen_fds() -> InheritedSockets {
+    use socket2::{Socket, Type};
+    use std::os::fd::{FromRawFd, RawFd};
+
+    /// First descriptor passed by systemd (`SD_LISTEN_FDS_START`)
+    const LISTEN_FDS_START: RawFd = 3;
+
+    let mut sockets = InheritedSockets::default();
+
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .map_or(false, |pid| pid == std::process::id());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .unwrap_or(0);
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    if !for_us || count <= 0 {
+        return sockets;
+    }
+
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        // SAFETY: systemd guarantees these descriptors are open and ours
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        match socket.r#type() {
+            Ok(Type::DGRAM) => sockets.udp.push(socket.into()),
+            Ok(Type::STREAM) => sockets.tcp.push(socket.into()),
+            Ok(other) => warn!("Ignoring inherited fd {} of unsupported type {:?}", fd, other),
+            Err(e) => warn!("Ignoring inherited fd {}: {}", fd, e),
+        }
+    }
+
+    debug!(
+        "Inherited {} UDP and {} TCP sockets from systemd",
+        sockets.udp.len(),
+        sockets.tcp.len()
+    );
+    sockets
+}
+
+#[cfg(not(unix))]
+pub fn listen_fds() -> InheritedSockets {
+    InheritedSockets::default()
+}
+
+/// Send a state string such as `READY=1` to the service manager, if any
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        let path = path.to_string_lossy();
+        match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                socket.send_to_addr(state.as_bytes(), &addr)
+            }
+            _ => socket.send_to(state.as_bytes(), path.as_ref()),
+        }
+    });
+
+    if let Err(e) = result {
+        warn!("sd_notify({}) failed: {}", state, e);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+/// Interval at which `WATCHDOG=1` must be sent, when the unit has
+/// `WatchdogSec=` set. Pings are sent at half the configured timeout.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Some(pid) = env::var("WATCHDOG_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) {
+        if pid != std::process::id() {
+            return None;
+        }
+    }
+
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Ping the watchdog for as long as the runtime keeps scheduling us
+pub fn spawn_watchdog() {
+    if let Some(interval) = watchdog_interval() {
+        debug!("systemd watchdog enabled, pinging every {:?}", interval);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                notify("WATCHDOG=1");
+            }
+        });
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    // Both tests touch process-wide environment variables, so they run as one
+    #[test]
+    fn test_notify_and_listen_fds_without_systemd() {
+        let dir = std::env::temp_dir().join(format!("llmdig-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&dir);
+        let receiver = UnixDatagram::bind(&dir).unwrap();
+
+        env::set_var("NOTIFY_SOCKET", &dir);
+        notify("READY=1");
+        env::remove_var("NOTIFY_SOCKET");
+
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        std::fs::remove_file(&dir).unwrap();
+
+        // Descriptors meant for another process are never claimed
+        env::set_var("LISTEN_PID", "1");
+        env::set_var("LISTEN_FDS", "2");
+        assert!(listen_fds().is_empty());
+        assert!(env::var("LISTEN_FDS").is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,50 @@
+use crate::config::TelemetryConfig;
+use anyhow::Result;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{self, Sampler, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Build the OTLP export layer, or `None` when telemetry is disabled.
+///
+/// Must be called from within a Tokio runtime because the batch exporter
+/// spawns its own background task.
+pub fn otel_layer<S>(config: &TelemetryConfig) -> Result<Option<OpenTelemetryLayer<S, Tracer>>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+        config.sampling_rate.clamp(0.0, 1.0),
+    )));
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&config.otlp_endpoint),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_sampler(sampler)
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    config.service_name.clone(),
+                )])),
+        )
+        .install_batch(runtime::Tokio)?;
+
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Flush any pending spans before the process exits
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
\ No newline at end of file
@@ -0,0 +1,365 @@
+use crate::config::AbuseConfig;
+use crate::utils::retention::PurgeFilter;
+use crate::utils::shard::{Sharded, DEFAULT_SHARDS};
+use crate::utils::storage::{self, Storage};
+use crate::Error;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::OnceCell;
+use tracing::{error, info, warn};
+
+/// Kinds of client misbehaviour worth counting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Offence {
+    /// A packet that could not be parsed as a DNS message
+    #[serde(rename = "malformed")]
+    Malformed,
+    /// A query turned away by a rate limit
+    #[serde(rename = "rate_limited")]
+    RateLimited,
+    /// A query that was refused or rejected as invalid
+    #[serde(rename = "error")]
+    Error,
+}
+
+/// Counts offences per client address over a sliding window, so repeat
+/// offenders can be told apart from the odd corrupted packet.
+pub struct AbuseTracker {
+    window: Duration,
+    offences: Sharded<HashMap<(IpAddr, Offence), VecDeque<Instant>>>,
+}
+
+impl AbuseTracker {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            offences: Sharded::new(DEFAULT_SHARDS, HashMap::new),
+        }
+    }
+
+    /// Record an offence by `ip`, returning how many of that kind it has
+    /// committed within the window, this one included
+    pub async fn record(&self, ip: IpAddr, offence: Offence) -> u64 {
+        let now = Instant::now();
+        let mut shard = self.offences.shard(&ip).write().await;
+
+        // Drop stale entries of every client in the shard, so addresses that
+        // went quiet do not linger
+        shard.retain(|_, times| {
+            Self::expire(times, now, self.window);
+            !times.is_empty()
+        });
+
+        let times = shard.entry((ip, offence)).or_default();
+        times.push_back(now);
+        times.len() as u64
+    }
+
+    /// Offences of one kind by `ip` within the window
+    pub async fn count(&self, ip: IpAddr, offence: Offence) -> u64 {
+        let now = Instant::now();
+        let shard = self.offences.shard(&ip).read().await;
+        shard
+            .get(&(ip, offence))
+            .map(|times| times.iter().filter(|time| now.duration_since(**time) < self.window).count() as u64)
+            .unwrap_or(0)
+    }
+
+    /// Drop the offence records `filter` matches, returning how many went
+    pub async fn forget(&self, filter: &PurgeFilter) -> usize {
+        let now = Instant::now();
+        let mut removed = 0;
+        for shard in self.offences.iter() {
+            shard.write().await.retain(|(ip, _), times| {
+                let before = times.len();
+                times.retain(|time| !filter.matches(Some(*ip), now.duration_since(*time)));
+                removed += before - times.len();
+                !times.is_empty()
+            });
+        }
+        removed
+    }
+
+    fn expire(times: &mut VecDeque<Instant>, now: Instant, window: Duration) {
+        while times.front().is_some_and(|time| now.duration_since(*time) >= window) {
+            times.pop_front();
+        }
+    }
+}
+
+/// A client that is refused service until `until`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ban {
+    pub ip: IpAddr,
+    /// The offence that tipped it over
+    pub reason: Offence,
+    /// Unix time in seconds
+    pub until: u64,
+}
+
+/// Keys of bans in storage
+const BAN_PREFIX: &str = "ban:";
+
+/// Bans clients whose offences of one kind pass a threshold within the
+/// tracker's window. Offences are counted even with bans disabled.
+///
+/// Bans live in storage, so instances sharing it ban together. Offences are
+/// counted per instance.
+pub struct AbuseDetector {
+    tracker: AbuseTracker,
+    config: AbuseConfig,
+    storage: Arc<dyn Storage>,
+    /// Bans read from `bans_file`, put into storage on first use
+    saved: Vec<Ban>,
+    loaded: OnceCell<()>,
+}
+
+impl AbuseDetector {
+    /// Read bans from `bans_file`, if any, dropping those that have expired
+    pub fn new(config: &AbuseConfig, storage: Arc<dyn Storage>) -> Result<Self> {
+        let mut saved = Vec::new();
+        if let Some(path) = &config.bans_file {
+            match std::fs::read_to_string(path) {
+                Ok(contents) => {
+                    let bans: Vec<Ban> = serde_json::from_str(&contents)
+                        .map_err(|e| Error::Configuration(format!("Invalid bans file {}: {}", path, e)))?;
+                    let now = unix_now();
+                    saved.extend(bans.into_iter().filter(|ban| ban.until > now));
+                    info!("Loaded {} bans from {}", saved.len(), path);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(Error::Configuration(format!("Could not read {}: {}", path, e)).into()),
+            }
+        }
+
+        Ok(Self {
+            tracker: AbuseTracker::new(Duration::from_secs(config.window_seconds.max(1))),
+            config: config.clone(),
+            storage,
+            saved,
+            loaded: OnceCell::new(),
+        })
+    }
+
+    /// Put the bans from `bans_file` into storage, once
+    async fn load(&self) {
+        self.loaded
+            .get_or_init(|| async {
+                for ban in &self.saved {
+                    self.store(ban).await;
+                }
+            })
+            .await;
+    }
+
+    async fn store(&self, ban: &Ban) {
+        let ttl = Duration::from_secs(ban.until.saturating_sub(unix_now()));
+        let key = format!("{}{}", BAN_PREFIX, ban.ip);
+        storage::or_default(self.storage.set_json(&key, ban, Some(ttl)).await, "store a ban");
+    }
+
+    /// Record an offence by `ip`, banning it if that was one too many.
+    /// Returns how many of that kind it has committed within the window.
+    pub async fn record(&self, ip: IpAddr, offence: Offence) -> u64 {
+        let count = self.tracker.record(ip, offence).await;
+
+        let limit = match offence {
+            Offence::Malformed => self.config.max_malformed,
+            Offence::RateLimited => self.config.max_rate_limited,
+            Offence::Error => self.config.max_errors,
+        };
+        if self.config.enabled && limit > 0 && count >= limit && !self.is_banned(ip).await {
+            warn!("Banning {} for {}s after {} {:?} offences", ip, self.config.ban_seconds, count, offence);
+            let ban = Ban {
+                ip,
+                reason: offence,
+                until: unix_now() + self.config.ban_seconds,
+            };
+            self.store(&ban).await;
+            self.save().await;
+        }
+
+        count
+    }
+
+    /// Whether `ip` is banned. Clients are let in when storage fails.
+    pub async fn is_banned(&self, ip: IpAddr) -> bool {
+        self.ban(ip).await.is_some()
+    }
+
+    /// The ban in force on `ip`, if any
+    pub async fn ban(&self, ip: IpAddr) -> Option<Ban> {
+        self.load().await;
+        let ban = self.storage.get_json::<Ban>(&format!("{}{}", BAN_PREFIX, ip)).await;
+        storage::or_default(ban, "look up a ban").filter(|ban| ban.until > unix_now())
+    }
+
+    /// Bans in force, soonest to expire first
+    pub async fn bans(&self) -> Vec<Ban> {
+        self.load().await;
+        let now = unix_now();
+        let stored = storage::or_default(self.storage.scan(BAN_PREFIX).await, "list bans");
+        let mut bans: Vec<Ban> = stored
+            .into_iter()
+            .filter_map(|(_, value)| serde_json::from_slice::<Ban>(&value).ok())
+            .filter(|ban| ban.until > now)
+            .collect();
+        bans.sort_by_key(|ban| (ban.until, ban.ip));
+        bans
+    }
+
+    /// Lift a ban, returning whether there was one
+    pub async fn unban(&self, ip: IpAddr) -> bool {
+        self.load().await;
+        let lifted = storage::or_default(self.storage.delete(&format!("{}{}", BAN_PREFIX, ip)).await, "lift a ban");
+        if lifted {
+            info!("Lifted ban on {}", ip);
+            self.save().await;
+        }
+        lifted
+    }
+
+    /// Drop the offence records `filter` matches. Bans stay in force until
+    /// they expire or are lifted.
+    pub async fn forget(&self, filter: &PurgeFilter) -> usize {
+        self.tracker.forget(filter).await
+    }
+
+    /// Write the bans in force to `bans_file`, replacing it in one step
+    async fn save(&self) {
+        let Some(path) = &self.config.bans_file else {
+            return;
+        };
+
+        let active = self.bans().await;
+        let temp = PathBuf::from(format!("{}.tmp", path));
+        let result = async {
+            tokio::fs::write(&temp, serde_json::to_vec_pretty(&active)?).await?;
+            tokio::fs::rename(&temp, path).await?;
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+        if let Err(e) = result {
+            error!("Could not save bans to {}: {}", path, e);
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn test_counts_per_address() {
+        let tracker = AbuseTracker::new(Duration::from_secs(60));
+        let first: IpAddr = "192.0.2.1".parse().unwrap();
+        let second: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert_eq!(tracker.record(first, Offence::Malformed).await, 1);
+        assert_eq!(tracker.record(first, Offence::Malformed).await, 2);
+        assert_eq!(tracker.record(second, Offence::Malformed).await, 1);
+
+        assert_eq!(tracker.count(first, Offence::Malformed).await, 2);
+        assert_eq!(tracker.count("192.0.2.3".parse().unwrap(), Offence::Malformed).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_window_expiry() {
+        let tracker = AbuseTracker::new(Duration::from_millis(50));
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+
+        tracker.record(ip, Offence::Malformed).await;
+        tracker.record(ip, Offence::Malformed).await;
+        tokio::time::sleep(Duration::from_millis(80)).await;
+
+        assert_eq!(tracker.count(ip, Offence::Malformed).await, 0);
+        assert_eq!(tracker.record(ip, Offence::Malformed).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_forget() {
+        let tracker = AbuseTracker::new(Duration::from_secs(60));
+        let first: IpAddr = "192.0.2.1".parse().unwrap();
+        let second: IpAddr = "192.0.2.2".parse().unwrap();
+        tracker.record(first, Offence::Malformed).await;
+        tracker.record(first, Offence::Error).await;
+        tracker.record(second, Offence::Malformed).await;
+
+        assert_eq!(tracker.forget(&PurgeFilter::client(first)).await, 2);
+        assert_eq!(tracker.count(first, Offence::Malformed).await, 0);
+        assert_eq!(tracker.count(second, Offence::Malformed).await, 1);
+
+        let everything = PurgeFilter {
+            max_age: Some(Duration::ZERO),
+            client: None,
+        };
+        assert_eq!(tracker.forget(&everything).await, 1);
+        assert_eq!(tracker.count(second, Offence::Malformed).await, 0);
+    }
+
+    fn ban_config(bans_file: Option<String>) -> AbuseConfig {
+        AbuseConfig {
+            enabled: true,
+            max_malformed: 2,
+            max_errors: 0,
+            bans_file,
+            ..Default::default()
+        }
+    }
+
+    fn memory() -> Arc<dyn Storage> {
+        Arc::new(MemoryStorage::default())
+    }
+
+    #[tokio::test]
+    async fn test_ban_and_unban() {
+        let storage = memory();
+        let detector = AbuseDetector::new(&ban_config(None), storage.clone()).unwrap();
+        let ip: IpAddr = "198.51.100.7".parse().unwrap();
+
+        detector.record(ip, Offence::Malformed).await;
+        assert!(!detector.is_banned(ip).await);
+        detector.record(ip, Offence::Malformed).await;
+        assert!(detector.is_banned(ip).await);
+        assert_eq!(detector.bans().await[0].reason, Offence::Malformed);
+        // Instances sharing storage share bans
+        let other_instance = AbuseDetector::new(&ban_config(None), storage).unwrap();
+        assert!(other_instance.is_banned(ip).await);
+
+        // A limit of 0 never bans
+        let other: IpAddr = "198.51.100.8".parse().unwrap();
+        for _ in 0..10 {
+            detector.record(other, Offence::Error).await;
+        }
+        assert!(!detector.is_banned(other).await);
+
+        assert!(detector.unban(ip).await);
+        assert!(!detector.unban(ip).await);
+        assert!(!detector.is_banned(ip).await);
+    }
+
+    #[tokio::test]
+    async fn test_bans_persist() {
+        let path = std::env::temp_dir().join(format!("llmdig-bans-{}.json", std::process::id()));
+        let config = ban_config(Some(path.to_string_lossy().into_owned()));
+        let ip: IpAddr = "2001:db8::7".parse().unwrap();
+
+        let detector = AbuseDetector::new(&config, memory()).unwrap();
+        detector.record(ip, Offence::Malformed).await;
+        detector.record(ip, Offence::Malformed).await;
+
+        let reloaded = AbuseDetector::new(&config, memory()).unwrap();
+        assert!(reloaded.is_banned(ip).await);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,264 @@
+use crate::config::AclConfig;
+use crate::Error;
+use anyhow::Result;
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::RwLock;
+use tracing::{debug, error, info};
+
+/// An IPv4 or IPv6 prefix such as `10.0.0.0/8` or `2001:db8::/32`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, normalize_ip(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = prefix_mask_u32(self.prefix);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = prefix_mask_u128(self.prefix);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Configuration(format!("Invalid CIDR: {}", s));
+
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+
+        let addr = normalize_ip(IpAddr::from_str(addr).map_err(|_| invalid())?);
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => max_prefix,
+        };
+
+        if prefix > max_prefix {
+            return Err(invalid());
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Treat IPv4-mapped IPv6 addresses (`::ffff:1.2.3.4`) as plain IPv4
+fn normalize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+fn prefix_mask_u32(prefix: u8) -> u32 {
+    if prefix == 0 {
+        0
+    } else {
+        u32::MAX << (32 - prefix as u32)
+    }
+}
+
+fn prefix_mask_u128(prefix: u8) -> u128 {
+    if prefix == 0 {
+        0
+    } else {
+        u128::MAX << (128 - prefix as u32)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct AclRules {
+    allow: Vec<IpNetwork>,
+    deny: Vec<IpNetwork>,
+}
+
+impl AclRules {
+    fn parse(allow: &[String], deny: &[String]) -> Result<Self> {
+        Ok(Self {
+            allow: allow.iter().map(|s| s.parse()).collect::<Result<_, Error>>()?,
+            deny: deny.iter().map(|s| s.parse()).collect::<Result<_, Error>>()?,
+        })
+    }
+}
+
+/// Lists file format used for hot reloading
+#[derive(Debug, Deserialize)]
+struct AclFile {
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+/// Client authorization by source address.
+///
+/// Deny entries always win. When the allow list is non-empty only matching
+/// clients are admitted; an empty allow list admits everyone not denied.
+pub struct AccessControl {
+    enabled: bool,
+    rules: Arc<RwLock<AclRules>>,
+}
+
+impl AccessControl {
+    pub fn new(config: &AclConfig) -> Result<Self> {
+        let mut rules = AclRules::parse(&config.allow, &config.deny)?;
+
+        if let Some(path) = &config.lists_file {
+            if Path::new(path).exists() {
+                rules = Self::load_file(Path::new(path))?;
+            }
+        }
+
+        Ok(Self {
+            enabled: config.enabled,
+            rules: Arc::new(RwLock::new(rules)),
+        })
+    }
+
+    pub async fn is_allowed(&self, ip: IpAddr) -> bool {
+        if !self.enabled {
+            return true;
+        }
+
+        let rules = self.rules.read().await;
+
+        if rules.deny.iter().any(|net| net.contains(ip)) {
+            return false;
+        }
+
+        rules.allow.is_empty() || rules.allow.iter().any(|net| net.contains(ip))
+    }
+
+    /// Atomically replace both lists. On a parse error the current lists are kept.
+    pub async fn reload(&self, allow: &[String], deny: &[String]) -> Result<()> {
+        let rules = AclRules::parse(allow, deny)?;
+        info!("ACL reloaded: {} allow, {} deny entries", rules.allow.len(), rules.deny.len());
+        *self.rules.write().await = rules;
+        Ok(())
+    }
+
+    fn load_file(path: &Path) -> Result<AclRules> {
+        let contents = std::fs::read_to_string(path)?;
+        let file: AclFile = toml::from_str(&contents)
+            .map_err(|e| Error::Configuration(format!("Invalid ACL file {}: {}", path.display(), e)))?;
+        AclRules::parse(&file.allow, &file.deny)
+    }
+
+    /// Poll `lists_file` and swap in its contents whenever it changes
+    pub fn spawn_reloader(self: &Arc<Self>, path: String, interval: Duration) {
+        let acl = Arc::clone(self);
+        let path = PathBuf::from(path);
+
+        tokio::spawn(async move {
+            let mut last_modified: Option<SystemTime> = None;
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+
+                let modified = match std::fs::metadata(&path).and_then(|m| m.modified()) {
+                    Ok(modified) => modified,
+                    Err(_) => continue,
+                };
+
+                if last_modified == Some(modified) {
+                    continue;
+                }
+                last_modified = Some(modified);
+
+                match Self::load_file(&path) {
+                    Ok(rules) => {
+                        debug!("Reloading ACL from {}", path.display());
+                        *acl.rules.write().await = rules;
+                    }
+                    Err(e) => error!("Keeping previous ACL, reload failed: {}", e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    fn acl(allow: &[&str], deny: &[&str]) -> AccessControl {
+        let config = AclConfig {
+            enabled: true,
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            deny: deny.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        };
+        AccessControl::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_cidr_matching() {
+        let net: IpNetwork = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains(ip("10.1.2.3")));
+        assert!(!net.contains(ip("11.0.0.1")));
+        assert!(net.contains(ip("::ffff:10.9.9.9")));
+
+        let net: IpNetwork = "2001:db8::/32".parse().unwrap();
+        assert!(net.contains(ip("2001:db8:1::1")));
+        assert!(!net.contains(ip("2001:db9::1")));
+        assert!(!net.contains(ip("10.0.0.1")));
+
+        let host: IpNetwork = "192.168.1.5".parse().unwrap();
+        assert!(host.contains(ip("192.168.1.5")));
+        assert!(!host.contains(ip("192.168.1.6")));
+
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("not-an-ip/8".parse::<IpNetwork>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_allow_and_deny() {
+        let acl = acl(&["10.0.0.0/8", "::1"], &["10.0.0.13"]);
+        assert!(acl.is_allowed(ip("10.2.3.4")).await);
+        assert!(acl.is_allowed(ip("::1")).await);
+        assert!(!acl.is_allowed(ip("10.0.0.13")).await);
+        assert!(!acl.is_allowed(ip("192.168.1.1")).await);
+
+        let open = acl(&[], &["192.168.0.0/16"]);
+        assert!(open.is_allowed(ip("8.8.8.8")).await);
+        assert!(!open.is_allowed(ip("192.168.4.4")).await);
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let acl = acl(&["127.0.0.1"], &[]);
+        assert!(!acl.is_allowed(ip("10.0.0.1")).await);
+
+        acl.reload(&["10.0.0.0/8".to_string()], &[]).await.unwrap();
+        assert!(acl.is_allowed(ip("10.0.0.1")).await);
+
+        // Invalid entries leave the current lists untouched
+        assert!(acl.reload(&["bogus".to_string()], &[]).await.is_err());
+        assert!(acl.is_allowed(ip("10.0.0.1")).await);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,101 @@
+use crate::config::AnswerTtlConfig;
+use crate::Error;
+use anyhow::Result;
+use regex::Regex;
+use tracing::debug;
+
+struct TtlRule {
+    category: String,
+    patterns: Vec<Regex>,
+    ttl: u32,
+}
+
+/// Picks the TTL of TXT answers from what was asked, so resolvers keep
+/// answers about the weather for a minute and settled facts for a day
+pub struct AnswerTtl {
+    default: u32,
+    rules: Vec<TtlRule>,
+}
+
+impl AnswerTtl {
+    pub fn new(config: &AnswerTtlConfig) -> Result<Self> {
+        let mut rules = Vec::with_capacity(config.rules.len());
+        for rule in &config.rules {
+            let patterns = rule
+                .patterns
+                .iter()
+                .map(|p| Regex::new(p).map_err(|e| Error::Configuration(format!("Invalid TTL pattern {}: {}", p, e))))
+                .collect::<Result<Vec<_>, _>>()?;
+            rules.push(TtlRule {
+                category: rule.category.clone(),
+                patterns,
+                ttl: rule.ttl_seconds,
+            });
+        }
+
+        Ok(Self {
+            default: config.default_seconds,
+            rules,
+        })
+    }
+
+    /// TTL of the first rule matching `question`, falling back to
+    /// `zone_default` and then the configured default
+    pub fn ttl(&self, question: Option<&str>, zone_default: Option<u32>) -> u32 {
+        let rule = question.and_then(|question| {
+            self.rules
+                .iter()
+                .find(|rule| rule.patterns.iter().any(|p| p.is_match(question)))
+        });
+        match rule {
+            Some(rule) => {
+                debug!("Answer TTL from rule {}: {}s", rule.category, rule.ttl);
+                rule.ttl
+            }
+            None => zone_default.unwrap_or(self.default),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TtlRuleConfig;
+
+    #[test]
+    fn test_rules() {
+        let config = AnswerTtlConfig {
+            default_seconds: 300,
+            rules: vec![
+                TtlRuleConfig {
+                    category: "current".to_string(),
+                    patterns: vec![r"(?i)\b(time|weather|today|now)\b".to_string()],
+                    ttl_seconds: 60,
+                },
+                TtlRuleConfig {
+                    category: "factual".to_string(),
+                    patterns: vec![r"(?i)^(what|who) (is|was)\b".to_string()],
+                    ttl_seconds: 86400,
+                },
+            ],
+        };
+        let ttl = AnswerTtl::new(&config).unwrap();
+
+        assert_eq!(ttl.ttl(Some("weather in paris"), None), 60);
+        assert_eq!(ttl.ttl(Some("what is the time in tokyo"), None), 60);
+        assert_eq!(ttl.ttl(Some("who was ada lovelace"), Some(30)), 86400);
+        assert_eq!(ttl.ttl(Some("tell me a joke"), None), 300);
+        assert_eq!(ttl.ttl(Some("tell me a joke"), Some(30)), 30);
+        assert_eq!(ttl.ttl(None, None), 300);
+
+        let invalid = AnswerTtlConfig {
+            rules: vec![TtlRuleConfig {
+                category: "broken".to_string(),
+                patterns: vec!["(".to_string()],
+                ttl_seconds: 60,
+            }],
+            ..AnswerTtlConfig::default()
+        };
+        assert!(AnswerTtl::new(&invalid).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,250 @@
+use crate::config::{ApiKeysConfig, UnauthenticatedPolicy};
+use crate::utils::rate_limiter::RateLimiter;
+use crate::utils::storage::{self, Storage};
+use crate::Error;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+use trust_dns_proto::rr::Name;
+
+/// Label prefix marking an access token, as in `tok-abc123.what.is.rust.llm`
+pub const TOKEN_PREFIX: &str = "tok-";
+
+pub struct ApiKey {
+    pub name: String,
+    /// Model used for this key's queries instead of `llm.model`
+    pub model: Option<String>,
+    rate_limiter: Option<RateLimiter<String>>,
+    /// Where the request counters are kept
+    storage: Arc<dyn Storage>,
+}
+
+impl ApiKey {
+    /// Apply the key's own limit. Returns `None` when the key has no limit and
+    /// the per-client limiter should be used instead.
+    pub async fn check_rate_limit(&self) -> Option<bool> {
+        let limiter = self.rate_limiter.as_ref()?;
+        let allowed = limiter.allow_request(self.name.clone()).await;
+        if !allowed {
+            self.count("rate_limited").await;
+        }
+        Some(allowed)
+    }
+
+    pub async fn record_request(&self) {
+        self.count("requests").await;
+    }
+
+    fn counter_key(&self, counter: &str) -> String {
+        format!("usage:key:{}:{}", self.name, counter)
+    }
+
+    async fn count(&self, counter: &str) {
+        let result = self.storage.increment(&self.counter_key(counter), 1).await;
+        storage::or_default(result, "count an API key request");
+    }
+
+    pub async fn usage(&self) -> KeyUsage {
+        let counter = |counter| async move {
+            storage::or_default(self.storage.counter(&self.counter_key(counter)).await, "read API key usage")
+        };
+        KeyUsage {
+            name: self.name.clone(),
+            requests: counter("requests").await,
+            rate_limited: counter("rate_limited").await,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyUsage {
+    pub name: String,
+    pub requests: u64,
+    pub rate_limited: u64,
+}
+
+pub enum Authentication<'a> {
+    /// No token label was present
+    Anonymous,
+    Key(&'a ApiKey),
+    /// A token label was present but does not match any key
+    Invalid,
+}
+
+/// Access tokens accepted in the first label of the query name
+pub struct ApiKeyStore {
+    enabled: bool,
+    keys: HashMap<String, ApiKey>,
+    unauthenticated: UnauthenticatedPolicy,
+    fallback_model: Option<String>,
+}
+
+impl ApiKeyStore {
+    pub fn new(config: &ApiKeysConfig, storage: Arc<dyn Storage>) -> Result<Self> {
+        let mut keys = HashMap::new();
+
+        for key in &config.keys {
+            let token = key.token.expose();
+            if token.is_empty() || token.contains('.') {
+                return Err(Error::Configuration(format!("Invalid token for API key {}", key.name)).into());
+            }
+
+            let rate_limiter = key
+                .requests_per_minute
+                .map(|rpm| RateLimiter::new(rpm, key.burst_size.unwrap_or(rpm.max(1))));
+
+            let previous = keys.insert(
+                token.to_lowercase(),
+                ApiKey {
+                    name: key.name.clone(),
+                    model: key.model.clone(),
+                    rate_limiter,
+                    storage: storage.clone(),
+                },
+            );
+
+            if previous.is_some() {
+                return Err(Error::Configuration(format!("Duplicate token for API key {}", key.name)).into());
+            }
+        }
+
+        if config.unauthenticated == UnauthenticatedPolicy::Fallback && config.fallback_model.is_none() {
+            return Err(Error::Configuration(
+                "api_keys.unauthenticated = \"fallback\" requires api_keys.fallback_model".to_string(),
+            )
+            .into());
+        }
+
+        Ok(Self {
+            enabled: config.enabled,
+            keys,
+            unauthenticated: config.unauthenticated.clone(),
+            fallback_model: config.fallback_model.clone(),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn unauthenticated_policy(&self) -> &UnauthenticatedPolicy {
+        &self.unauthenticated
+    }
+
+    pub fn fallback_model(&self) -> Option<&str> {
+        self.fallback_model.as_deref()
+    }
+
+    /// Split a leading `tok-...` label off the query name
+    pub fn split_token(&self, name: &Name) -> (Option<String>, Name) {
+        if !self.enabled {
+            return (None, name.clone());
+        }
+        split_token(name)
+    }
+
+    pub fn authenticate(&self, token: Option<&str>) -> Authentication<'_> {
+        match token {
+            None => Authentication::Anonymous,
+            Some(token) => match self.keys.get(&token.to_lowercase()) {
+                Some(key) => Authentication::Key(key),
+                None => Authentication::Invalid,
+            },
+        }
+    }
+
+    pub async fn usage(&self) -> Vec<KeyUsage> {
+        let mut usage = Vec::with_capacity(self.keys.len());
+        for key in self.keys.values() {
+            usage.push(key.usage().await);
+        }
+        usage.sort_by(|a, b| a.name.cmp(&b.name));
+        usage
+    }
+}
+
+/// Split a leading `tok-...` label off `name`, whether or not API keys are
+/// enabled
+pub fn split_token(name: &Name) -> (Option<String>, Name) {
+    let first = match name.iter().next() {
+        Some(label) => String::from_utf8_lossy(label).to_lowercase(),
+        None => return (None, name.clone()),
+    };
+
+    match first.strip_prefix(TOKEN_PREFIX) {
+        Some(token) => {
+            let mut rest = Name::from_labels(name.iter().skip(1)).unwrap_or_else(|_| Name::root());
+            rest.set_fqdn(name.is_fqdn());
+            (Some(token.to_string()), rest)
+        }
+        None => (None, name.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiKeyConfig;
+    use crate::utils::storage::MemoryStorage;
+    use std::str::FromStr;
+
+    fn store() -> ApiKeyStore {
+        let config = ApiKeysConfig {
+            enabled: true,
+            keys: vec![ApiKeyConfig {
+                name: "team-a".to_string(),
+                token: "abc123".into(),
+                model: Some("gpt-4o".to_string()),
+                requests_per_minute: Some(60),
+                burst_size: Some(1),
+            }],
+            ..Default::default()
+        };
+        ApiKeyStore::new(&config, Arc::new(MemoryStorage::default())).unwrap()
+    }
+
+    #[test]
+    fn test_split_token() {
+        let store = store();
+
+        let (token, rest) = store.split_token(&Name::from_str("tok-abc123.what.is.rust.llm.").unwrap());
+        assert_eq!(token.as_deref(), Some("abc123"));
+        assert_eq!(rest.to_string(), "what.is.rust.llm.");
+
+        let (token, rest) = store.split_token(&Name::from_str("what.is.rust.llm.").unwrap());
+        assert_eq!(token, None);
+        assert_eq!(rest.to_string(), "what.is.rust.llm.");
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_and_limit() {
+        let store = store();
+
+        assert!(matches!(store.authenticate(None), Authentication::Anonymous));
+        assert!(matches!(store.authenticate(Some("nope")), Authentication::Invalid));
+
+        let key = match store.authenticate(Some("ABC123")) {
+            Authentication::Key(key) => key,
+            _ => panic!("token should authenticate"),
+        };
+        assert_eq!(key.model.as_deref(), Some("gpt-4o"));
+
+        key.record_request().await;
+        assert_eq!(key.check_rate_limit().await, Some(true));
+        assert_eq!(key.check_rate_limit().await, Some(false));
+
+        let usage = store.usage().await;
+        assert_eq!(usage[0].requests, 1);
+        assert_eq!(usage[0].rate_limited, 1);
+    }
+
+    #[test]
+    fn test_fallback_requires_model() {
+        let config = ApiKeysConfig {
+            enabled: true,
+            unauthenticated: UnauthenticatedPolicy::Fallback,
+            ..Default::default()
+        };
+        assert!(ApiKeyStore::new(&config, Arc::new(MemoryStorage::default())).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,189 @@
+use crate::utils::storage::Storage;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// Counter handing out sequence numbers
+const SEQUENCE_KEY: &str = "audit:sequence";
+
+/// Keys of entries, followed by the zero-padded sequence number so that
+/// they sort in the order they were recorded
+const ENTRY_PREFIX: &str = "audit:entry:";
+
+/// Entries returned by a query that sets no limit
+const DEFAULT_LIMIT: usize = 100;
+
+/// Who performed an administrative action
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Actor {
+    /// Address the request came from
+    pub address: Option<IpAddr>,
+    /// Common name of the client certificate, when one was required
+    pub common_name: Option<String>,
+}
+
+impl Actor {
+    /// Whether `name` is the actor's certificate common name or address
+    fn is(&self, name: &str) -> bool {
+        self.common_name.as_deref() == Some(name) || self.address.is_some_and(|address| address.to_string() == name)
+    }
+}
+
+/// One administrative action, as recorded in the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Position in the log, counting from 1
+    pub sequence: u64,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub actor: Actor,
+    /// What was done, e.g. `unban` or `flush_cache`
+    pub action: String,
+    /// What it was done to, e.g. a client address
+    pub target: Option<String>,
+    /// State the action changed, before and after it; `null` where there
+    /// was none
+    pub before: Value,
+    pub after: Value,
+}
+
+/// Which entries a query returns
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditQuery {
+    pub action: Option<String>,
+    /// Certificate common name or address of the actor
+    pub actor: Option<String>,
+    /// Entries recorded at or after this many milliseconds since the epoch
+    pub since: Option<u64>,
+    /// The latest this many of the matching entries, 100 if not given
+    pub limit: Option<usize>,
+}
+
+/// Append-only log of administrative actions, kept in storage so that
+/// instances sharing it share one log. Entries are never changed or
+/// removed, by retention purges included.
+pub struct AuditLog {
+    storage: Arc<dyn Storage>,
+}
+
+impl AuditLog {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    /// Append an entry. Failing to is logged rather than undoing the
+    /// action, which has already taken effect.
+    pub async fn record(&self, actor: Actor, action: &str, target: Option<String>, before: Value, after: Value) {
+        if let Err(e) = self.append(actor, action, target, before, after).await {
+            error!("Could not record {} in the audit log: {}", action, e);
+        }
+    }
+
+    async fn append(
+        &self,
+        actor: Actor,
+        action: &str,
+        target: Option<String>,
+        before: Value,
+        after: Value,
+    ) -> Result<()> {
+        let sequence = self.storage.increment(SEQUENCE_KEY, 1).await?;
+        let entry = AuditEntry {
+            sequence,
+            timestamp: unix_millis(),
+            actor,
+            action: action.to_string(),
+            target,
+            before,
+            after,
+        };
+        let key = format!("{}{:020}", ENTRY_PREFIX, sequence);
+        self.storage.set_json(&key, &entry, None).await
+    }
+
+    /// Entries `query` matches, oldest first
+    pub async fn entries(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        let mut stored = self.storage.scan(ENTRY_PREFIX).await?;
+        stored.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut entries: Vec<AuditEntry> = stored
+            .into_iter()
+            .filter_map(|(_, value)| serde_json::from_slice::<AuditEntry>(&value).ok())
+            .filter(|entry| query.action.as_ref().is_none_or(|action| &entry.action == action))
+            .filter(|entry| query.actor.as_ref().is_none_or(|actor| entry.actor.is(actor)))
+            .filter(|entry| query.since.is_none_or(|since| entry.timestamp >= since))
+            .collect();
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        entries.drain(..entries.len().saturating_sub(limit));
+        Ok(entries)
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::storage::MemoryStorage;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let log = AuditLog::new(storage.clone());
+        let ops = Actor {
+            address: Some("192.0.2.7".parse().unwrap()),
+            common_name: Some("ops".to_string()),
+        };
+        let anonymous = Actor {
+            address: Some("127.0.0.1".parse().unwrap()),
+            common_name: None,
+        };
+        for n in 0..12 {
+            let actor = if n % 2 == 0 { ops.clone() } else { anonymous.clone() };
+            log.record(actor, "maintenance", None, json!({ "enabled": n % 3 == 0 }), json!({ "enabled": n % 3 != 0 }))
+                .await;
+        }
+        log.record(ops.clone(), "unban", Some("203.0.113.9".to_string()), json!({ "reason": "malformed" }), Value::Null)
+            .await;
+
+        // Sequence numbers past 9 still sort after single digits
+        let all = log.entries(&AuditQuery::default()).await.unwrap();
+        assert_eq!(all.len(), 13);
+        assert!(all.windows(2).all(|pair| pair[0].sequence + 1 == pair[1].sequence));
+        assert_eq!(all[12].target.as_deref(), Some("203.0.113.9"));
+
+        let query = AuditQuery {
+            actor: Some("127.0.0.1".to_string()),
+            ..AuditQuery::default()
+        };
+        assert_eq!(log.entries(&query).await.unwrap().len(), 6);
+        let query = AuditQuery {
+            actor: Some("ops".to_string()),
+            action: Some("maintenance".to_string()),
+            limit: Some(2),
+            ..AuditQuery::default()
+        };
+        let latest: Vec<u64> = log.entries(&query).await.unwrap().iter().map(|entry| entry.sequence).collect();
+        assert_eq!(latest, vec![9, 11]);
+        let query = AuditQuery {
+            since: Some(unix_millis() + 60_000),
+            ..AuditQuery::default()
+        };
+        assert!(log.entries(&query).await.unwrap().is_empty());
+
+        // Another instance on the same storage continues the same log
+        let other_instance = AuditLog::new(storage);
+        other_instance.record(anonymous, "flush_cache", None, json!({ "cached_answers": 3 }), json!({ "cached_answers": 0 })).await;
+        assert_eq!(log.entries(&AuditQuery::default()).await.unwrap().last().unwrap().sequence, 14);
+    }
+}
@@ -1,9 +1,15 @@
-use std::collections::HashMap;
+use crate::config::Config;
+use crate::utils::embeddings::Embedder;
+use crate::utils::llm_keys::LlmKeyRing;
+use crate::utils::retention::PurgeFilter;
+use crate::utils::shard::{Sharded, DEFAULT_SHARDS};
+use std::collections::{HashMap, VecDeque};
 use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
-use tracing::{debug, info, warn};
+use tracing::{debug, info};
 
 #[derive(Debug, Clone)]
 pub struct CacheEntry<T> {
@@ -44,10 +50,188 @@ impl<T> CacheEntry<T> {
     }
 }
 
+/// Slab-backed doubly linked list plus a key index, giving O(1) lookup,
+/// promotion and eviction of the least recently used entry
+#[derive(Debug)]
+struct LruMap<T> {
+    index: HashMap<String, usize>,
+    nodes: Vec<Option<Node<T>>>,
+    free: Vec<usize>,
+    /// Most recently used
+    head: Option<usize>,
+    /// Least recently used
+    tail: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    key: String,
+    entry: CacheEntry<T>,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+impl<T> LruMap<T> {
+    fn new() -> Self {
+        Self {
+            index: HashMap::new(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+
+    fn node(&self, idx: usize) -> &Node<T> {
+        self.nodes[idx].as_ref().expect("live LRU node")
+    }
+
+    fn node_mut(&mut self, idx: usize) -> &mut Node<T> {
+        self.nodes[idx].as_mut().expect("live LRU node")
+    }
+
+    fn unlink(&mut self, idx: usize) {
+        let (prev, next) = {
+            let node = self.node(idx);
+            (node.prev, node.next)
+        };
+
+        match prev {
+            Some(prev) => self.node_mut(prev).next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.node_mut(next).prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    fn push_front(&mut self, idx: usize) {
+        let old_head = self.head;
+        {
+            let node = self.node_mut(idx);
+            node.prev = None;
+            node.next = old_head;
+        }
+
+        match old_head {
+            Some(head) => self.node_mut(head).prev = Some(idx),
+            None => self.tail = Some(idx),
+        }
+        self.head = Some(idx);
+    }
+
+    fn promote(&mut self, idx: usize) {
+        if self.head != Some(idx) {
+            self.unlink(idx);
+            self.push_front(idx);
+        }
+    }
+
+    /// Look up an entry and mark it most recently used
+    fn get_mut(&mut self, key: &str) -> Option<&mut CacheEntry<T>> {
+        let idx = *self.index.get(key)?;
+        self.promote(idx);
+        Some(&mut self.node_mut(idx).entry)
+    }
+
+    /// Insert or replace an entry as the most recently used one
+    fn insert(&mut self, key: String, entry: CacheEntry<T>) {
+        if let Some(&idx) = self.index.get(&key) {
+            self.node_mut(idx).entry = entry;
+            self.promote(idx);
+            return;
+        }
+
+        let node = Node {
+            key: key.clone(),
+            entry,
+            prev: None,
+            next: None,
+        };
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx] = Some(node);
+                idx
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+
+        self.index.insert(key, idx);
+        self.push_front(idx);
+    }
+
+    fn take(&mut self, idx: usize) -> Node<T> {
+        self.unlink(idx);
+        self.free.push(idx);
+        self.nodes[idx].take().expect("live LRU node")
+    }
+
+    fn remove(&mut self, key: &str) -> Option<CacheEntry<T>> {
+        let idx = self.index.remove(key)?;
+        Some(self.take(idx).entry)
+    }
+
+    fn pop_lru(&mut self) -> Option<(String, CacheEntry<T>)> {
+        let idx = self.tail?;
+        let node = self.take(idx);
+        self.index.remove(&node.key);
+        Some((node.key, node.entry))
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&str, &CacheEntry<T>) -> bool) {
+        let doomed: Vec<usize> = self
+            .index
+            .iter()
+            .filter(|(key, &idx)| !keep(key, &self.node(idx).entry))
+            .map(|(_, &idx)| idx)
+            .collect();
+
+        for idx in doomed {
+            let node = self.take(idx);
+            self.index.remove(&node.key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.index.clear();
+        self.nodes.clear();
+        self.free.clear();
+        self.head = None;
+        self.tail = None;
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&String, &CacheEntry<T>)> {
+        self.nodes.iter().flatten().map(|node| (&node.key, &node.entry))
+    }
+
+    fn values(&self) -> impl Iterator<Item = &CacheEntry<T>> {
+        self.iter().map(|(_, entry)| entry)
+    }
+}
+
+/// Below this many entries per shard, LRU order is kept exact with a single shard
+const MIN_SHARD_CAPACITY: usize = 256;
+
+/// TTL cache with LRU eviction. Large caches are split into independently
+/// locked shards, each evicting its own least recently used entry, so
+/// concurrent writers only contend when their keys hash to the same shard.
 #[derive(Debug)]
 pub struct Cache<T> {
-    entries: Arc<RwLock<HashMap<String, CacheEntry<T>>>>,
+    entries: Arc<Sharded<LruMap<T>>>,
     max_size: usize,
+    shard_capacity: usize,
     default_ttl: Duration,
     cleanup_interval: Duration,
     last_cleanup: Arc<RwLock<Instant>>,
@@ -58,9 +242,18 @@ where
     T: Clone + Send + Sync + 'static,
 {
     pub fn new(max_size: usize, default_ttl: Duration) -> Self {
+        let shards = (max_size / MIN_SHARD_CAPACITY).clamp(1, DEFAULT_SHARDS);
+        Self::with_shards(max_size, default_ttl, shards)
+    }
+
+    pub fn with_shards(max_size: usize, default_ttl: Duration, shards: usize) -> Self {
+        let entries = Sharded::new(shards, LruMap::new);
+        let shard_capacity = max_size / entries.len();
+
         Self {
-            entries: Arc::new(RwLock::new(HashMap::new())),
+            entries: Arc::new(entries),
             max_size,
+            shard_capacity,
             default_ttl,
             cleanup_interval: Duration::from_secs(300), // 5 minutes
             last_cleanup: Arc::new(RwLock::new(Instant::now())),
@@ -68,7 +261,7 @@ where
     }
 
     pub async fn get(&self, key: &str) -> Option<T> {
-        let mut entries = self.entries.write().await;
+        let mut entries = self.entries.shard(key).write().await;
         
         if let Some(entry) = entries.get_mut(key) {
             if entry.is_expired() {
@@ -88,61 +281,93 @@ where
     }
 
     pub async fn set_with_ttl(&self, key: String, value: T, ttl: Duration) {
-        let mut entries = self.entries.write().await;
+        if self.shard_capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.shard(key.as_str()).write().await;
         
         // Check if we need to evict entries
-        if entries.len() >= self.max_size {
-            self.evict_entries(&mut entries).await;
+        if entries.len() >= self.shard_capacity && !entries.contains_key(&key) {
+            self.evict_entries(&mut entries);
         }
         
+        // Keys can hold questions, which only the caller knows how to log
+        debug!("Cache set (TTL: {:?})", ttl);
+        
         let entry = CacheEntry::new(value, ttl);
         entries.insert(key, entry);
-        
-        debug!("Cache set: {} (TTL: {:?})", key, ttl);
     }
 
     pub async fn remove(&self, key: &str) -> Option<T> {
-        let mut entries = self.entries.write().await;
+        let mut entries = self.entries.shard(key).write().await;
         entries.remove(key).map(|entry| entry.value)
     }
 
     pub async fn clear(&self) {
-        let mut entries = self.entries.write().await;
-        entries.clear();
+        for shard in self.entries.iter() {
+            shard.write().await.clear();
+        }
         info!("Cache cleared");
     }
 
     pub async fn size(&self) -> usize {
-        let entries = self.entries.read().await;
-        entries.len()
+        let mut size = 0;
+        for shard in self.entries.iter() {
+            size += shard.read().await.len();
+        }
+        size
     }
 
     pub async fn is_empty(&self) -> bool {
         self.size().await == 0
     }
 
+    /// Keep only the entries `keep` picks, given each key and value,
+    /// returning how many went
+    pub async fn retain(&self, mut keep: impl FnMut(&str, &T) -> bool) -> usize {
+        let mut removed = 0;
+        for shard in self.entries.iter() {
+            let mut entries = shard.write().await;
+            let before = entries.len();
+            entries.retain(|key, entry| keep(key, &entry.value));
+            removed += before - entries.len();
+        }
+        removed
+    }
+
+    /// The keys of the entries, expired or not
+    pub async fn keys(&self) -> Vec<String> {
+        let mut keys = Vec::new();
+        for shard in self.entries.iter() {
+            keys.extend(shard.read().await.iter().map(|(key, _)| key.clone()));
+        }
+        keys
+    }
+
     pub async fn contains_key(&self, key: &str) -> bool {
-        let entries = self.entries.read().await;
+        let entries = self.entries.shard(key).read().await;
         entries.contains_key(key)
     }
 
     pub async fn get_stats(&self) -> CacheStats {
-        let entries = self.entries.read().await;
-        let now = Instant::now();
-        
         let mut total_age = Duration::ZERO;
         let mut total_access_count = 0;
         let mut expired_count = 0;
+        let mut entry_count = 0;
         
-        for entry in entries.values() {
-            total_age += entry.age();
-            total_access_count += entry.access_count;
-            if entry.is_expired() {
-                expired_count += 1;
+        for shard in self.entries.iter() {
+            let entries = shard.read().await;
+            entry_count += entries.len();
+            for entry in entries.values() {
+                total_age += entry.age();
+                total_access_count += entry.access_count;
+                if entry.is_expired() {
+                    expired_count += 1;
+                }
             }
         }
         
-        let entry_count = entries.len();
         let avg_age = if entry_count > 0 {
             total_age / entry_count as u32
         } else {
@@ -165,32 +390,24 @@ where
         }
     }
 
-    async fn evict_entries(&self, entries: &mut HashMap<String, CacheEntry<T>>) {
-        // Remove expired entries first
-        entries.retain(|_, entry| !entry.is_expired());
-        
-        // If still over limit, use LRU eviction
-        if entries.len() >= self.max_size {
-            let mut entries_vec: Vec<_> = entries.drain().collect();
-            entries_vec.sort_by(|a, b| a.1.last_accessed.cmp(&b.1.last_accessed));
-            
-            // Keep the most recently used entries
-            let to_keep = self.max_size / 2; // Keep half
-            for (key, entry) in entries_vec.into_iter().take(to_keep) {
-                entries.insert(key, entry);
-            }
-            
-            warn!("Cache evicted {} entries due to size limit", self.max_size - to_keep);
+    fn evict_entries(&self, entries: &mut LruMap<T>) {
+        // Expired entries are reclaimed lazily on access and by
+        // cleanup_expired, so only the least recently used one goes here
+        if entries.pop_lru().is_some() {
+            debug!("Cache evicted its least recently used entry");
         }
     }
 
     pub async fn cleanup_expired(&self) -> usize {
-        let mut entries = self.entries.write().await;
-        let initial_size = entries.len();
+        let mut removed = 0;
         
-        entries.retain(|_, entry| !entry.is_expired());
+        for shard in self.entries.iter() {
+            let mut entries = shard.write().await;
+            let initial_size = entries.len();
+            entries.retain(|_, entry| !entry.is_expired());
+            removed += initial_size - entries.len();
+        }
         
-        let removed = initial_size - entries.len();
         if removed > 0 {
             debug!("Cache cleanup removed {} expired entries", removed);
         }
@@ -210,11 +427,11 @@ where
     }
 
     pub async fn get_hot_keys(&self, limit: usize) -> Vec<(String, u64)> {
-        let entries = self.entries.read().await;
-        let mut hot_keys: Vec<_> = entries
-            .iter()
-            .map(|(key, entry)| (key.clone(), entry.access_count))
-            .collect();
+        let mut hot_keys = Vec::new();
+        for shard in self.entries.iter() {
+            let entries = shard.read().await;
+            hot_keys.extend(entries.iter().map(|(key, entry)| (key.clone(), entry.access_count)));
+        }
         
         hot_keys.sort_by(|a, b| b.1.cmp(&a.1));
         hot_keys.truncate(limit);
@@ -222,11 +439,11 @@ where
     }
 
     pub async fn get_old_keys(&self, limit: usize) -> Vec<(String, Duration)> {
-        let entries = self.entries.read().await;
-        let mut old_keys: Vec<_> = entries
-            .iter()
-            .map(|(key, entry)| (key.clone(), entry.age()))
-            .collect();
+        let mut old_keys = Vec::new();
+        for shard in self.entries.iter() {
+            let entries = shard.read().await;
+            old_keys.extend(entries.iter().map(|(key, entry)| (key.clone(), entry.age())));
+        }
         
         old_keys.sort_by(|a, b| b.1.cmp(&a.1));
         old_keys.truncate(limit);
@@ -313,6 +530,205 @@ impl CacheMiddleware {
     }
 }
 
+/// Brute-force nearest-neighbour index over unit-length embeddings. A linear
+/// scan is fast enough for the few thousand answers a semantic cache holds.
+#[derive(Debug)]
+pub struct VectorIndex<T> {
+    entries: VecDeque<VectorEntry<T>>,
+    max_entries: usize,
+    ttl: Duration,
+}
+
+#[derive(Debug)]
+struct VectorEntry<T> {
+    embedding: Vec<f32>,
+    value: T,
+    created_at: Instant,
+}
+
+impl<T: Clone> VectorIndex<T> {
+    pub fn new(max_entries: usize, ttl: Duration) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            max_entries,
+            ttl,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add an embedding, dropping the oldest entry when full
+    pub fn insert(&mut self, embedding: Vec<f32>, value: T) {
+        let embedding = match unit_vector(embedding) {
+            Some(embedding) if self.max_entries > 0 => embedding,
+            _ => return,
+        };
+
+        let ttl = self.ttl;
+        self.entries.retain(|entry| entry.created_at.elapsed() <= ttl);
+        while self.entries.len() >= self.max_entries {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(VectorEntry {
+            embedding,
+            value,
+            created_at: Instant::now(),
+        });
+    }
+
+    /// Keep only the entries `keep` picks, given each value and its age,
+    /// returning how many went
+    pub fn retain(&mut self, mut keep: impl FnMut(&T, Duration) -> bool) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|entry| keep(&entry.value, entry.created_at.elapsed()));
+        before - self.entries.len()
+    }
+
+    /// The values of the entries, live or not, oldest first
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.entries.iter().map(|entry| &entry.value)
+    }
+
+    /// The most similar live entry, if its cosine similarity reaches `threshold`
+    pub fn nearest(&self, embedding: &[f32], threshold: f32) -> Option<(T, f32)> {
+        self.nearest_matching(embedding, threshold, |_| true)
+    }
+
+    /// [`VectorIndex::nearest`] among the entries whose value `matches`
+    pub fn nearest_matching(
+        &self,
+        embedding: &[f32],
+        threshold: f32,
+        matches: impl Fn(&T) -> bool,
+    ) -> Option<(T, f32)> {
+        let query = unit_vector(embedding.to_vec())?;
+
+        self.entries
+            .iter()
+            .filter(|entry| entry.created_at.elapsed() <= self.ttl)
+            .filter(|entry| matches(&entry.value))
+            .filter(|entry| entry.embedding.len() == query.len())
+            .map(|entry| (entry, dot(&entry.embedding, &query)))
+            .filter(|(_, similarity)| *similarity >= threshold)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(entry, similarity)| (entry.value.clone(), similarity))
+    }
+}
+
+/// Cosine similarity of two vectors of equal length, or 0 when either is zero
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    match (unit_vector(a.to_vec()), unit_vector(b.to_vec())) {
+        (Some(a), Some(b)) if a.len() == b.len() => dot(&a, &b),
+        _ => 0.0,
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn unit_vector(mut v: Vec<f32>) -> Option<Vec<f32>> {
+    let norm = dot(&v, &v).sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        return None;
+    }
+    v.iter_mut().for_each(|x| *x /= norm);
+    Some(v)
+}
+
+pub enum SemanticLookup {
+    /// A cached answer to a sufficiently similar question
+    Hit { answer: String, similarity: f32 },
+    /// Nothing close enough; carries the question's embedding for `insert`
+    Miss(Vec<f32>),
+}
+
+/// An answer in the semantic cache
+#[derive(Debug, Clone)]
+struct SemanticAnswer {
+    answer: String,
+    /// Client whose question it answers
+    client: Option<IpAddr>,
+    /// Generation fingerprint of the settings that produced it
+    fingerprint: String,
+}
+
+/// Answer cache keyed by question meaning rather than spelling. Answers
+/// are only served to questions asked with the same generation fingerprint.
+pub struct SemanticCache {
+    embedder: Embedder,
+    index: RwLock<VectorIndex<SemanticAnswer>>,
+    threshold: f32,
+}
+
+impl SemanticCache {
+    pub fn new(config: &Config, keys: Arc<LlmKeyRing>) -> anyhow::Result<Self> {
+        let semantic = &config.semantic_cache;
+
+        Ok(Self {
+            embedder: Embedder::new(config, keys)?,
+            index: RwLock::new(VectorIndex::new(
+                semantic.max_entries,
+                Duration::from_secs(semantic.ttl_seconds),
+            )),
+            threshold: semantic.similarity_threshold,
+        })
+    }
+
+    pub async fn lookup(&self, question: &str, fingerprint: &str) -> anyhow::Result<SemanticLookup> {
+        let embedding = self.embedder.embed(question).await?;
+
+        let nearest = self.index.read().await.nearest_matching(&embedding, self.threshold, |cached| {
+            cached.fingerprint == fingerprint
+        });
+        Ok(match nearest {
+            Some((cached, similarity)) => SemanticLookup::Hit {
+                answer: cached.answer,
+                similarity,
+            },
+            None => SemanticLookup::Miss(embedding),
+        })
+    }
+
+    pub async fn insert(&self, embedding: Vec<f32>, answer: String, client: Option<IpAddr>, fingerprint: &str) {
+        let answer = SemanticAnswer {
+            answer,
+            client,
+            fingerprint: fingerprint.to_string(),
+        };
+        self.index.write().await.insert(embedding, answer);
+    }
+
+    /// Drop the answers `filter` matches, returning how many went
+    pub async fn purge(&self, filter: &PurgeFilter) -> usize {
+        self.index
+            .write()
+            .await
+            .retain(|cached, age| !filter.matches(cached.client, age))
+    }
+
+    /// Drop the answers generated with `fingerprint`, returning how many went
+    pub async fn invalidate(&self, fingerprint: &str) -> usize {
+        self.index.write().await.retain(|cached, _| cached.fingerprint != fingerprint)
+    }
+
+    /// How many answers there are per generation fingerprint
+    pub async fn fingerprints(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for cached in self.index.read().await.values() {
+            *counts.entry(cached.fingerprint.clone()).or_default() += 1;
+        }
+        counts
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -353,6 +769,45 @@ mod tests {
         
         // Should have evicted oldest entry
         assert_eq!(cache.size().await, 2);
+        assert!(!cache.contains_key("key1").await);
+    }
+
+    #[tokio::test]
+    async fn test_cache_lru_order() {
+        let cache = Cache::new(2, Duration::from_secs(10));
+        
+        cache.set("key1".to_string(), "value1".to_string()).await;
+        cache.set("key2".to_string(), "value2".to_string()).await;
+        
+        // Touching key1 makes key2 the least recently used
+        assert!(cache.get("key1").await.is_some());
+        cache.set("key3".to_string(), "value3".to_string()).await;
+        assert!(cache.contains_key("key1").await);
+        assert!(!cache.contains_key("key2").await);
+        
+        // Replacing an existing key never evicts
+        cache.set("key1".to_string(), "updated".to_string()).await;
+        assert_eq!(cache.size().await, 2);
+        assert_eq!(cache.get("key1").await, Some("updated".to_string()));
+        
+        // Removed slots are reused
+        cache.remove("key3").await;
+        cache.set("key4".to_string(), "value4".to_string()).await;
+        assert_eq!(cache.size().await, 2);
+        assert!(cache.contains_key("key4").await);
+    }
+
+    #[tokio::test]
+    async fn test_cache_retain() {
+        let cache = Cache::new(100, Duration::from_secs(10));
+        cache.set("a:1".to_string(), "value1".to_string()).await;
+        cache.set("a:2".to_string(), "value2".to_string()).await;
+        cache.set("b:1".to_string(), "value1".to_string()).await;
+
+        assert_eq!(cache.retain(|key, _| !key.starts_with("a:")).await, 2);
+        assert_eq!(cache.keys().await, vec!["b:1".to_string()]);
+        assert_eq!(cache.retain(|_, value| value != "value1").await, 1);
+        assert!(cache.is_empty().await);
     }
 
     #[tokio::test]
@@ -367,4 +822,42 @@ mod tests {
         assert_eq!(stats.max_size, 100);
         assert_eq!(stats.hit_rate(), 100.0);
     }
+
+    #[test]
+    fn test_vector_index_nearest() {
+        let mut index = VectorIndex::new(10, Duration::from_secs(60));
+        index.insert(vec![1.0, 0.0, 0.0], "rust");
+        index.insert(vec![0.0, 1.0, 0.0], "python");
+
+        let (value, similarity) = index.nearest(&[0.9, 0.1, 0.0], 0.9).unwrap();
+        assert_eq!(value, "rust");
+        assert!(similarity > 0.99);
+
+        // Nothing is similar enough to an orthogonal query
+        assert!(index.nearest(&[0.0, 0.0, 1.0], 0.9).is_none());
+        // Mismatched dimensions and zero vectors never match
+        assert!(index.nearest(&[1.0, 0.0], 0.0).is_none());
+        assert!(index.nearest(&[0.0, 0.0, 0.0], 0.0).is_none());
+
+        // Entries that do not match are passed over for the next nearest
+        let (value, _) = index.nearest_matching(&[0.9, 0.1, 0.0], 0.0, |value| *value != "rust").unwrap();
+        assert_eq!(value, "python");
+        assert_eq!(index.values().count(), 2);
+    }
+
+    #[test]
+    fn test_vector_index_capacity() {
+        let mut index = VectorIndex::new(2, Duration::from_secs(60));
+        index.insert(vec![1.0, 0.0], "a");
+        index.insert(vec![0.0, 1.0], "b");
+        index.insert(vec![1.0, 1.0], "c");
+
+        // The oldest entry made room for the newest
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.nearest(&[1.0, 0.0], 0.99), None);
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+
+        assert_eq!(index.retain(|value, _| *value != "b"), 1);
+        assert_eq!(index.len(), 1);
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,155 @@
+use crate::config::{CacheConfig, Config};
+use crate::llm::GenerationOptions;
+use crate::utils::log_policy::question_hash;
+
+/// Suffixes removed by the light stemmer, longest first
+const SUFFIXES: &[&str] = &["ing", "ies", "ed", "es", "ly", "s"];
+
+/// Shortest stem the stemmer will leave behind
+const MIN_STEM_LEN: usize = 3;
+
+/// Builds canonical cache keys so that differently written forms of the same
+/// question share one entry. The key is only used for lookups; the prompt
+/// sent to the backend is left as the user wrote it.
+#[derive(Debug, Clone)]
+pub struct CacheKeyNormalizer {
+    enabled: bool,
+    stemming: bool,
+}
+
+impl CacheKeyNormalizer {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            enabled: config.normalize_keys,
+            stemming: config.stemming,
+        }
+    }
+
+    pub fn normalize(&self, question: &str) -> String {
+        if !self.enabled {
+            return question.to_string();
+        }
+
+        // Lowercase and turn punctuation into word breaks. Non-ASCII symbols
+        // are kept, since they include the combining marks of decoded IDN labels.
+        let cleaned: String = question
+            .chars()
+            .flat_map(char::to_lowercase)
+            .map(|c| {
+                if c.is_whitespace() || c.is_ascii_punctuation() || c.is_control() {
+                    ' '
+                } else {
+                    c
+                }
+            })
+            .collect();
+
+        // Collapse whitespace, optionally stemming each word
+        cleaned
+            .split_whitespace()
+            .map(|word| if self.stemming { stem(word) } else { word })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Short identifier of the settings an answer is generated with: the
+/// backend, model, system prompt, sampling settings and
+/// `cache.prompt_version`. Cache keys start with it, so answers stop being
+/// served once any of these changes, on this instance or another sharing
+/// its cache.
+pub fn generation_fingerprint(config: &Config, generation: &GenerationOptions) -> String {
+    let llm = &config.llm;
+    let (temperature, seed) = if llm.deterministic {
+        (0.0, Some(llm.seed))
+    } else {
+        (generation.temperature_or(llm.temperature), generation.seed)
+    };
+    let settings = [
+        llm.backend.name().to_string(),
+        generation.model_or(&llm.model).to_string(),
+        generation.system_prompt.clone().unwrap_or_default(),
+        temperature.to_string(),
+        seed.map(|seed| seed.to_string()).unwrap_or_default(),
+        llm.max_tokens.to_string(),
+        config.cache.prompt_version.clone().unwrap_or_default(),
+    ];
+    question_hash(&settings.join("\0"))
+}
+
+/// Strip one common English suffix, keeping at least `MIN_STEM_LEN` characters
+fn stem(word: &str) -> &str {
+    for suffix in SUFFIXES {
+        if let Some(stem) = word.strip_suffix(suffix) {
+            if stem.chars().count() >= MIN_STEM_LEN {
+                return stem;
+            }
+        }
+    }
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalizer(stemming: bool) -> CacheKeyNormalizer {
+        CacheKeyNormalizer::new(&CacheConfig {
+            normalize_keys: true,
+            stemming,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_equivalent_questions_share_a_key() {
+        let normalizer = normalizer(false);
+        assert_eq!(normalizer.normalize("What is Rust?"), "what is rust");
+        assert_eq!(normalizer.normalize("what-is-rust"), "what is rust");
+        assert_eq!(normalizer.normalize("  WHAT   is\trust!! "), "what is rust");
+        assert_eq!(normalizer.normalize("Nasıl, Ankara?"), "nasıl ankara");
+    }
+
+    #[test]
+    fn test_stemming() {
+        let normalizer = normalizer(true);
+        assert_eq!(
+            normalizer.normalize("Cooking boiled eggs"),
+            normalizer.normalize("cook boil egg")
+        );
+        // Short words are left alone
+        assert_eq!(normalizer.normalize("is a bus"), "is a bus");
+    }
+
+    #[test]
+    fn test_generation_fingerprint() {
+        let mut config = Config::default();
+        let generation = GenerationOptions::default();
+        let fingerprint = generation_fingerprint(&config, &generation);
+        assert_eq!(fingerprint.len(), 16);
+        assert_eq!(generation_fingerprint(&config.clone(), &generation), fingerprint);
+
+        let other_model = GenerationOptions {
+            model: Some("gpt-4o".to_string()),
+            ..Default::default()
+        };
+        let other_prompt = GenerationOptions {
+            system_prompt: Some("Answer in French.".to_string()),
+            ..Default::default()
+        };
+        assert_ne!(generation_fingerprint(&config, &other_model), fingerprint);
+        assert_ne!(generation_fingerprint(&config, &other_prompt), fingerprint);
+
+        config.cache.prompt_version = Some("2".to_string());
+        assert_ne!(generation_fingerprint(&config, &generation), fingerprint);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let normalizer = CacheKeyNormalizer::new(&CacheConfig {
+            normalize_keys: false,
+            ..Default::default()
+        });
+        assert_eq!(normalizer.normalize("What is Rust?"), "What is Rust?");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,222 @@
+use crate::llm::{Generation, GenerationOptions, LlmBackend, TokenUsage};
+use crate::utils::tools::ToolRegistry;
+use crate::Error;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// One backend call and its answer, a line of a cassette file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CassetteEntry {
+    pub prompt: String,
+    /// Model asked for, when it was not the configured one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    pub response: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+}
+
+/// What a recorded answer is looked up by: the prompt, model and system
+/// prompt. Temperature and seed are left out, so a cassette still plays
+/// back after sampling settings change.
+type CassetteKey = (String, Option<String>, Option<String>);
+
+fn key(prompt: &str, options: &GenerationOptions) -> CassetteKey {
+    (prompt.to_string(), options.model.clone(), options.system_prompt.clone())
+}
+
+/// Appends calls to a cassette file, shared by every endpoint of the pool
+pub struct CassetteRecorder {
+    path: PathBuf,
+    /// Held while a line is appended, so lines never interleave
+    file: Mutex<()>,
+}
+
+impl CassetteRecorder {
+    pub fn new(path: &str) -> Self {
+        info!("Recording LLM calls to {}", path);
+        Self {
+            path: PathBuf::from(path),
+            file: Mutex::new(()),
+        }
+    }
+
+    async fn record(&self, entry: &CassetteEntry) -> Result<()> {
+        let _file = self.file.lock().await;
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).await?;
+            }
+        }
+
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Passes calls on to a backend and records every answer it gives
+pub struct RecordingBackend {
+    inner: Box<dyn LlmBackend>,
+    recorder: Arc<CassetteRecorder>,
+}
+
+impl RecordingBackend {
+    pub fn new(inner: Box<dyn LlmBackend>, recorder: Arc<CassetteRecorder>) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+#[async_trait]
+impl LlmBackend for RecordingBackend {
+    async fn generate_response(&self, prompt: &str) -> Result<String> {
+        Ok(self.generate_with_usage(prompt, &GenerationOptions::default(), None).await?.text)
+    }
+
+    async fn generate_with_options(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        Ok(self.generate_with_usage(prompt, options, None).await?.text)
+    }
+
+    async fn generate_with_tools(
+        &self,
+        prompt: &str,
+        options: &GenerationOptions,
+        tools: &ToolRegistry,
+    ) -> Result<String> {
+        Ok(self.generate_with_usage(prompt, options, Some(tools)).await?.text)
+    }
+
+    async fn generate_with_usage(
+        &self,
+        prompt: &str,
+        options: &GenerationOptions,
+        tools: Option<&ToolRegistry>,
+    ) -> Result<Generation> {
+        let generation = self.inner.generate_with_usage(prompt, options, tools).await?;
+        let entry = CassetteEntry {
+            prompt: prompt.to_string(),
+            model: options.model.clone(),
+            system_prompt: options.system_prompt.clone(),
+            response: generation.text.clone(),
+            usage: generation.usage,
+        };
+        if let Err(e) = self.recorder.record(&entry).await {
+            warn!("Could not record LLM call to {}: {}", self.recorder.path.display(), e);
+        }
+        Ok(generation)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+
+    async fn prepare(&self) -> Result<()> {
+        self.inner.prepare().await
+    }
+}
+
+/// Answers from a cassette instead of a backend, never touching the network.
+/// Where a prompt was recorded more than once the last answer is given.
+pub struct ReplayBackend {
+    path: String,
+    answers: HashMap<CassetteKey, CassetteEntry>,
+}
+
+impl ReplayBackend {
+    pub fn open(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::Configuration(format!("Could not read cassette {}: {}", path, e)))?;
+
+        let mut answers = HashMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: CassetteEntry = serde_json::from_str(line)
+                .map_err(|e| Error::Configuration(format!("Invalid cassette {} line {}: {}", path, number + 1, e)))?;
+            answers.insert((entry.prompt.clone(), entry.model.clone(), entry.system_prompt.clone()), entry);
+        }
+        info!("Replaying {} recorded LLM answers from {}", answers.len(), path);
+
+        Ok(Self {
+            path: path.to_string(),
+            answers,
+        })
+    }
+}
+
+#[async_trait]
+impl LlmBackend for ReplayBackend {
+    async fn generate_response(&self, prompt: &str) -> Result<String> {
+        Ok(self.generate_with_usage(prompt, &GenerationOptions::default(), None).await?.text)
+    }
+
+    async fn generate_with_options(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        Ok(self.generate_with_usage(prompt, options, None).await?.text)
+    }
+
+    async fn generate_with_usage(
+        &self,
+        prompt: &str,
+        options: &GenerationOptions,
+        _tools: Option<&ToolRegistry>,
+    ) -> Result<Generation> {
+        match self.answers.get(&key(prompt, options)) {
+            Some(entry) => Ok(Generation {
+                text: entry.response.clone(),
+                usage: entry.usage,
+            }),
+            None => {
+                Err(Error::LlmApi(format!("Cassette {} has no answer recorded for {:?}", self.path, prompt)).into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MockConfig;
+    use crate::llm::MockBackend;
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let path = std::env::temp_dir().join(format!("llmdig-cassette-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_string_lossy().into_owned();
+
+        // Echoes the prompt back
+        let mock = MockBackend::new(MockConfig::default()).unwrap();
+        let recording = RecordingBackend::new(Box::new(mock), Arc::new(CassetteRecorder::new(&path)));
+        let options = GenerationOptions {
+            model: Some("small".to_string()),
+            ..GenerationOptions::default()
+        };
+        assert_eq!(recording.generate_with_options("what is dns", &options).await.unwrap(), "what is dns");
+        assert_eq!(recording.generate_response("what is rust").await.unwrap(), "what is rust");
+
+        let replay = ReplayBackend::open(&path).unwrap();
+        assert_eq!(replay.generate_with_options("what is dns", &options).await.unwrap(), "what is dns");
+        assert_eq!(replay.generate_response("what is rust").await.unwrap(), "what is rust");
+        // Only calls made with the same model are played back
+        assert!(replay.generate_response("what is dns").await.is_err());
+        assert!(replay.generate_response("what is tls").await.is_err());
+
+        std::fs::write(&path, "{\"prompt\": \"what is dns\"}\n").unwrap();
+        assert!(ReplayBackend::open(&path).unwrap_err().to_string().contains("line 1"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
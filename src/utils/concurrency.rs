@@ -0,0 +1,149 @@
+use crate::config::{OverflowConfig, OverflowPolicy};
+use crate::utils::metrics::Metrics;
+use crate::Error;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+
+/// Caps how many LLM requests are in flight at once.
+///
+/// Requests over the cap either wait for a free slot until a deadline or
+/// are shed immediately, depending on the overflow policy. Either way a
+/// rejected request fails with `Error::Overloaded`.
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    /// What is being limited, for error messages
+    scope: String,
+    limit: usize,
+    /// `None` when unlimited
+    semaphore: Option<Arc<Semaphore>>,
+    policy: OverflowPolicy,
+    queue_timeout: Duration,
+}
+
+impl ConcurrencyLimiter {
+    /// A limit of 0 lets every request through
+    pub fn new(scope: &str, limit: usize, overflow: &OverflowConfig) -> Self {
+        Self {
+            scope: scope.to_string(),
+            limit,
+            semaphore: (limit > 0).then(|| Arc::new(Semaphore::new(limit))),
+            policy: overflow.policy.clone(),
+            queue_timeout: Duration::from_millis(overflow.queue_timeout_ms),
+        }
+    }
+
+    /// Whether a request would get a slot without waiting
+    pub fn has_capacity(&self) -> bool {
+        match &self.semaphore {
+            Some(semaphore) => semaphore.available_permits() > 0,
+            None => true,
+        }
+    }
+
+    /// Wait for a slot, which is held until the returned permit is dropped
+    pub async fn acquire(&self, metrics: &Metrics) -> Result<Option<OwnedSemaphorePermit>> {
+        let Some(semaphore) = &self.semaphore else {
+            return Ok(None);
+        };
+
+        if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+            return Ok(Some(permit));
+        }
+
+        if let OverflowPolicy::Queue = self.policy {
+            let queued = {
+                let _waiting = QueueDepthGuard::new(metrics);
+                timeout(self.queue_timeout, semaphore.clone().acquire_owned()).await
+            };
+
+            if let Ok(Ok(permit)) = queued {
+                return Ok(Some(permit));
+            }
+        }
+
+        metrics.increment_shed_llm_requests();
+        Err(Error::Overloaded(format!(
+            "{} LLM requests to {} already in flight",
+            self.limit, self.scope
+        ))
+        .into())
+    }
+}
+
+/// Counts a request in the queue depth while it waits, including when the
+/// waiting request is cancelled
+struct QueueDepthGuard<'a>(&'a Metrics);
+
+impl<'a> QueueDepthGuard<'a> {
+    fn new(metrics: &'a Metrics) -> Self {
+        metrics.increment_llm_queue_depth();
+        Self(metrics)
+    }
+}
+
+impl Drop for QueueDepthGuard<'_> {
+    fn drop(&mut self) {
+        self.0.decrement_llm_queue_depth();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    fn overflow(policy: OverflowPolicy, queue_timeout_ms: u64) -> OverflowConfig {
+        OverflowConfig {
+            policy,
+            queue_timeout_ms,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shed_over_limit() {
+        let metrics = Metrics::new();
+        let limiter = ConcurrencyLimiter::new("all endpoints", 1, &overflow(OverflowPolicy::Shed, 1000));
+
+        let permit = limiter.acquire(&metrics).await.unwrap();
+        let error = limiter.acquire(&metrics).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<Error>(), Some(Error::Overloaded(_))));
+        assert_eq!(metrics.shed_llm_requests.load(Ordering::Relaxed), 1);
+
+        drop(permit);
+        assert!(limiter.acquire(&metrics).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queue_waits_for_slot() {
+        let metrics = Arc::new(Metrics::new());
+        let limiter = Arc::new(ConcurrencyLimiter::new("all endpoints", 1, &overflow(OverflowPolicy::Queue, 1000)));
+        let permit = limiter.acquire(&metrics).await.unwrap();
+
+        let waiter = {
+            let (limiter, metrics) = (limiter.clone(), metrics.clone());
+            tokio::spawn(async move { limiter.acquire(&metrics).await.is_ok() })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(metrics.llm_queue_depth.load(Ordering::Relaxed), 1);
+
+        drop(permit);
+        assert!(waiter.await.unwrap());
+        assert_eq!(metrics.llm_queue_depth.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_queue_deadline_and_unlimited() {
+        let metrics = Metrics::new();
+        let limiter = ConcurrencyLimiter::new("all endpoints", 1, &overflow(OverflowPolicy::Queue, 20));
+        let _permit = limiter.acquire(&metrics).await.unwrap();
+        assert!(limiter.acquire(&metrics).await.is_err());
+
+        let unlimited = ConcurrencyLimiter::new("all endpoints", 0, &overflow(OverflowPolicy::Shed, 0));
+        for _ in 0..100 {
+            assert!(unlimited.acquire(&metrics).await.unwrap().is_none());
+        }
+    }
+}
\ No newline at end of file
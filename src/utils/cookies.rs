@@ -0,0 +1,210 @@
+use crate::config::CookiesConfig;
+use crate::utils::rate_limiter::RateLimiter;
+use crate::utils::retention::PurgeFilter;
+use crate::Error;
+use anyhow::Result;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CLIENT_COOKIE_LEN: usize = 8;
+const SERVER_COOKIE_LEN: usize = 16;
+/// Longest server cookie a client may echo back (RFC 7873 §4)
+const MAX_SERVER_COOKIE_LEN: usize = 32;
+/// Server cookie layout version (RFC 9018)
+const COOKIE_VERSION: u8 = 1;
+/// Server cookies are accepted for an hour after they are issued
+const COOKIE_LIFETIME_SECS: u32 = 3600;
+/// and up to five minutes ahead, for clock skew between instances
+const COOKIE_SKEW_SECS: u32 = 300;
+
+/// What to do with a query given its COOKIE option
+#[derive(Debug, PartialEq)]
+pub enum CookieVerdict {
+    /// Answer the query, returning this COOKIE option data if any
+    Accept(Option<Vec<u8>>),
+    /// The option is malformed
+    FormErr,
+    /// Refuse with BADCOOKIE so the client retries with this cookie
+    BadCookie(Vec<u8>),
+    /// Answer with the TC bit and nothing else, so the client retries over TCP
+    Truncate,
+}
+
+/// Issues and checks server cookies (RFC 7873) in the interoperable format
+/// of RFC 9018, with HMAC-SHA256 in place of SipHash.
+///
+/// A valid server cookie proves the client has received an answer at its
+/// address before. Clients without one are held to a per-address limit
+/// over UDP, so a flood with spoofed sources is turned away with tiny
+/// responses instead of LLM calls.
+pub struct DnsCookies {
+    secret: Vec<u8>,
+    /// `None` when cookie-less clients are never turned away
+    cookieless_limiter: Option<RateLimiter<IpAddr>>,
+}
+
+impl DnsCookies {
+    pub fn new(config: &CookiesConfig) -> Result<Self> {
+        let secret = match &config.secret {
+            Some(secret) => base64::engine::general_purpose::STANDARD
+                .decode(secret.expose().trim())
+                .map_err(|e| Error::Configuration(format!("Invalid cookie secret: {}", e)))?,
+            None => rand::random::<[u8; 32]>().to_vec(),
+        };
+        if secret.len() < 16 {
+            return Err(Error::Configuration("Cookie secret must be at least 16 bytes".to_string()).into());
+        }
+
+        let cookieless_limiter = (config.cookieless_requests_per_minute > 0).then(|| {
+            RateLimiter::new(config.cookieless_requests_per_minute, config.cookieless_burst.max(1))
+        });
+
+        Ok(Self {
+            secret,
+            cookieless_limiter,
+        })
+    }
+
+    /// Check the COOKIE option data of a query from `client`. Queries over
+    /// TCP have proven their address already and are never turned away.
+    pub async fn verify(&self, option: Option<&[u8]>, client: IpAddr, tcp: bool) -> CookieVerdict {
+        let Some(option) = option else {
+            return if tcp || self.allow_cookieless(client).await {
+                CookieVerdict::Accept(None)
+            } else {
+                CookieVerdict::Truncate
+            };
+        };
+
+        // A client cookie, alone or with a server cookie of 8 to 32 bytes
+        let server_lengths = CLIENT_COOKIE_LEN + 8..=CLIENT_COOKIE_LEN + MAX_SERVER_COOKIE_LEN;
+        if option.len() != CLIENT_COOKIE_LEN && !server_lengths.contains(&option.len()) {
+            return CookieVerdict::FormErr;
+        }
+
+        let (client_cookie, server_cookie) = option.split_at(CLIENT_COOKIE_LEN);
+        let reply = self.reply(client_cookie, client, now());
+        if self.is_valid(client_cookie, server_cookie, client, now()) || tcp || self.allow_cookieless(client).await {
+            CookieVerdict::Accept(Some(reply))
+        } else {
+            CookieVerdict::BadCookie(reply)
+        }
+    }
+
+    async fn allow_cookieless(&self, client: IpAddr) -> bool {
+        match &self.cookieless_limiter {
+            Some(limiter) => limiter.allow_request(client).await,
+            None => true,
+        }
+    }
+
+    /// Drop the cookieless rate-limit buckets `filter` matches
+    pub async fn forget(&self, filter: &PurgeFilter) -> usize {
+        match &self.cookieless_limiter {
+            Some(limiter) => limiter.forget(|client, idle| filter.matches(Some(*client), idle)).await,
+            None => 0,
+        }
+    }
+
+    /// COOKIE option data for a response: the client cookie followed by a
+    /// fresh server cookie
+    fn reply(&self, client_cookie: &[u8], client: IpAddr, timestamp: u32) -> Vec<u8> {
+        let mut reply = Vec::with_capacity(CLIENT_COOKIE_LEN + SERVER_COOKIE_LEN);
+        reply.extend_from_slice(client_cookie);
+        reply.extend_from_slice(&self.server_cookie(client_cookie, client, timestamp));
+        reply
+    }
+
+    /// Version, three reserved bytes, timestamp and an 8-byte hash
+    fn server_cookie(&self, client_cookie: &[u8], client: IpAddr, timestamp: u32) -> [u8; SERVER_COOKIE_LEN] {
+        let mut cookie = [0u8; SERVER_COOKIE_LEN];
+        cookie[0] = COOKIE_VERSION;
+        cookie[4..8].copy_from_slice(&timestamp.to_be_bytes());
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC takes keys of any length");
+        mac.update(client_cookie);
+        mac.update(&cookie[..8]);
+        match client {
+            IpAddr::V4(ip) => mac.update(&ip.octets()),
+            IpAddr::V6(ip) => mac.update(&ip.octets()),
+        }
+        cookie[8..].copy_from_slice(&mac.finalize().into_bytes()[..8]);
+        cookie
+    }
+
+    fn is_valid(&self, client_cookie: &[u8], server_cookie: &[u8], client: IpAddr, now: u32) -> bool {
+        if server_cookie.len() != SERVER_COOKIE_LEN || server_cookie[0] != COOKIE_VERSION {
+            return false;
+        }
+
+        // Serial number arithmetic, so the check survives the timestamp wrapping
+        let timestamp = u32::from_be_bytes([server_cookie[4], server_cookie[5], server_cookie[6], server_cookie[7]]);
+        let age = now.wrapping_sub(timestamp);
+        if age > COOKIE_LIFETIME_SECS && timestamp.wrapping_sub(now) > COOKIE_SKEW_SECS {
+            return false;
+        }
+
+        self.server_cookie(client_cookie, client, timestamp)[..] == *server_cookie
+    }
+}
+
+fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs() as u32).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT_COOKIE: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    fn cookies(cookieless_requests_per_minute: usize) -> DnsCookies {
+        DnsCookies::new(&CookiesConfig {
+            enabled: true,
+            cookieless_requests_per_minute,
+            cookieless_burst: 1,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_server_cookie_validation() {
+        let cookies = cookies(0);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let issued = now() - 60;
+        let server_cookie = cookies.server_cookie(&CLIENT_COOKIE, client, issued);
+
+        assert!(cookies.is_valid(&CLIENT_COOKIE, &server_cookie, client, now()));
+        // Bound to the client's address and cookie, and to the secret
+        assert!(!cookies.is_valid(&CLIENT_COOKIE, &server_cookie, "192.0.2.2".parse().unwrap(), now()));
+        assert!(!cookies.is_valid(&[0; 8], &server_cookie, client, now()));
+        assert!(!DnsCookies::new(&CookiesConfig::default()).unwrap().is_valid(&CLIENT_COOKIE, &server_cookie, client, now()));
+        // Expired
+        assert!(!cookies.is_valid(&CLIENT_COOKIE, &server_cookie, client, issued + COOKIE_LIFETIME_SECS + 1));
+    }
+
+    #[tokio::test]
+    async fn test_verdicts() {
+        let cookies = cookies(1);
+        let client: IpAddr = "2001:db8::1".parse().unwrap();
+
+        // One cookie-less query gets through, the next is pushed to TCP
+        assert_eq!(cookies.verify(None, client, false).await, CookieVerdict::Accept(None));
+        assert_eq!(cookies.verify(None, client, false).await, CookieVerdict::Truncate);
+        assert_eq!(cookies.verify(None, client, true).await, CookieVerdict::Accept(None));
+
+        // A client cookie alone earns a server cookie to retry with
+        let CookieVerdict::BadCookie(reply) = cookies.verify(Some(&CLIENT_COOKIE), client, false).await else {
+            panic!("expected BADCOOKIE");
+        };
+        assert_eq!(reply.len(), CLIENT_COOKIE_LEN + SERVER_COOKIE_LEN);
+        assert!(matches!(cookies.verify(Some(&reply), client, false).await, CookieVerdict::Accept(Some(_))));
+
+        assert_eq!(cookies.verify(Some(&[1, 2, 3]), client, false).await, CookieVerdict::FormErr);
+        assert_eq!(cookies.verify(Some(&[0; 12]), client, false).await, CookieVerdict::FormErr);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,171 @@
+use crate::config::{Config, EmbeddingProvider};
+use crate::llm::http_client;
+use crate::utils::llm_keys::LlmKeyRing;
+use crate::Error;
+use anyhow::Result;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, instrument};
+
+const OPENAI_EMBEDDINGS_URL: &str = "https://api.openai.com/v1/embeddings";
+
+/// Computes text embeddings for the semantic cache and document retrieval,
+/// either through the OpenAI embeddings API or a local model served behind a
+/// custom endpoint
+pub struct Embedder {
+    client: Client,
+    provider: EmbeddingProvider,
+    model: String,
+    openai_url: String,
+    /// The LLM provider's keys, used for OpenAI embeddings
+    keys: Arc<LlmKeyRing>,
+}
+
+impl Embedder {
+    /// Embedder configured by `semantic_cache`
+    pub fn new(config: &Config, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        let semantic = &config.semantic_cache;
+        Self::with_model(config, &semantic.provider, &semantic.model, keys)
+    }
+
+    pub fn with_model(
+        config: &Config,
+        provider: &EmbeddingProvider,
+        model: &str,
+        keys: Arc<LlmKeyRing>,
+    ) -> Result<Self> {
+        if matches!(provider, EmbeddingProvider::OpenAI) && keys.candidates().is_empty() {
+            return Err(Error::Configuration("OpenAI embeddings require an API key".to_string()).into());
+        }
+
+        let client = http_client(config)?;
+
+        Ok(Self {
+            client,
+            provider: provider.clone(),
+            model: model.to_string(),
+            openai_url: OPENAI_EMBEDDINGS_URL.to_string(),
+            keys,
+        })
+    }
+
+    #[instrument(name = "embeddings", skip_all, fields(model = %self.model))]
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        match &self.provider {
+            EmbeddingProvider::OpenAI => self.embed_openai(text).await,
+            EmbeddingProvider::Custom(url) => self.embed_custom(url, text).await,
+        }
+    }
+
+    async fn embed_openai(&self, text: &str) -> Result<Vec<f32>> {
+        let request = OpenAiEmbeddingRequest {
+            model: self.model.clone(),
+            input: text.to_string(),
+        };
+
+        let response = self
+            .keys
+            .send(|| self.client.post(&self.openai_url).json(&request))
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            error!("OpenAI embeddings error: {}", error_text);
+            return Err(Error::LlmApi(error_text).into());
+        }
+
+        let response: OpenAiEmbeddingResponse = response.json().await?;
+        response
+            .data
+            .into_iter()
+            .next()
+            .map(|data| data.embedding)
+            .ok_or_else(|| Error::LlmApi("Empty embeddings response".to_string()).into())
+    }
+
+    async fn embed_custom(&self, url: &str, text: &str) -> Result<Vec<f32>> {
+        let request = CustomEmbeddingRequest {
+            prompt: text.to_string(),
+            model: self.model.clone(),
+        };
+
+        let response = self.client.post(url).json(&request).send().await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            error!("Custom embeddings error: {}", error_text);
+            return Err(Error::LlmApi(error_text).into());
+        }
+
+        let response: CustomEmbeddingResponse = response.json().await?;
+        Ok(response.embedding)
+    }
+}
+
+#[derive(Serialize)]
+struct OpenAiEmbeddingRequest {
+    model: String,
+    input: String,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbeddingResponse {
+    data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    embedding: Vec<f32>,
+}
+
+#[derive(Serialize)]
+struct CustomEmbeddingRequest {
+    prompt: String,
+    model: String,
+}
+
+#[derive(Deserialize)]
+struct CustomEmbeddingResponse {
+    embedding: Vec<f32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_openai_embeddings_follow_key_rotation() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/embeddings"))
+            .and(header("authorization", "Bearer sk-embed-new-0002"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": [{ "embedding": [0.6, 0.8] }] })))
+            .mount(&server)
+            .await;
+        // Every other key has been revoked
+        Mock::given(method("POST"))
+            .and(path("/embeddings"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+
+        let keys = Arc::new(LlmKeyRing::new(
+            Some(&"sk-embed-old-0001".into()),
+            Some(&"sk-embed-new-0002".into()),
+        ));
+        let mut embedder = Embedder::with_model(&Config::default(), &EmbeddingProvider::OpenAI, "test", keys.clone()).unwrap();
+        embedder.openai_url = format!("{}/embeddings", server.uri());
+
+        // The revoked primary fails over to the secondary, and once that is
+        // promoted it is the only key sent
+        assert_eq!(embedder.embed("what is rust").await.unwrap(), vec![0.6, 0.8]);
+        assert!(keys.status().failed_over);
+        keys.promote_secondary().unwrap();
+        assert_eq!(embedder.embed("what is rust").await.unwrap(), vec![0.6, 0.8]);
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::config::{FaqConfig, FaqEntryConfig, FaqMatch};
+use crate::llm::MAX_TXT_ANSWER;
+use crate::Error;
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+
+enum Matcher {
+    Exact(String),
+    Pattern(Regex),
+}
+
+/// Operator-written answers to common questions, such as the office wifi
+/// password, served at no token cost
+#[derive(Default)]
+pub struct FaqTable {
+    entries: Vec<(Matcher, String)>,
+}
+
+impl FaqTable {
+    pub fn new(config: &FaqConfig) -> Result<Self> {
+        if !config.enabled {
+            return Ok(Self::default());
+        }
+
+        let mut entries = config.entries.clone();
+        if let Some(path) = &config.file {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| Error::Configuration(format!("Could not read FAQ file {}: {}", path, e)))?;
+            let more: Vec<FaqEntryConfig> = serde_yaml::from_str(&contents)
+                .map_err(|e| Error::Configuration(format!("Invalid FAQ file {}: {}", path, e)))?;
+            entries.extend(more);
+        }
+
+        let entries = entries
+            .into_iter()
+            .map(|entry| Ok((Self::matcher(&entry)?, entry.answer)))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some((_, answer)) = entries.iter().find(|(_, answer)| answer.is_empty() || answer.len() > MAX_TXT_ANSWER) {
+            let message = format!("FAQ answers must have 1 to {} bytes: {:?}", MAX_TXT_ANSWER, answer);
+            return Err(Error::Configuration(message).into());
+        }
+        Ok(Self { entries })
+    }
+
+    fn matcher(entry: &FaqEntryConfig) -> Result<Matcher> {
+        let pattern = match entry.match_kind {
+            FaqMatch::Exact => return Ok(Matcher::Exact(normalize(&entry.pattern))),
+            FaqMatch::Glob => glob_to_regex(&entry.pattern),
+            FaqMatch::Regex => entry.pattern.clone(),
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build()
+            .map(Matcher::Pattern)
+            .map_err(|e| Error::Configuration(format!("Invalid FAQ pattern {}: {}", entry.pattern, e)).into())
+    }
+
+    /// The answer of the first entry matching `question`
+    pub fn lookup(&self, question: &str) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        let question = normalize(question);
+        self.entries
+            .iter()
+            .find(|(matcher, _)| match matcher {
+                Matcher::Exact(pattern) => *pattern == question,
+                Matcher::Pattern(regex) => regex.is_match(&question),
+            })
+            .map(|(_, answer)| answer.as_str())
+    }
+}
+
+/// Lowercase with single spaces between words and no trailing question mark
+pub(crate) fn normalize(question: &str) -> String {
+    let question = question.to_lowercase();
+    let words: Vec<_> = question.split_whitespace().collect();
+    words.join(" ").trim_end_matches('?').trim_end().to_string()
+}
+
+/// An anchored regex matching what the glob `pattern` matches
+fn glob_to_regex(pattern: &str) -> String {
+    let words: Vec<_> = pattern.split_whitespace().collect();
+    let mut regex = "^".to_string();
+    for c in words.join(" ").chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(match_kind: FaqMatch, pattern: &str, answer: &str) -> FaqEntryConfig {
+        FaqEntryConfig {
+            match_kind,
+            pattern: pattern.to_string(),
+            answer: answer.to_string(),
+        }
+    }
+
+    fn table(entries: Vec<FaqEntryConfig>) -> Result<FaqTable> {
+        FaqTable::new(&FaqConfig {
+            enabled: true,
+            entries,
+            ..FaqConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_lookup() {
+        let table = table(vec![
+            entry(FaqMatch::Exact, "What is the wifi password?", "hunter2"),
+            entry(FaqMatch::Glob, "where is the * room", "Second floor"),
+            entry(FaqMatch::Regex, r"\b(lunch|canteen)\b", "12:00 to 14:00"),
+        ])
+        .unwrap();
+
+        assert_eq!(table.lookup("what is the  WIFI password"), Some("hunter2"));
+        assert_eq!(table.lookup("what is the wifi password for guests"), None);
+        assert_eq!(table.lookup("where is the meeting room"), Some("Second floor"));
+        assert_eq!(table.lookup("where is the room"), None);
+        assert_eq!(table.lookup("when does the canteen open"), Some("12:00 to 14:00"));
+        assert_eq!(table.lookup("what is lunchtime"), None);
+    }
+
+    #[test]
+    fn test_invalid_entries() {
+        assert!(table(vec![entry(FaqMatch::Regex, "(unclosed", "answer")]).is_err());
+        assert!(table(vec![entry(FaqMatch::Exact, "question", "")]).is_err());
+        assert!(table(vec![entry(FaqMatch::Exact, "question", &"a".repeat(MAX_TXT_ANSWER + 1))]).is_err());
+
+        // Nothing is looked up while the table is off
+        let config = FaqConfig {
+            entries: vec![entry(FaqMatch::Exact, "question", "answer")],
+            ..FaqConfig::default()
+        };
+        assert_eq!(FaqTable::new(&config).unwrap().lookup("question"), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,378 @@
+use crate::config::{ForwardingConfig, UpstreamConfig, UpstreamTransport};
+use crate::Error;
+use anyhow::Result;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+use tokio_native_tls::{native_tls, TlsConnector};
+use tracing::{debug, warn};
+
+/// Largest UDP response accepted from the upstream (EDNS0 payloads included)
+const MAX_UDP_RESPONSE: usize = 4096;
+
+/// Media type of DNS messages carried over HTTPS
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// Relays raw DNS messages to upstream resolvers.
+///
+/// Queries are sent as-is, so the upstream response already carries the
+/// client's message id and can be returned without re-encoding. Upstreams
+/// are tried in order until one answers. Truncated UDP answers are retried
+/// over TCP.
+#[derive(Clone)]
+pub struct Forwarder {
+    upstreams: Vec<Upstream>,
+    timeout: Duration,
+}
+
+#[derive(Clone)]
+struct Upstream {
+    /// Address as configured, for logs and errors
+    name: String,
+    transport: Transport,
+}
+
+#[derive(Clone)]
+enum Transport {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+    Tls {
+        /// Host name and port to resolve, or the bootstrap address
+        connect: (String, u16),
+        /// Name the certificate must be valid for
+        server_name: String,
+        connector: TlsConnector,
+    },
+    Https {
+        url: String,
+        client: reqwest::Client,
+    },
+}
+
+impl Forwarder {
+    pub fn new(config: &ForwardingConfig) -> Result<Self> {
+        let upstreams = if config.upstreams.is_empty() {
+            vec![Upstream {
+                name: config.upstream.clone(),
+                transport: Transport::Udp(parse_upstream(&config.upstream)?),
+            }]
+        } else {
+            config.upstreams.iter().map(Upstream::new).collect::<Result<_>>()?
+        };
+
+        Ok(Self {
+            upstreams,
+            timeout: Duration::from_millis(config.timeout_ms.max(1)),
+        })
+    }
+
+    /// Forward `query` and return the first upstream response's bytes
+    pub async fn forward(&self, query: &[u8]) -> Result<Vec<u8>> {
+        if query.len() < 12 {
+            return Err(Error::Dns("Query too short to forward".to_string()).into());
+        }
+
+        let mut last_error = None;
+        for (i, upstream) in self.upstreams.iter().enumerate() {
+            let error = match timeout(self.timeout, upstream.exchange(query)).await {
+                Ok(Ok(response)) => {
+                    debug!("Upstream {} answered", upstream.name);
+                    return Ok(response);
+                }
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => "timed out".to_string(),
+            };
+            if i + 1 < self.upstreams.len() {
+                warn!("Upstream {} failed ({}), trying the next one", upstream.name, error);
+            }
+            last_error = Some(format!("{}: {}", upstream.name, error));
+        }
+
+        Err(Error::Network(format!(
+            "No upstream answered ({})",
+            last_error.unwrap_or_else(|| "none configured".to_string())
+        ))
+        .into())
+    }
+}
+
+impl std::fmt::Debug for Forwarder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = self.upstreams.iter().map(|upstream| upstream.name.as_str()).collect();
+        f.debug_struct("Forwarder")
+            .field("upstreams", &names)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Upstream {
+    fn new(config: &UpstreamConfig) -> Result<Self> {
+        let bootstrap = config
+            .bootstrap
+            .as_deref()
+            .map(|ip| {
+                ip.parse::<IpAddr>()
+                    .map_err(|_| Error::Configuration(format!("Invalid bootstrap address: {}", ip)))
+            })
+            .transpose()?;
+
+        let transport = match config.transport {
+            UpstreamTransport::Udp => Transport::Udp(parse_upstream(&config.address)?),
+            UpstreamTransport::Tcp => Transport::Tcp(parse_upstream(&config.address)?),
+            UpstreamTransport::Tls => {
+                let (host, port) = split_host_port(&config.address, 853)?;
+                let connector = native_tls::TlsConnector::new()
+                    .map_err(|e| Error::Configuration(format!("Could not set up TLS: {}", e)))?;
+                Transport::Tls {
+                    connect: (bootstrap.map_or_else(|| host.clone(), |ip| ip.to_string()), port),
+                    server_name: host,
+                    connector: TlsConnector::from(connector),
+                }
+            }
+            UpstreamTransport::Https => {
+                let url = url::Url::parse(&config.address)
+                    .ok()
+                    .filter(|url| url.scheme() == "https")
+                    .ok_or_else(|| Error::Configuration(format!("Invalid DNS over HTTPS URL: {}", config.address)))?;
+
+                let mut client = reqwest::Client::builder();
+                if let (Some(ip), Some(host)) = (bootstrap, url.host_str()) {
+                    client = client.resolve(host, SocketAddr::new(ip, url.port_or_known_default().unwrap_or(443)));
+                }
+                Transport::Https {
+                    url: url.to_string(),
+                    client: client
+                        .build()
+                        .map_err(|e| Error::Configuration(format!("Could not set up HTTPS client: {}", e)))?,
+                }
+            }
+        };
+
+        Ok(Self {
+            name: config.address.clone(),
+            transport,
+        })
+    }
+
+    async fn exchange(&self, query: &[u8]) -> Result<Vec<u8>> {
+        match &self.transport {
+            Transport::Udp(addr) => {
+                let response = forward_udp(*addr, query).await?;
+                if !is_truncated(&response) {
+                    return Ok(response);
+                }
+                debug!("Upstream {} truncated the response, retrying over TCP", addr);
+                exchange_stream(TcpStream::connect(addr).await?, query).await
+            }
+            Transport::Tcp(addr) => exchange_stream(TcpStream::connect(addr).await?, query).await,
+            Transport::Tls {
+                connect,
+                server_name,
+                connector,
+            } => {
+                let stream = TcpStream::connect((connect.0.as_str(), connect.1)).await?;
+                let stream = connector
+                    .connect(server_name, stream)
+                    .await
+                    .map_err(|e| Error::Network(format!("TLS handshake failed: {}", e)))?;
+                exchange_stream(stream, query).await
+            }
+            Transport::Https { url, client } => {
+                let response = client
+                    .post(url)
+                    .header(reqwest::header::CONTENT_TYPE, DNS_MESSAGE)
+                    .header(reqwest::header::ACCEPT, DNS_MESSAGE)
+                    .body(query.to_vec())
+                    .send()
+                    .await
+                    .map_err(|e| Error::Network(e.to_string()))?;
+                if !response.status().is_success() {
+                    return Err(Error::Network(format!("HTTP {}", response.status())).into());
+                }
+                let body = response.bytes().await.map_err(|e| Error::Network(e.to_string()))?;
+                Ok(body.to_vec())
+            }
+        }
+    }
+}
+
+async fn forward_udp(upstream: SocketAddr, query: &[u8]) -> Result<Vec<u8>> {
+    let bind: SocketAddr = match upstream {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse()?,
+        SocketAddr::V6(_) => "[::]:0".parse()?,
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(upstream).await?;
+    socket.send(query).await?;
+
+    // Skip stray datagrams that do not answer this query
+    let mut buf = vec![0u8; MAX_UDP_RESPONSE];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        if len >= 12 && buf[..2] == query[..2] {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+        debug!("Ignoring mismatched upstream datagram ({} bytes)", len);
+    }
+}
+
+/// Send `query` with the two-byte length prefix used by DNS over TCP and
+/// TLS, and read the answer
+async fn exchange_stream(mut stream: impl AsyncRead + AsyncWrite + Unpin, query: &[u8]) -> Result<Vec<u8>> {
+    let length = u16::try_from(query.len())
+        .map_err(|_| Error::Dns("Query too long to forward".to_string()))?;
+
+    let mut framed = Vec::with_capacity(query.len() + 2);
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(query);
+    stream.write_all(&framed).await?;
+
+    let length = stream.read_u16().await? as usize;
+    let mut response = vec![0u8; length];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+/// Split `host:port`, `[v6]:port` or a bare host, using `default_port`
+fn split_host_port(address: &str, default_port: u16) -> Result<(String, u16)> {
+    let invalid = || Error::Configuration(format!("Invalid upstream address: {}", address));
+
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return Ok((addr.ip().to_string(), addr.port()));
+    }
+    if let Ok(ip) = address.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        return Ok((ip.to_string(), default_port));
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) => Ok((host.to_string(), port.parse().map_err(|_| invalid())?)),
+        None if !address.is_empty() => Ok((address.to_string(), default_port)),
+        None => Err(invalid().into()),
+    }
+}
+
+/// Accept `ip:port`, `[v6]:port` or a bare address using port 53
+fn parse_upstream(upstream: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = upstream.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    upstream
+        .parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, 53))
+        .map_err(|_| Error::Configuration(format!("Invalid upstream resolver address: {}", upstream)).into())
+}
+
+/// Whether the TC flag is set in a DNS header
+fn is_truncated(message: &[u8]) -> bool {
+    message.len() > 2 && message[2] & 0x02 != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarder(upstream: SocketAddr, timeout_ms: u64) -> Forwarder {
+        Forwarder::new(&ForwardingConfig {
+            enabled: true,
+            upstream: upstream.to_string(),
+            timeout_ms,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn query(id: u16) -> Vec<u8> {
+        let mut message = vec![0u8; 12];
+        message[..2].copy_from_slice(&id.to_be_bytes());
+        message
+    }
+
+    #[test]
+    fn test_parse_upstream() {
+        assert_eq!(parse_upstream("9.9.9.9").unwrap(), "9.9.9.9:53".parse().unwrap());
+        assert_eq!(parse_upstream("[::1]:5353").unwrap(), "[::1]:5353".parse().unwrap());
+        assert!(parse_upstream("resolver.example").is_err());
+
+        assert_eq!(split_host_port("dns.quad9.net", 853).unwrap(), ("dns.quad9.net".to_string(), 853));
+        assert_eq!(split_host_port("dns.quad9.net:8853", 853).unwrap(), ("dns.quad9.net".to_string(), 8853));
+        assert_eq!(split_host_port("[2620:fe::fe]", 853).unwrap(), ("2620:fe::fe".to_string(), 853));
+        assert!(split_host_port("dns.quad9.net:tls", 853).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_forward_relays_matching_response() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = upstream.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, client) = upstream.recv_from(&mut buf).await.unwrap();
+            // A stale answer to another query arrives first
+            upstream.send_to(&query(0xbeef), client).await.unwrap();
+            buf[2] |= 0x80;
+            upstream.send_to(&buf[..len], client).await.unwrap();
+        });
+
+        let response = forwarder(addr, 1000).forward(&query(0x1234)).await.unwrap();
+        assert_eq!(&response[..2], &[0x12, 0x34]);
+        assert_eq!(response[2] & 0x80, 0x80);
+    }
+
+    #[tokio::test]
+    async fn test_forward_times_out() {
+        // Bound but never answers
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = upstream.local_addr().unwrap();
+
+        assert!(forwarder(addr, 50).forward(&query(1)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_forward_falls_back_to_next_upstream() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstreams = [silent.local_addr().unwrap(), upstream.local_addr().unwrap()]
+            .iter()
+            .map(|addr| UpstreamConfig {
+                address: addr.to_string(),
+                ..Default::default()
+            })
+            .collect();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, client) = upstream.recv_from(&mut buf).await.unwrap();
+            upstream.send_to(&buf[..len], client).await.unwrap();
+        });
+
+        let forwarder = Forwarder::new(&ForwardingConfig {
+            enabled: true,
+            upstreams,
+            timeout_ms: 100,
+            ..Default::default()
+        })
+        .unwrap();
+        let response = forwarder.forward(&query(0x4242)).await.unwrap();
+        assert_eq!(&response[..2], &[0x42, 0x42]);
+    }
+
+    #[test]
+    fn test_invalid_upstreams() {
+        let https = UpstreamConfig {
+            address: "http://dns.example/dns-query".to_string(),
+            transport: UpstreamTransport::Https,
+            bootstrap: None,
+        };
+        assert!(Upstream::new(&https).is_err());
+
+        let tls = UpstreamConfig {
+            address: "dns.example".to_string(),
+            transport: UpstreamTransport::Tls,
+            bootstrap: Some("dns.example".to_string()),
+        };
+        assert!(Upstream::new(&tls).is_err());
+    }
+}
\ No newline at end of file
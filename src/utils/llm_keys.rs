@@ -0,0 +1,185 @@
+use crate::config::Config;
+use crate::utils::secret::SecretString;
+use crate::Error;
+use anyhow::Result;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+
+#[derive(Default)]
+struct Keys {
+    primary: Option<SecretString>,
+    secondary: Option<SecretString>,
+    /// Set once the provider refused the primary; requests try the
+    /// secondary first until it is promoted
+    failed_over: bool,
+}
+
+/// What is known of the keys, safe to log and return from the admin API
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyStatus {
+    /// Last characters of the primary key
+    pub primary: Option<String>,
+    pub secondary: Option<String>,
+    pub failed_over: bool,
+}
+
+/// The primary and secondary API keys of an LLM provider. Requests use the
+/// primary and fail over to the secondary when it is refused, so a key can
+/// be rotated without downtime: add the new key as the secondary, promote
+/// it, then revoke the old one. Retired keys are wiped from memory.
+///
+/// One ring is shared by everything that calls the provider with the
+/// configured keys: the backends, moderation and embeddings.
+#[derive(Default)]
+pub struct LlmKeyRing {
+    keys: RwLock<Keys>,
+}
+
+impl LlmKeyRing {
+    pub fn new(primary: Option<&SecretString>, secondary: Option<&SecretString>) -> Self {
+        Self {
+            keys: RwLock::new(Keys {
+                primary: primary.cloned(),
+                secondary: secondary.cloned(),
+                failed_over: false,
+            }),
+        }
+    }
+
+    /// A ring with the `llm.api_key` and `llm.secondary_api_key` of `config`
+    pub fn for_config(config: &Config) -> Arc<Self> {
+        Arc::new(Self::new(config.llm.api_key.as_ref(), config.llm.secondary_api_key.as_ref()))
+    }
+
+    /// Keys to try, in order; empty when the provider needs none
+    pub fn candidates(&self) -> Vec<SecretString> {
+        let keys = self.keys.read().unwrap();
+        let mut candidates: Vec<SecretString> = keys.primary.iter().chain(&keys.secondary).cloned().collect();
+        if keys.failed_over {
+            candidates.reverse();
+        }
+        candidates
+    }
+
+    /// Note that the provider refused `key`, returning whether the next
+    /// candidate is worth trying
+    pub fn refused(&self, key: &SecretString) -> bool {
+        let mut keys = self.keys.write().unwrap();
+        let is_primary = keys.primary.as_ref() == Some(key);
+        if is_primary && keys.secondary.is_some() {
+            if !keys.failed_over {
+                warn!("The LLM provider refused the primary API key; failing over to the secondary");
+                keys.failed_over = true;
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Make `key` the secondary, wiping the one it replaces
+    pub fn set_secondary(&self, key: SecretString) -> KeyStatus {
+        let mut keys = self.keys.write().unwrap();
+        keys.secondary = Some(key);
+        keys.failed_over = false;
+        info!("New secondary LLM API key set");
+        status(&keys)
+    }
+
+    /// Make the secondary key the primary, wiping the retired primary
+    pub fn promote_secondary(&self) -> Result<KeyStatus> {
+        let mut keys = self.keys.write().unwrap();
+        let secondary = keys
+            .secondary
+            .take()
+            .ok_or_else(|| Error::Configuration("There is no secondary LLM API key to promote".to_string()))?;
+        // Dropping the old primary zeroizes it
+        keys.primary = Some(secondary);
+        keys.failed_over = false;
+        info!("Secondary LLM API key promoted to primary");
+        Ok(status(&keys))
+    }
+
+    pub fn status(&self) -> KeyStatus {
+        status(&self.keys.read().unwrap())
+    }
+
+    /// Send a request built by `build`, with the primary key and then the
+    /// secondary if the provider refuses the primary. Without keys it is
+    /// sent as it is.
+    pub async fn send(&self, build: impl Fn() -> RequestBuilder) -> reqwest::Result<Response> {
+        let keys = self.candidates();
+        if keys.is_empty() {
+            return build().send().await;
+        }
+        for (n, key) in keys.iter().enumerate() {
+            let response = build().bearer_auth(key.expose()).send().await?;
+            let refused = matches!(response.status(), StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN);
+            if !refused || !self.refused(key) || n + 1 == keys.len() {
+                return Ok(response);
+            }
+        }
+        unreachable!("the last key's response is always returned")
+    }
+}
+
+fn status(keys: &Keys) -> KeyStatus {
+    KeyStatus {
+        primary: keys.primary.as_ref().map(|key| mask(key.expose())),
+        secondary: keys.secondary.as_ref().map(|key| mask(key.expose())),
+        failed_over: keys.failed_over,
+    }
+}
+
+/// The last four characters of a long enough key, to tell keys apart
+/// without revealing them
+pub fn mask(key: &str) -> String {
+    let chars = key.chars().count();
+    if chars < 12 {
+        return "****".to_string();
+    }
+    let tail: String = key.chars().skip(chars - 4).collect();
+    format!("****{}", tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failover_and_promotion() {
+        let (old, new) = (SecretString::from("sk-old-0000000001"), SecretString::from("sk-new-0000000002"));
+        let ring = LlmKeyRing::new(Some(&old), Some(&new));
+        let order = |ring: &LlmKeyRing| -> Vec<String> { ring.candidates().iter().map(|key| key.expose().to_string()).collect() };
+        assert_eq!(order(&ring), vec!["sk-old-0000000001", "sk-new-0000000002"]);
+
+        // A refused secondary leaves nothing more to try
+        assert!(!ring.refused(&new));
+        assert!(ring.refused(&old));
+        assert_eq!(order(&ring), vec!["sk-new-0000000002", "sk-old-0000000001"]);
+        assert!(ring.status().failed_over);
+
+        let status = ring.promote_secondary().unwrap();
+        assert_eq!(status.primary.as_deref(), Some("****0002"));
+        assert_eq!(status.secondary, None);
+        assert!(!status.failed_over);
+        assert_eq!(order(&ring), vec!["sk-new-0000000002"]);
+        assert!(ring.promote_secondary().is_err());
+        assert!(!ring.refused(&new));
+
+        assert_eq!(ring.set_secondary("sk-next-000000003".into()).secondary.as_deref(), Some("****0003"));
+        assert_eq!(mask("short"), "****");
+        assert!(LlmKeyRing::default().candidates().is_empty());
+    }
+
+    #[test]
+    fn test_for_config() {
+        let mut config = Config::default();
+        config.llm.api_key = Some("sk-config-test-0001".into());
+        config.llm.secondary_api_key = Some("sk-config-test-0002".into());
+        let status = LlmKeyRing::for_config(&config).status();
+        assert_eq!(status.primary.as_deref(), Some("****0001"));
+        assert_eq!(status.secondary.as_deref(), Some("****0002"));
+    }
+}
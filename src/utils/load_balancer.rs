@@ -0,0 +1,129 @@
+use crate::config::BalancingStrategy;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Chooses which of several weighted endpoints serves the next request.
+///
+/// Weighted round-robin uses the smooth algorithm from nginx, which
+/// interleaves endpoints instead of sending bursts to the heaviest one.
+/// Least-outstanding compares requests in flight relative to weight.
+#[derive(Debug)]
+pub struct LoadBalancer {
+    strategy: BalancingStrategy,
+    weights: Vec<u32>,
+    current: Mutex<Vec<i64>>,
+    outstanding: Vec<Arc<AtomicUsize>>,
+}
+
+/// Counts a request against its endpoint until dropped
+#[derive(Debug)]
+pub struct OutstandingGuard(Arc<AtomicUsize>);
+
+impl Drop for OutstandingGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl LoadBalancer {
+    pub fn new(weights: Vec<u32>, strategy: BalancingStrategy) -> Self {
+        Self {
+            strategy,
+            current: Mutex::new(vec![0; weights.len()]),
+            outstanding: weights.iter().map(|_| Arc::new(AtomicUsize::new(0))).collect(),
+            weights,
+        }
+    }
+
+    /// Pick among the endpoints marked `available`. Endpoints with weight 0
+    /// are never picked; `None` when nothing is left.
+    pub fn pick(&self, available: &[bool]) -> Option<usize> {
+        let candidates: Vec<usize> = (0..self.weights.len())
+            .filter(|&i| available.get(i).copied().unwrap_or(false) && self.weights[i] > 0)
+            .collect();
+
+        match self.strategy {
+            BalancingStrategy::WeightedRoundRobin => self.pick_round_robin(&candidates),
+            BalancingStrategy::LeastOutstanding => self.pick_least_outstanding(&candidates),
+        }
+    }
+
+    /// Track a request sent to `index`
+    pub fn start(&self, index: usize) -> OutstandingGuard {
+        self.outstanding[index].fetch_add(1, Ordering::Relaxed);
+        OutstandingGuard(self.outstanding[index].clone())
+    }
+
+    /// Requests currently in flight to `index`
+    pub fn outstanding(&self, index: usize) -> usize {
+        self.outstanding[index].load(Ordering::Relaxed)
+    }
+
+    fn pick_round_robin(&self, candidates: &[usize]) -> Option<usize> {
+        let mut current = self.current.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut total = 0i64;
+        let mut best: Option<usize> = None;
+
+        for &i in candidates {
+            current[i] += self.weights[i] as i64;
+            total += self.weights[i] as i64;
+            best = match best {
+                Some(b) if current[b] >= current[i] => Some(b),
+                _ => Some(i),
+            };
+        }
+
+        let best = best?;
+        current[best] -= total;
+        Some(best)
+    }
+
+    fn pick_least_outstanding(&self, candidates: &[usize]) -> Option<usize> {
+        // Compare (outstanding + 1) / weight without dividing, so that idle
+        // endpoints are still told apart by weight
+        candidates.iter().copied().min_by(|&a, &b| {
+            let load_a = (self.outstanding(a) as u64 + 1) * self.weights[b] as u64;
+            let load_b = (self.outstanding(b) as u64 + 1) * self.weights[a] as u64;
+            load_a.cmp(&load_b)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let balancer = LoadBalancer::new(vec![3, 1], BalancingStrategy::WeightedRoundRobin);
+        let picks: Vec<usize> = (0..8).map(|_| balancer.pick(&[true, true]).unwrap()).collect();
+
+        assert_eq!(picks.iter().filter(|&&i| i == 0).count(), 6);
+        // Smooth: the light endpoint is not starved until the end of a cycle
+        assert_eq!(&picks[..4], &[0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_unavailable_endpoints_are_skipped() {
+        let balancer = LoadBalancer::new(vec![1, 1, 0], BalancingStrategy::WeightedRoundRobin);
+        for _ in 0..4 {
+            assert_eq!(balancer.pick(&[false, true, true]), Some(1));
+        }
+        assert_eq!(balancer.pick(&[false, false, true]), None);
+    }
+
+    #[test]
+    fn test_least_outstanding() {
+        let balancer = LoadBalancer::new(vec![1, 1], BalancingStrategy::LeastOutstanding);
+        let busy = balancer.start(0);
+        assert_eq!(balancer.pick(&[true, true]), Some(1));
+
+        drop(busy);
+        assert_eq!(balancer.outstanding(0), 0);
+
+        // A heavier endpoint keeps being preferred with requests in flight
+        let weighted = LoadBalancer::new(vec![1, 4], BalancingStrategy::LeastOutstanding);
+        let _first = weighted.start(1);
+        assert_eq!(weighted.pick(&[true, true]), Some(1));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,211 @@
+use crate::config::DowngradeConfig;
+use crate::utils::metrics::{LatencyHistogram, Metrics};
+use crate::Error;
+use anyhow::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// Ladder step that answers from the cache alone
+const CACHE_ONLY: &str = "cache-only";
+
+/// How questions are answered at a step of the ladder
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rung {
+    Model(String),
+    CacheOnly,
+}
+
+struct Observation {
+    /// Response times as of the previous check
+    response_times: LatencyHistogram,
+    /// Checks under the limits in a row
+    calm_checks: u32,
+}
+
+/// Watches latency and queue depth and moves down the model ladder while
+/// they are over their limits, one step per check, and back up once they
+/// have been under them for `recover_after_checks` checks in a row
+pub struct LoadController {
+    ladder: Vec<Rung>,
+    max_p95_latency_ms: u64,
+    max_queue_depth: usize,
+    recover_after_checks: u32,
+    /// Steps taken down the ladder; 0 is `llm.model`
+    level: AtomicUsize,
+    observation: Mutex<Observation>,
+}
+
+impl LoadController {
+    pub fn new(config: &DowngradeConfig) -> Result<Self> {
+        let ladder: Vec<_> = config
+            .ladder
+            .iter()
+            .map(|model| match model.as_str() {
+                CACHE_ONLY => Rung::CacheOnly,
+                _ => Rung::Model(model.clone()),
+            })
+            .collect();
+        if config.enabled && ladder.is_empty() {
+            return Err(Error::Configuration("Model downgrades need a ladder of models".to_string()).into());
+        }
+        if ladder.iter().rev().skip(1).any(|rung| *rung == Rung::CacheOnly) {
+            return Err(Error::Configuration(format!("{} must be the last step of the ladder", CACHE_ONLY)).into());
+        }
+
+        Ok(Self {
+            ladder: if config.enabled { ladder } else { Vec::new() },
+            max_p95_latency_ms: config.max_p95_latency_ms,
+            max_queue_depth: config.max_queue_depth,
+            recover_after_checks: config.recover_after_checks.max(1),
+            level: AtomicUsize::new(0),
+            observation: Mutex::new(Observation {
+                response_times: LatencyHistogram::default(),
+                calm_checks: 0,
+            }),
+        })
+    }
+
+    pub fn level(&self) -> usize {
+        self.level.load(Ordering::Relaxed)
+    }
+
+    /// The step questions are answered at, or `None` while not downgraded
+    pub fn rung(&self) -> Option<&Rung> {
+        self.level().checked_sub(1).and_then(|step| self.ladder.get(step))
+    }
+
+    /// Compare the load since the last check with the limits and move one
+    /// step down or up the ladder if called for
+    pub async fn check(&self, metrics: &Metrics) {
+        if self.ladder.is_empty() {
+            return;
+        }
+
+        let response_times = metrics.get_detailed_stats().await.response_times;
+        let stats = metrics.get_stats().await;
+        let queue_depth = stats.request_queue_depth + stats.llm_queue_depth;
+
+        let mut observation = self.observation.lock().unwrap();
+        let recent = response_times.since(&observation.response_times);
+        observation.response_times = response_times;
+        let p95 = recent.percentile(0.95);
+
+        let slow = self.max_p95_latency_ms > 0 && p95 > self.max_p95_latency_ms as f64;
+        let queued = self.max_queue_depth > 0 && queue_depth > self.max_queue_depth;
+        let level = self.level();
+        let new_level = if slow || queued {
+            observation.calm_checks = 0;
+            (level + 1).min(self.ladder.len())
+        } else {
+            observation.calm_checks += 1;
+            if level > 0 && observation.calm_checks >= self.recover_after_checks {
+                observation.calm_checks = 0;
+                level - 1
+            } else {
+                level
+            }
+        };
+
+        if new_level != level {
+            self.level.store(new_level, Ordering::Relaxed);
+            metrics.set_downgrade_level(new_level);
+            let rung = match self.rung() {
+                Some(Rung::Model(model)) => model.as_str(),
+                Some(Rung::CacheOnly) => CACHE_ONLY,
+                None => "the configured model",
+            };
+            if new_level > level {
+                warn!(p95_ms = p95, queue_depth, "Under load, switching to {}", rung);
+            } else {
+                info!("Load has subsided, switching back to {}", rung);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn controller() -> LoadController {
+        LoadController::new(&DowngradeConfig {
+            enabled: true,
+            ladder: vec!["gpt-4o-mini".to_string(), "cache-only".to_string()],
+            max_p95_latency_ms: 1000,
+            max_queue_depth: 0,
+            recover_after_checks: 2,
+            ..DowngradeConfig::default()
+        })
+        .unwrap()
+    }
+
+    async fn respond(metrics: &Metrics, ms: u64, times: usize) {
+        for _ in 0..times {
+            metrics.record_response_time(Duration::from_millis(ms)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ladder() {
+        let controller = controller();
+        let metrics = Metrics::new();
+        assert_eq!(controller.rung(), None);
+
+        respond(&metrics, 3000, 10).await;
+        controller.check(&metrics).await;
+        assert_eq!(controller.rung(), Some(&Rung::Model("gpt-4o-mini".to_string())));
+        respond(&metrics, 3000, 10).await;
+        controller.check(&metrics).await;
+        assert_eq!(controller.rung(), Some(&Rung::CacheOnly));
+        // There is no step below the last
+        respond(&metrics, 3000, 10).await;
+        controller.check(&metrics).await;
+        assert_eq!(controller.level(), 2);
+        assert_eq!(metrics.get_stats().await.downgrade_level, 2);
+
+        // Only the responses since the last check count, and recovery
+        // takes two calm checks per step
+        respond(&metrics, 20, 100).await;
+        controller.check(&metrics).await;
+        assert_eq!(controller.level(), 2);
+        controller.check(&metrics).await;
+        assert_eq!(controller.level(), 1);
+        controller.check(&metrics).await;
+        controller.check(&metrics).await;
+        assert_eq!(controller.rung(), None);
+    }
+
+    #[tokio::test]
+    async fn test_queue_depth() {
+        let controller = LoadController::new(&DowngradeConfig {
+            enabled: true,
+            ladder: vec!["small".to_string()],
+            max_queue_depth: 1,
+            ..DowngradeConfig::default()
+        })
+        .unwrap();
+        let metrics = Metrics::new();
+        metrics.increment_request_queue_depth();
+        metrics.increment_llm_queue_depth();
+        controller.check(&metrics).await;
+        assert_eq!(controller.level(), 1);
+    }
+
+    #[test]
+    fn test_invalid_ladders() {
+        let config = DowngradeConfig {
+            enabled: true,
+            ..DowngradeConfig::default()
+        };
+        assert!(LoadController::new(&config).is_err());
+        let config = DowngradeConfig {
+            ladder: vec!["cache-only".to_string(), "small".to_string()],
+            ..config
+        };
+        assert!(LoadController::new(&config).is_err());
+        // A disabled controller never moves
+        assert!(LoadController::new(&DowngradeConfig::default()).unwrap().rung().is_none());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,666 @@
+use crate::config::{LocalAnswererKind, LocalAnswersConfig};
+use crate::utils::faq::normalize;
+use crate::utils::tools::{civil_date, format_number, UnitConversion};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    static ref TIME_QUESTION: Regex = Regex::new(
+        r"^(?:what(?: is|s)? (?:the )?(?:current )?(?:time|date|day)(?: is it)?(?: today| now| right now)?|(?:current |local )?(?:time|date)(?: now)?)(?: in (?P<zone>\S+))?$"
+    )
+    .unwrap();
+    static ref ZONE_QUESTION: Regex = Regex::new(
+        r"^(?:convert |what is |whats )?(?P<hour>\d{1,2})(?:[: ]?(?P<minute>\d{2}))? ?(?P<meridiem>am|pm)? (?P<from>\S+) (?:to|in|into) (?P<to>\S+)$"
+    )
+    .unwrap();
+    static ref UTC_OFFSET: Regex = Regex::new(r"^(?:utc|gmt)(?:(?P<sign>[+-])(?P<hours>\d{1,2})(?::?(?P<minutes>\d{2}))?)?$").unwrap();
+    static ref UNIT_QUESTION: Regex = Regex::new(
+        r"^(?:convert |what is |whats |how much is )?(?P<value>-?\d+(?:[.,]\d+)?) (?P<from>.+?) (?:to|in|into) (?P<to>.+)$"
+    )
+    .unwrap();
+    static ref MATH_QUESTION: Regex =
+        Regex::new(r"^(?:what is |whats |calculate |compute |how much is |solve )?(?P<expression>.+?)(?: equals?)?$").unwrap();
+}
+
+/// Fixed offsets from UTC in minutes of common zone abbreviations. Where an
+/// abbreviation is ambiguous, the North American zone wins.
+const ZONES: &[(&str, i32)] = &[
+    ("utc", 0),
+    ("gmt", 0),
+    ("wet", 0),
+    ("west", 60),
+    ("bst", 60),
+    ("cet", 60),
+    ("cest", 120),
+    ("eet", 120),
+    ("eest", 180),
+    ("msk", 180),
+    ("trt", 180),
+    ("gst", 240),
+    ("pkt", 300),
+    ("ist", 330),
+    ("ict", 420),
+    ("wib", 420),
+    ("hkt", 480),
+    ("sgt", 480),
+    ("awst", 480),
+    ("jst", 540),
+    ("kst", 540),
+    ("acst", 570),
+    ("aest", 600),
+    ("aedt", 660),
+    ("nzst", 720),
+    ("nzdt", 780),
+    ("nst", -210),
+    ("brt", -180),
+    ("art", -180),
+    ("ast", -240),
+    ("edt", -240),
+    ("est", -300),
+    ("cdt", -300),
+    ("cst", -360),
+    ("mdt", -360),
+    ("mst", -420),
+    ("pdt", -420),
+    ("pst", -480),
+    ("akdt", -480),
+    ("akst", -540),
+    ("hst", -600),
+];
+
+/// Spelled-out unit names and the abbreviations the unit converter knows
+const UNIT_NAMES: &[(&str, &str)] = &[
+    ("meter", "m"),
+    ("meters", "m"),
+    ("metre", "m"),
+    ("metres", "m"),
+    ("kilometer", "km"),
+    ("kilometers", "km"),
+    ("kilometre", "km"),
+    ("kilometres", "km"),
+    ("centimeter", "cm"),
+    ("centimeters", "cm"),
+    ("centimetre", "cm"),
+    ("centimetres", "cm"),
+    ("millimeter", "mm"),
+    ("millimeters", "mm"),
+    ("millimetre", "mm"),
+    ("millimetres", "mm"),
+    ("mile", "mi"),
+    ("miles", "mi"),
+    ("yard", "yd"),
+    ("yards", "yd"),
+    ("foot", "ft"),
+    ("feet", "ft"),
+    ("inch", "in"),
+    ("inches", "in"),
+    ("nautical miles", "nmi"),
+    ("kilogram", "kg"),
+    ("kilograms", "kg"),
+    ("kilo", "kg"),
+    ("kilos", "kg"),
+    ("gram", "g"),
+    ("grams", "g"),
+    ("milligram", "mg"),
+    ("milligrams", "mg"),
+    ("ton", "t"),
+    ("tons", "t"),
+    ("tonne", "t"),
+    ("tonnes", "t"),
+    ("pound", "lb"),
+    ("pounds", "lb"),
+    ("lbs", "lb"),
+    ("ounce", "oz"),
+    ("ounces", "oz"),
+    ("liter", "l"),
+    ("liters", "l"),
+    ("litre", "l"),
+    ("litres", "l"),
+    ("milliliter", "ml"),
+    ("milliliters", "ml"),
+    ("millilitre", "ml"),
+    ("millilitres", "ml"),
+    ("gallon", "gal"),
+    ("gallons", "gal"),
+    ("quart", "qt"),
+    ("quarts", "qt"),
+    ("pint", "pt"),
+    ("pints", "pt"),
+    ("kph", "km/h"),
+    ("kmh", "km/h"),
+    ("knot", "kn"),
+    ("knots", "kn"),
+    ("second", "s"),
+    ("seconds", "s"),
+    ("sec", "s"),
+    ("secs", "s"),
+    ("minute", "min"),
+    ("minutes", "min"),
+    ("mins", "min"),
+    ("hour", "h"),
+    ("hours", "h"),
+    ("hr", "h"),
+    ("hrs", "h"),
+    ("day", "d"),
+    ("days", "d"),
+    ("byte", "b"),
+    ("bytes", "b"),
+    ("kilobyte", "kb"),
+    ("kilobytes", "kb"),
+    ("megabyte", "mb"),
+    ("megabytes", "mb"),
+    ("gigabyte", "gb"),
+    ("gigabytes", "gb"),
+    ("terabyte", "tb"),
+    ("terabytes", "tb"),
+    ("celsius", "c"),
+    ("centigrade", "c"),
+    ("fahrenheit", "f"),
+    ("kelvin", "k"),
+];
+
+const WEEKDAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+/// Answers one kind of question on its own, without asking the backend
+pub trait LocalAnswerer: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// The answer to `question`, given in lowercase with single spaces and
+    /// no trailing question mark, or `None` for questions of another kind
+    fn answer(&self, question: &str) -> Option<String>;
+
+    /// TTL of the answers, for answers that go stale sooner than the
+    /// configured TTL allows
+    fn ttl(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// An answer found without the backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalAnswer {
+    /// Name of the answerer that found it
+    pub answerer: String,
+    pub text: String,
+    pub ttl: Option<u32>,
+}
+
+/// The local answerers, tried in order before the cache and the backend
+#[derive(Default)]
+pub struct LocalAnswers {
+    answerers: Vec<Box<dyn LocalAnswerer>>,
+}
+
+impl LocalAnswers {
+    /// The built-in answerers switched on by `local_answers`
+    pub fn new(config: &LocalAnswersConfig) -> Self {
+        let mut local_answers = Self::default();
+        if !config.enabled {
+            return local_answers;
+        }
+
+        for kind in &config.answerers {
+            let answerer: Box<dyn LocalAnswerer> = match kind {
+                LocalAnswererKind::Time => Box::new(CurrentTime),
+                LocalAnswererKind::Timezone => Box::new(TimezoneConversion),
+                LocalAnswererKind::Units => Box::new(Units),
+                LocalAnswererKind::Math => Box::new(Arithmetic),
+            };
+            local_answers.register(answerer);
+        }
+        local_answers
+    }
+
+    /// Add an answerer after the others, replacing any registered under
+    /// the same name
+    pub fn register(&mut self, answerer: Box<dyn LocalAnswerer>) {
+        self.answerers.retain(|existing| existing.name() != answerer.name());
+        self.answerers.push(answerer);
+    }
+
+    /// The answer of the first answerer that has one
+    pub fn answer(&self, question: &str) -> Option<LocalAnswer> {
+        if self.answerers.is_empty() {
+            return None;
+        }
+
+        let question = normalize(question);
+        self.answerers.iter().find_map(|answerer| {
+            Some(LocalAnswer {
+                text: answerer.answer(&question)?,
+                answerer: answerer.name().to_string(),
+                ttl: answerer.ttl(),
+            })
+        })
+    }
+}
+
+/// Display name and offset from UTC in minutes of `zone`, an abbreviation
+/// or an offset such as `utc+2` or `gmt+05:30`
+fn zone_offset(zone: &str) -> Option<(String, i32)> {
+    match UTC_OFFSET.captures(zone) {
+        Some(captures) => {
+            let number = |name| captures.name(name).map_or(Some(0), |m| m.as_str().parse::<i32>().ok());
+            let (hours, minutes) = (number("hours")?, number("minutes")?);
+            if hours > 14 || minutes > 59 {
+                return None;
+            }
+            let sign = if captures.name("sign").map(|m| m.as_str()) == Some("-") { -1 } else { 1 };
+            let offset = sign * (hours * 60 + minutes);
+            let name = if offset == 0 { zone.to_uppercase() } else { format_offset(offset) };
+            Some((name, offset))
+        }
+        None => {
+            let (_, offset) = ZONES.iter().find(|(name, _)| *name == zone)?;
+            Some((zone.to_uppercase(), *offset))
+        }
+    }
+}
+
+/// `UTC+05:30` style offset
+fn format_offset(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("UTC{}{:02}:{:02}", sign, offset.abs() / 60, offset.abs() % 60)
+}
+
+struct CurrentTime;
+
+impl CurrentTime {
+    fn answer_at(question: &str, now: u64) -> Option<String> {
+        let captures = TIME_QUESTION.captures(question)?;
+        let (zone, offset) = match captures.name("zone") {
+            Some(zone) => zone_offset(zone.as_str())?,
+            None => ("UTC".to_string(), 0),
+        };
+        // Abbreviations are followed by the offset they stand for
+        let zone = if offset == 0 || zone.starts_with("UTC") {
+            zone
+        } else {
+            format!("{} ({})", zone, format_offset(offset))
+        };
+
+        let local = now as i64 + i64::from(offset) * 60;
+        let days = local.div_euclid(86_400);
+        let seconds = local.rem_euclid(86_400);
+        let (year, month, day) = civil_date(days);
+        Some(format!(
+            "{} {:04}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+            WEEKDAYS[(days + 4).rem_euclid(7) as usize],
+            year,
+            month,
+            day,
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60,
+            zone
+        ))
+    }
+}
+
+impl LocalAnswerer for CurrentTime {
+    fn name(&self) -> &str {
+        "time"
+    }
+
+    fn answer(&self, question: &str) -> Option<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+        Self::answer_at(question, now.as_secs())
+    }
+
+    /// Resolvers should not keep the time
+    fn ttl(&self) -> Option<u32> {
+        Some(0)
+    }
+}
+
+struct TimezoneConversion;
+
+impl LocalAnswerer for TimezoneConversion {
+    fn name(&self) -> &str {
+        "timezone"
+    }
+
+    fn answer(&self, question: &str) -> Option<String> {
+        let captures = ZONE_QUESTION.captures(question)?;
+        let (from, from_offset) = zone_offset(&captures["from"])?;
+        let (to, to_offset) = zone_offset(&captures["to"])?;
+
+        let mut hour: i32 = captures["hour"].parse().ok()?;
+        let minute: i32 = captures.name("minute").map_or(Some(0), |m| m.as_str().parse().ok())?;
+        match captures.name("meridiem").map(|m| m.as_str()) {
+            Some(meridiem) if (1..=12).contains(&hour) => hour = hour % 12 + if meridiem == "pm" { 12 } else { 0 },
+            Some(_) => return None,
+            None if hour > 23 => return None,
+            None => {}
+        }
+        if minute > 59 {
+            return None;
+        }
+
+        let converted = hour * 60 + minute - from_offset + to_offset;
+        let day = match converted.div_euclid(1440) {
+            0 => "",
+            1 => " the next day",
+            _ => " the previous day",
+        };
+        let converted = converted.rem_euclid(1440);
+        Some(format!(
+            "{:02}:{:02} {} is {:02}:{:02} {}{}",
+            hour,
+            minute,
+            from,
+            converted / 60,
+            converted % 60,
+            to,
+            day
+        ))
+    }
+}
+
+struct Units;
+
+impl Units {
+    /// The converter's abbreviation for a unit as asked
+    fn unit(name: &str) -> &str {
+        let name = name.strip_prefix("degrees ").unwrap_or(name);
+        UNIT_NAMES
+            .iter()
+            .find(|(spelled, _)| *spelled == name)
+            .map_or(name, |(_, unit)| unit)
+    }
+}
+
+impl LocalAnswerer for Units {
+    fn name(&self) -> &str {
+        "units"
+    }
+
+    fn answer(&self, question: &str) -> Option<String> {
+        let captures = UNIT_QUESTION.captures(question)?;
+        let value: f64 = captures["value"].replace(',', ".").parse().ok()?;
+        let (from, to) = (&captures["from"], &captures["to"]);
+
+        let converted = UnitConversion::convert(value, Self::unit(from), Self::unit(to)).ok()?;
+        Some(format!("{} {} = {} {}", format_number(value), from, format_number(converted), to))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Number(f64),
+    Operator(char),
+    Open,
+    Close,
+}
+
+/// Expressions longer than this are left to the backend
+const MAX_TOKENS: usize = 64;
+
+struct Arithmetic;
+
+impl Arithmetic {
+    fn tokenize(expression: &str) -> Option<Vec<Token>> {
+        let expression = format!(" {} ", expression)
+            .replace(" to the power of ", " ^ ")
+            .replace(" multiplied by ", " * ")
+            .replace(" divided by ", " / ");
+
+        let mut tokens = Vec::new();
+        let mut chars = expression.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c.is_ascii_digit() || c == '.' {
+                let mut number = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                    number.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Number(number.parse().ok()?));
+            } else if c.is_alphabetic() {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_alphabetic()) {
+                    word.push(c);
+                    chars.next();
+                }
+                match word.as_str() {
+                    "plus" => tokens.push(Token::Operator('+')),
+                    "minus" => tokens.push(Token::Operator('-')),
+                    "times" | "x" => tokens.push(Token::Operator('*')),
+                    "over" => tokens.push(Token::Operator('/')),
+                    "mod" | "modulo" => tokens.push(Token::Operator('%')),
+                    "squared" => tokens.extend([Token::Operator('^'), Token::Number(2.0)]),
+                    "cubed" => tokens.extend([Token::Operator('^'), Token::Number(3.0)]),
+                    _ => return None,
+                }
+            } else {
+                chars.next();
+                tokens.push(match c {
+                    '+' | '-' | '*' | '/' | '%' | '^' => Token::Operator(c),
+                    '×' => Token::Operator('*'),
+                    '÷' => Token::Operator('/'),
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    _ => return None,
+                });
+            }
+        }
+        (tokens.len() <= MAX_TOKENS).then_some(tokens)
+    }
+
+    /// The expression written out with spaces around binary operators
+    fn render(tokens: &[Token]) -> String {
+        let mut text = String::new();
+        for (i, token) in tokens.iter().enumerate() {
+            match token {
+                Token::Number(number) => text.push_str(&format_number(*number)),
+                Token::Operator(operator) if is_unary(tokens, i) => text.push(*operator),
+                Token::Operator(operator) => {
+                    text.push(' ');
+                    text.push(*operator);
+                    text.push(' ');
+                }
+                Token::Open => text.push('('),
+                Token::Close => text.push(')'),
+            }
+        }
+        text
+    }
+}
+
+/// Whether the operator at `index` is a sign rather than between operands
+fn is_unary(tokens: &[Token], index: usize) -> bool {
+    index == 0 || matches!(tokens[index - 1], Token::Operator(_) | Token::Open)
+}
+
+/// Recursive descent over `+ -`, then `* / %`, then signs, then `^`
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next_operator(&mut self, operators: &str) -> Option<char> {
+        match self.peek() {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                self.position += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn sum(&mut self) -> Option<f64> {
+        let mut value = self.product()?;
+        while let Some(operator) = self.next_operator("+-") {
+            let rhs = self.product()?;
+            value = if operator == '+' { value + rhs } else { value - rhs };
+        }
+        Some(value)
+    }
+
+    fn product(&mut self) -> Option<f64> {
+        let mut value = self.signed()?;
+        while let Some(operator) = self.next_operator("*/%") {
+            let rhs = self.signed()?;
+            value = match operator {
+                '*' => value * rhs,
+                '/' => value / rhs,
+                _ => value % rhs,
+            };
+        }
+        Some(value)
+    }
+
+    fn signed(&mut self) -> Option<f64> {
+        match self.next_operator("+-") {
+            Some('-') => Some(-self.signed()?),
+            Some(_) => self.signed(),
+            None => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Option<f64> {
+        let base = self.atom()?;
+        match self.next_operator("^") {
+            Some(_) => Some(base.powf(self.signed()?)),
+            None => Some(base),
+        }
+    }
+
+    fn atom(&mut self) -> Option<f64> {
+        let token = self.peek()?;
+        self.position += 1;
+        match token {
+            Token::Number(number) => Some(number),
+            Token::Open => {
+                let value = self.sum()?;
+                (self.peek() == Some(Token::Close)).then(|| {
+                    self.position += 1;
+                    value
+                })
+            }
+            Token::Operator(_) | Token::Close => None,
+        }
+    }
+}
+
+impl LocalAnswerer for Arithmetic {
+    fn name(&self) -> &str {
+        "math"
+    }
+
+    fn answer(&self, question: &str) -> Option<String> {
+        let captures = MATH_QUESTION.captures(question)?;
+        let tokens = Self::tokenize(&captures["expression"])?;
+        // A lone number is not a sum
+        let binary = |(i, token): (usize, &Token)| matches!(token, Token::Operator(_)) && !is_unary(&tokens, i);
+        if !tokens.iter().enumerate().any(binary) {
+            return None;
+        }
+
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+        let value = parser.sum().filter(|value| value.is_finite())?;
+        if parser.position != tokens.len() {
+            return None;
+        }
+        Some(format!("{} = {}", Self::render(&tokens), format_number(value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(question: &str) -> Option<String> {
+        LocalAnswers::new(&LocalAnswersConfig {
+            enabled: true,
+            ..LocalAnswersConfig::default()
+        })
+        .answer(question)
+        .map(|answer| answer.text)
+    }
+
+    #[test]
+    fn test_time() {
+        // 2026-10-16T23:30:00Z, a Friday
+        let now = 1_792_193_400;
+        assert_eq!(
+            CurrentTime::answer_at("what time is it", now).as_deref(),
+            Some("Friday 2026-10-16 23:30:00 UTC")
+        );
+        assert_eq!(
+            CurrentTime::answer_at("whats the date in cest", now).as_deref(),
+            Some("Saturday 2026-10-17 01:30:00 CEST (UTC+02:00)")
+        );
+        assert_eq!(
+            CurrentTime::answer_at("what time is it in utc-09:30", now).as_deref(),
+            Some("Friday 2026-10-16 14:00:00 UTC-09:30")
+        );
+        assert_eq!(CurrentTime::answer_at("what time is it in narnia", now), None);
+        assert_eq!(CurrentTime::answer_at("what is the time complexity of quicksort", now), None);
+    }
+
+    #[test]
+    fn test_timezone_conversion() {
+        assert_eq!(answer("Convert 10:00 UTC to CET?").as_deref(), Some("10:00 UTC is 11:00 CET"));
+        assert_eq!(answer("9 30 pm est in jst").as_deref(), Some("21:30 EST is 11:30 JST the next day"));
+        assert_eq!(answer("1am utc to pst").as_deref(), Some("01:00 UTC is 17:00 PST the previous day"));
+        assert_eq!(answer("25:00 utc to cet"), None);
+    }
+
+    #[test]
+    fn test_units() {
+        assert_eq!(answer("convert 10 miles to km").as_deref(), Some("10 miles = 16.09344 km"));
+        assert_eq!(answer("100 degrees celsius in fahrenheit").as_deref(), Some("100 degrees celsius = 212 fahrenheit"));
+        assert_eq!(answer("5 people in london"), None);
+        assert_eq!(answer("convert 1 kg to meters"), None);
+    }
+
+    #[test]
+    fn test_math() {
+        assert_eq!(answer("what is 12 times 7").as_deref(), Some("12 * 7 = 84"));
+        assert_eq!(answer("(2+3)*-4").as_deref(), Some("(2 + 3) * -4 = -20"));
+        assert_eq!(answer("2 to the power of 10 minus 24").as_deref(), Some("2 ^ 10 - 24 = 1000"));
+        assert_eq!(answer("100 divided by 8").as_deref(), Some("100 / 8 = 12.5"));
+        assert_eq!(answer("what is 42"), None);
+        assert_eq!(answer("1 / 0"), None);
+        assert_eq!(answer("(1 + 2"), None);
+        assert_eq!(answer("what is the capital of france"), None);
+    }
+
+    #[test]
+    fn test_registry() {
+        struct Greeting;
+
+        impl LocalAnswerer for Greeting {
+            fn name(&self) -> &str {
+                "greeting"
+            }
+
+            fn answer(&self, question: &str) -> Option<String> {
+                (question == "hello").then(|| "Hi there".to_string())
+            }
+        }
+
+        let mut local_answers = LocalAnswers::new(&LocalAnswersConfig::default());
+        assert_eq!(local_answers.answer("1 + 1"), None);
+        local_answers.register(Box::new(Greeting));
+        assert_eq!(
+            local_answers.answer("Hello?"),
+            Some(LocalAnswer {
+                answerer: "greeting".to_string(),
+                text: "Hi there".to_string(),
+                ttl: None,
+            })
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use crate::config::{LoggingConfig, QuestionLogMode};
+use crate::utils::text;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Decides what the DNS handler's informational logs may contain and how
+/// many queries write them. Warnings and errors bypass the policy.
+pub struct LogPolicy {
+    questions: QuestionLogMode,
+    question_max_chars: usize,
+    sample_above_qps: u64,
+    sample_rate: f64,
+    started: Instant,
+    /// `(second since start, queries seen in it)`
+    window: Mutex<(u64, u64)>,
+}
+
+impl LogPolicy {
+    pub fn new(config: &LoggingConfig) -> Self {
+        Self {
+            questions: config.questions,
+            question_max_chars: config.question_max_chars,
+            sample_above_qps: config.sample_above_qps,
+            sample_rate: config.sample_rate.clamp(0.0, 1.0),
+            started: Instant::now(),
+            window: Mutex::new((0, 0)),
+        }
+    }
+
+    /// How a question, or a query name carrying one, appears in
+    /// informational logs
+    pub fn question<'a>(&self, question: &'a str) -> Cow<'a, str> {
+        match self.questions {
+            QuestionLogMode::Full => Cow::Borrowed(question),
+            QuestionLogMode::Truncate => match text::truncate_to_chars(question, self.question_max_chars) {
+                start if start.len() < question.len() => Cow::Owned(format!("{}...", start)),
+                _ => Cow::Borrowed(question),
+            },
+            QuestionLogMode::Hash => Cow::Owned(format!("#{}", question_hash(question))),
+        }
+    }
+
+    /// Whether the query being started writes informational lines. Call
+    /// once per query; every query is logged until the rate passes
+    /// `sample_above_qps`.
+    pub fn sample(&self) -> bool {
+        if self.sample_above_qps == 0 {
+            return true;
+        }
+
+        let second = self.started.elapsed().as_secs();
+        let seen = {
+            let mut window = self.window.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if window.0 != second {
+                *window = (second, 0);
+            }
+            window.1 += 1;
+            window.1
+        };
+
+        seen <= self.sample_above_qps || rand::random::<f64>() < self.sample_rate
+    }
+}
+
+/// Short stable identifier of a question, so log lines about the same
+/// question can be grouped without logging it
+pub fn question_hash(question: &str) -> String {
+    Sha256::digest(question.as_bytes())
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(questions: QuestionLogMode) -> LogPolicy {
+        LogPolicy::new(&LoggingConfig {
+            questions,
+            question_max_chars: 10,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_question_redaction() {
+        let question = "what is my neighbour's wifi password";
+        assert_eq!(policy(QuestionLogMode::Full).question(question), question);
+        assert_eq!(policy(QuestionLogMode::Truncate).question(question), "what is my...");
+        assert_eq!(policy(QuestionLogMode::Truncate).question("short"), "short");
+        assert_eq!(
+            policy(QuestionLogMode::Hash).question(question),
+            format!("#{}", question_hash(question))
+        );
+        assert_eq!(question_hash(question).len(), 16);
+    }
+
+    #[test]
+    fn test_sampling_above_threshold() {
+        let sampled = LogPolicy::new(&LoggingConfig {
+            sample_above_qps: 5,
+            sample_rate: 0.0,
+            ..Default::default()
+        });
+
+        // A second boundary in between would restart the count
+        let logged = (0..20).filter(|_| sampled.sample()).count();
+        assert!((5..=10).contains(&logged));
+        assert!(policy(QuestionLogMode::Full).sample());
+    }
+}
\ No newline at end of file
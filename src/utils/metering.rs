@@ -0,0 +1,334 @@
+use crate::config::{MeteringConfig, MeteringFormat, ModelPriceConfig};
+use crate::llm::TokenUsage;
+use crate::Error;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+const CSV_HEADER: &str =
+    "period_start,period_end,tenant,api_key,queries,cache_hits,prompt_tokens,completion_tokens,cost\n";
+
+/// The `[[llm.prices]]`, for estimating what an answer cost
+#[derive(Debug, Clone, Default)]
+pub struct Pricing {
+    prices: HashMap<String, ModelPriceConfig>,
+}
+
+impl Pricing {
+    pub fn new(prices: &[ModelPriceConfig]) -> Self {
+        Self {
+            prices: prices.iter().map(|price| (price.model.clone(), price.clone())).collect(),
+        }
+    }
+
+    /// Cost in US dollars of `usage` on `model`, or `None` when the model
+    /// has no price
+    pub fn cost(&self, model: &str, usage: TokenUsage) -> Option<f64> {
+        let price = self.prices.get(model)?;
+        Some(
+            (usage.prompt_tokens as f64 * price.prompt_per_million
+                + usage.completion_tokens as f64 * price.completion_per_million)
+                / 1_000_000.0,
+        )
+    }
+}
+
+/// Rough token count of `text`, at about four characters a token
+pub fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+/// Usage of one tenant and API key over one period. Queries without a
+/// tenant or key are filed under an empty name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UsageRecord {
+    /// Unix time in seconds
+    pub period_start: u64,
+    pub period_end: u64,
+    pub tenant: String,
+    pub api_key: String,
+    pub queries: u64,
+    pub cache_hits: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Estimated cost in US dollars of answers from priced models
+    pub cost: f64,
+}
+
+impl UsageRecord {
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{:.6}\n",
+            self.period_start,
+            self.period_end,
+            csv_field(&self.tenant),
+            csv_field(&self.api_key),
+            self.queries,
+            self.cache_hits,
+            self.prompt_tokens,
+            self.completion_tokens,
+            self.cost
+        )
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs()
+}
+
+struct Period {
+    started: SystemTime,
+    usage: HashMap<(String, String), UsageRecord>,
+}
+
+impl Period {
+    fn new() -> Self {
+        Self {
+            started: SystemTime::now(),
+            usage: HashMap::new(),
+        }
+    }
+}
+
+/// Adds up queries, tokens and cost per tenant and API key, and exports
+/// them once per period to a file, an HTTP endpoint or both. Records a
+/// destination could not take are kept and sent with the next period's.
+pub struct UsageMeter {
+    format: MeteringFormat,
+    path: Option<String>,
+    endpoint: Option<String>,
+    http: reqwest::Client,
+    period: Mutex<Period>,
+    file_backlog: tokio::sync::Mutex<Vec<UsageRecord>>,
+    endpoint_backlog: tokio::sync::Mutex<Vec<UsageRecord>>,
+}
+
+impl UsageMeter {
+    pub fn new(config: &MeteringConfig) -> Result<Self> {
+        if config.path.is_none() && config.endpoint.is_none() {
+            return Err(Error::Configuration("Metering needs a path or an endpoint to export to".to_string()).into());
+        }
+
+        Ok(Self {
+            format: config.format,
+            path: config.path.clone(),
+            endpoint: config.endpoint.clone(),
+            http: reqwest::Client::builder().timeout(Duration::from_secs(30)).build()?,
+            period: Mutex::new(Period::new()),
+            file_backlog: tokio::sync::Mutex::new(Vec::new()),
+            endpoint_backlog: tokio::sync::Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Meter one query, with its estimated cost in US dollars if known
+    pub fn record(
+        &self,
+        tenant: Option<&str>,
+        api_key: Option<&str>,
+        cache_hit: bool,
+        usage: Option<TokenUsage>,
+        cost: Option<f64>,
+    ) {
+        let mut period = self.period.lock().unwrap();
+        let key = (tenant.unwrap_or_default().to_string(), api_key.unwrap_or_default().to_string());
+        let record = period.usage.entry(key).or_insert_with_key(|(tenant, api_key)| UsageRecord {
+            tenant: tenant.clone(),
+            api_key: api_key.clone(),
+            ..UsageRecord::default()
+        });
+        record.queries += 1;
+        if cache_hit {
+            record.cache_hits += 1;
+        }
+        if let Some(usage) = usage {
+            record.prompt_tokens += usage.prompt_tokens;
+            record.completion_tokens += usage.completion_tokens;
+        }
+        record.cost += cost.unwrap_or(0.0);
+    }
+
+    /// End the current period and start a new one, returning its records
+    fn close_period(&self) -> Vec<UsageRecord> {
+        let period = std::mem::replace(&mut *self.period.lock().unwrap(), Period::new());
+        let (start, end) = (unix_seconds(period.started), unix_seconds(SystemTime::now()));
+
+        let mut records: Vec<_> = period
+            .usage
+            .into_values()
+            .map(|record| UsageRecord {
+                period_start: start,
+                period_end: end,
+                ..record
+            })
+            .collect();
+        records.sort_by(|a, b| (&a.tenant, &a.api_key).cmp(&(&b.tenant, &b.api_key)));
+        records
+    }
+
+    /// Close the current period and export its records, returning how many
+    /// there were
+    pub async fn export(&self) -> Result<usize> {
+        let records = self.close_period();
+        let mut result = Ok(records.len());
+
+        if let Some(path) = &self.path {
+            let mut backlog = self.file_backlog.lock().await;
+            backlog.extend(records.iter().cloned());
+            match self.append(Path::new(path), &backlog).await {
+                Ok(()) => backlog.clear(),
+                Err(e) => result = result.and(Err(e.context(format!("Writing usage to {}", path)))),
+            }
+        }
+        if let Some(endpoint) = &self.endpoint {
+            let mut backlog = self.endpoint_backlog.lock().await;
+            backlog.extend(records);
+            match self.push(endpoint, &backlog).await {
+                Ok(()) => backlog.clear(),
+                Err(e) => result = result.and(Err(e.context(format!("Sending usage to {}", endpoint)))),
+            }
+        }
+        result
+    }
+
+    async fn append(&self, path: &Path, records: &[UsageRecord]) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).await?;
+            }
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path).await?;
+        let mut text = String::new();
+        match self.format {
+            MeteringFormat::Csv => {
+                if file.metadata().await?.len() == 0 {
+                    text.push_str(CSV_HEADER);
+                }
+                text.extend(records.iter().map(UsageRecord::to_csv));
+            }
+            MeteringFormat::Jsonl => {
+                for record in records {
+                    text.push_str(&serde_json::to_string(record)?);
+                    text.push('\n');
+                }
+            }
+        }
+        file.write_all(text.as_bytes()).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn push(&self, endpoint: &str, records: &[UsageRecord]) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.http
+            .post(endpoint)
+            .json(records)
+            .send()
+            .await
+            .context("Request failed")?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(prompt_tokens: u64, completion_tokens: u64) -> Option<TokenUsage> {
+        Some(TokenUsage {
+            prompt_tokens,
+            completion_tokens,
+        })
+    }
+
+    #[test]
+    fn test_pricing() {
+        let pricing = Pricing::new(&[ModelPriceConfig {
+            model: "gpt-4o-mini".to_string(),
+            prompt_per_million: 0.15,
+            completion_per_million: 0.6,
+        }]);
+        let cost = pricing.cost("gpt-4o-mini", usage(1_000_000, 500_000).unwrap()).unwrap();
+        assert!((cost - 0.45).abs() < 1e-9);
+        assert!(pricing.cost("unpriced", usage(10, 10).unwrap()).is_none());
+        assert_eq!(estimate_tokens("what is the capital of france"), 8);
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let dir = std::env::temp_dir().join(format!("llmdig-metering-{}", std::process::id()));
+        let path = dir.join("usage.csv");
+        let meter = UsageMeter::new(&MeteringConfig {
+            enabled: true,
+            path: Some(path.to_string_lossy().into_owned()),
+            ..MeteringConfig::default()
+        })
+        .unwrap();
+
+        meter.record(Some("sales"), None, false, usage(1, 2), Some(5.0));
+        meter.record(Some("sales"), None, true, None, None);
+        meter.record(None, Some("ops, west"), false, usage(5, 5), None);
+        assert_eq!(meter.export().await.unwrap(), 2);
+        // An empty period adds nothing
+        assert_eq!(meter.export().await.unwrap(), 0);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER.trim_end());
+        assert!(lines[1].ends_with(",,\"ops, west\",1,0,5,5,0.000000"));
+        assert!(lines[2].ends_with(",sales,,2,1,1,2,5.000000"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(UsageMeter::new(&MeteringConfig {
+            path: None,
+            ..MeteringConfig::default()
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_export_is_retried() {
+        // A directory cannot be appended to, so the first export fails
+        let dir = std::env::temp_dir().join(format!("llmdig-metering-retry-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let meter = UsageMeter::new(&MeteringConfig {
+            enabled: true,
+            format: MeteringFormat::Jsonl,
+            path: Some(dir.to_string_lossy().into_owned()),
+            ..MeteringConfig::default()
+        })
+        .unwrap();
+
+        meter.record(Some("sales"), None, false, None, None);
+        assert!(meter.export().await.is_err());
+        std::fs::remove_dir(&dir).unwrap();
+
+        meter.record(Some("sales"), None, false, None, None);
+        assert_eq!(meter.export().await.unwrap(), 1);
+        let text = std::fs::read_to_string(&dir).unwrap();
+        let records: Vec<serde_json::Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records.len(), 2, "the failed period is written with the next");
+        assert_eq!(records[0]["tenant"], "sales");
+        std::fs::remove_file(&dir).unwrap();
+    }
+}
\ No newline at end of file
@@ -1,9 +1,10 @@
 use std::collections::HashMap;
+use std::fmt::Write;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
-use tracing::{debug, info};
+use tracing::info;
 
 #[derive(Debug, Clone)]
 pub struct Metrics {
@@ -14,12 +15,75 @@ pub struct Metrics {
     pub cache_hits: Arc<AtomicU64>,
     pub cache_misses: Arc<AtomicU64>,
     pub llm_api_calls: Arc<AtomicU64>,
-    pub average_response_time: Arc<RwLock<f64>>,
+    pub moderated_responses: Arc<AtomicU64>,
+    pub negative_cache_hits: Arc<AtomicU64>,
+    pub semantic_cache_hits: Arc<AtomicU64>,
+    /// Questions refused as prompt injection attempts
+    pub injection_detections: Arc<AtomicU64>,
+    /// Questions answered from the FAQ table
+    pub faq_hits: Arc<AtomicU64>,
+    /// Questions answered by a local answerer
+    pub local_answers: Arc<AtomicU64>,
+    /// LLM requests waiting for a concurrency slot
+    pub llm_queue_depth: Arc<AtomicUsize>,
+    /// LLM requests rejected by a concurrency limit
+    pub shed_llm_requests: Arc<AtomicU64>,
+    /// Packets waiting for a worker
+    pub request_queue_depth: Arc<AtomicUsize>,
+    /// Packets dropped because the request queue was full
+    pub dropped_requests: Arc<AtomicU64>,
+    /// Packets that could not be parsed as DNS messages
+    pub malformed_packets: Arc<AtomicU64>,
+    /// Queries the honeypot logged as scans or attacks
+    pub threat_queries: Arc<AtomicU64>,
+    /// Estimated spend on the LLM in millionths of a US dollar
+    pub llm_cost_microdollars: Arc<AtomicU64>,
+    /// Questions whose estimated cost was over `llm.max_cost_per_query`
+    pub over_budget_queries: Arc<AtomicU64>,
+    /// Steps down the model ladder taken because of load; 0 is `llm.model`
+    pub downgrade_level: Arc<AtomicUsize>,
     pub active_connections: Arc<AtomicUsize>,
-    pub uptime_start: Arc<RwLock<Instant>>,
-    pub request_times: Arc<RwLock<Vec<Duration>>>,
-    pub error_counts: Arc<RwLock<HashMap<String, u64>>>,
-    pub backend_stats: Arc<RwLock<HashMap<String, BackendStats>>>,
+    /// Not moved by `reset`, so uptime is the process's
+    pub uptime_start: Instant,
+    /// Everything that is not a plain counter. Snapshots read the counters
+    /// while holding this lock and `reset` clears them while holding it
+    /// exclusively, so a snapshot never sees a half-reset registry.
+    state: Arc<RwLock<MetricsState>>,
+}
+
+#[derive(Debug)]
+struct MetricsState {
+    response_times: LatencyHistogram,
+    error_counts: HashMap<String, u64>,
+    backend_stats: HashMap<String, BackendStats>,
+    queries: LabeledCounter,
+}
+
+impl Default for MetricsState {
+    fn default() -> Self {
+        Self {
+            response_times: LatencyHistogram::default(),
+            error_counts: HashMap::new(),
+            backend_stats: HashMap::new(),
+            queries: LabeledCounter::new(&QUERY_LABELS),
+        }
+    }
+}
+
+/// Label names of the per-query counter, in the order of `QueryLabels`
+const QUERY_LABELS: [&str; 5] = ["qtype", "zone", "cache", "persona", "backend"];
+
+/// Dimensions of one DNS query. Labels that do not apply are left empty.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueryLabels<'a> {
+    pub query_type: &'a str,
+    /// Served zone the query name falls in
+    pub zone: &'a str,
+    /// `hit`, `miss`, or empty when no cache was consulted
+    pub cache: &'a str,
+    pub persona: &'a str,
+    /// Backend asked for the answer
+    pub backend: &'a str,
 }
 
 #[derive(Debug, Clone)]
@@ -27,6 +91,8 @@ pub struct BackendStats {
     pub total_calls: u64,
     pub successful_calls: u64,
     pub failed_calls: u64,
+    /// Failures since the last successful call
+    pub consecutive_failures: u64,
     pub average_response_time: f64,
     pub last_call: Option<Instant>,
 }
@@ -41,15 +107,53 @@ impl Metrics {
             cache_hits: Arc::new(AtomicU64::new(0)),
             cache_misses: Arc::new(AtomicU64::new(0)),
             llm_api_calls: Arc::new(AtomicU64::new(0)),
-            average_response_time: Arc::new(RwLock::new(0.0)),
+            moderated_responses: Arc::new(AtomicU64::new(0)),
+            negative_cache_hits: Arc::new(AtomicU64::new(0)),
+            semantic_cache_hits: Arc::new(AtomicU64::new(0)),
+            injection_detections: Arc::new(AtomicU64::new(0)),
+            faq_hits: Arc::new(AtomicU64::new(0)),
+            local_answers: Arc::new(AtomicU64::new(0)),
+            llm_queue_depth: Arc::new(AtomicUsize::new(0)),
+            shed_llm_requests: Arc::new(AtomicU64::new(0)),
+            request_queue_depth: Arc::new(AtomicUsize::new(0)),
+            dropped_requests: Arc::new(AtomicU64::new(0)),
+            malformed_packets: Arc::new(AtomicU64::new(0)),
+            threat_queries: Arc::new(AtomicU64::new(0)),
+            llm_cost_microdollars: Arc::new(AtomicU64::new(0)),
+            over_budget_queries: Arc::new(AtomicU64::new(0)),
+            downgrade_level: Arc::new(AtomicUsize::new(0)),
             active_connections: Arc::new(AtomicUsize::new(0)),
-            uptime_start: Arc::new(RwLock::new(Instant::now())),
-            request_times: Arc::new(RwLock::new(Vec::new())),
-            error_counts: Arc::new(RwLock::new(HashMap::new())),
-            backend_stats: Arc::new(RwLock::new(HashMap::new())),
+            uptime_start: Instant::now(),
+            state: Arc::new(RwLock::new(MetricsState::default())),
         }
     }
 
+    /// Counters that `reset` zeroes. Gauges such as queue depths describe
+    /// the present and are left alone.
+    fn counters(&self) -> [&AtomicU64; 19] {
+        [
+            &self.total_requests,
+            &self.successful_requests,
+            &self.failed_requests,
+            &self.rate_limited_requests,
+            &self.cache_hits,
+            &self.cache_misses,
+            &self.llm_api_calls,
+            &self.moderated_responses,
+            &self.negative_cache_hits,
+            &self.semantic_cache_hits,
+            &self.injection_detections,
+            &self.faq_hits,
+            &self.local_answers,
+            &self.shed_llm_requests,
+            &self.dropped_requests,
+            &self.malformed_packets,
+            &self.threat_queries,
+            &self.llm_cost_microdollars,
+            &self.over_budget_queries,
+        ]
+    }
+
     pub fn increment_total_requests(&self) {
         self.total_requests.fetch_add(1, Ordering::Relaxed);
     }
@@ -78,38 +182,96 @@ impl Metrics {
         self.llm_api_calls.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn increment_moderated_responses(&self) {
+        self.moderated_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_negative_cache_hits(&self) {
+        self.negative_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_semantic_cache_hits(&self) {
+        self.semantic_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_injection_detections(&self) {
+        self.injection_detections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_faq_hits(&self) {
+        self.faq_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_local_answers(&self) {
+        self.local_answers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_llm_queue_depth(&self) {
+        self.llm_queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decrement_llm_queue_depth(&self) {
+        self.llm_queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_shed_llm_requests(&self) {
+        self.shed_llm_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_request_queue_depth(&self) {
+        self.request_queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decrement_request_queue_depth(&self) {
+        self.request_queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_dropped_requests(&self) {
+        self.dropped_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_malformed_packets(&self) {
+        self.malformed_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_threat_queries(&self) {
+        self.threat_queries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add the estimated cost in US dollars of one LLM answer
+    pub fn add_llm_cost(&self, dollars: f64) {
+        self.llm_cost_microdollars
+            .fetch_add((dollars * 1_000_000.0).round() as u64, Ordering::Relaxed);
+    }
+
+    pub fn increment_over_budget_queries(&self) {
+        self.over_budget_queries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_downgrade_level(&self, level: usize) {
+        self.downgrade_level.store(level, Ordering::Relaxed);
+    }
+
     pub fn set_active_connections(&self, count: usize) {
         self.active_connections.store(count, Ordering::Relaxed);
     }
 
     pub async fn record_response_time(&self, duration: Duration) {
-        let mut times = self.request_times.write().await;
-        times.push(duration);
-        
-        // Keep only last 1000 times for average calculation
-        if times.len() > 1000 {
-            times.remove(0);
-        }
-        
-        // Calculate new average
-        let total: Duration = times.iter().sum();
-        let avg = total.as_millis() as f64 / times.len() as f64;
-        
-        let mut avg_time = self.average_response_time.write().await;
-        *avg_time = avg;
+        self.state.write().await.response_times.record(duration);
     }
 
     pub async fn record_error(&self, error_type: String) {
-        let mut errors = self.error_counts.write().await;
-        *errors.entry(error_type).or_insert(0) += 1;
+        let mut state = self.state.write().await;
+        *state.error_counts.entry(error_type).or_insert(0) += 1;
     }
 
     pub async fn record_backend_call(&self, backend: String, success: bool, duration: Duration) {
-        let mut stats = self.backend_stats.write().await;
-        let backend_stat = stats.entry(backend).or_insert(BackendStats {
+        let mut state = self.state.write().await;
+        let backend_stat = state.backend_stats.entry(backend).or_insert(BackendStats {
             total_calls: 0,
             successful_calls: 0,
             failed_calls: 0,
+            consecutive_failures: 0,
             average_response_time: 0.0,
             last_call: None,
         });
@@ -119,8 +281,10 @@ impl Metrics {
 
         if success {
             backend_stat.successful_calls += 1;
+            backend_stat.consecutive_failures = 0;
         } else {
             backend_stat.failed_calls += 1;
+            backend_stat.consecutive_failures += 1;
         }
 
         // Update average response time
@@ -128,12 +292,32 @@ impl Metrics {
         backend_stat.average_response_time = (total_time + duration.as_millis() as f64) / backend_stat.total_calls as f64;
     }
 
+    pub async fn record_query(&self, labels: QueryLabels<'_>) {
+        self.state.write().await.queries.increment(&[
+            labels.query_type,
+            labels.zone,
+            labels.cache,
+            labels.persona,
+            labels.backend,
+        ]);
+    }
+
+    /// Stats of one backend endpoint, if it has been called
+    pub async fn backend_stats(&self, backend: &str) -> Option<BackendStats> {
+        self.state.read().await.backend_stats.get(backend).cloned()
+    }
+
     pub fn get_uptime(&self) -> Duration {
-        let start = self.uptime_start.blocking_read();
-        start.elapsed()
+        self.uptime_start.elapsed()
+    }
+
+    pub async fn get_stats(&self) -> MetricsSnapshot {
+        let _state = self.state.read().await;
+        self.load_counters()
     }
 
-    pub fn get_stats(&self) -> MetricsSnapshot {
+    /// The counters, read without waiting for the state lock
+    pub(crate) fn load_counters(&self) -> MetricsSnapshot {
         MetricsSnapshot {
             total_requests: self.total_requests.load(Ordering::Relaxed),
             successful_requests: self.successful_requests.load(Ordering::Relaxed),
@@ -142,51 +326,238 @@ impl Metrics {
             cache_hits: self.cache_hits.load(Ordering::Relaxed),
             cache_misses: self.cache_misses.load(Ordering::Relaxed),
             llm_api_calls: self.llm_api_calls.load(Ordering::Relaxed),
+            moderated_responses: self.moderated_responses.load(Ordering::Relaxed),
+            negative_cache_hits: self.negative_cache_hits.load(Ordering::Relaxed),
+            semantic_cache_hits: self.semantic_cache_hits.load(Ordering::Relaxed),
+            injection_detections: self.injection_detections.load(Ordering::Relaxed),
+            faq_hits: self.faq_hits.load(Ordering::Relaxed),
+            local_answers: self.local_answers.load(Ordering::Relaxed),
+            llm_queue_depth: self.llm_queue_depth.load(Ordering::Relaxed),
+            shed_llm_requests: self.shed_llm_requests.load(Ordering::Relaxed),
+            request_queue_depth: self.request_queue_depth.load(Ordering::Relaxed),
+            dropped_requests: self.dropped_requests.load(Ordering::Relaxed),
+            malformed_packets: self.malformed_packets.load(Ordering::Relaxed),
+            threat_queries: self.threat_queries.load(Ordering::Relaxed),
+            llm_spend: self.llm_cost_microdollars.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+            over_budget_queries: self.over_budget_queries.load(Ordering::Relaxed),
+            downgrade_level: self.downgrade_level.load(Ordering::Relaxed),
             active_connections: self.active_connections.load(Ordering::Relaxed),
             uptime: self.get_uptime(),
         }
     }
 
     pub async fn get_detailed_stats(&self) -> DetailedMetricsSnapshot {
-        let avg_response_time = *self.average_response_time.read().await;
-        let error_counts = self.error_counts.read().await.clone();
-        let backend_stats = self.backend_stats.read().await.clone();
+        let state = self.state.read().await;
 
         DetailedMetricsSnapshot {
-            basic: self.get_stats(),
-            average_response_time: avg_response_time,
-            error_counts,
-            backend_stats,
+            basic: self.load_counters(),
+            average_response_time: state.response_times.mean(),
+            p50_response_time: state.response_times.percentile(0.50),
+            p90_response_time: state.response_times.percentile(0.90),
+            p99_response_time: state.response_times.percentile(0.99),
+            response_times: state.response_times.clone(),
+            error_counts: state.error_counts.clone(),
+            backend_stats: state.backend_stats.clone(),
+            queries: state.queries.clone(),
         }
     }
 
-    pub fn reset(&self) {
-        self.total_requests.store(0, Ordering::Relaxed);
-        self.successful_requests.store(0, Ordering::Relaxed);
-        self.failed_requests.store(0, Ordering::Relaxed);
-        self.rate_limited_requests.store(0, Ordering::Relaxed);
-        self.cache_hits.store(0, Ordering::Relaxed);
-        self.cache_misses.store(0, Ordering::Relaxed);
-        self.llm_api_calls.store(0, Ordering::Relaxed);
-        self.active_connections.store(0, Ordering::Relaxed);
-        
-        // Reset async fields
-        tokio::spawn(async move {
-            let mut avg_time = self.average_response_time.write().await;
-            *avg_time = 0.0;
-            
-            let mut times = self.request_times.write().await;
-            times.clear();
-            
-            let mut errors = self.error_counts.write().await;
-            errors.clear();
-            
-            let mut backends = self.backend_stats.write().await;
-            backends.clear();
-        });
+    /// Zero the counters and forget recorded timings, errors, backend stats
+    /// and query breakdowns
+    pub async fn reset(&self) {
+        let mut state = self.state.write().await;
+        for counter in self.counters() {
+            counter.store(0, Ordering::Relaxed);
+        }
+        *state = MetricsState::default();
+        info!("Metrics reset");
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Upper bounds of the latency buckets in milliseconds
+const LATENCY_BUCKETS_MS: [f64; 14] = [
+    1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 30000.0,
+];
+
+/// Response times counted in fixed buckets, so tail latency survives
+/// without keeping every sample
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    /// One count per bucket, plus one for slower responses
+    counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    count: u64,
+    sum_ms: f64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; LATENCY_BUCKETS_MS.len() + 1],
+            count: 0,
+            sum_ms: 0.0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, duration: Duration) {
+        let ms = duration.as_micros() as f64 / 1000.0;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| ms <= bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Total of all response times in milliseconds
+    pub fn sum_ms(&self) -> f64 {
+        self.sum_ms
+    }
+
+    /// The responses recorded since `earlier`, a snapshot of this histogram
+    pub fn since(&self, earlier: &LatencyHistogram) -> Self {
+        let mut counts = self.counts;
+        for (count, before) in counts.iter_mut().zip(earlier.counts) {
+            *count = count.saturating_sub(before);
+        }
+        Self {
+            counts,
+            count: self.count.saturating_sub(earlier.count),
+            sum_ms: (self.sum_ms - earlier.sum_ms).max(0.0),
+        }
+    }
+
+    /// Mean response time in milliseconds
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum_ms / self.count as f64
+        }
+    }
+
+    /// Estimated `quantile` (0.0 to 1.0) in milliseconds, interpolated
+    /// within its bucket. Responses slower than the last bucket are
+    /// reported at its bound.
+    pub fn percentile(&self, quantile: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+
+        let rank = quantile.clamp(0.0, 1.0) * self.count as f64;
+        let mut below = 0u64;
+        for (i, &count) in self.counts.iter().enumerate() {
+            if count > 0 && (below + count) as f64 >= rank {
+                let Some(&upper) = LATENCY_BUCKETS_MS.get(i) else {
+                    return LATENCY_BUCKETS_MS[LATENCY_BUCKETS_MS.len() - 1];
+                };
+                let lower = if i == 0 { 0.0 } else { LATENCY_BUCKETS_MS[i - 1] };
+                return lower + (upper - lower) * ((rank - below as f64) / count as f64);
+            }
+            below += count;
+        }
+        LATENCY_BUCKETS_MS[LATENCY_BUCKETS_MS.len() - 1]
+    }
+
+    /// `(upper bound in ms, cumulative count)` per bucket, ending with
+    /// `+Inf`, as Prometheus histograms expect
+    pub fn cumulative_buckets(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        LATENCY_BUCKETS_MS
+            .iter()
+            .chain(std::iter::once(&f64::INFINITY))
+            .zip(self.counts)
+            .map(|(&bound, count)| {
+                total += count;
+                (bound, total)
+            })
+            .collect()
+    }
+}
+
+/// A counter split by a fixed set of labels, with one count per
+/// combination of label values seen
+#[derive(Debug, Clone)]
+pub struct LabeledCounter {
+    names: &'static [&'static str],
+    counts: HashMap<Vec<String>, u64>,
+}
+
+impl LabeledCounter {
+    pub fn new(names: &'static [&'static str]) -> Self {
+        Self {
+            names,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Count one event. `values` are in the order of the label names.
+    pub fn increment(&mut self, values: &[&str]) {
+        debug_assert_eq!(values.len(), self.names.len());
+        *self.counts.entry(Self::key(values)).or_insert(0) += 1;
+    }
+
+    pub fn get(&self, values: &[&str]) -> u64 {
+        self.counts.get(&Self::key(values)).copied().unwrap_or(0)
+    }
+
+    fn key(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    /// Total over every label combination matching `label = value`
+    pub fn sum_by(&self, label: &str, value: &str) -> u64 {
+        let Some(index) = self.names.iter().position(|&name| name == label) else {
+            return 0;
+        };
+        self.counts
+            .iter()
+            .filter(|(key, _)| key[index] == value)
+            .map(|(_, &count)| count)
+            .sum()
+    }
+
+    pub fn names(&self) -> &[&'static str] {
+        self.names
+    }
+
+    /// `(label values, count)` pairs, sorted by label values
+    pub fn entries(&self) -> Vec<(&[String], u64)> {
+        let mut entries: Vec<_> = self.counts.iter().map(|(key, &count)| (key.as_slice(), count)).collect();
+        entries.sort();
+        entries
+    }
+
+    fn write_prometheus(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+        for (values, count) in self.entries() {
+            let labels: Vec<String> = self
+                .names
+                .iter()
+                .zip(values)
+                .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+                .collect();
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels.join(","), count);
+        }
     }
 }
 
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
 #[derive(Debug, Clone)]
 pub struct MetricsSnapshot {
     pub total_requests: u64,
@@ -196,6 +567,22 @@ pub struct MetricsSnapshot {
     pub cache_hits: u64,
     pub cache_misses: u64,
     pub llm_api_calls: u64,
+    pub moderated_responses: u64,
+    pub negative_cache_hits: u64,
+    pub semantic_cache_hits: u64,
+    pub injection_detections: u64,
+    pub faq_hits: u64,
+    pub local_answers: u64,
+    pub llm_queue_depth: usize,
+    pub shed_llm_requests: u64,
+    pub request_queue_depth: usize,
+    pub dropped_requests: u64,
+    pub malformed_packets: u64,
+    pub threat_queries: u64,
+    /// Estimated spend on the LLM in US dollars
+    pub llm_spend: f64,
+    pub over_budget_queries: u64,
+    pub downgrade_level: usize,
     pub active_connections: usize,
     pub uptime: Duration,
 }
@@ -203,9 +590,85 @@ pub struct MetricsSnapshot {
 #[derive(Debug, Clone)]
 pub struct DetailedMetricsSnapshot {
     pub basic: MetricsSnapshot,
+    /// Response times in milliseconds
     pub average_response_time: f64,
+    pub p50_response_time: f64,
+    pub p90_response_time: f64,
+    pub p99_response_time: f64,
+    pub response_times: LatencyHistogram,
     pub error_counts: HashMap<String, u64>,
     pub backend_stats: HashMap<String, BackendStats>,
+    /// Queries by type, zone, cache result, persona and backend
+    pub queries: LabeledCounter,
+}
+
+impl DetailedMetricsSnapshot {
+    /// Render in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let basic = &self.basic;
+        let mut out = String::new();
+
+        let counters = [
+            ("llmdig_requests_total", "DNS requests received", basic.total_requests),
+            ("llmdig_successful_requests_total", "Requests answered", basic.successful_requests),
+            ("llmdig_failed_requests_total", "Requests that failed", basic.failed_requests),
+            ("llmdig_rate_limited_requests_total", "Requests refused by rate limits", basic.rate_limited_requests),
+            ("llmdig_cache_hits_total", "Answers served from the cache", basic.cache_hits),
+            ("llmdig_cache_misses_total", "Questions not found in the cache", basic.cache_misses),
+            ("llmdig_llm_api_calls_total", "Calls to the LLM backend", basic.llm_api_calls),
+            ("llmdig_moderated_responses_total", "Answers changed by moderation", basic.moderated_responses),
+            ("llmdig_negative_cache_hits_total", "Questions answered from the negative cache", basic.negative_cache_hits),
+            ("llmdig_semantic_cache_hits_total", "Answers served from the semantic cache", basic.semantic_cache_hits),
+            ("llmdig_injection_detections_total", "Questions refused as prompt injection attempts", basic.injection_detections),
+            ("llmdig_faq_hits_total", "Questions answered from the FAQ table", basic.faq_hits),
+            ("llmdig_local_answers_total", "Questions answered by a local answerer", basic.local_answers),
+            ("llmdig_shed_llm_requests_total", "LLM requests rejected by a concurrency limit", basic.shed_llm_requests),
+            ("llmdig_dropped_requests_total", "Packets dropped because the request queue was full", basic.dropped_requests),
+            ("llmdig_malformed_packets_total", "Packets that could not be parsed as DNS messages", basic.malformed_packets),
+            ("llmdig_threat_queries_total", "Queries the honeypot logged as scans or attacks", basic.threat_queries),
+            ("llmdig_over_budget_queries_total", "Questions estimated to cost more than llm.max_cost_per_query", basic.over_budget_queries),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value);
+        }
+
+        let name = "llmdig_llm_cost_dollars_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Estimated spend on the LLM in US dollars\n# TYPE {} counter\n{} {}",
+            name, name, name, basic.llm_spend
+        );
+
+        let gauges = [
+            ("llmdig_llm_queue_depth", "LLM requests waiting for a concurrency slot", basic.llm_queue_depth as f64),
+            ("llmdig_request_queue_depth", "Packets waiting for a worker", basic.request_queue_depth as f64),
+            ("llmdig_downgrade_level", "Steps down the model ladder taken because of load", basic.downgrade_level as f64),
+            ("llmdig_active_connections", "Open client connections", basic.active_connections as f64),
+            ("llmdig_uptime_seconds", "Seconds since the server started", basic.uptime.as_secs_f64()),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, value);
+        }
+
+        let name = "llmdig_response_time_seconds";
+        let _ = writeln!(out, "# HELP {} Time to answer a DNS request\n# TYPE {} histogram", name, name);
+        for (bound, count) in self.response_times.cumulative_buckets() {
+            let le = if bound.is_infinite() { "+Inf".to_string() } else { (bound / 1000.0).to_string() };
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, count);
+        }
+        let _ = writeln!(out, "{}_sum {}", name, self.response_times.sum_ms() / 1000.0);
+        let _ = writeln!(out, "{}_count {}", name, self.response_times.count());
+
+        let name = "llmdig_response_time_quantile_seconds";
+        let _ = writeln!(out, "# HELP {} Estimated response time percentiles\n# TYPE {} gauge", name, name);
+        for (quantile, ms) in [("0.5", self.p50_response_time), ("0.9", self.p90_response_time), ("0.99", self.p99_response_time)] {
+            let _ = writeln!(out, "{}{{quantile=\"{}\"}} {}", name, quantile, ms / 1000.0);
+        }
+
+        self.queries.write_prometheus(&mut out, "llmdig_queries_total", "DNS queries by type, zone, cache result, persona and backend");
+
+        out
+    }
 }
 
 impl MetricsSnapshot {
@@ -270,7 +733,6 @@ impl MetricsMiddleware {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio::time::sleep;
 
     #[tokio::test]
     async fn test_metrics_basic() {
@@ -280,7 +742,7 @@ mod tests {
         metrics.increment_successful_requests();
         metrics.increment_cache_hits();
         
-        let stats = metrics.get_stats();
+        let stats = metrics.get_stats().await;
         assert_eq!(stats.total_requests, 1);
         assert_eq!(stats.successful_requests, 1);
         assert_eq!(stats.cache_hits, 1);
@@ -311,5 +773,100 @@ mod tests {
         assert_eq!(openai_stats.total_calls, 2);
         assert_eq!(openai_stats.successful_calls, 1);
         assert_eq!(openai_stats.failed_calls, 1);
+        assert_eq!(openai_stats.consecutive_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_reset() {
+        let metrics = Metrics::new();
+        metrics.increment_total_requests();
+        metrics.increment_llm_queue_depth();
+        metrics.record_error("timeout".to_string()).await;
+        metrics.record_backend_call("openai".to_string(), true, Duration::from_millis(100)).await;
+        metrics.record_query(QueryLabels { query_type: "TXT", ..Default::default() }).await;
+
+        metrics.reset().await;
+
+        let detailed = metrics.get_detailed_stats().await;
+        assert_eq!(detailed.basic.total_requests, 0);
+        assert!(detailed.error_counts.is_empty());
+        assert!(metrics.backend_stats("openai").await.is_none());
+        assert!(detailed.queries.entries().is_empty());
+        // Gauges and uptime describe the present and survive a reset
+        assert_eq!(detailed.basic.llm_queue_depth, 1);
+        assert!(detailed.basic.uptime <= metrics.get_uptime());
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let mut histogram = LatencyHistogram::default();
+        for _ in 0..90 {
+            histogram.record(Duration::from_millis(20));
+        }
+        for _ in 0..10 {
+            histogram.record(Duration::from_millis(2000));
+        }
+
+        // Both fall in the 10..25 ms and 1000..2500 ms buckets
+        assert!((10.0..=25.0).contains(&histogram.percentile(0.5)));
+        assert!((10.0..=25.0).contains(&histogram.percentile(0.9)));
+        assert!((1000.0..=2500.0).contains(&histogram.percentile(0.99)));
+        assert_eq!(histogram.mean(), 218.0);
+        assert_eq!(LatencyHistogram::default().percentile(0.99), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_prometheus_output() {
+        let metrics = Metrics::new();
+        metrics.increment_total_requests();
+        metrics.record_response_time(Duration::from_millis(40)).await;
+        metrics.add_llm_cost(0.0125);
+        metrics.add_llm_cost(0.0005);
+
+        let text = metrics.get_detailed_stats().await.to_prometheus();
+        assert!(text.contains("llmdig_requests_total 1\n"));
+        assert!(text.contains("llmdig_llm_cost_dollars_total 0.013\n"));
+        assert!(text.contains("llmdig_response_time_seconds_bucket{le=\"0.025\"} 0\n"));
+        assert!(text.contains("llmdig_response_time_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(text.contains("llmdig_response_time_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("llmdig_response_time_seconds_count 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_query_breakdown() {
+        let metrics = Metrics::new();
+        let answered = QueryLabels {
+            query_type: "TXT",
+            zone: "llm.example.com.",
+            cache: "miss",
+            persona: "pirate",
+            backend: "openai",
+        };
+        metrics.record_query(answered).await;
+        metrics.record_query(QueryLabels { cache: "hit", backend: "", ..answered }).await;
+        metrics.record_query(QueryLabels { query_type: "A", ..Default::default() }).await;
+
+        let queries = metrics.get_detailed_stats().await.queries;
+        assert_eq!(queries.get(&["TXT", "llm.example.com.", "miss", "pirate", "openai"]), 1);
+        assert_eq!(queries.sum_by("qtype", "TXT"), 2);
+        assert_eq!(queries.sum_by("persona", "pirate"), 2);
+        assert_eq!(queries.sum_by("cache", "hit"), 1);
+        assert_eq!(queries.sum_by("unknown", "TXT"), 0);
+    }
+
+    #[test]
+    fn test_labeled_counter_prometheus() {
+        let mut counter = LabeledCounter::new(&["qtype", "persona"]);
+        counter.increment(&["TXT", "say \"hi\""]);
+        counter.increment(&["A", ""]);
+
+        let mut out = String::new();
+        counter.write_prometheus(&mut out, "llmdig_queries_total", "DNS queries");
+        assert_eq!(
+            out,
+            "# HELP llmdig_queries_total DNS queries\n# TYPE llmdig_queries_total counter\n\
+             llmdig_queries_total{qtype=\"A\",persona=\"\"} 1\n\
+             llmdig_queries_total{qtype=\"TXT\",persona=\"say \\\"hi\\\"\"} 1\n"
+        );
     }
-} 
\ No newline at end of file
+}
\ No newline at end of file
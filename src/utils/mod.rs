@@ -4,4 +4,44 @@ pub mod metrics;
 pub mod cache;
 pub mod network;
 pub mod validation;
-pub mod encryption; 
\ No newline at end of file
+pub mod encryption; 
+pub mod query_log;
+pub mod acl;
+pub mod tsig;
+pub mod api_keys;
+pub mod moderation;
+pub mod question_policy;
+pub mod injection;
+pub mod shard;
+pub mod cache_key;
+pub mod embeddings;
+pub mod forwarder;
+pub mod zones;
+pub mod zone_profiles;
+pub mod answer_ttl;
+pub mod static_records;
+pub mod load_balancer;
+pub mod concurrency;
+pub mod work_queue;
+pub mod post_process;
+pub mod personas;
+pub mod tools;
+pub mod rag;
+pub mod log_policy;
+pub mod abuse;
+pub mod cookies;
+pub mod retention;
+pub mod tenants;
+pub mod metering;
+pub mod load_control;
+pub mod shadow;
+pub mod faq;
+pub mod local_answers;
+pub mod reverse;
+pub mod threats;
+pub mod storage;
+pub mod cassette;
+pub mod text;
+pub mod audit;
+pub mod llm_keys;
+pub mod secret;
\ No newline at end of file
@@ -0,0 +1,267 @@
+use crate::config::{Config, ModerationAction};
+use crate::llm::http_client;
+use crate::utils::llm_keys::LlmKeyRing;
+use crate::Error;
+use anyhow::Result;
+use regex::Regex;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, warn};
+
+const REDACTION: &str = "[redacted]";
+
+const OPENAI_MODERATION_URL: &str = "https://api.openai.com/v1/moderations";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModerationVerdict {
+    Allowed,
+    /// Offending spans were replaced; carries the cleaned text
+    Redacted(String),
+    /// The whole answer must be withheld; carries the reason
+    Blocked(String),
+}
+
+/// Post-processing filter applied to LLM answers before they are encoded
+pub struct Moderator {
+    action: ModerationAction,
+    patterns: Vec<Regex>,
+    openai: Option<OpenAiModeration>,
+    blocked_message: String,
+}
+
+impl Moderator {
+    /// `keys` are the LLM provider's, used for OpenAI moderation
+    pub fn new(config: &Config, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        let moderation = &config.moderation;
+
+        let mut patterns = moderation
+            .denylist_patterns
+            .iter()
+            .map(|p| {
+                Regex::new(p).map_err(|e| Error::Configuration(format!("Invalid moderation pattern {}: {}", p, e)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut keywords = moderation.keywords.clone();
+        if let Some(path) = &moderation.keywords_file {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| Error::Configuration(format!("Cannot read keywords file {}: {}", path, e)))?;
+            keywords.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+
+        if !keywords.is_empty() {
+            let alternatives: Vec<_> = keywords.iter().map(|k| regex::escape(k)).collect();
+            patterns.push(Regex::new(&format!(r"(?i)\b({})\b", alternatives.join("|")))?);
+        }
+
+        let openai = if moderation.openai_moderation {
+            Some(OpenAiModeration::new(config, keys)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            action: moderation.action.clone(),
+            patterns,
+            openai,
+            blocked_message: moderation.blocked_message.clone(),
+        })
+    }
+
+    /// Text returned to clients in place of a blocked answer
+    pub fn blocked_message(&self) -> &str {
+        &self.blocked_message
+    }
+
+    pub async fn moderate(&self, text: &str) -> ModerationVerdict {
+        let matched: Vec<&Regex> = self.patterns.iter().filter(|p| p.is_match(text)).collect();
+
+        let mut verdict = if matched.is_empty() {
+            ModerationVerdict::Allowed
+        } else {
+            match self.action {
+                ModerationAction::Block => {
+                    ModerationVerdict::Blocked(format!("matched denylist pattern {}", matched[0].as_str()))
+                }
+                ModerationAction::Redact => {
+                    let mut redacted = text.to_string();
+                    for pattern in matched {
+                        redacted = pattern.replace_all(&redacted, REDACTION).to_string();
+                    }
+                    ModerationVerdict::Redacted(redacted)
+                }
+            }
+        };
+
+        // The remote classifier cannot point at spans, so a flag always blocks
+        let already_blocked = matches!(verdict, ModerationVerdict::Blocked(_));
+        if let Some(openai) = self.openai.as_ref().filter(|_| !already_blocked) {
+            let checked = match &verdict {
+                ModerationVerdict::Redacted(redacted) => redacted.as_str(),
+                _ => text,
+            };
+
+            match openai.check(checked).await {
+                Ok(Some(category)) => verdict = ModerationVerdict::Blocked(format!("flagged as {}", category)),
+                Ok(None) => {}
+                Err(e) => {
+                    // Fail closed: an operator who enabled moderation wants nothing unchecked
+                    error!("Moderation endpoint failed, withholding answer: {}", e);
+                    verdict = ModerationVerdict::Blocked("moderation endpoint unavailable".to_string());
+                }
+            }
+        }
+
+        if let ModerationVerdict::Blocked(reason) = &verdict {
+            warn!("Answer blocked by moderation: {}", reason);
+        }
+
+        verdict
+    }
+}
+
+struct OpenAiModeration {
+    client: Client,
+    url: String,
+    keys: Arc<LlmKeyRing>,
+}
+
+impl OpenAiModeration {
+    fn new(config: &Config, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        if keys.candidates().is_empty() {
+            return Err(Error::Configuration("OpenAI moderation requires an API key".to_string()).into());
+        }
+
+        let client = http_client(config)?;
+
+        Ok(Self {
+            client,
+            url: OPENAI_MODERATION_URL.to_string(),
+            keys,
+        })
+    }
+
+    /// Returns the first flagged category, if any
+    async fn check(&self, text: &str) -> Result<Option<String>> {
+        let request = ModerationRequest { input: text.to_string() };
+        let response = self.keys.send(|| self.client.post(&self.url).json(&request)).await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(Error::LlmApi(error_text).into());
+        }
+
+        let response: ModerationResponse = response.json().await?;
+        let flagged = response.results.into_iter().find(|result| result.flagged);
+
+        Ok(flagged.map(|result| {
+            result
+                .categories
+                .into_iter()
+                .find(|(_, flagged)| *flagged)
+                .map(|(category, _)| category)
+                .unwrap_or_else(|| "unspecified".to_string())
+        }))
+    }
+}
+
+#[derive(Serialize)]
+struct ModerationRequest {
+    input: String,
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    #[serde(default)]
+    categories: std::collections::HashMap<String, bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModerationConfig;
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn moderator(action: ModerationAction) -> Moderator {
+        let mut config = Config::default();
+        config.moderation = ModerationConfig {
+            enabled: true,
+            action,
+            denylist_patterns: vec![r"\b\d{3}-\d{2}-\d{4}\b".to_string()],
+            keywords: vec!["darn".to_string()],
+            ..Default::default()
+        };
+        Moderator::new(&config, LlmKeyRing::for_config(&config)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_openai_moderation_follows_key_rotation() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/moderations"))
+            .and(header("authorization", "Bearer sk-mod-new-0002"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "results": [{ "flagged": false }] })))
+            .mount(&server)
+            .await;
+        // Every other key has been revoked
+        Mock::given(method("POST"))
+            .and(path("/moderations"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+
+        let keys = Arc::new(LlmKeyRing::new(Some(&"sk-mod-old-0001".into()), Some(&"sk-mod-new-0002".into())));
+        keys.promote_secondary().unwrap();
+        let moderation = OpenAiModeration {
+            client: Client::new(),
+            url: format!("{}/moderations", server.uri()),
+            keys: keys.clone(),
+        };
+        assert_eq!(moderation.check("Rust is a systems language").await.unwrap(), None);
+
+        // Before promotion, the revoked primary fails over to the secondary
+        let keys = Arc::new(LlmKeyRing::new(Some(&"sk-mod-old-0001".into()), Some(&"sk-mod-new-0002".into())));
+        let moderation = OpenAiModeration { keys: keys.clone(), ..moderation };
+        assert_eq!(moderation.check("Rust is a systems language").await.unwrap(), None);
+        assert!(keys.status().failed_over);
+    }
+
+    #[tokio::test]
+    async fn test_allowed() {
+        let moderator = moderator(ModerationAction::Block);
+        assert_eq!(moderator.moderate("Rust is a systems language").await, ModerationVerdict::Allowed);
+    }
+
+    #[tokio::test]
+    async fn test_block() {
+        let moderator = moderator(ModerationAction::Block);
+        assert!(matches!(
+            moderator.moderate("my ssn is 123-45-6789").await,
+            ModerationVerdict::Blocked(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_redact() {
+        let moderator = moderator(ModerationAction::Redact);
+        assert_eq!(
+            moderator.moderate("Darn, the number is 123-45-6789").await,
+            ModerationVerdict::Redacted("[redacted], the number is [redacted]".to_string())
+        );
+    }
+}
\ No newline at end of file
@@ -1,8 +1,13 @@
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use crate::config::{ForwardingConfig, UpstreamConfig};
+use crate::utils::forwarder::Forwarder;
+use network_interface::{NetworkInterface as OsInterface, NetworkInterfaceConfig};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
 use std::time::Duration;
 use tokio::net::UdpSocket;
 use tokio::time::timeout;
 use tracing::{debug, error, info, warn};
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::{Name, RecordType};
 
 #[derive(Debug, Clone)]
 pub struct NetworkConfig {
@@ -208,6 +213,22 @@ impl DnsNetworkUtils {
         data[1] = id_bytes[1];
         true
     }
+
+    /// Build a header-only FORMERR response to a packet that could not be
+    /// parsed. Returns `None` when there is no whole header to answer or the
+    /// packet claims to be a response, which must never be answered.
+    pub fn format_error_response(data: &[u8]) -> Option<Vec<u8>> {
+        if !Self::is_dns_query(data) {
+            return None;
+        }
+
+        let mut response = vec![0u8; 12];
+        response[..2].copy_from_slice(&data[..2]);
+        // QR set, opcode and RD echoed, RCODE 1 (FORMERR)
+        response[2] = 0x80 | (data[2] & 0x79);
+        response[3] = 0x01;
+        Some(response)
+    }
 }
 
 // Network diagnostics
@@ -233,25 +254,49 @@ impl NetworkDiagnostics {
         None
     }
 
-    /// Test DNS resolution
+    /// Test DNS resolution of `domain` through `nameserver` (`ip:port`, or
+    /// an address using port 53) over UDP, retried over TCP when the answer
+    /// is truncated
     pub async fn test_dns_resolution(domain: &str, nameserver: &str) -> Result<Duration, Box<dyn std::error::Error>> {
-        use std::process::Command;
-        
-        let start = std::time::Instant::now();
+        let upstream = UpstreamConfig {
+            address: nameserver.to_string(),
+            ..Default::default()
+        };
+        Self::test_upstream_resolution(domain, &upstream, Duration::from_secs(5)).await
+    }
+    
+    /// Test DNS resolution of `domain` through an upstream over any transport
+    /// the forwarder speaks: UDP, TCP, DNS over TLS or DNS over HTTPS. Fails
+    /// unless an answer without error arrives within `time_limit`.
+    pub async fn test_upstream_resolution(
+        domain: &str,
+        upstream: &UpstreamConfig,
+        time_limit: Duration,
+    ) -> Result<Duration, Box<dyn std::error::Error>> {
+        let forwarder = Forwarder::new(&ForwardingConfig {
+            enabled: true,
+            upstreams: vec![upstream.clone()],
+            timeout_ms: time_limit.as_millis() as u64,
+            ..Default::default()
+        })?;
         
-        let output = Command::new("dig")
-            .arg("@".to_string() + nameserver)
-            .arg(domain)
-            .arg("+short")
-            .arg("+timeout=5")
-            .output()?;
+        let mut query = Message::new();
+        query.set_id(rand::random());
+        query.set_message_type(MessageType::Query);
+        query.set_op_code(OpCode::Query);
+        query.set_recursion_desired(true);
+        query.add_query(Query::query(Name::from_ascii(domain)?, RecordType::A));
         
+        let start = std::time::Instant::now();
+        let response = Message::from_vec(&forwarder.forward(&query.to_vec()?).await?)?;
         let duration = start.elapsed();
         
-        if output.status.success() {
-            Ok(duration)
-        } else {
-            Err("DNS resolution failed".into())
+        if response.id() != query.id() || response.message_type() != MessageType::Response {
+            return Err(format!("{} did not answer the query", upstream.address).into());
+        }
+        match response.response_code() {
+            ResponseCode::NoError => Ok(duration),
+            code => Err(format!("{} answered {}", upstream.address, code).into()),
         }
     }
 
@@ -266,49 +311,50 @@ impl NetworkDiagnostics {
         }
     }
 
-    /// Get network interface information
+    /// Every network interface with its hardware and IP addresses, from
+    /// `getifaddrs` on Unix and `GetAdaptersAddresses` on Windows
     pub fn get_network_interfaces() -> Result<Vec<NetworkInterface>, Box<dyn std::error::Error>> {
-        #[cfg(target_os = "linux")]
-        {
-            use std::fs;
-            
-            let mut interfaces = Vec::new();
-            
-            for entry in fs::read_dir("/sys/class/net")? {
-                let entry = entry?;
-                let name = entry.file_name().to_string_lossy().to_string();
-                
-                if let Ok(addr) = fs::read_to_string(format!("/sys/class/net/{}/address", name)) {
-                    interfaces.push(NetworkInterface {
-                        name,
-                        mac_address: addr.trim().to_string(),
-                        ip_addresses: Vec::new(), // Would need more complex parsing
-                    });
-                }
-            }
-            
-            Ok(interfaces)
-        }
-        
-        #[cfg(not(target_os = "linux"))]
-        {
-            // Fallback for other platforms
-            Ok(vec![NetworkInterface {
-                name: "default".to_string(),
-                mac_address: "unknown".to_string(),
-                ip_addresses: vec![],
-            }])
-        }
+        let mut interfaces: Vec<NetworkInterface> = OsInterface::show()?
+            .into_iter()
+            .map(|interface| NetworkInterface {
+                ip_addresses: interface.addr.iter().map(|addr| addr.ip()).collect(),
+                mac_address: interface.mac_addr.filter(|mac| !mac.is_empty()),
+                index: interface.index,
+                name: interface.name,
+            })
+            .collect();
+        interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(interfaces)
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct NetworkInterface {
     pub name: String,
-    pub mac_address: String,
+    /// The system's number for the interface, the scope of its link-local
+    /// IPv6 addresses
+    pub index: u32,
+    /// Hardware address as `aa:bb:cc:dd:ee:ff`, when the interface has one
+    pub mac_address: Option<String>,
     pub ip_addresses: Vec<IpAddr>,
 }
 
+impl NetworkInterface {
+    /// One socket address per IP address of the interface, link-local IPv6
+    /// ones scoped to it so they can be bound
+    pub fn socket_addrs(&self, port: u16) -> Vec<SocketAddr> {
+        self.ip_addresses
+            .iter()
+            .map(|ip| match ip {
+                IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80 => {
+                    SocketAddr::V6(SocketAddrV6::new(*ip, port, 0, self.index))
+                }
+                ip => SocketAddr::new(*ip, port),
+            })
+            .collect()
+    }
+}
+
 // Connection pool for managing multiple connections
 pub struct ConnectionPool {
     max_connections: usize,
@@ -387,6 +433,72 @@ mod tests {
         assert_eq!(DnsNetworkUtils::get_dns_id(&packet), Some(0x5678));
     }
 
+    #[test]
+    fn test_format_error_response() {
+        // Query with RD set whose question section is cut short
+        let packet = vec![0xab, 0xcd, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, b'f'];
+        let response = DnsNetworkUtils::format_error_response(&packet).unwrap();
+        assert_eq!(response, vec![0xab, 0xcd, 0x81, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        // Responses and partial headers get no answer
+        let response_packet = vec![0xab, 0xcd, 0x81, 0x80, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(DnsNetworkUtils::format_error_response(&response_packet), None);
+        assert_eq!(DnsNetworkUtils::format_error_response(&packet[..5]), None);
+    }
+
+    #[tokio::test]
+    async fn test_dns_resolution() {
+        // Answers over UDP without error, and over TCP with NXDOMAIN
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = tokio::net::TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, client) = udp.recv_from(&mut buf).await.unwrap();
+            buf[2] |= 0x80;
+            udp.send_to(&buf[..len], client).await.unwrap();
+        });
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let mut query = vec![0u8; stream.read_u16().await.unwrap() as usize];
+            stream.read_exact(&mut query).await.unwrap();
+            query[2] |= 0x80;
+            query[3] = 0x03;
+            stream.write_u16(query.len() as u16).await.unwrap();
+            stream.write_all(&query).await.unwrap();
+        });
+
+        assert!(NetworkDiagnostics::test_dns_resolution("example.com", &addr.to_string()).await.is_ok());
+
+        let upstream = UpstreamConfig {
+            address: addr.to_string(),
+            transport: crate::config::UpstreamTransport::Tcp,
+            bootstrap: None,
+        };
+        let error = NetworkDiagnostics::test_upstream_resolution("example.com", &upstream, Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Non-Existent Domain"), "{}", error);
+    }
+
+    #[test]
+    fn test_network_interfaces() {
+        let interfaces = NetworkDiagnostics::get_network_interfaces().unwrap();
+        assert!(interfaces.iter().any(|interface| interface.ip_addresses.contains(&IpAddr::V4(Ipv4Addr::LOCALHOST))));
+
+        let interface = NetworkInterface {
+            name: "eth0".to_string(),
+            index: 2,
+            mac_address: Some("02:42:ac:11:00:02".to_string()),
+            ip_addresses: vec!["10.0.0.5".parse().unwrap(), "fe80::1".parse().unwrap(), "2001:db8::5".parse().unwrap()],
+        };
+        let addrs = interface.socket_addrs(53);
+        assert_eq!(addrs[0], "10.0.0.5:53".parse().unwrap());
+        assert_eq!(addrs[1], "[fe80::1%2]:53".parse().unwrap());
+        assert_eq!(addrs[2], "[2001:db8::5]:53".parse().unwrap());
+    }
+
     #[tokio::test]
     async fn test_network_manager() {
         let config = NetworkConfig {
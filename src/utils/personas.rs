@@ -0,0 +1,165 @@
+use crate::config::{PersonaConfig, PersonasConfig};
+use crate::llm::GenerationOptions;
+use crate::Error;
+use anyhow::Result;
+use std::collections::HashMap;
+use trust_dns_proto::rr::Name;
+
+#[derive(Debug, Clone)]
+pub struct Persona {
+    pub name: String,
+    pub system_prompt: String,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+}
+
+impl Persona {
+    fn new(config: &PersonaConfig) -> Self {
+        Self {
+            name: config.name.to_lowercase(),
+            system_prompt: config.system_prompt.clone(),
+            model: config.model.clone(),
+            temperature: config.temperature,
+        }
+    }
+
+    /// Layer the persona's settings over the request's generation options
+    pub fn apply(&self, options: &mut GenerationOptions) {
+        options.system_prompt = Some(self.system_prompt.clone());
+        if self.model.is_some() {
+            options.model = self.model.clone();
+        }
+        if self.temperature.is_some() {
+            options.temperature = self.temperature;
+        }
+    }
+}
+
+/// System-prompt presets selected by the first label of the question
+pub struct Personas {
+    enabled: bool,
+    presets: HashMap<String, Persona>,
+}
+
+impl Personas {
+    pub fn new(config: &PersonasConfig) -> Result<Self> {
+        let mut presets = HashMap::new();
+
+        for preset in &config.presets {
+            if preset.name.is_empty() || preset.name.contains('.') {
+                return Err(Error::Configuration(format!("Invalid persona name {:?}", preset.name)).into());
+            }
+
+            let persona = Persona::new(preset);
+            if presets.insert(persona.name.clone(), persona).is_some() {
+                return Err(Error::Configuration(format!("Duplicate persona {}", preset.name)).into());
+            }
+        }
+
+        Ok(Self {
+            enabled: config.enabled,
+            presets,
+        })
+    }
+
+    /// The persona with this name, if personas are enabled
+    pub fn get(&self, name: &str) -> Option<&Persona> {
+        if !self.enabled {
+            return None;
+        }
+        self.presets.get(&name.to_lowercase())
+    }
+
+    /// Split a leading persona label off the query name. Names whose first
+    /// label is not a configured persona are returned unchanged.
+    pub fn split(&self, name: &Name) -> (Option<&Persona>, Name) {
+        if !self.enabled {
+            return (None, name.clone());
+        }
+
+        let persona = name
+            .iter()
+            .next()
+            .and_then(|label| self.presets.get(&String::from_utf8_lossy(label).to_lowercase()));
+
+        match persona {
+            Some(persona) => {
+                let mut rest = Name::from_labels(name.iter().skip(1)).unwrap_or_else(|_| Name::root());
+                rest.set_fqdn(name.is_fqdn());
+                (Some(persona), rest)
+            }
+            None => (None, name.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn personas() -> Personas {
+        let config = PersonasConfig {
+            enabled: true,
+            presets: vec![PersonaConfig {
+                name: "Pirate".to_string(),
+                system_prompt: "Answer like a pirate.".to_string(),
+                model: None,
+                temperature: Some(1.2),
+            }],
+        };
+        Personas::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_split_persona() {
+        let personas = personas();
+
+        let (persona, rest) = personas.split(&Name::from_str("PIRATE.what.is.dns.llm.").unwrap());
+        assert_eq!(persona.map(|p| p.name.as_str()), Some("pirate"));
+        assert_eq!(rest.to_string(), "what.is.dns.llm.");
+
+        let (persona, rest) = personas.split(&Name::from_str("eli5.what.is.dns.llm.").unwrap());
+        assert!(persona.is_none());
+        assert_eq!(rest.to_string(), "eli5.what.is.dns.llm.");
+    }
+
+    #[test]
+    fn test_apply_keeps_unset_overrides() {
+        let personas = personas();
+        let (persona, _) = personas.split(&Name::from_str("pirate.what.is.dns.llm.").unwrap());
+        let mut options = GenerationOptions {
+            model: Some("gpt-4o".to_string()),
+            ..Default::default()
+        };
+        persona.unwrap().apply(&mut options);
+
+        assert_eq!(options.system_prompt.as_deref(), Some("Answer like a pirate."));
+        assert_eq!(options.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(options.temperature, Some(1.2));
+    }
+
+    #[test]
+    fn test_invalid_presets() {
+        let preset = PersonaConfig {
+            name: "eli5".to_string(),
+            system_prompt: "Explain it simply.".to_string(),
+            model: None,
+            temperature: None,
+        };
+        let duplicate = PersonasConfig {
+            enabled: true,
+            presets: vec![preset.clone(), preset.clone()],
+        };
+        assert!(Personas::new(&duplicate).is_err());
+
+        let dotted = PersonasConfig {
+            enabled: true,
+            presets: vec![PersonaConfig {
+                name: "eli.5".to_string(),
+                ..preset
+            }],
+        };
+        assert!(Personas::new(&dotted).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,96 @@
+use crate::utils::text::truncate_to_chars;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref CODE_FENCE: Regex = Regex::new(r"(?m)^[ \t]*(```|~~~).*$").unwrap();
+    static ref HEADING: Regex = Regex::new(r"(?m)^[ \t]*#{1,6}[ \t]+").unwrap();
+    static ref BLOCKQUOTE: Regex = Regex::new(r"(?m)^[ \t]*>[ \t]?").unwrap();
+    static ref BULLET: Regex = Regex::new(r"(?m)^[ \t]*[*+][ \t]+").unwrap();
+    static ref IMAGE: Regex = Regex::new(r"!\[([^\]]*)\]\([^)]*\)").unwrap();
+    static ref LINK: Regex = Regex::new(r"\[([^\]]+)\]\([^)]*\)").unwrap();
+    static ref BOLD: Regex = Regex::new(r"(\*\*|__)(\S(?:.*?\S)?)(\*\*|__)").unwrap();
+    static ref ITALIC: Regex = Regex::new(r"\*(\S(?:[^*]*?\S)?)\*").unwrap();
+    static ref INLINE_CODE: Regex = Regex::new(r"`([^`]+)`").unwrap();
+    static ref PREAMBLE: Regex = Regex::new(
+        r"(?i)^\s*(?:(?:sure|certainly|of course|absolutely|great question|good question)[!.,]\s*)?(?:here(?:'s| is| are)\b[^\n]*?:\s*)?"
+    )
+    .unwrap();
+}
+
+/// Remove markdown syntax while keeping the text it marks up
+pub fn strip_markdown(text: &str) -> String {
+    let text = CODE_FENCE.replace_all(text, "");
+    let text = HEADING.replace_all(&text, "");
+    let text = BLOCKQUOTE.replace_all(&text, "");
+    let text = BULLET.replace_all(&text, "- ");
+    let text = IMAGE.replace_all(&text, "$1");
+    let text = LINK.replace_all(&text, "$1");
+    let text = BOLD.replace_all(&text, "$2");
+    let text = ITALIC.replace_all(&text, "$1");
+    INLINE_CODE.replace_all(&text, "$1").into_owned()
+}
+
+/// Drop chatty openers such as "Sure! Here's an explanation:"
+pub fn strip_preamble(text: &str) -> String {
+    let stripped = PREAMBLE.replace(text, "");
+    // Never strip a whole answer that was nothing but a preamble
+    if stripped.trim().is_empty() {
+        text.to_string()
+    } else {
+        stripped.into_owned()
+    }
+}
+
+/// Replace runs of whitespace, newlines included, with a single space
+pub fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Cut `text` to at most `max_chars` characters, ending in an ellipsis
+/// when anything was removed
+pub fn truncate_chars(text: &str, max_chars: usize) -> String {
+    if max_chars == 0 || text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+
+    let keep = truncate_to_chars(text, max_chars - 1);
+    format!("{}…", keep.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_markdown() {
+        let markdown = "## DNS\n\n**DNS** maps *names* to `addresses`.\n\n* one\n+ two\n> quoted\n\n```bash\ndig example.com\n```\nSee [the RFC](https://www.rfc-editor.org/rfc/rfc1035).";
+        assert_eq!(
+            collapse_whitespace(&strip_markdown(markdown)),
+            "DNS DNS maps names to addresses. - one - two quoted dig example.com See the RFC."
+        );
+
+        // Identifiers and arithmetic are left alone
+        assert_eq!(strip_markdown("use snake_case and 2 * 3 * 4"), "use snake_case and 2 * 3 * 4");
+    }
+
+    #[test]
+    fn test_strip_preamble() {
+        assert_eq!(
+            strip_preamble("Sure! Here's a short explanation:\nDNS maps names to addresses."),
+            "DNS maps names to addresses."
+        );
+        assert_eq!(strip_preamble("Certainly, DNS maps names."), "DNS maps names.");
+        assert_eq!(strip_preamble("Here is the answer: 42"), "42");
+        assert_eq!(strip_preamble("Surely not."), "Surely not.");
+        assert_eq!(strip_preamble("Sure!"), "Sure!");
+    }
+
+    #[test]
+    fn test_truncate_chars() {
+        assert_eq!(truncate_chars("short", 10), "short");
+        assert_eq!(truncate_chars("short", 0), "short");
+        assert_eq!(truncate_chars("héllo wörld", 7), "héllo…");
+        assert_eq!(truncate_chars("日本語のテキスト", 4), "日本語…");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,420 @@
+use crate::config::LoggingConfig;
+use crate::utils::retention::PurgeFilter;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error, info};
+
+/// A single line of the query log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryLogEntry {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub client_ip: IpAddr,
+    pub question: Option<String>,
+    pub query_type: String,
+    pub backend: String,
+    pub latency_ms: u64,
+    pub cache_hit: bool,
+    pub response_size: usize,
+    pub response_code: String,
+}
+
+impl QueryLogEntry {
+    pub fn new(client_ip: IpAddr, query_type: String, backend: String) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        Self {
+            timestamp,
+            client_ip,
+            question: None,
+            query_type,
+            backend,
+            latency_ms: 0,
+            cache_hit: false,
+            response_size: 0,
+            response_code: String::new(),
+        }
+    }
+}
+
+/// Work for the writer task
+enum LogCommand {
+    Write(QueryLogEntry),
+    /// Remove the entries a filter matches from every file, reporting how
+    /// many went
+    Purge(PurgeFilter, oneshot::Sender<std::io::Result<usize>>),
+}
+
+/// Asynchronous JSONL query logger.
+///
+/// Entries are handed to a background task over a bounded channel so the
+/// request path never waits on disk I/O. When the channel is full the entry
+/// is dropped and counted instead.
+pub struct QueryLogger {
+    sender: mpsc::Sender<LogCommand>,
+    anonymize_ips: bool,
+    dropped: Arc<AtomicU64>,
+}
+
+impl QueryLogger {
+    /// Create the logger and spawn its writer task. Must be called from
+    /// within a Tokio runtime.
+    pub fn new(config: &LoggingConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(config.buffer_size.max(1));
+        let writer = RotatingWriter::new(config);
+        tokio::spawn(writer.run(receiver));
+
+        info!("Query logging enabled: {}", config.query_log_path);
+
+        Self {
+            sender,
+            anonymize_ips: config.anonymize_ips,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn log(&self, mut entry: QueryLogEntry) {
+        if self.anonymize_ips {
+            entry.client_ip = anonymize_ip(entry.client_ip);
+        }
+
+        if self.sender.try_send(LogCommand::Write(entry)).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            debug!("Query log buffer full, dropped {} entries so far", dropped);
+        }
+    }
+
+    /// Remove the entries `filter` matches from the active and rotated
+    /// files, returning how many went. Runs on the writer task, after the
+    /// entries queued before it.
+    pub async fn purge(&self, mut filter: PurgeFilter) -> std::io::Result<usize> {
+        // Entries only hold the masked address
+        if self.anonymize_ips {
+            filter.client = filter.client.map(anonymize_ip);
+        }
+
+        let (done, result) = oneshot::channel();
+        let stopped = || std::io::Error::new(std::io::ErrorKind::BrokenPipe, "query log writer stopped");
+        self.sender.send(LogCommand::Purge(filter, done)).await.map_err(|_| stopped())?;
+        result.await.map_err(|_| stopped())?
+    }
+
+    /// Number of entries dropped because the writer could not keep up
+    pub fn dropped_entries(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Mask the host part of an address: IPv4 keeps the /24, IPv6 keeps the /48
+pub fn anonymize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            IpAddr::V6(Ipv6Addr::new(segments[0], segments[1], segments[2], 0, 0, 0, 0, 0))
+        }
+    }
+}
+
+struct RotatingWriter {
+    path: PathBuf,
+    max_size: Option<u64>,
+    rotation_interval: Option<Duration>,
+    max_files: usize,
+    file: Option<File>,
+    written: u64,
+    opened_at: Instant,
+}
+
+impl RotatingWriter {
+    fn new(config: &LoggingConfig) -> Self {
+        let max_size = if config.max_file_size_mb > 0 {
+            Some(config.max_file_size_mb * 1024 * 1024)
+        } else {
+            None
+        };
+
+        let rotation_interval = if config.rotation_interval_hours > 0 {
+            Some(Duration::from_secs(config.rotation_interval_hours * 3600))
+        } else {
+            None
+        };
+
+        Self {
+            path: PathBuf::from(&config.query_log_path),
+            max_size,
+            rotation_interval,
+            max_files: config.max_files,
+            file: None,
+            written: 0,
+            opened_at: Instant::now(),
+        }
+    }
+
+    async fn run(mut self, mut receiver: mpsc::Receiver<LogCommand>) {
+        while let Some(command) = receiver.recv().await {
+            match command {
+                LogCommand::Write(entry) => {
+                    if let Err(e) = self.write_entry(&entry).await {
+                        error!("Failed to write query log entry: {}", e);
+                        // Reopen on the next entry in case the file was removed underneath us
+                        self.file = None;
+                    }
+                }
+                LogCommand::Purge(filter, done) => {
+                    let _ = done.send(self.purge(&filter).await);
+                }
+            }
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            let _ = file.flush().await;
+        }
+    }
+
+    async fn write_entry(&mut self, entry: &QueryLogEntry) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        if self.should_rotate(line.len() as u64) {
+            self.rotate().await?;
+        }
+
+        let file = self.open().await?;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        self.written += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn should_rotate(&self, incoming: u64) -> bool {
+        if self.written == 0 {
+            return false;
+        }
+
+        if let Some(max_size) = self.max_size {
+            if self.written + incoming > max_size {
+                return true;
+            }
+        }
+
+        if let Some(interval) = self.rotation_interval {
+            if self.opened_at.elapsed() >= interval {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    async fn open(&mut self) -> std::io::Result<&mut File> {
+        if self.file.is_none() {
+            if let Some(parent) = self.path.parent() {
+                if !parent.as_os_str().is_empty() {
+                    fs::create_dir_all(parent).await?;
+                }
+            }
+
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+
+            self.written = file.metadata().await?.len();
+            self.opened_at = Instant::now();
+            self.file = Some(file);
+        }
+
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    async fn rotate(&mut self) -> std::io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush().await?;
+        }
+
+        if self.max_files == 0 {
+            fs::remove_file(&self.path).await?;
+        } else {
+            // queries.jsonl.N is the oldest and gets overwritten
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if fs::metadata(&from).await.is_ok() {
+                    fs::rename(&from, rotated_path(&self.path, index + 1)).await?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1)).await?;
+        }
+
+        self.written = 0;
+        info!("Rotated query log {}", self.path.display());
+        Ok(())
+    }
+
+    async fn purge(&mut self, filter: &PurgeFilter) -> std::io::Result<usize> {
+        // Reopened, at its new length, by the next entry
+        if let Some(mut file) = self.file.take() {
+            file.flush().await?;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut removed = 0;
+        let rotated = (1..=self.max_files).map(|index| rotated_path(&self.path, index));
+        for path in std::iter::once(self.path.clone()).chain(rotated) {
+            let contents = match fs::read_to_string(&path).await {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            let mut kept = String::with_capacity(contents.len());
+            for line in contents.lines() {
+                // Lines that do not parse are kept rather than guessed at
+                let purge = serde_json::from_str::<QueryLogEntry>(line).is_ok_and(|entry| {
+                    let age = Duration::from_millis(now.saturating_sub(entry.timestamp));
+                    filter.matches(Some(entry.client_ip), age)
+                });
+                if purge {
+                    removed += 1;
+                } else {
+                    kept.push_str(line);
+                    kept.push('\n');
+                }
+            }
+
+            if kept.len() == contents.len() {
+                continue;
+            }
+            if kept.is_empty() && path != self.path {
+                fs::remove_file(&path).await?;
+            } else {
+                // Replaced in one step so a crash never leaves half a file
+                let mut temp = path.clone().into_os_string();
+                temp.push(".tmp");
+                fs::write(&temp, kept).await?;
+                fs::rename(&temp, &path).await?;
+            }
+        }
+
+        if removed > 0 {
+            info!("Purged {} entries from query log {}", removed, self.path.display());
+        }
+        Ok(removed)
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn temp_log_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("llmdig-query-log-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("queries.jsonl")
+    }
+
+    #[test]
+    fn test_anonymize_ip() {
+        let v4 = IpAddr::from_str("192.168.1.42").unwrap();
+        assert_eq!(anonymize_ip(v4), IpAddr::from_str("192.168.1.0").unwrap());
+
+        let v6 = IpAddr::from_str("2001:db8:abcd:12::1").unwrap();
+        assert_eq!(anonymize_ip(v6), IpAddr::from_str("2001:db8:abcd::").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_rotation_by_size() {
+        let path = temp_log_path("rotation");
+        let config = LoggingConfig {
+            query_log_enabled: true,
+            query_log_path: path.to_string_lossy().to_string(),
+            max_file_size_mb: 0,
+            rotation_interval_hours: 0,
+            max_files: 2,
+            ..Default::default()
+        };
+
+        let mut writer = RotatingWriter::new(&config);
+        writer.max_size = Some(200);
+
+        let entry = QueryLogEntry::new(
+            IpAddr::from_str("127.0.0.1").unwrap(),
+            "TXT".to_string(),
+            "openai".to_string(),
+        );
+        for _ in 0..10 {
+            writer.write_entry(&entry).await.unwrap();
+        }
+
+        assert!(path.exists());
+        assert!(rotated_path(&path, 1).exists());
+        assert!(rotated_path(&path, 2).exists());
+        assert!(!rotated_path(&path, 3).exists());
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let line: serde_json::Value = serde_json::from_str(contents.lines().next().unwrap()).unwrap();
+        assert_eq!(line["client_ip"], "127.0.0.1");
+        assert_eq!(line["backend"], "openai");
+    }
+
+    #[tokio::test]
+    async fn test_purge() {
+        let path = temp_log_path("purge");
+        let config = LoggingConfig {
+            query_log_enabled: true,
+            query_log_path: path.to_string_lossy().to_string(),
+            max_files: 2,
+            ..Default::default()
+        };
+
+        let mut writer = RotatingWriter::new(&config);
+        let entry = |ip: &str, age_hours: u64| {
+            let mut entry = QueryLogEntry::new(IpAddr::from_str(ip).unwrap(), "TXT".to_string(), "mock".to_string());
+            entry.timestamp -= age_hours * 3600 * 1000;
+            entry
+        };
+        writer.write_entry(&entry("192.0.2.1", 48)).await.unwrap();
+        writer.rotate().await.unwrap();
+        writer.write_entry(&entry("192.0.2.1", 0)).await.unwrap();
+        writer.write_entry(&entry("192.0.2.2", 0)).await.unwrap();
+
+        // The rotated file held nothing newer and is gone
+        assert_eq!(writer.purge(&PurgeFilter::older_than_hours(24)).await.unwrap(), 1);
+        assert!(!rotated_path(&path, 1).exists());
+
+        let client = PurgeFilter::client(IpAddr::from_str("192.0.2.1").unwrap());
+        assert_eq!(writer.purge(&client).await.unwrap(), 1);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert!(contents.contains("192.0.2.2"));
+
+        // Writing carries on after the active file
+        writer.write_entry(&entry("192.0.2.3", 0)).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+}
\ No newline at end of file
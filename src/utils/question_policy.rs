@@ -0,0 +1,142 @@
+use crate::config::QuestionPolicyConfig;
+use crate::llm::LlmClient;
+use crate::Error;
+use anyhow::Result;
+use regex::Regex;
+use tracing::{debug, warn};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyDecision {
+    Allow,
+    /// The question falls in a blocked category
+    Refuse { category: String },
+}
+
+struct CategoryRule {
+    category: String,
+    patterns: Vec<Regex>,
+}
+
+/// Pre-filter that refuses out-of-policy questions before any tokens are spent.
+///
+/// Questions are first matched against the configured regex rules; if none
+/// match and the LLM classifier is enabled, the backend is asked to name a
+/// category instead.
+pub struct QuestionPolicy {
+    enabled: bool,
+    rules: Vec<CategoryRule>,
+    blocked_categories: Vec<String>,
+    llm_classifier: bool,
+    refusal_message: String,
+}
+
+impl QuestionPolicy {
+    pub fn new(config: &QuestionPolicyConfig) -> Result<Self> {
+        let mut rules = Vec::new();
+
+        for rule in &config.rules {
+            let patterns = rule
+                .patterns
+                .iter()
+                .map(|p| {
+                    Regex::new(p).map_err(|e| Error::Configuration(format!("Invalid policy pattern {}: {}", p, e)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            rules.push(CategoryRule {
+                category: rule.category.to_lowercase(),
+                patterns,
+            });
+        }
+
+        Ok(Self {
+            enabled: config.enabled,
+            rules,
+            blocked_categories: config.blocked_categories.iter().map(|c| c.to_lowercase()).collect(),
+            llm_classifier: config.llm_classifier,
+            refusal_message: config.refusal_message.clone(),
+        })
+    }
+
+    pub fn refusal_message(&self) -> &str {
+        &self.refusal_message
+    }
+
+    /// Category of the first rule with a matching pattern
+    pub fn classify_by_rules(&self, question: &str) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|rule| rule.patterns.iter().any(|p| p.is_match(question)))
+            .map(|rule| rule.category.as_str())
+    }
+
+    pub async fn evaluate(&self, question: &str, llm_client: &LlmClient) -> PolicyDecision {
+        if !self.enabled || self.blocked_categories.is_empty() {
+            return PolicyDecision::Allow;
+        }
+
+        let category = match self.classify_by_rules(question) {
+            Some(category) => Some(category.to_string()),
+            None if self.llm_classifier => self.classify_with_llm(question, llm_client).await,
+            None => None,
+        };
+
+        match category {
+            Some(category) if self.blocked_categories.contains(&category) => PolicyDecision::Refuse { category },
+            _ => PolicyDecision::Allow,
+        }
+    }
+
+    async fn classify_with_llm(&self, question: &str, llm_client: &LlmClient) -> Option<String> {
+        let mut categories: Vec<&str> = self.rules.iter().map(|rule| rule.category.as_str()).collect();
+        categories.extend(self.blocked_categories.iter().map(String::as_str));
+        categories.push("factual");
+        categories.push("other");
+        categories.sort_unstable();
+        categories.dedup();
+
+        let prompt = format!(
+            "Classify the following question into exactly one of these categories: {}. \
+             Reply with the category name only.\n\nQuestion: {}",
+            categories.join(", "),
+            question
+        );
+
+        match llm_client.query(&prompt).await {
+            Ok(answer) => {
+                let answer = answer.trim().trim_end_matches('.').to_lowercase();
+                debug!("LLM classified question as {}", answer);
+                categories
+                    .into_iter()
+                    .find(|category| answer == *category)
+                    .map(str::to_string)
+            }
+            Err(e) => {
+                // Fail open: a classifier outage should not take the service down
+                warn!("Question classifier failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_rules() {
+        let policy = QuestionPolicy::new(&QuestionPolicyConfig::default()).unwrap();
+
+        assert_eq!(policy.classify_by_rules("write a python script to sort a list"), Some("code"));
+        assert_eq!(policy.classify_by_rules("write an essay about the roman empire"), Some("essay"));
+        assert_eq!(policy.classify_by_rules("what is the capital of france"), None);
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let mut config = QuestionPolicyConfig::default();
+        config.rules[0].patterns.push("(unclosed".to_string());
+        assert!(QuestionPolicy::new(&config).is_err());
+    }
+}
\ No newline at end of file
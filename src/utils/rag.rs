@@ -0,0 +1,228 @@
+use crate::config::{Config, RagConfig};
+use crate::utils::cache::cosine_similarity;
+use crate::utils::embeddings::Embedder;
+use crate::utils::llm_keys::LlmKeyRing;
+use crate::Error;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
+
+/// A piece of a document, embedded for retrieval
+#[derive(Debug, Clone)]
+pub struct Passage {
+    /// Path of the document relative to the documents directory
+    pub source: String,
+    pub text: String,
+    embedding: Vec<f32>,
+}
+
+/// Document corpus that answers are grounded in.
+///
+/// Documents are read and split when the store is created; the passages
+/// are embedded once, on the first retrieval or when `index` is called at
+/// startup.
+pub struct DocumentStore {
+    embedder: Embedder,
+    /// `(source, text)` of every passage, in corpus order
+    chunks: Vec<(String, String)>,
+    passages: OnceCell<Vec<Passage>>,
+    top_k: usize,
+    min_similarity: f32,
+}
+
+impl DocumentStore {
+    pub fn new(config: &Config, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        let rag = &config.rag;
+        let dir = rag
+            .documents_dir
+            .as_ref()
+            .ok_or_else(|| Error::Configuration("rag.documents_dir is not set".to_string()))?;
+
+        let chunks = read_corpus(Path::new(dir), rag)?;
+        if chunks.is_empty() {
+            warn!("No documents to index in {}", dir);
+        }
+
+        Ok(Self {
+            embedder: Embedder::with_model(config, &rag.provider, &rag.model, keys)?,
+            chunks,
+            passages: OnceCell::new(),
+            top_k: rag.top_k,
+            min_similarity: rag.min_similarity,
+        })
+    }
+
+    /// Embed every passage, unless that was already done
+    pub async fn index(&self) -> Result<()> {
+        self.passages().await.map(|_| ())
+    }
+
+    async fn passages(&self) -> Result<&Vec<Passage>> {
+        self.passages
+            .get_or_try_init(|| async {
+                let mut passages = Vec::with_capacity(self.chunks.len());
+                for (source, text) in &self.chunks {
+                    passages.push(Passage {
+                        source: source.clone(),
+                        text: text.clone(),
+                        embedding: self.embedder.embed(text).await?,
+                    });
+                }
+                info!("Indexed {} passages for retrieval", passages.len());
+                Ok::<_, anyhow::Error>(passages)
+            })
+            .await
+    }
+
+    /// The passages most similar to `question`, best first
+    pub async fn retrieve(&self, question: &str) -> Result<Vec<&Passage>> {
+        let passages = self.passages().await?;
+        if passages.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let embedding = self.embedder.embed(question).await?;
+        let mut scored: Vec<(f32, &Passage)> = passages
+            .iter()
+            .map(|passage| (cosine_similarity(&embedding, &passage.embedding), passage))
+            .filter(|(similarity, _)| *similarity >= self.min_similarity)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Ok(scored.into_iter().take(self.top_k).map(|(_, passage)| passage).collect())
+    }
+
+    /// The question with the most relevant passages in front of it, or the
+    /// question alone when none are relevant
+    pub async fn augment(&self, question: &str) -> Result<String> {
+        Ok(augmented_prompt(question, &self.retrieve(question).await?))
+    }
+}
+
+fn augmented_prompt(question: &str, passages: &[&Passage]) -> String {
+    if passages.is_empty() {
+        return question.to_string();
+    }
+
+    let mut prompt = String::from(
+        "Answer the question using the context below. If the context does not contain the answer, say so.\n\nContext:\n",
+    );
+    for (i, passage) in passages.iter().enumerate() {
+        prompt.push_str(&format!("[{}] ({})\n{}\n\n", i + 1, passage.source, passage.text));
+    }
+    prompt.push_str(&format!("Question: {}", question));
+    prompt
+}
+
+/// Read every document under `dir` and split it into passages
+fn read_corpus(dir: &Path, config: &RagConfig) -> Result<Vec<(String, String)>> {
+    let mut files = Vec::new();
+    collect_files(dir, config, &mut files)
+        .map_err(|e| Error::Configuration(format!("Could not read documents in {}: {}", dir.display(), e)))?;
+    // Stable passage order regardless of directory listing order
+    files.sort();
+
+    let mut chunks = Vec::new();
+    for path in files {
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| Error::Configuration(format!("Could not read document {}: {}", path.display(), e)))?;
+        let source = path.strip_prefix(dir).unwrap_or(&path).display().to_string();
+        for chunk in split_passages(&text, config.chunk_chars) {
+            chunks.push((source.clone(), chunk));
+        }
+    }
+    Ok(chunks)
+}
+
+fn collect_files(dir: &Path, config: &RagConfig, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, config, files)?;
+        } else if path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| config.extensions.iter().any(|wanted| wanted.eq_ignore_ascii_case(extension)))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Split at blank lines, packing paragraphs into passages of at most
+/// `max_chars` characters. Longer paragraphs are split at word boundaries.
+fn split_passages(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut passages = Vec::new();
+    let mut current = String::new();
+
+    let mut push = |current: &mut String, piece: &str| {
+        if !current.is_empty() && current.chars().count() + piece.chars().count() + 2 > max_chars {
+            passages.push(std::mem::take(current));
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(piece);
+    };
+
+    for paragraph in text.split("\n\n").map(str::trim).filter(|paragraph| !paragraph.is_empty()) {
+        if paragraph.chars().count() <= max_chars {
+            push(&mut current, paragraph);
+            continue;
+        }
+
+        let mut piece = String::new();
+        for word in paragraph.split_whitespace() {
+            if !piece.is_empty() && piece.chars().count() + word.chars().count() + 1 > max_chars {
+                push(&mut current, &std::mem::take(&mut piece));
+            }
+            if !piece.is_empty() {
+                piece.push(' ');
+            }
+            piece.push_str(word);
+        }
+        push(&mut current, &piece);
+    }
+
+    if !current.is_empty() {
+        passages.push(current);
+    }
+    passages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_passages() {
+        let text = "# Restarting\n\nStop the service.\n\nStart it again.\n\n\n\nCheck the logs.";
+        assert_eq!(
+            split_passages(text, 40),
+            vec!["# Restarting\n\nStop the service.", "Start it again.\n\nCheck the logs."]
+        );
+
+        // Paragraphs longer than a passage are split between words
+        let passages = split_passages("one two three four five six", 10);
+        assert_eq!(passages, vec!["one two", "three four", "five six"]);
+    }
+
+    #[test]
+    fn test_augmented_prompt() {
+        let passage = Passage {
+            source: "runbooks/dns.md".to_string(),
+            text: "Flush the resolver cache with `unbound-control flush_zone`.".to_string(),
+            embedding: Vec::new(),
+        };
+
+        let prompt = augmented_prompt("how do I flush the cache", &[&passage]);
+        assert!(prompt.contains("[1] (runbooks/dns.md)\nFlush the resolver cache"));
+        assert!(prompt.ends_with("Question: how do I flush the cache"));
+
+        assert_eq!(augmented_prompt("what is dns", &[]), "what is dns");
+    }
+}
\ No newline at end of file
@@ -1,8 +1,14 @@
+use crate::config::{Config, RateLimitBackend};
+use crate::utils::shard::{Sharded, DEFAULT_SHARDS};
+use anyhow::Result;
 use std::collections::HashMap;
+use std::hash::Hash;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
+#[cfg(feature = "redis-storage")]
+use tracing::warn;
 
 #[derive(Debug, Clone)]
 struct TokenBucket {
@@ -43,21 +49,31 @@ impl TokenBucket {
     }
 }
 
-pub struct RateLimiter {
-    buckets: Arc<RwLock<HashMap<SocketAddr, TokenBucket>>>,
+/// Token-bucket limiter keyed by client address by default, or by any other
+/// identity (e.g. a TSIG key name) via the `K` parameter. Buckets are spread
+/// over independently locked shards so that clients do not contend.
+pub struct RateLimiter<K = SocketAddr> {
+    buckets: Arc<Sharded<HashMap<K, TokenBucket>>>,
     capacity: f64,
     refill_rate: f64,
     cleanup_interval: Duration,
     last_cleanup: Arc<RwLock<Instant>>,
 }
 
-impl RateLimiter {
+impl<K> RateLimiter<K>
+where
+    K: Eq + Hash,
+{
     pub fn new(requests_per_minute: usize, burst_size: usize) -> Self {
+        Self::with_shards(requests_per_minute, burst_size, DEFAULT_SHARDS)
+    }
+
+    pub fn with_shards(requests_per_minute: usize, burst_size: usize, shards: usize) -> Self {
         let refill_rate = requests_per_minute as f64 / 60.0; // tokens per second
         let capacity = burst_size as f64;
         
         Self {
-            buckets: Arc::new(RwLock::new(HashMap::new())),
+            buckets: Arc::new(Sharded::new(shards, HashMap::new)),
             capacity,
             refill_rate,
             cleanup_interval: Duration::from_secs(300), // 5 minutes
@@ -65,35 +81,115 @@ impl RateLimiter {
         }
     }
 
-    pub async fn allow_request(&self, addr: SocketAddr) -> bool {
+    pub async fn allow_request(&self, key: K) -> bool {
         // Check if cleanup is needed
         self.cleanup_if_needed().await;
         
-        let mut buckets = self.buckets.write().await;
+        let mut buckets = self.buckets.shard(&key).write().await;
         
-        let bucket = buckets.entry(addr).or_insert_with(|| {
+        let bucket = buckets.entry(key).or_insert_with(|| {
             TokenBucket::new(self.capacity, self.refill_rate)
         });
         
         bucket.try_consume(1.0)
     }
 
+    /// Drop the buckets `forget` picks, given each key and how long it has
+    /// been idle, returning how many went
+    pub async fn forget(&self, mut forget: impl FnMut(&K, Duration) -> bool) -> usize {
+        let now = Instant::now();
+        let mut removed = 0;
+        for shard in self.buckets.iter() {
+            let mut buckets = shard.write().await;
+            let before = buckets.len();
+            buckets.retain(|key, bucket| !forget(key, now.duration_since(bucket.last_refill)));
+            removed += before - buckets.len();
+        }
+        removed
+    }
+
     async fn cleanup_if_needed(&self) {
+        // Cheap shared check first so the hot path never queues on a write lock
+        if self.last_cleanup.read().await.elapsed() < self.cleanup_interval {
+            return;
+        }
+
         let mut last_cleanup = self.last_cleanup.write().await;
         if last_cleanup.elapsed() >= self.cleanup_interval {
-            let mut buckets = self.buckets.write().await;
-            
             // Remove buckets that haven't been used recently
             let now = Instant::now();
-            buckets.retain(|_, bucket| {
-                now.duration_since(bucket.last_refill) < Duration::from_secs(600) // 10 minutes
-            });
+            for shard in self.buckets.iter() {
+                shard.write().await.retain(|_, bucket| {
+                    now.duration_since(bucket.last_refill) < Duration::from_secs(600) // 10 minutes
+                });
+            }
             
             *last_cleanup = now;
         }
     }
 }
 
+/// The `[rate_limit]` limiter, with buckets in this instance or in Redis,
+/// where every instance shares them
+pub enum ClientRateLimiter {
+    Local(RateLimiter),
+    #[cfg(feature = "redis-storage")]
+    Redis {
+        shared: Box<crate::redis_storage::RedisRateLimiter>,
+        /// Limits clients while Redis cannot be reached
+        fallback: RateLimiter,
+    },
+}
+
+impl ClientRateLimiter {
+    pub fn new(config: &Config) -> Result<Self> {
+        let limits = &config.rate_limit;
+        let local = RateLimiter::new(limits.requests_per_minute, limits.burst_size);
+        match limits.backend {
+            RateLimitBackend::Memory => Ok(Self::Local(local)),
+            #[cfg(feature = "redis-storage")]
+            RateLimitBackend::Redis => Ok(Self::Redis {
+                shared: Box::new(crate::redis_storage::RedisRateLimiter::new(
+                    &config.storage.url,
+                    &config.storage.key_prefix,
+                    limits.requests_per_minute,
+                    limits.burst_size,
+                )?),
+                fallback: local,
+            }),
+            #[cfg(not(feature = "redis-storage"))]
+            RateLimitBackend::Redis => Err(crate::Error::Configuration(
+                "The redis rate limit backend needs LLMdig built with the redis-storage feature".to_string(),
+            )
+            .into()),
+        }
+    }
+
+    pub async fn allow_request(&self, client_addr: SocketAddr) -> bool {
+        match self {
+            Self::Local(limiter) => limiter.allow_request(client_addr).await,
+            #[cfg(feature = "redis-storage")]
+            Self::Redis { shared, fallback } => match shared.allow_request(&client_addr.to_string()).await {
+                Ok(allowed) => allowed,
+                Err(e) => {
+                    warn!("Shared rate limit unavailable, limiting {} locally: {}", client_addr, e);
+                    fallback.allow_request(client_addr).await
+                }
+            },
+        }
+    }
+
+    /// Drop the local buckets `forget` picks. Buckets in Redis expire on
+    /// their own once they are full again.
+    pub async fn forget(&self, forget: impl FnMut(&SocketAddr, Duration) -> bool) -> usize {
+        match self {
+            Self::Local(limiter) => limiter.forget(forget).await,
+            #[cfg(feature = "redis-storage")]
+            Self::Redis { fallback, .. } => fallback.forget(forget).await,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,4 +227,59 @@ mod tests {
         // Should succeed again
         assert!(limiter.allow_request(addr).await);
     }
+
+    #[tokio::test]
+    async fn test_rate_limiter_forget() {
+        let limiter = RateLimiter::new(60, 1);
+        let first = SocketAddr::new(IpAddr::from_str("192.0.2.1").unwrap(), 53);
+        let second = SocketAddr::new(IpAddr::from_str("192.0.2.2").unwrap(), 53);
+        assert!(limiter.allow_request(first).await);
+        assert!(limiter.allow_request(second).await);
+
+        assert_eq!(limiter.forget(|addr, _| addr.ip() == first.ip()).await, 1);
+        // A forgotten client starts over with a full bucket
+        assert!(limiter.allow_request(first).await);
+        assert!(!limiter.allow_request(second).await);
+    }
+
+    #[tokio::test]
+    async fn test_client_rate_limiter() {
+        let mut config = Config::default();
+        config.rate_limit.burst_size = 1;
+        let limiter = ClientRateLimiter::new(&config).unwrap();
+        let addr = SocketAddr::new(IpAddr::from_str("192.0.2.1").unwrap(), 53);
+        assert!(limiter.allow_request(addr).await);
+        assert!(!limiter.allow_request(addr).await);
+
+        // Without the redis-storage feature, or with a bad URL, Redis is refused
+        config.rate_limit.backend = RateLimitBackend::Redis;
+        config.storage.url = "not a url".to_string();
+        assert!(ClientRateLimiter::new(&config).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_rate_limiter_concurrent_clients() {
+        let limiter = Arc::new(RateLimiter::new(1, 5));
+
+        // Every client gets exactly its burst even when all of them race
+        let handles: Vec<_> = (0..64u16)
+            .map(|port| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move {
+                    let addr = SocketAddr::new(IpAddr::from_str("127.0.0.1").unwrap(), port);
+                    let mut allowed = 0;
+                    for _ in 0..10 {
+                        if limiter.allow_request(addr).await {
+                            allowed += 1;
+                        }
+                    }
+                    allowed
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.await.unwrap(), 5);
+        }
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,80 @@
+use serde::Serialize;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// What a purge removes: records older than `max_age`, records about
+/// `client`, or both
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PurgeFilter {
+    pub max_age: Option<Duration>,
+    pub client: Option<IpAddr>,
+}
+
+impl PurgeFilter {
+    /// Records older than `hours`, or none at all when `hours` is 0
+    pub fn older_than_hours(hours: u64) -> Self {
+        Self {
+            max_age: (hours > 0).then(|| Duration::from_secs(hours * 3600)),
+            client: None,
+        }
+    }
+
+    /// Every record about `client`, whatever its age
+    pub fn client(client: IpAddr) -> Self {
+        Self {
+            max_age: None,
+            client: Some(client),
+        }
+    }
+
+    /// Whether the filter matches nothing
+    pub fn is_empty(&self) -> bool {
+        self.max_age.is_none() && self.client.is_none()
+    }
+
+    /// Whether a record `age` old, about `client` if known, is to go
+    pub fn matches(&self, client: Option<IpAddr>, age: Duration) -> bool {
+        self.max_age.is_some_and(|max_age| age >= max_age) || (self.client.is_some() && client == self.client)
+    }
+}
+
+/// Records removed by a purge, by kind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct PurgeReport {
+    pub query_log_entries: usize,
+    /// Exact, semantic and negative cache entries
+    pub cached_answers: usize,
+    /// Rate-limit buckets and offence records
+    pub client_records: usize,
+}
+
+impl PurgeReport {
+    pub fn total(&self) -> usize {
+        self.query_log_entries + self.cached_answers + self.client_records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let client: IpAddr = "192.0.2.7".parse().unwrap();
+        let other: IpAddr = "192.0.2.8".parse().unwrap();
+
+        let by_age = PurgeFilter::older_than_hours(1);
+        assert!(by_age.matches(Some(client), Duration::from_secs(3600)));
+        assert!(!by_age.matches(Some(client), Duration::from_secs(3599)));
+        assert!(by_age.matches(None, Duration::from_secs(7200)));
+
+        let by_client = PurgeFilter::client(client);
+        assert!(by_client.matches(Some(client), Duration::ZERO));
+        assert!(!by_client.matches(Some(other), Duration::from_secs(86400)));
+        assert!(!by_client.matches(None, Duration::ZERO));
+
+        // A limit of 0 keeps everything
+        assert!(PurgeFilter::older_than_hours(0).is_empty());
+        assert!(!PurgeFilter::older_than_hours(0).matches(None, Duration::MAX));
+    }
+}
\ No newline at end of file
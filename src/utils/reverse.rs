@@ -0,0 +1,270 @@
+use crate::config::{ReverseConfig, ReverseMode};
+use crate::utils::text::truncate_to_bytes;
+use crate::Error;
+use anyhow::Result;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use trust_dns_proto::rr::Name;
+
+/// Longest label in a DNS name
+const MAX_LABEL: usize = 63;
+
+/// Labels of description in a made-up PTR name
+const MAX_PTR_LABELS: usize = 3;
+
+/// Answers reverse lookups in the `[reverse]` zones with a description of
+/// the address, filled in from a template or asked of the LLM
+#[derive(Debug, Clone, Default)]
+pub struct ReverseLookups {
+    zones: Vec<Name>,
+    mode: Option<ReverseMode>,
+    prompt: String,
+    template: String,
+    domain: Option<Name>,
+}
+
+impl ReverseLookups {
+    pub fn new(config: &ReverseConfig) -> Result<Self> {
+        if !config.enabled {
+            return Ok(Self::default());
+        }
+
+        let parse = |name: &str| {
+            Name::from_ascii(name)
+                .map(|name| {
+                    let mut name = name.to_lowercase();
+                    name.set_fqdn(true);
+                    name
+                })
+                .map_err(|e| Error::Configuration(format!("Invalid reverse zone or domain {}: {}", name, e)))
+        };
+        let zones = config.zones.iter().map(|zone| parse(zone)).collect::<Result<Vec<_>, _>>()?;
+        if let Some(zone) = zones.iter().find(|zone| !reverse_root().iter().any(|root| root.zone_of(zone))) {
+            return Err(Error::Configuration(format!("{} is not under in-addr.arpa or ip6.arpa", zone)).into());
+        }
+
+        Ok(Self {
+            zones,
+            mode: Some(config.mode),
+            prompt: config.prompt.clone(),
+            template: config.template.clone(),
+            domain: Some(parse(&config.domain)?),
+        })
+    }
+
+    /// Whether `name` lies in one of the reverse zones answered
+    pub fn serves(&self, name: &Name) -> bool {
+        self.zones.iter().any(|zone| zone.zone_of(name))
+    }
+
+    /// Question for the LLM about `address`, or `None` when answers come
+    /// from the template
+    pub fn prompt(&self, address: IpAddr) -> Option<String> {
+        (self.mode == Some(ReverseMode::Llm)).then(|| self.prompt.replace("{ip}", &address.to_string()))
+    }
+
+    /// The template filled in for `address`
+    pub fn describe(&self, address: IpAddr) -> String {
+        self.template
+            .replace("{ip}", &address.to_string())
+            .replace("{kind}", address_kind(address))
+    }
+
+    /// A host name under `domain` spelling out `description`, for PTR
+    /// answers, which can only carry names
+    pub fn ptr_name(&self, description: &str) -> Option<Name> {
+        let domain = self.domain.as_ref()?;
+        let words: Vec<String> = description
+            .to_lowercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| truncate_to_bytes(word, MAX_LABEL).to_string())
+            .collect();
+
+        // Words are joined with hyphens into labels as long as they fit
+        let mut labels: Vec<String> = Vec::new();
+        for word in words {
+            let full = labels.len() == MAX_PTR_LABELS;
+            match labels.last_mut() {
+                Some(label) if label.len() + 1 + word.len() <= MAX_LABEL => {
+                    label.push('-');
+                    label.push_str(&word);
+                }
+                _ if full => break,
+                _ => labels.push(word),
+            }
+        }
+        if labels.is_empty() {
+            labels.push("unknown".to_string());
+        }
+
+        Name::from_labels(labels.iter().map(String::as_bytes))
+            .and_then(|name| name.append_domain(domain))
+            .ok()
+    }
+}
+
+fn reverse_root() -> [Name; 2] {
+    [
+        Name::from_ascii("in-addr.arpa.").unwrap(),
+        Name::from_ascii("ip6.arpa.").unwrap(),
+    ]
+}
+
+/// The address a reverse name such as `1.2.0.192.in-addr.arpa` stands for,
+/// or `None` for names that do not spell out a whole address
+pub fn address(name: &Name) -> Option<IpAddr> {
+    let labels: Vec<String> = name
+        .iter()
+        .map(|label| String::from_utf8_lossy(label).to_lowercase())
+        .collect();
+    let (digits, suffix) = labels.split_at(labels.len().checked_sub(2)?);
+    let mut digits: Vec<&str> = digits.iter().map(String::as_str).collect();
+    digits.reverse();
+
+    match (suffix[0].as_str(), suffix[1].as_str(), digits.len()) {
+        ("in-addr", "arpa", 4) => {
+            let mut octets = [0u8; 4];
+            for (octet, digit) in octets.iter_mut().zip(&digits) {
+                // No leading zeros, so every address has one name
+                if digit.len() > 1 && digit.starts_with('0') {
+                    return None;
+                }
+                *octet = digit.parse().ok()?;
+            }
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        ("ip6", "arpa", 32) => {
+            let mut value = 0u128;
+            for digit in digits {
+                if digit.len() != 1 {
+                    return None;
+                }
+                value = (value << 4) | u128::from_str_radix(digit, 16).ok()?;
+            }
+            Some(IpAddr::V6(Ipv6Addr::from(value)))
+        }
+        _ => None,
+    }
+}
+
+/// What sort of address `address` is, for the template's `{kind}`
+pub fn address_kind(address: IpAddr) -> &'static str {
+    match address {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            if v4.is_unspecified() {
+                "unspecified"
+            } else if v4.is_loopback() {
+                "loopback"
+            } else if v4.is_private() {
+                "private (RFC 1918)"
+            } else if a == 100 && (64..128).contains(&b) {
+                "shared carrier-grade NAT (RFC 6598)"
+            } else if v4.is_link_local() {
+                "link-local"
+            } else if v4.is_documentation() {
+                "documentation (RFC 5737)"
+            } else if a == 198 && (b == 18 || b == 19) {
+                "benchmarking (RFC 2544)"
+            } else if v4.is_multicast() {
+                "multicast"
+            } else if v4.is_broadcast() {
+                "broadcast"
+            } else if a >= 240 || (a == 192 && b == 0 && c == 0) {
+                "reserved"
+            } else {
+                "public"
+            }
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            if v6.is_unspecified() {
+                "unspecified"
+            } else if v6.is_loopback() {
+                "loopback"
+            } else if first & 0xfe00 == 0xfc00 {
+                "unique local (RFC 4193)"
+            } else if first & 0xffc0 == 0xfe80 {
+                "link-local"
+            } else if first == 0x2001 && v6.segments()[1] == 0x0db8 {
+                "documentation (RFC 3849)"
+            } else if v6.is_multicast() {
+                "multicast"
+            } else if v6.to_ipv4_mapped().is_some() {
+                "IPv4-mapped"
+            } else {
+                "public"
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    #[test]
+    fn test_address() {
+        assert_eq!(address(&name("1.2.0.192.in-addr.arpa.")), Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(address(&name("1.2.0.192.IN-ADDR.ARPA")), Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(
+            address(&name("1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa.")),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(address(&name("2.0.192.in-addr.arpa.")), None);
+        assert_eq!(address(&name("1.2.0.256.in-addr.arpa.")), None);
+        assert_eq!(address(&name("1.02.0.192.in-addr.arpa.")), None);
+        assert_eq!(address(&name("arpa.")), None);
+    }
+
+    #[test]
+    fn test_template() {
+        let reverse = ReverseLookups::new(&ReverseConfig {
+            enabled: true,
+            zones: vec!["10.in-addr.arpa".to_string()],
+            ..ReverseConfig::default()
+        })
+        .unwrap();
+
+        assert!(reverse.serves(&name("4.3.2.10.in-addr.arpa.")));
+        assert!(!reverse.serves(&name("1.2.0.192.in-addr.arpa.")));
+        assert_eq!(reverse.prompt("10.2.3.4".parse().unwrap()), None);
+        let description = reverse.describe("10.2.3.4".parse().unwrap());
+        assert_eq!(description, "10.2.3.4 is a private (RFC 1918) address");
+        assert_eq!(
+            reverse.ptr_name(&description),
+            Some(name("10-2-3-4-is-a-private-rfc-1918-address.llmdig.invalid."))
+        );
+        assert_eq!(reverse.ptr_name("???"), Some(name("unknown.llmdig.invalid.")));
+        assert_eq!(address_kind("8.8.8.8".parse().unwrap()), "public");
+        assert_eq!(address_kind("fd00::1".parse().unwrap()), "unique local (RFC 4193)");
+
+        // Only reverse zones can be answered
+        let config = ReverseConfig {
+            enabled: true,
+            zones: vec!["example.com".to_string()],
+            ..ReverseConfig::default()
+        };
+        assert!(ReverseLookups::new(&config).is_err());
+    }
+
+    #[test]
+    fn test_long_ptr_names() {
+        let reverse = ReverseLookups::new(&ReverseConfig {
+            enabled: true,
+            mode: ReverseMode::Llm,
+            ..ReverseConfig::default()
+        })
+        .unwrap();
+        assert!(reverse.prompt("8.8.8.8".parse().unwrap()).unwrap().contains(" 8.8.8.8 "));
+
+        let ptr = reverse.ptr_name(&"Google Public DNS resolver operated by AS15169 ".repeat(20)).unwrap();
+        assert_eq!(ptr.num_labels(), 5);
+        assert!(ptr.iter().all(|label| label.len() <= MAX_LABEL));
+    }
+}
@@ -1,97 +1,190 @@
+use crate::config::{SanitizerConfig, SanitizerLevel};
+use crate::utils::text;
+use data_encoding::BASE32_NOPAD;
 use regex::Regex;
 use std::collections::HashSet;
+use idna::punycode;
 use lazy_static::lazy_static;
+use unicode_normalization::UnicodeNormalization;
+use unicode_properties::{GeneralCategoryGroup, UnicodeGeneralCategory};
+
+/// Longest sanitized question, in characters
+const MAX_QUESTION_CHARS: usize = 200;
 
 lazy_static! {
-    static ref DANGEROUS_PATTERNS: Vec<Regex> = vec![
+    /// Words common in script and SQL injection, and in plain English
+    static ref KEYWORD_PATTERNS: Vec<Regex> = vec![
         Regex::new(r"(?i)(script|javascript|vbscript|expression|onload|onerror|onclick)").unwrap(),
         Regex::new(r"(?i)(union|select|insert|update|delete|drop|create|alter)").unwrap(),
         Regex::new(r"(?i)(eval|exec|system|shell|cmd|powershell)").unwrap(),
-        Regex::new(r"[<>\"'&]").unwrap(),
     ];
     
-    static ref ALLOWED_CHARS: HashSet<char> = {
-        let mut set = HashSet::new();
-        // Allow letters, numbers, spaces, and common punctuation
-        for c in 'a'..='z' { set.insert(c); }
-        for c in 'A'..='Z' { set.insert(c); }
-        for c in '0'..='9' { set.insert(c); }
-        set.insert(' ');
-        set.insert('.');
-        set.insert(',');
-        set.insert('!');
-        set.insert('?');
-        set.insert('-');
-        set.insert('_');
-        set.insert('\'');
-        set.insert('"');
-        set.insert('(');
-        set.insert(')');
-        set.insert(':');
-        set.insert(';');
-        set
-    };
+    static ref MARKUP_PATTERN: Regex = Regex::new(r#"[<>"'&]"#).unwrap();
+    
+    static ref STRICT: Sanitizer = Sanitizer::new(&SanitizerConfig {
+        level: SanitizerLevel::Strict,
+        ..SanitizerConfig::default()
+    });
 }
 
-pub struct Sanitizer;
+/// Cleans questions up before they reach the backend
+pub struct Sanitizer {
+    level: SanitizerLevel,
+    ascii_only: bool,
+    /// ASCII punctuation kept besides letters and digits
+    allowed_punctuation: HashSet<char>,
+}
 
 impl Sanitizer {
-    /// Sanitize a DNS query string to prevent injection attacks
+    pub fn new(config: &SanitizerConfig) -> Self {
+        Self {
+            level: config.level,
+            ascii_only: config.ascii_only,
+            allowed_punctuation: config.allowed_punctuation.chars().collect(),
+        }
+    }
+    
+    /// Sanitize a DNS query string at the strict level
     pub fn sanitize_query(query: &str) -> String {
+        STRICT.sanitize(query)
+    }
+    
+    /// Validate if a query is safe to process at the strict level
+    pub fn is_safe(query: &str) -> bool {
+        STRICT.check(query)
+    }
+    
+    /// Patterns whose matches are deleted from questions at this level
+    fn removed_patterns(&self) -> Vec<&'static Regex> {
+        match self.level {
+            SanitizerLevel::Off => Vec::new(),
+            SanitizerLevel::Lenient => vec![&*MARKUP_PATTERN],
+            SanitizerLevel::Strict => KEYWORD_PATTERNS.iter().chain([&*MARKUP_PATTERN]).collect(),
+        }
+    }
+    
+    /// Sanitize a DNS query string to prevent injection attacks
+    pub fn sanitize(&self, query: &str) -> String {
         let mut sanitized = query.to_string();
         
-        // Convert to lowercase for consistency
-        sanitized = sanitized.to_lowercase();
+        if self.level != SanitizerLevel::Off {
+            // Convert to lowercase for consistency, then compose accents and
+            // their letters so every spelling of a word reads the same
+            sanitized = sanitized.to_lowercase().nfc().collect();
+            
+            // Remove non-allowed characters, turning any whitespace into spaces
+            sanitized = sanitized
+                .chars()
+                .map(|c| if c.is_whitespace() { ' ' } else { c })
+                .filter(|&c| c == ' ' || self.allows(c))
+                .collect();
+        }
         
-        // Remove dangerous patterns
-        for pattern in DANGEROUS_PATTERNS.iter() {
+        // Remove dangerous patterns, until removing one no longer joins the
+        // text around it into another ("scrscriptipt")
+        let patterns = self.removed_patterns();
+        while let Some(pattern) = patterns.iter().find(|pattern| pattern.is_match(&sanitized)) {
             sanitized = pattern.replace_all(&sanitized, "").to_string();
         }
         
-        // Remove non-allowed characters
-        sanitized = sanitized
-            .chars()
-            .filter(|c| ALLOWED_CHARS.contains(c))
-            .collect();
-        
-        // Normalize whitespace
+        // Normalize whitespace, and normalize again, as removing characters
+        // can leave combining marks next to each other out of order
         sanitized = sanitized
             .split_whitespace()
             .collect::<Vec<_>>()
-            .join(" ");
+            .join(" ")
+            .nfc()
+            .collect();
         
-        // Truncate if too long
-        if sanitized.len() > 200 {
-            sanitized = sanitized[..200].to_string();
-        }
+        // Truncate if too long, counting characters rather than bytes so
+        // questions in other scripts get as much room
+        let end = text::truncate_to_chars(&sanitized, MAX_QUESTION_CHARS).trim_end().len();
+        sanitized.truncate(end);
         
         sanitized
     }
     
     /// Validate if a query is safe to process
-    pub fn is_safe(query: &str) -> bool {
-        let sanitized = Self::sanitize_query(query);
+    pub fn check(&self, query: &str) -> bool {
+        let query: String = query.nfc().collect();
+        let sanitized = self.sanitize(&query);
+        let length = sanitized.chars().count();
         
         // Check if sanitization significantly changed the query
-        if sanitized.len() < query.len() * 3 / 4 {
+        if length < query.chars().count() * 3 / 4 {
             return false;
         }
         
         // Check for dangerous patterns
-        for pattern in DANGEROUS_PATTERNS.iter() {
-            if pattern.is_match(query) {
+        for pattern in self.removed_patterns() {
+            if pattern.is_match(&query) {
                 return false;
             }
         }
         
         // Check if query is too short or too long
-        if sanitized.len() < 3 || sanitized.len() > 200 {
+        if !(3..=MAX_QUESTION_CHARS).contains(&length) {
             return false;
         }
         
         true
     }
     
+    /// Words of `query` that look like script or SQL injection. Strict
+    /// sanitizing deletes them; at the lenient level they are only flagged,
+    /// as most are ordinary English ("how do I update rust").
+    pub fn flagged_words(&self, query: &str) -> Vec<String> {
+        let query = query.to_lowercase();
+        let mut words: Vec<String> = KEYWORD_PATTERNS
+            .iter()
+            .flat_map(|pattern| pattern.find_iter(&query))
+            .map(|found| found.as_str().to_string())
+            .collect();
+        words.sort();
+        words.dedup();
+        words
+    }
+    
+    /// Whether `c` is kept in questions: ASCII letters, digits and the
+    /// allowed punctuation, and unless limited to ASCII, the letters,
+    /// combining marks, numbers and punctuation of every other script
+    fn allows(&self, c: char) -> bool {
+        if c.is_ascii() {
+            return c.is_ascii_alphanumeric() || self.allowed_punctuation.contains(&c);
+        }
+        if self.ascii_only {
+            return false;
+        }
+        // Joiners are invisible, but spell words in Persian and Indic scripts
+        matches!(c, '\u{200c}' | '\u{200d}')
+            || matches!(
+                c.general_category_group(),
+                GeneralCategoryGroup::Letter
+                    | GeneralCategoryGroup::Mark
+                    | GeneralCategoryGroup::Number
+                    | GeneralCategoryGroup::Punctuation
+            )
+    }
+    
+    /// Decode a punycode (`xn--`) label to Unicode, leaving other labels untouched
+    pub fn decode_label(label: &str) -> String {
+        let encoded = label.get(4..).filter(|encoded| !encoded.is_empty() && label[..4].eq_ignore_ascii_case("xn--"));
+        if let Some(encoded) = encoded {
+            if let Some(decoded) = punycode::decode_to_string(encoded) {
+                return decoded;
+            }
+        }
+        label.to_string()
+    }
+    
+    /// Decode a question spread over base32 labels (RFC 4648, unpadded,
+    /// any case)
+    pub fn decode_base32(labels: &[String]) -> Option<String> {
+        let encoded = labels.concat().to_ascii_uppercase();
+        let bytes = BASE32_NOPAD.decode(encoded.as_bytes()).ok()?;
+        String::from_utf8(bytes).ok()
+    }
+    
     /// Extract and validate a question from a domain name
     pub fn extract_question_from_domain(domain: &str) -> Option<String> {
         let domain = domain.trim_end_matches('.');
@@ -102,11 +195,19 @@ impl Sanitizer {
         }
         
         // The question is everything except the last part (TLD)
-        let question_parts = &parts[..parts.len() - 1];
+        let question_parts: Vec<String> = parts[..parts.len() - 1]
+            .iter()
+            .map(|label| Self::decode_label(label))
+            .collect();
         let question = question_parts.join(" ");
         
-        // Clean up the question
-        let question = question.replace('-', " ").replace('_', " ");
+        // Clean up the question: hyphens and underscores separate words, and
+        // empty labels or hyphens at label edges leave no empty words
+        let question = question
+            .split(|c: char| c == '-' || c == '_' || c.is_whitespace())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
         
         if Self::is_safe(&question) {
             Some(question)
@@ -173,4 +274,64 @@ mod tests {
             None
         );
     }
+
+    #[test]
+    fn test_sanitize_unicode() {
+        assert_eq!(Sanitizer::sanitize_query("Nasıl Çalışır?"), "nasıl çalışır?");
+        assert_eq!(Sanitizer::sanitize_query("Wie heißt der Fluss in Köln?"), "wie heißt der fluss in köln?");
+        assert_eq!(Sanitizer::sanitize_query("東京の人口は？"), "東京の人口は？");
+        assert_eq!(Sanitizer::sanitize_query("हिन्दी क्या है"), "हिन्दी क्या है");
+        // Decomposed accents are composed, and invisible controls and symbols dropped
+        assert_eq!(Sanitizer::sanitize_query("cafe\u{301}\u{202e} ☕ menu"), "café menu");
+        assert!(Sanitizer::is_safe("Nasıl Çalışır?"));
+        assert!(Sanitizer::is_safe("東京の人口は？"));
+    }
+
+    #[test]
+    fn test_sanitize_ascii_only() {
+        let sanitizer = Sanitizer::new(&SanitizerConfig {
+            ascii_only: true,
+            allowed_punctuation: "?".to_string(),
+            ..SanitizerConfig::default()
+        });
+        assert_eq!(sanitizer.sanitize("Wie heißt Köln, bitte?"), "wie heit kln bitte?");
+        assert!(!sanitizer.check("東京の人口は？"));
+    }
+
+    #[test]
+    fn test_sanitize_levels() {
+        let sanitizer = |level| {
+            Sanitizer::new(&SanitizerConfig {
+                level,
+                ..SanitizerConfig::default()
+            })
+        };
+        let question = "How do I update Rust's \"select\" macro?";
+        assert_eq!(sanitizer(SanitizerLevel::Strict).sanitize(question), "how do i rusts macro?");
+        assert_eq!(sanitizer(SanitizerLevel::Lenient).sanitize(question), "how do i update rusts select macro?");
+        assert_eq!(sanitizer(SanitizerLevel::Off).sanitize("How  do I\tupdate <Rust>? "), "How do I update <Rust>?");
+
+        let lenient = sanitizer(SanitizerLevel::Lenient);
+        assert!(lenient.check("how do I update rust"));
+        assert!(!lenient.check("what is <script>"));
+        assert!(!Sanitizer::is_safe("how do I update rust"));
+        assert_eq!(lenient.flagged_words("SELECT or update, then select"), vec!["select", "update"]);
+        assert!(lenient.flagged_words("what is the weather").is_empty());
+    }
+
+    #[test]
+    fn test_decode_label() {
+        assert_eq!(Sanitizer::decode_label("xn--nasl-nza"), "nasıl");
+        assert_eq!(Sanitizer::decode_label("xn--ls8h"), "💩");
+        assert_eq!(Sanitizer::decode_label("weather"), "weather");
+        // Malformed punycode is passed through rather than dropped
+        assert_eq!(Sanitizer::decode_label("xn--"), "xn--");
+    }
+
+    #[test]
+    fn test_decode_base32() {
+        let labels = vec!["k5ugc5banfz".to_string(), "sautvon2d6".to_string()];
+        assert_eq!(Sanitizer::decode_base32(&labels), Some("What is Rust?".to_string()));
+        assert_eq!(Sanitizer::decode_base32(&["not-base32".to_string()]), None);
+    }
 } 
\ No newline at end of file
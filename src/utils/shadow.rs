@@ -0,0 +1,198 @@
+use crate::config::Config;
+use crate::llm::{GenerationOptions, LlmClient};
+use crate::utils::llm_keys::LlmKeyRing;
+use crate::utils::zone_profiles::{backend_client, backend_config};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// One question answered by both backends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShadowComparison {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// The question as the log policy allows it to be written
+    pub question: String,
+    pub model: String,
+    pub answer: String,
+    pub shadow_backend: String,
+    pub shadow_model: String,
+    pub shadow_answer: Option<String>,
+    /// Why the shadow gave no answer
+    pub shadow_error: Option<String>,
+    /// Share of words the answers have in common, from 0 to 1
+    pub similarity: Option<f64>,
+    pub shadow_latency_ms: u64,
+}
+
+/// The `[shadow]` backend, asked a sample of the questions the LLM answers
+pub struct ShadowBackend {
+    llm_client: LlmClient,
+    model: Option<String>,
+    default_model: String,
+    rate: f64,
+    path: PathBuf,
+    /// Held while a line is appended, so lines never interleave
+    file: Mutex<()>,
+}
+
+impl ShadowBackend {
+    /// Without an API key of its own, the shadow calls its backend with `keys`
+    pub fn new(config: &Config, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        let shadow = &config.shadow;
+        let backend_config = backend_config(
+            config,
+            shadow.backend.as_ref(),
+            shadow.base_url.as_ref(),
+            shadow.api_key.as_ref(),
+        );
+        info!("Shadowing {}% of questions on {}", shadow.percent, backend_config.llm.backend.name());
+
+        Ok(Self {
+            // Without the shared metrics, so shadow calls do not count as traffic
+            llm_client: backend_client(backend_config, shadow.api_key.as_ref(), &keys)?,
+            model: shadow.model.clone(),
+            default_model: config.llm.model.clone(),
+            rate: (shadow.percent / 100.0).clamp(0.0, 1.0),
+            path: PathBuf::from(&shadow.path),
+            file: Mutex::new(()),
+        })
+    }
+
+    /// Whether the question being answered is also sent to the shadow
+    pub fn sample(&self) -> bool {
+        self.rate > 0.0 && rand::random::<f64>() < self.rate
+    }
+
+    /// Ask the shadow what it makes of `prompt`, answered with `answer` by
+    /// `model`, and log the comparison
+    pub async fn compare(
+        &self,
+        question: String,
+        prompt: String,
+        mut options: GenerationOptions,
+        model: String,
+        answer: String,
+    ) -> ShadowComparison {
+        if self.model.is_some() {
+            options.model = self.model.clone();
+        }
+        let shadow_model = options.model_or(&self.default_model).to_string();
+
+        let start = Instant::now();
+        let result = self.llm_client.query_detailed(&prompt, &options).await;
+        let shadow_latency_ms = start.elapsed().as_millis() as u64;
+
+        let (shadow_answer, shadow_error) = match result {
+            Ok(generation) => (Some(generation.text), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let comparison = ShadowComparison {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_millis() as u64)
+                .unwrap_or_default(),
+            question,
+            similarity: shadow_answer.as_deref().map(|shadow| similarity(&answer, shadow)),
+            model,
+            answer,
+            shadow_backend: self.llm_client.backend_name().to_string(),
+            shadow_model,
+            shadow_answer,
+            shadow_error,
+            shadow_latency_ms,
+        };
+
+        if let Err(e) = self.write(&comparison).await {
+            warn!("Could not log shadow comparison to {}: {}", self.path.display(), e);
+        }
+        comparison
+    }
+
+    async fn write(&self, comparison: &ShadowComparison) -> Result<()> {
+        let _file = self.file.lock().await;
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).await?;
+            }
+        }
+
+        let mut line = serde_json::to_string(comparison)?;
+        line.push('\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Jaccard similarity of the answers' sets of lowercase words, rounded to
+/// three places; two empty answers are alike
+pub fn similarity(a: &str, b: &str) -> f64 {
+    fn words(text: &str) -> HashSet<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    }
+
+    let (a, b) = (words(a), words(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    let score = a.intersection(&b).count() as f64 / union as f64;
+    (score * 1000.0).round() / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{LlmBackendType, MockMode};
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity("Paris is the capital.", "paris IS the capital"), 1.0);
+        assert_eq!(similarity("Paris", "Lyon"), 0.0);
+        assert_eq!(similarity("the capital is Paris", "the capital is Lyon"), 0.6);
+        assert_eq!(similarity("", "..."), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_compare() {
+        let path = std::env::temp_dir().join(format!("llmdig-shadow-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut config = Config::default();
+        config.shadow.enabled = true;
+        config.shadow.backend = Some(LlmBackendType::Mock);
+        config.shadow.model = Some("candidate".to_string());
+        config.shadow.path = path.to_string_lossy().into_owned();
+        config.llm.mock.mode = MockMode::Fixed;
+        config.llm.mock.response = "Paris is the capital".to_string();
+        let shadow = ShadowBackend::new(&config, LlmKeyRing::for_config(&config)).unwrap();
+
+        let comparison = shadow
+            .compare(
+                "capital of france".to_string(),
+                "capital of france".to_string(),
+                GenerationOptions::default(),
+                "gpt-3.5-turbo".to_string(),
+                "The capital of France is Paris".to_string(),
+            )
+            .await;
+        assert_eq!(comparison.shadow_model, "candidate");
+        assert_eq!(comparison.shadow_backend, "mock");
+        assert_eq!(comparison.similarity, Some(0.667));
+
+        let logged: ShadowComparison = serde_json::from_str(std::fs::read_to_string(&path).unwrap().trim()).unwrap();
+        assert_eq!(logged.shadow_answer.as_deref(), Some("Paris is the capital"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
\ No newline at end of file
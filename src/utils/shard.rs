@@ -0,0 +1,68 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use tokio::sync::RwLock;
+
+/// Shard count used by `Cache` and `RateLimiter` unless told otherwise
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// A fixed set of independently locked values selected by key hash, so that
+/// writers for unrelated keys do not serialize on a single lock
+#[derive(Debug)]
+pub struct Sharded<T> {
+    shards: Vec<RwLock<T>>,
+    hasher: RandomState,
+}
+
+impl<T> Sharded<T> {
+    pub fn new(count: usize, mut init: impl FnMut() -> T) -> Self {
+        Self {
+            shards: (0..count.max(1)).map(|_| RwLock::new(init())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// The shard responsible for `key`
+    pub fn shard<K: Hash + ?Sized>(&self, key: &K) -> &RwLock<T> {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RwLock<T>> {
+        self.shards.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_same_key_same_shard() {
+        let sharded: Sharded<Vec<&str>> = Sharded::new(DEFAULT_SHARDS, Vec::new);
+        assert_eq!(sharded.len(), DEFAULT_SHARDS);
+
+        sharded.shard("key").write().await.push("value");
+        assert_eq!(*sharded.shard("key").read().await, vec!["value"]);
+
+        let total: usize = futures::future::join_all(sharded.iter().map(|shard| shard.read()))
+            .await
+            .iter()
+            .map(|shard| shard.len())
+            .sum();
+        assert_eq!(total, 1);
+    }
+
+    #[test]
+    fn test_at_least_one_shard() {
+        let sharded: Sharded<()> = Sharded::new(0, || ());
+        assert_eq!(sharded.len(), 1);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,343 @@
+use crate::config::StaticRecordsConfig;
+use crate::Error;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use tracing::info;
+use trust_dns_proto::rr::rdata::{A, AAAA, CNAME, MX, NS, SOA, TXT};
+use trust_dns_proto::rr::{LowerName, Name, RData, Record, RecordType};
+
+/// TTL used when neither the record nor a `$TTL` directive sets one
+const DEFAULT_TTL: u32 = 3600;
+
+/// Operator-defined records served ahead of the LLM.
+///
+/// Records come from a zone file in a subset of the RFC 1035 master format
+/// (one record per line, `$ORIGIN` and `$TTL` directives, `;` comments, no
+/// parentheses) and from `[[static_records.records]]` tables in the config.
+#[derive(Debug, Default)]
+pub struct StaticRecords {
+    records: HashMap<LowerName, Vec<Record>>,
+}
+
+impl StaticRecords {
+    pub fn new(config: &StaticRecordsConfig) -> Result<Self> {
+        let mut static_records = Self::default();
+
+        if let Some(path) = &config.zone_file {
+            let contents = fs::read_to_string(path)
+                .map_err(|e| Error::Configuration(format!("Could not read zone file {}: {}", path, e)))?;
+            for record in parse_zone_file(&contents)
+                .map_err(|e| Error::Configuration(format!("Zone file {}: {}", path, e)))?
+            {
+                static_records.add(record);
+            }
+        }
+
+        for record in &config.records {
+            let parsed = parse_record(&record.name, &record.record_type, &record.value, record.ttl)
+                .map_err(|e| Error::Configuration(format!("Static record {}: {}", record.name, e)))?;
+            static_records.add(parsed);
+        }
+
+        if !static_records.is_empty() {
+            info!("Loaded static records for {} names", static_records.records.len());
+        }
+        Ok(static_records)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Whether any record of any type exists for `name`
+    pub fn contains(&self, name: &Name) -> bool {
+        self.records.contains_key(&LowerName::new(name))
+    }
+
+    /// Records of `record_type` for `name`, renamed to the queried spelling
+    pub fn lookup(&self, name: &Name, record_type: RecordType) -> Option<Vec<Record>> {
+        let records: Vec<Record> = self
+            .records
+            .get(&LowerName::new(name))?
+            .iter()
+            .filter(|record| record.record_type() == record_type)
+            .map(|record| {
+                let mut record = record.clone();
+                record.set_name(name.clone());
+                record
+            })
+            .collect();
+
+        (!records.is_empty()).then_some(records)
+    }
+
+    fn add(&mut self, record: Record) {
+        self.records
+            .entry(LowerName::new(record.name()))
+            .or_default()
+            .push(record);
+    }
+}
+
+fn parse_zone_file(contents: &str) -> std::result::Result<Vec<Record>, String> {
+    let mut origin: Option<Name> = None;
+    let mut default_ttl = DEFAULT_TTL;
+    let mut records = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let at_line = |e: String| format!("line {}: {}", index + 1, e);
+        let tokens = tokenize(line).map_err(at_line)?;
+        let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+
+        match tokens.as_slice() {
+            [] => {}
+            ["$ORIGIN", name] => origin = Some(parse_name(name, None).map_err(at_line)?),
+            ["$TTL", ttl] => default_ttl = parse_number(ttl).map_err(at_line)?,
+            [directive, ..] if directive.starts_with('$') => {
+                return Err(at_line(format!("unsupported directive {}", directive)));
+            }
+            [name, rest @ ..] => {
+                let name = parse_name(name, origin.as_ref()).map_err(at_line)?;
+                records.push(parse_record_fields(name, rest, origin.as_ref(), default_ttl).map_err(at_line)?);
+            }
+        }
+    }
+
+    Ok(records)
+}
+
+fn parse_record(name: &str, record_type: &str, value: &str, ttl: Option<u32>) -> std::result::Result<Record, String> {
+    let name = parse_name(name, None)?;
+    let record_type = parse_type(record_type)?;
+    let fields = tokenize(value)?;
+    let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+    let rdata = parse_rdata(record_type, &fields, None)?;
+    Ok(Record::from_rdata(name, ttl.unwrap_or(DEFAULT_TTL), rdata))
+}
+
+/// Parse `[ttl] [IN] type rdata...`, where TTL and class may come in either order
+fn parse_record_fields(
+    name: Name,
+    fields: &[&str],
+    origin: Option<&Name>,
+    default_ttl: u32,
+) -> std::result::Result<Record, String> {
+    let mut ttl = default_ttl;
+    let mut fields = fields;
+
+    for _ in 0..2 {
+        match fields.first() {
+            Some(field) if field.eq_ignore_ascii_case("IN") => fields = &fields[1..],
+            Some(field) if field.chars().all(|c| c.is_ascii_digit()) => {
+                ttl = parse_number(field)?;
+                fields = &fields[1..];
+            }
+            _ => break,
+        }
+    }
+
+    let (record_type, rdata) = fields.split_first().ok_or("missing record type")?;
+    let rdata = parse_rdata(parse_type(record_type)?, rdata, origin)?;
+    Ok(Record::from_rdata(name, ttl, rdata))
+}
+
+fn parse_type(record_type: &str) -> std::result::Result<RecordType, String> {
+    let parsed = RecordType::from_str(&record_type.to_ascii_uppercase())
+        .map_err(|_| format!("unknown record type {}", record_type))?;
+
+    match parsed {
+        RecordType::A
+        | RecordType::AAAA
+        | RecordType::CNAME
+        | RecordType::MX
+        | RecordType::NS
+        | RecordType::SOA
+        | RecordType::TXT => Ok(parsed),
+        _ => Err(format!("unsupported record type {}", record_type)),
+    }
+}
+
+fn parse_rdata(record_type: RecordType, fields: &[&str], origin: Option<&Name>) -> std::result::Result<RData, String> {
+    let expect = |count: usize| {
+        if fields.len() == count {
+            Ok(())
+        } else {
+            Err(format!("{} expects {} fields, got {}", record_type, count, fields.len()))
+        }
+    };
+
+    let rdata = match record_type {
+        RecordType::A => {
+            expect(1)?;
+            RData::A(A(Ipv4Addr::from_str(fields[0]).map_err(|e| e.to_string())?))
+        }
+        RecordType::AAAA => {
+            expect(1)?;
+            RData::AAAA(AAAA(Ipv6Addr::from_str(fields[0]).map_err(|e| e.to_string())?))
+        }
+        RecordType::CNAME => {
+            expect(1)?;
+            RData::CNAME(CNAME(parse_name(fields[0], origin)?))
+        }
+        RecordType::NS => {
+            expect(1)?;
+            RData::NS(NS(parse_name(fields[0], origin)?))
+        }
+        RecordType::MX => {
+            expect(2)?;
+            RData::MX(MX::new(parse_number(fields[0])?, parse_name(fields[1], origin)?))
+        }
+        RecordType::SOA => {
+            expect(7)?;
+            RData::SOA(SOA::new(
+                parse_name(fields[0], origin)?,
+                parse_name(fields[1], origin)?,
+                parse_number(fields[2])?,
+                parse_number(fields[3])?,
+                parse_number(fields[4])?,
+                parse_number(fields[5])?,
+                parse_number(fields[6])?,
+            ))
+        }
+        RecordType::TXT => {
+            if fields.is_empty() {
+                return Err("TXT expects at least one string".to_string());
+            }
+            RData::TXT(TXT::new(fields.iter().map(|field| field.to_string()).collect()))
+        }
+        _ => return Err(format!("unsupported record type {}", record_type)),
+    };
+
+    Ok(rdata)
+}
+
+/// Resolve `@` and names relative to `$ORIGIN`
+fn parse_name(name: &str, origin: Option<&Name>) -> std::result::Result<Name, String> {
+    if name == "@" {
+        return origin.cloned().ok_or_else(|| "@ used without $ORIGIN".to_string());
+    }
+
+    let mut parsed = Name::from_ascii(name).map_err(|e| format!("invalid name {}: {}", name, e))?;
+    if !name.ends_with('.') {
+        if let Some(origin) = origin {
+            parsed = parsed
+                .append_domain(origin)
+                .map_err(|e| format!("invalid name {}: {}", name, e))?;
+        }
+    }
+    parsed.set_fqdn(true);
+    Ok(parsed)
+}
+
+fn parse_number<T: FromStr>(field: &str) -> std::result::Result<T, String> {
+    field.parse().map_err(|_| format!("invalid number {}", field))
+}
+
+/// Split a line on whitespace, keeping quoted strings together and
+/// dropping everything after an unquoted `;`
+fn tokenize(line: &str) -> std::result::Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => break,
+            c if c.is_whitespace() => {}
+            '"' => {
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => token.extend(chars.next()),
+                        Some(c) => token.push(c),
+                        None => return Err("unterminated quoted string".to_string()),
+                    }
+                }
+                tokens.push(token);
+            }
+            c => {
+                let mut token = c.to_string();
+                for c in chars.by_ref() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    token.push(c);
+                }
+                tokens.push(token);
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StaticRecordConfig;
+
+    const ZONE: &str = r#"
+$ORIGIN q.example.com.
+$TTL 600
+@        IN SOA ns1.example.com. hostmaster.example.com. 1 7200 900 1209600 300
+@        IN NS  ns1.example.com.
+www  60  IN A   192.0.2.10 ; web front end
+motd        TXT "hello; world" "second string"
+"#;
+
+    #[test]
+    fn test_parse_zone_file() {
+        let records = parse_zone_file(ZONE).unwrap();
+        assert_eq!(records.len(), 4);
+
+        let apex = Name::from_ascii("q.example.com.").unwrap();
+        assert_eq!(records[0].name(), &apex);
+        assert_eq!(records[0].record_type(), RecordType::SOA);
+        assert_eq!(records[0].ttl(), 600);
+
+        let www = &records[2];
+        assert_eq!(www.name(), &Name::from_ascii("www.q.example.com.").unwrap());
+        assert_eq!(www.ttl(), 60);
+        assert_eq!(www.data(), Some(&RData::A(A(Ipv4Addr::new(192, 0, 2, 10)))));
+
+        let motd = &records[3];
+        assert_eq!(
+            motd.data(),
+            Some(&RData::TXT(TXT::new(vec!["hello; world".to_string(), "second string".to_string()])))
+        );
+    }
+
+    #[test]
+    fn test_lookup_from_config_tables() {
+        let config = StaticRecordsConfig {
+            zone_file: None,
+            records: vec![StaticRecordConfig {
+                name: "status.q.example.com".to_string(),
+                record_type: "txt".to_string(),
+                value: "\"all systems go\"".to_string(),
+                ttl: Some(30),
+            }],
+        };
+        let records = StaticRecords::new(&config).unwrap();
+
+        let name = Name::from_ascii("STATUS.q.example.com.").unwrap();
+        let found = records.lookup(&name, RecordType::TXT).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name(), &name);
+        assert_eq!(found[0].ttl(), 30);
+
+        assert!(records.contains(&name));
+        assert!(records.lookup(&name, RecordType::A).is_none());
+    }
+
+    #[test]
+    fn test_invalid_records() {
+        assert!(parse_zone_file("www IN A not-an-ip").is_err());
+        assert!(parse_zone_file("www IN SRV 0 0 53 dns.example.com.").is_err());
+        assert!(parse_zone_file("@ IN NS ns1.example.com.").is_err());
+        assert!(parse_zone_file("motd TXT \"unterminated").is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,196 @@
+use crate::config::{StorageBackend, StorageConfig};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::error;
+
+/// Key-value store for state that instances of one deployment share: bans,
+/// and the usage counters of API keys and tenants.
+///
+/// Keys are `:`-separated paths such as `ban:192.0.2.1`. Counters are kept
+/// as decimal text, so that `get` reads them too.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// The value at `key`, unless there is none or it has expired
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Store `value` at `key`, to expire after `ttl` if given
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()>;
+
+    /// Remove `key`, returning whether it held a value
+    async fn delete(&self, key: &str) -> Result<bool>;
+
+    /// Every key starting with `prefix`, with its value
+    async fn scan(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>>;
+
+    /// Add `by` to the counter at `key`, returning the new count
+    async fn increment(&self, key: &str, by: u64) -> Result<u64>;
+}
+
+impl dyn Storage {
+    pub async fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.get(key).await? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set_json<T: Serialize + Sync>(&self, key: &str, value: &T, ttl: Option<Duration>) -> Result<()> {
+        self.set(key, serde_json::to_vec(value)?, ttl).await
+    }
+
+    /// The counter at `key`, 0 if it was never incremented
+    pub async fn counter(&self, key: &str) -> Result<u64> {
+        match self.get(key).await? {
+            Some(value) => parse_counter(&value),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Open the store `[storage]` configures
+pub fn open(config: &StorageConfig) -> Result<Arc<dyn Storage>> {
+    match config.backend {
+        StorageBackend::Memory => Ok(Arc::new(MemoryStorage::default())),
+        #[cfg(feature = "sled-storage")]
+        StorageBackend::Sled => Ok(Arc::new(crate::sled_storage::SledStorage::open(&config.path)?)),
+        #[cfg(not(feature = "sled-storage"))]
+        StorageBackend::Sled => Err(crate::Error::Configuration(
+            "The sled storage backend needs LLMdig built with the sled-storage feature".to_string(),
+        )
+        .into()),
+        #[cfg(feature = "redis-storage")]
+        StorageBackend::Redis => Ok(Arc::new(crate::redis_storage::RedisStorage::new(
+            &config.url,
+            &config.key_prefix,
+        )?)),
+        #[cfg(not(feature = "redis-storage"))]
+        StorageBackend::Redis => Err(crate::Error::Configuration(
+            "The redis storage backend needs LLMdig built with the redis-storage feature".to_string(),
+        )
+        .into()),
+    }
+}
+
+/// The result of a storage call, or the default after logging the failure,
+/// for callers that carry on without the store
+pub fn or_default<T: Default>(result: Result<T>, action: &str) -> T {
+    result.unwrap_or_else(|e| {
+        error!("Storage failed to {}: {}", action, e);
+        T::default()
+    })
+}
+
+pub(crate) fn parse_counter(value: &[u8]) -> Result<u64> {
+    std::str::from_utf8(value)?
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid counter value: {}", e))
+}
+
+/// A value and when it expires, if ever
+type Entry = (Vec<u8>, Option<Instant>);
+
+/// Storage in this process only, lost on restart. The default.
+#[derive(Default)]
+pub struct MemoryStorage {
+    entries: RwLock<HashMap<String, Entry>>,
+}
+
+fn is_live(expires_at: &Option<Instant>, now: Instant) -> bool {
+    expires_at.is_none_or(|expires_at| expires_at > now)
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let entries = self.entries.read().await;
+        Ok(entries
+            .get(key)
+            .filter(|(_, expires_at)| is_live(expires_at, Instant::now()))
+            .map(|(value, _)| value.clone()))
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()> {
+        let now = Instant::now();
+        let mut entries = self.entries.write().await;
+        // Expired entries go whenever something is stored
+        entries.retain(|_, (_, expires_at)| is_live(expires_at, now));
+        entries.insert(key.to_string(), (value, ttl.map(|ttl| now + ttl)));
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool> {
+        let removed = self.entries.write().await.remove(key);
+        Ok(removed.is_some_and(|(_, expires_at)| is_live(&expires_at, Instant::now())))
+    }
+
+    async fn scan(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let now = Instant::now();
+        let entries = self.entries.read().await;
+        Ok(entries
+            .iter()
+            .filter(|(key, (_, expires_at))| key.starts_with(prefix) && is_live(expires_at, now))
+            .map(|(key, (value, _))| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    async fn increment(&self, key: &str, by: u64) -> Result<u64> {
+        let now = Instant::now();
+        let mut entries = self.entries.write().await;
+        // Like Redis, a counter keeps its expiry
+        let (count, expires_at) = match entries.get(key) {
+            Some((value, expires_at)) if is_live(expires_at, now) => (parse_counter(value)?, *expires_at),
+            _ => (0, None),
+        };
+        let count = count + by;
+        entries.insert(key.to_string(), (count.to_string().into_bytes(), expires_at));
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_storage() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+
+        storage.set("ban:192.0.2.1", b"one".to_vec(), None).await.unwrap();
+        storage.set_json("ban:192.0.2.2", &vec![2], Some(Duration::from_secs(60))).await.unwrap();
+        storage.set("usage:key:a", b"other".to_vec(), None).await.unwrap();
+        assert_eq!(storage.get("ban:192.0.2.1").await.unwrap(), Some(b"one".to_vec()));
+        assert_eq!(storage.get_json::<Vec<u8>>("ban:192.0.2.2").await.unwrap(), Some(vec![2]));
+
+        let mut keys: Vec<String> = storage.scan("ban:").await.unwrap().into_iter().map(|(key, _)| key).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["ban:192.0.2.1", "ban:192.0.2.2"]);
+
+        assert!(storage.delete("ban:192.0.2.1").await.unwrap());
+        assert!(!storage.delete("ban:192.0.2.1").await.unwrap());
+        assert_eq!(storage.get("ban:192.0.2.1").await.unwrap(), None);
+
+        assert_eq!(storage.counter("usage:key:b").await.unwrap(), 0);
+        assert_eq!(storage.increment("usage:key:b", 1).await.unwrap(), 1);
+        assert_eq!(storage.increment("usage:key:b", 41).await.unwrap(), 42);
+        assert_eq!(storage.counter("usage:key:b").await.unwrap(), 42);
+        assert!(storage.increment("usage:key:a", 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_memory_expiry() {
+        let storage = MemoryStorage::default();
+        storage.set("ban:192.0.2.1", b"soon".to_vec(), Some(Duration::from_millis(20))).await.unwrap();
+        storage.set("ban:192.0.2.2", b"later".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(40)).await;
+
+        assert_eq!(storage.get("ban:192.0.2.1").await.unwrap(), None);
+        assert_eq!(storage.scan("ban:").await.unwrap().len(), 1);
+        assert!(!storage.delete("ban:192.0.2.1").await.unwrap());
+    }
+}
\ No newline at end of file
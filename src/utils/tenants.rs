@@ -0,0 +1,342 @@
+use crate::config::{Config, RateLimitConfig, TenantConfig};
+use crate::llm::{GenerationOptions, LlmClient, TokenUsage};
+use crate::utils::llm_keys::LlmKeyRing;
+use crate::utils::metrics::Metrics;
+use crate::utils::rate_limiter::RateLimiter;
+use crate::utils::retention::PurgeFilter;
+use crate::utils::secret::SecretString;
+use crate::utils::storage::{self, Storage};
+use crate::utils::zone_profiles::{backend_client, backend_config, normalize_zone};
+use crate::Error;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// One team sharing the server, with its settings and what it has used
+pub struct Tenant {
+    pub name: String,
+    /// Client for a tenant with a backend of its own
+    llm_client: Option<LlmClient>,
+    model: Option<String>,
+    system_prompt: Option<String>,
+    rate_limit: Option<(RateLimitConfig, RateLimiter)>,
+    /// Where the usage counters are kept
+    storage: Arc<dyn Storage>,
+}
+
+/// What a tenant has used, as counted in the store
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TenantUsage {
+    pub name: String,
+    pub queries: u64,
+    pub cache_hits: u64,
+    pub rate_limited: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl Tenant {
+    fn new(
+        config: &Config,
+        tenant: &TenantConfig,
+        metrics: &Arc<Metrics>,
+        keys: &Arc<LlmKeyRing>,
+        storage: Arc<dyn Storage>,
+    ) -> Result<Self> {
+        let llm_client = if tenant.backend.is_some() || tenant.base_url.is_some() || tenant.api_key.is_some() {
+            let config = backend_config(
+                config,
+                tenant.backend.as_ref(),
+                tenant.base_url.as_ref(),
+                tenant.api_key.as_ref(),
+            );
+            Some(backend_client(config, tenant.api_key.as_ref(), keys)?.with_metrics(metrics.clone()))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            name: tenant.name.clone(),
+            llm_client,
+            model: tenant.model.clone(),
+            system_prompt: tenant.system_prompt.clone(),
+            rate_limit: tenant.rate_limit.clone().map(|limits| {
+                let limiter = RateLimiter::new(limits.requests_per_minute, limits.burst_size);
+                (limits, limiter)
+            }),
+            storage,
+        })
+    }
+
+    /// Fill in the tenant's settings where the request has none
+    pub fn apply(&self, options: &mut GenerationOptions) {
+        if options.model.is_none() {
+            options.model = self.model.clone();
+        }
+        if options.system_prompt.is_none() {
+            options.system_prompt = self.system_prompt.clone();
+        }
+    }
+
+    /// The tenant's own backend, if it has one
+    pub fn llm_client(&self) -> Option<&LlmClient> {
+        self.llm_client.as_ref()
+    }
+
+    /// Check the tenant's own rate limit for `client_addr`, or `None` when
+    /// the tenant has none
+    pub async fn allow_request(&self, client_addr: SocketAddr) -> Option<bool> {
+        let (limits, limiter) = self.rate_limit.as_ref()?;
+        let allowed = !limits.enabled || limiter.allow_request(client_addr).await;
+        if !allowed {
+            self.count("rate_limited", 1).await;
+        }
+        Some(allowed)
+    }
+
+    /// Meter one query answered for the tenant
+    pub async fn record_query(&self, cache_hit: bool, usage: Option<TokenUsage>) {
+        self.count("queries", 1).await;
+        if cache_hit {
+            self.count("cache_hits", 1).await;
+        }
+        if let Some(usage) = usage {
+            self.count("prompt_tokens", usage.prompt_tokens).await;
+            self.count("completion_tokens", usage.completion_tokens).await;
+        }
+    }
+
+    fn counter_key(&self, counter: &str) -> String {
+        format!("usage:tenant:{}:{}", self.name, counter)
+    }
+
+    async fn count(&self, counter: &str, by: u64) {
+        if by > 0 {
+            let result = self.storage.increment(&self.counter_key(counter), by).await;
+            storage::or_default(result, "meter a tenant");
+        }
+    }
+
+    pub async fn usage(&self) -> TenantUsage {
+        let counter = |counter| async move {
+            storage::or_default(self.storage.counter(&self.counter_key(counter)).await, "read tenant usage")
+        };
+        TenantUsage {
+            name: self.name.clone(),
+            queries: counter("queries").await,
+            cache_hits: counter("cache_hits").await,
+            rate_limited: counter("rate_limited").await,
+            prompt_tokens: counter("prompt_tokens").await,
+            completion_tokens: counter("completion_tokens").await,
+        }
+    }
+}
+
+/// The `[[tenants]]`, found by access token or by the zone a question is in
+#[derive(Default)]
+pub struct Tenants {
+    tenants: HashMap<String, Tenant>,
+    by_zone: HashMap<String, String>,
+    by_token: HashMap<String, String>,
+}
+
+impl Tenants {
+    /// Tenants without an API key of their own call their backend with `keys`
+    pub fn new(
+        config: &Config,
+        metrics: Arc<Metrics>,
+        keys: Arc<LlmKeyRing>,
+        storage: Arc<dyn Storage>,
+    ) -> Result<Self> {
+        let mut tenants = Self::default();
+        for tenant in &config.tenants {
+            if tenant.name.is_empty() {
+                return Err(Error::Configuration("Tenant without a name in [[tenants]]".to_string()).into());
+            }
+            if tenants.tenants.contains_key(&tenant.name) {
+                return Err(Error::Configuration(format!("Duplicate tenant {}", tenant.name)).into());
+            }
+
+            for zone in &tenant.zones {
+                let zone = normalize_zone(zone)?;
+                if let Some(owner) = tenants.by_zone.insert(zone.clone(), tenant.name.clone()) {
+                    let message = format!("Zone {} belongs to tenants {} and {}", zone, owner, tenant.name);
+                    return Err(Error::Configuration(message).into());
+                }
+            }
+            if let Some(token) = tenant.token.as_ref().map(SecretString::expose) {
+                // Tokens travel in a single DNS label, which is case-insensitive
+                if token.is_empty() || token.contains('.') {
+                    return Err(Error::Configuration(format!("Invalid token for tenant {}", tenant.name)).into());
+                }
+                let clashes_with_key = config.api_keys.keys.iter().any(|key| key.token.expose().eq_ignore_ascii_case(token));
+                if tenants.by_token.insert(token.to_lowercase(), tenant.name.clone()).is_some() || clashes_with_key {
+                    return Err(Error::Configuration(format!("Duplicate token for tenant {}", tenant.name)).into());
+                }
+            }
+
+            let state = Tenant::new(config, tenant, &metrics, &keys, storage.clone())?;
+            tenants.tenants.insert(tenant.name.clone(), state);
+        }
+        Ok(tenants)
+    }
+
+    /// Whether any tenant is known by an access token
+    pub fn has_tokens(&self) -> bool {
+        !self.by_token.is_empty()
+    }
+
+    /// The tenant of a query: the one its access token names, or else the
+    /// one owning its zone, a served zone as a lowercase FQDN
+    pub fn resolve(&self, token: Option<&str>, zone: Option<&str>) -> Option<&Tenant> {
+        let name = match token.and_then(|token| self.by_token.get(&token.to_lowercase())) {
+            Some(name) => name,
+            None => self.by_zone.get(zone?)?,
+        };
+        self.tenants.get(name)
+    }
+
+    pub fn get(&self, name: Option<&str>) -> Option<&Tenant> {
+        self.tenants.get(name?)
+    }
+
+    /// Run the startup checks of the tenants' own backends
+    pub async fn prepare_backends(&self) -> Result<()> {
+        for client in self.tenants.values().filter_map(Tenant::llm_client) {
+            client.prepare_backend().await?;
+        }
+        Ok(())
+    }
+
+    /// Drop the rate-limit buckets of the tenants' own limits that
+    /// `filter` matches
+    pub async fn forget(&self, filter: &PurgeFilter) -> usize {
+        let mut removed = 0;
+        for (_, limiter) in self.tenants.values().filter_map(|tenant| tenant.rate_limit.as_ref()) {
+            removed += limiter.forget(|client, idle| filter.matches(Some(client.ip()), idle)).await;
+        }
+        removed
+    }
+
+    /// Usage of every tenant, by name
+    pub async fn usage(&self) -> Vec<TenantUsage> {
+        let mut usage = Vec::with_capacity(self.tenants.len());
+        for tenant in self.tenants.values() {
+            usage.push(tenant.usage().await);
+        }
+        usage.sort_by(|a, b| a.name.cmp(&b.name));
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiKeyConfig, LlmBackendType, RateLimitBackend};
+    use crate::utils::storage::MemoryStorage;
+
+    fn load(tenants: Vec<TenantConfig>) -> Result<Tenants> {
+        let config = Config {
+            tenants,
+            ..Config::default()
+        };
+        Tenants::new(&config, Arc::new(Metrics::new()), LlmKeyRing::for_config(&config), Arc::new(MemoryStorage::default()))
+    }
+
+    fn team(name: &str, zone: &str, token: &str) -> TenantConfig {
+        TenantConfig {
+            name: name.to_string(),
+            zones: vec![zone.to_string()],
+            token: Some(token.into()),
+            ..TenantConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_resolution() {
+        let tenants = load(vec![
+            TenantConfig {
+                backend: Some(LlmBackendType::Mock),
+                model: Some("internal".to_string()),
+                ..team("research", "Ask.Research.Corp.com", "r3s")
+            },
+            team("sales", "ask.sales.corp.com", "s4l"),
+        ])
+        .unwrap();
+
+        assert_eq!(tenants.resolve(None, Some("ask.research.corp.com.")).unwrap().name, "research");
+        // The token wins over the zone
+        assert_eq!(tenants.resolve(Some("S4L"), Some("ask.research.corp.com.")).unwrap().name, "sales");
+        assert_eq!(tenants.resolve(Some("unknown"), Some("ask.sales.corp.com.")).unwrap().name, "sales");
+        assert!(tenants.resolve(None, Some("other.com.")).is_none());
+        assert!(tenants.resolve(None, None).is_none());
+        assert!(tenants.has_tokens());
+
+        let research = tenants.get(Some("research")).unwrap();
+        assert!(research.llm_client().is_some());
+        assert!(tenants.get(Some("sales")).unwrap().llm_client().is_none());
+        let mut options = GenerationOptions::default();
+        research.apply(&mut options);
+        assert_eq!(options.model.as_deref(), Some("internal"));
+    }
+
+    #[test]
+    fn test_invalid_tenants() {
+        assert!(load(vec![TenantConfig::default()]).is_err(), "tenants need a name");
+        assert!(load(vec![team("a", "q.corp.com", "x"), team("a", "r.corp.com", "y")]).is_err());
+        assert!(load(vec![team("a", "q.corp.com", "x"), team("b", "Q.corp.com", "y")]).is_err());
+        assert!(load(vec![team("a", "q.corp.com", "x"), team("b", "r.corp.com", "X")]).is_err());
+        assert!(load(vec![team("a", "q.corp.com", "x.y")]).is_err());
+
+        let config = Config {
+            tenants: vec![team("a", "q.corp.com", "shared")],
+            api_keys: crate::config::ApiKeysConfig {
+                keys: vec![ApiKeyConfig {
+                    name: "key".to_string(),
+                    token: "shared".into(),
+                    model: None,
+                    requests_per_minute: None,
+                    burst_size: None,
+                }],
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        assert!(Tenants::new(&config, Arc::new(Metrics::new()), LlmKeyRing::for_config(&config), Arc::new(MemoryStorage::default())).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_metering() {
+        let tenants = load(vec![TenantConfig {
+            rate_limit: Some(RateLimitConfig {
+                requests_per_minute: 60,
+                burst_size: 1,
+                enabled: true,
+                backend: RateLimitBackend::Memory,
+            }),
+            ..team("sales", "ask.sales.corp.com", "s4l")
+        }])
+        .unwrap();
+        let sales = tenants.get(Some("sales")).unwrap();
+
+        let client = "192.0.2.1:5353".parse().unwrap();
+        assert_eq!(sales.allow_request(client).await, Some(true));
+        assert_eq!(sales.allow_request(client).await, Some(false));
+        sales.record_query(false, Some(TokenUsage { prompt_tokens: 12, completion_tokens: 30 })).await;
+        sales.record_query(true, None).await;
+
+        assert_eq!(
+            tenants.usage().await,
+            vec![TenantUsage {
+                name: "sales".to_string(),
+                queries: 2,
+                cache_hits: 1,
+                rate_limited: 1,
+                prompt_tokens: 12,
+                completion_tokens: 30,
+            }]
+        );
+    }
+}
\ No newline at end of file
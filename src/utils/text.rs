@@ -0,0 +1,50 @@
+/// The largest index of at most `index` that falls between characters of
+/// `text`, so slicing there never splits one
+pub fn floor_char_boundary(text: &str, index: usize) -> usize {
+    if index >= text.len() {
+        return text.len();
+    }
+    let mut boundary = index;
+    while !text.is_char_boundary(boundary) {
+        boundary -= 1;
+    }
+    boundary
+}
+
+/// The longest start of `text` that fits in `max_bytes`, cut between
+/// characters
+pub fn truncate_to_bytes(text: &str, max_bytes: usize) -> &str {
+    &text[..floor_char_boundary(text, max_bytes)]
+}
+
+/// The first `max_chars` characters of `text`, or all of it if shorter
+pub fn truncate_to_chars(text: &str, max_chars: usize) -> &str {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_to_bytes() {
+        assert_eq!(truncate_to_bytes("hello", 3), "hel");
+        assert_eq!(truncate_to_bytes("hello", 10), "hello");
+        // Each emoji is four bytes and each CJK character three
+        assert_eq!(truncate_to_bytes("🦀🦀🦀", 6), "🦀");
+        assert_eq!(truncate_to_bytes("日本語", 8), "日本");
+        assert_eq!(truncate_to_bytes("日本語", 2), "");
+        assert_eq!(floor_char_boundary("a🦀", 3), 1);
+    }
+
+    #[test]
+    fn test_truncate_to_chars() {
+        assert_eq!(truncate_to_chars("🦀🦀🦀", 2), "🦀🦀");
+        assert_eq!(truncate_to_chars("日本語のテキスト", 3), "日本語");
+        assert_eq!(truncate_to_chars("short", 10), "short");
+        assert_eq!(truncate_to_chars("short", 0), "");
+    }
+}
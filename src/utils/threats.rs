@@ -0,0 +1,344 @@
+use crate::config::HoneypotConfig;
+use crate::utils::retention::PurgeFilter;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, RwLock};
+use tracing::{debug, error, info};
+use trust_dns_proto::rr::Name;
+use trust_dns_server::server::Request;
+
+/// Distinct fingerprints kept per attacker
+const MAX_FINGERPRINTS: usize = 8;
+
+/// Names scanners ask in the CHAOS class to learn the server software
+const VERSION_PROBES: [&str; 5] = ["version.bind", "hostname.bind", "id.server", "version.server", "authors.bind"];
+
+/// What a suspicious query looks like it is up to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreatKind {
+    /// ANY queries, mostly from amplification floods
+    Any,
+    /// version.bind and friends, fingerprinting the server
+    VersionProbe,
+    /// AXFR and IXFR
+    ZoneTransfer,
+    /// Names outside the served zones
+    OutsideZone,
+    /// Record types LLMdig has no answers of
+    UnexpectedType,
+    /// Random labels meant to miss every cache
+    RandomSubdomain,
+}
+
+/// A single line of the threat log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreatLogEntry {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub client_ip: IpAddr,
+    pub kind: ThreatKind,
+    pub name: String,
+    pub query_type: String,
+    pub query_class: String,
+    /// How the message was put together, see [`fingerprint`]
+    pub fingerprint: String,
+}
+
+impl ThreatLogEntry {
+    pub fn new(request: &Request, kind: ThreatKind) -> Self {
+        let query = request.query();
+        Self {
+            timestamp: unix_millis(),
+            client_ip: request.src().ip(),
+            kind,
+            name: query.name().to_string(),
+            query_type: format!("{:?}", query.query_type()),
+            query_class: format!("{:?}", query.query_class()),
+            fingerprint: fingerprint(request),
+        }
+    }
+}
+
+/// What has been seen of one attacker
+#[derive(Debug, Clone, Serialize)]
+pub struct AttackerStats {
+    pub ip: IpAddr,
+    /// Milliseconds since the Unix epoch
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub queries: u64,
+    pub kinds: BTreeMap<ThreatKind, u64>,
+    /// The first few distinct fingerprints
+    pub fingerprints: Vec<String>,
+    #[serde(skip)]
+    seen_at: Instant,
+}
+
+/// Logs suspicious queries to the threat log and keeps statistics per
+/// attacker.
+///
+/// Entries reach the file through a bounded channel like the query log's,
+/// so a flood never waits on disk I/O; entries that do not fit are dropped
+/// and counted.
+pub struct ThreatMonitor {
+    sender: mpsc::Sender<ThreatLogEntry>,
+    attackers: RwLock<HashMap<IpAddr, AttackerStats>>,
+    random_label_length: usize,
+    max_attackers: usize,
+    dropped: AtomicU64,
+}
+
+impl ThreatMonitor {
+    /// Create the monitor and spawn its writer task. Must be called from
+    /// within a Tokio runtime.
+    pub fn new(config: &HoneypotConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(config.buffer_size.max(1));
+        tokio::spawn(write_entries(PathBuf::from(&config.threat_log_path), receiver));
+
+        info!("Honeypot enabled, logging threats to {}", config.threat_log_path);
+
+        Self {
+            sender,
+            attackers: RwLock::new(HashMap::new()),
+            random_label_length: config.random_label_length.max(1),
+            max_attackers: config.max_attackers.max(1),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether a label of `name` looks made up to miss the caches
+    pub fn has_random_label(&self, name: &Name) -> bool {
+        name.iter().any(|label| looks_random(label, self.random_label_length))
+    }
+
+    /// Log `entry` and count it against its client
+    pub async fn record(&self, entry: ThreatLogEntry) {
+        {
+            let mut attackers = self.attackers.write().await;
+            if !attackers.contains_key(&entry.client_ip) && attackers.len() >= self.max_attackers {
+                let oldest = attackers.values().min_by_key(|stats| stats.seen_at).map(|stats| stats.ip);
+                if let Some(ip) = oldest {
+                    attackers.remove(&ip);
+                }
+            }
+
+            let stats = attackers.entry(entry.client_ip).or_insert_with(|| AttackerStats {
+                ip: entry.client_ip,
+                first_seen: entry.timestamp,
+                last_seen: entry.timestamp,
+                queries: 0,
+                kinds: BTreeMap::new(),
+                fingerprints: Vec::new(),
+                seen_at: Instant::now(),
+            });
+            stats.last_seen = entry.timestamp;
+            stats.seen_at = Instant::now();
+            stats.queries += 1;
+            *stats.kinds.entry(entry.kind).or_insert(0) += 1;
+            if stats.fingerprints.len() < MAX_FINGERPRINTS && !stats.fingerprints.contains(&entry.fingerprint) {
+                stats.fingerprints.push(entry.fingerprint.clone());
+            }
+        }
+
+        if self.sender.try_send(entry).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            debug!("Threat log buffer full, dropped {} entries so far", dropped);
+        }
+    }
+
+    /// Every attacker seen, busiest first
+    pub async fn attackers(&self) -> Vec<AttackerStats> {
+        let mut attackers: Vec<_> = self.attackers.read().await.values().cloned().collect();
+        attackers.sort_by(|a, b| b.queries.cmp(&a.queries).then(a.ip.cmp(&b.ip)));
+        attackers
+    }
+
+    /// Drop the statistics `filter` matches, returning how many went. The
+    /// threat log itself is left to the operator.
+    pub async fn forget(&self, filter: &PurgeFilter) -> usize {
+        let mut attackers = self.attackers.write().await;
+        let before = attackers.len();
+        attackers.retain(|ip, stats| !filter.matches(Some(*ip), stats.seen_at.elapsed()));
+        before - attackers.len()
+    }
+
+    /// Number of entries dropped because the writer could not keep up
+    pub fn dropped_entries(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether `name` is one of the CHAOS names that ask for the server software
+pub fn is_version_probe(name: &Name) -> bool {
+    let name = name.to_lowercase().to_string();
+    VERSION_PROBES.contains(&name.trim_end_matches('.'))
+}
+
+/// Whether `label` looks generated rather than written: long, and either
+/// mixing letters and digits or nearly without vowels
+fn looks_random(label: &[u8], min_length: usize) -> bool {
+    if label.len() < min_length || !label.iter().all(u8::is_ascii_alphanumeric) {
+        return false;
+    }
+
+    let digits = label.iter().filter(|c| c.is_ascii_digit()).count();
+    let letters = label.len() - digits;
+    let vowels = label
+        .iter()
+        .filter(|c| matches!(c.to_ascii_lowercase(), b'a' | b'e' | b'i' | b'o' | b'u' | b'y'))
+        .count();
+    (digits >= 2 && letters >= 2) || (digits == 0 && vowels * 5 < letters)
+}
+
+/// Traits of the message that tell scanning tools apart: transport, header
+/// flags and EDNS settings, such as `udp rd edns0/4096 do opt10`
+pub fn fingerprint(request: &Request) -> String {
+    let header = request.header();
+    let mut parts = vec![format!("{:?}", request.protocol()).to_lowercase()];
+    for (set, flag) in [
+        (header.recursion_desired(), "rd"),
+        (header.checking_disabled(), "cd"),
+        (header.authentic_data(), "ad"),
+    ] {
+        if set {
+            parts.push(flag.to_string());
+        }
+    }
+
+    match request.edns() {
+        Some(edns) => {
+            parts.push(format!("edns{}/{}", edns.version(), edns.max_payload()));
+            if edns.dnssec_ok() {
+                parts.push("do".to_string());
+            }
+            let mut codes: Vec<u16> = edns.options().as_ref().keys().map(|code| u16::from(*code)).collect();
+            codes.sort_unstable();
+            parts.extend(codes.into_iter().map(|code| format!("opt{}", code)));
+        }
+        None => parts.push("noedns".to_string()),
+    }
+    parts.join(" ")
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+async fn write_entries(path: PathBuf, mut receiver: mpsc::Receiver<ThreatLogEntry>) {
+    let mut file: Option<File> = None;
+    while let Some(entry) = receiver.recv().await {
+        if let Err(e) = write_entry(&path, &mut file, &entry).await {
+            error!("Failed to write threat log entry: {}", e);
+            // Reopen on the next entry in case the file was removed underneath us
+            file = None;
+        }
+    }
+
+    if let Some(file) = file.as_mut() {
+        let _ = file.flush().await;
+    }
+}
+
+async fn write_entry(path: &Path, file: &mut Option<File>, entry: &ThreatLogEntry) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    if file.is_none() {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).await?;
+            }
+        }
+        *file = Some(OpenOptions::new().create(true).append(true).open(path).await?);
+    }
+
+    let file = file.as_mut().unwrap();
+    file.write_all(&line).await?;
+    file.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn entry(ip: &str, kind: ThreatKind, fingerprint: &str) -> ThreatLogEntry {
+        ThreatLogEntry {
+            timestamp: unix_millis(),
+            client_ip: ip.parse().unwrap(),
+            kind,
+            name: "version.bind.".to_string(),
+            query_type: "TXT".to_string(),
+            query_class: "CH".to_string(),
+            fingerprint: fingerprint.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_random_labels() {
+        assert!(looks_random(b"x7k2mq9z1abf", 12));
+        assert!(looks_random(b"qzkxwvbtrmpl", 12));
+        assert!(!looks_random(b"x7k2mq9z1ab", 12));
+        assert!(!looks_random(b"whatisthemeaningoflife", 12));
+        assert!(!looks_random(b"202410161230", 12));
+        assert!(!looks_random(b"tok-x7k2mq9z1abf", 12));
+
+        assert!(is_version_probe(&Name::from_str("VERSION.BIND").unwrap()));
+        assert!(!is_version_probe(&Name::from_str("version.example.com").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_attacker_stats() {
+        let dir = std::env::temp_dir().join(format!("llmdig-threats-{}", std::process::id()));
+        let path = dir.join("threats.jsonl");
+        let monitor = ThreatMonitor::new(&HoneypotConfig {
+            enabled: true,
+            threat_log_path: path.to_string_lossy().to_string(),
+            max_attackers: 2,
+            ..HoneypotConfig::default()
+        });
+
+        monitor.record(entry("192.0.2.1", ThreatKind::VersionProbe, "udp noedns")).await;
+        monitor.record(entry("192.0.2.1", ThreatKind::Any, "udp rd edns0/4096")).await;
+        monitor.record(entry("192.0.2.1", ThreatKind::Any, "udp rd edns0/4096")).await;
+        monitor.record(entry("192.0.2.2", ThreatKind::ZoneTransfer, "tcp noedns")).await;
+
+        let attackers = monitor.attackers().await;
+        assert_eq!(attackers.len(), 2);
+        assert_eq!(attackers[0].ip, "192.0.2.1".parse::<IpAddr>().unwrap());
+        assert_eq!(attackers[0].queries, 3);
+        assert_eq!(attackers[0].kinds[&ThreatKind::Any], 2);
+        assert_eq!(attackers[0].fingerprints, vec!["udp noedns", "udp rd edns0/4096"]);
+
+        // A third attacker takes the place of the one seen longest ago
+        monitor.record(entry("192.0.2.3", ThreatKind::OutsideZone, "udp rd noedns")).await;
+        let ips: Vec<IpAddr> = monitor.attackers().await.iter().map(|stats| stats.ip).collect();
+        assert_eq!(ips, vec!["192.0.2.2".parse::<IpAddr>().unwrap(), "192.0.2.3".parse().unwrap()]);
+
+        assert_eq!(monitor.forget(&PurgeFilter::client("192.0.2.3".parse().unwrap())).await, 1);
+        assert_eq!(monitor.attackers().await.len(), 1);
+
+        // Every entry reaches the log
+        for _ in 0..50 {
+            let lines = tokio::fs::read_to_string(&path).await.unwrap_or_default();
+            if lines.lines().count() == 5 {
+                let first: ThreatLogEntry = serde_json::from_str(lines.lines().next().unwrap()).unwrap();
+                assert_eq!(first.kind, ThreatKind::VersionProbe);
+                let _ = std::fs::remove_dir_all(&dir);
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("threat log entries were not written");
+    }
+}
\ No newline at end of file
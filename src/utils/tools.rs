@@ -0,0 +1,446 @@
+use crate::config::{Config, ToolKind, ToolsConfig};
+use crate::utils::forwarder::Forwarder;
+use crate::utils::post_process::truncate_chars;
+use crate::Error;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing::{debug, info};
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query};
+use trust_dns_proto::rr::{Name, RecordType};
+use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable};
+
+/// Longest tool output handed back to the model
+const MAX_OUTPUT_CHARS: usize = 2000;
+
+/// Largest whois response read from a server
+const MAX_WHOIS_BYTES: u64 = 64 * 1024;
+
+/// A function the model can ask LLMdig to run while answering
+#[async_trait]
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    /// JSON schema of the arguments object
+    fn parameters(&self) -> Value;
+
+    async fn call(&self, arguments: &Value) -> Result<String>;
+}
+
+/// The tools offered to the model, looked up by name when it calls one
+pub struct ToolRegistry {
+    tools: Vec<Box<dyn Tool>>,
+    max_rounds: usize,
+    timeout: Duration,
+}
+
+impl ToolRegistry {
+    /// Registry with the built-in tools allowed by `llm.tools`
+    pub fn new(config: &Config) -> Result<Self> {
+        let tools_config = &config.llm.tools;
+        let mut registry = Self::empty(tools_config);
+
+        for kind in &tools_config.allowed {
+            let tool: Box<dyn Tool> = match kind {
+                ToolKind::CurrentTime => Box::new(CurrentTime),
+                ToolKind::Whois => Box::new(Whois {
+                    server: tools_config.whois_server.clone(),
+                }),
+                ToolKind::DnsLookup => Box::new(DnsLookup {
+                    forwarder: Forwarder::new(&config.forwarding)?,
+                }),
+                ToolKind::UnitConversion => Box::new(UnitConversion),
+            };
+            registry.register(tool);
+        }
+
+        Ok(registry)
+    }
+
+    pub fn empty(config: &ToolsConfig) -> Self {
+        Self {
+            tools: Vec::new(),
+            max_rounds: config.max_rounds,
+            timeout: Duration::from_millis(config.timeout_ms.max(1)),
+        }
+    }
+
+    /// Add a tool, replacing any registered under the same name
+    pub fn register(&mut self, tool: Box<dyn Tool>) {
+        self.tools.retain(|existing| existing.name() != tool.name());
+        self.tools.push(tool);
+    }
+
+    pub fn tools(&self) -> impl Iterator<Item = &dyn Tool> {
+        self.tools.iter().map(|tool| tool.as_ref())
+    }
+
+    pub fn max_rounds(&self) -> usize {
+        self.max_rounds
+    }
+
+    /// Run a tool on arguments as sent by the model, a JSON object in a
+    /// string. Failures are returned as text so the model can recover.
+    pub async fn call(&self, name: &str, arguments: &str) -> String {
+        let Some(tool) = self.tools.iter().find(|tool| tool.name() == name) else {
+            return format!("error: unknown tool {}", name);
+        };
+
+        let arguments: Value = if arguments.trim().is_empty() {
+            json!({})
+        } else {
+            match serde_json::from_str(arguments) {
+                Ok(arguments) => arguments,
+                Err(e) => return format!("error: arguments are not valid JSON: {}", e),
+            }
+        };
+
+        info!("Calling tool {} with {}", name, arguments);
+        let output = match timeout(self.timeout, tool.call(&arguments)).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => format!("error: {}", e),
+            Err(_) => "error: the tool timed out".to_string(),
+        };
+        debug!("Tool {} returned: {}", name, output);
+        truncate_chars(&output, MAX_OUTPUT_CHARS)
+    }
+}
+
+fn string_argument<'a>(arguments: &'a Value, name: &str) -> Result<&'a str> {
+    arguments
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| Error::InvalidQuery(format!("missing string argument {}", name)).into())
+}
+
+struct CurrentTime;
+
+#[async_trait]
+impl Tool for CurrentTime {
+    fn name(&self) -> &str {
+        "current_time"
+    }
+
+    fn description(&self) -> &str {
+        "Current date and time in UTC"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    async fn call(&self, _arguments: &Value) -> Result<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        Ok(format_utc(now.as_secs()))
+    }
+}
+
+/// RFC 3339 timestamp for seconds since the Unix epoch
+fn format_utc(timestamp: u64) -> String {
+    let (year, month, day) = civil_date((timestamp / 86_400) as i64);
+    let seconds = timestamp % 86_400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Year, month and day of the day `days` after 1970-01-01
+pub(crate) fn civil_date(days: i64) -> (i64, i64, i64) {
+    // Civil-from-days, after Howard Hinnant's date algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+struct Whois {
+    server: String,
+}
+
+impl Whois {
+    async fn query(server: &str, query: &str) -> Result<String> {
+        let mut stream = TcpStream::connect((server, 43)).await?;
+        stream.write_all(format!("{}\r\n", query).as_bytes()).await?;
+
+        let mut response = Vec::new();
+        stream.take(MAX_WHOIS_BYTES).read_to_end(&mut response).await?;
+        Ok(String::from_utf8_lossy(&response).into_owned())
+    }
+}
+
+#[async_trait]
+impl Tool for Whois {
+    fn name(&self) -> &str {
+        "whois"
+    }
+
+    fn description(&self) -> &str {
+        "Registration details of a domain name, IP address or AS number"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "query": { "type": "string", "description": "e.g. example.com, 192.0.2.1 or AS64496" }
+            },
+            "required": ["query"]
+        })
+    }
+
+    async fn call(&self, arguments: &Value) -> Result<String> {
+        let query = string_argument(arguments, "query")?.trim();
+        // Keep whois flags and extra lines out of the request
+        if query.is_empty()
+            || query.starts_with('-')
+            || query.len() > 253
+            || !query.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '/'))
+        {
+            return Err(Error::InvalidQuery(format!("not a whois query: {}", query)).into());
+        }
+
+        let response = Self::query(&self.server, query).await?;
+
+        // The IANA server only says which registry holds the details
+        let referral = response.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            matches!(key.trim(), "refer" | "whois").then(|| value.trim().to_string())
+        });
+        match referral {
+            Some(server) if !server.is_empty() && server != self.server => Self::query(&server, query).await,
+            _ => Ok(response),
+        }
+    }
+}
+
+struct DnsLookup {
+    forwarder: Forwarder,
+}
+
+#[async_trait]
+impl Tool for DnsLookup {
+    fn name(&self) -> &str {
+        "dns_lookup"
+    }
+
+    fn description(&self) -> &str {
+        "Look up real DNS records for a name"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "type": { "type": "string", "description": "Record type such as A, AAAA, MX, TXT or NS; defaults to A" }
+            },
+            "required": ["name"]
+        })
+    }
+
+    async fn call(&self, arguments: &Value) -> Result<String> {
+        let name = Name::from_str(string_argument(arguments, "name")?)?;
+        let record_type = match arguments.get("type").and_then(Value::as_str) {
+            Some(record_type) => RecordType::from_str(&record_type.to_uppercase())?,
+            None => RecordType::A,
+        };
+
+        let mut message = Message::new();
+        message.set_id(rand::random());
+        message.set_message_type(MessageType::Query);
+        message.set_op_code(OpCode::Query);
+        message.set_recursion_desired(true);
+        message.add_query(Query::query(name.clone(), record_type));
+
+        let response = Message::from_bytes(&self.forwarder.forward(&message.to_bytes()?).await?)?;
+        if response.answers().is_empty() {
+            return Ok(format!("No {} records for {} ({})", record_type, name, response.response_code()));
+        }
+
+        Ok(response
+            .answers()
+            .iter()
+            .filter_map(|record| {
+                let data = record.data()?;
+                Some(format!("{} {} {} {}", record.name(), record.ttl(), record.record_type(), data))
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+/// Units by dimension with their size in the dimension's base unit
+const UNITS: &[(&str, &str, f64)] = &[
+    ("m", "length", 1.0),
+    ("km", "length", 1000.0),
+    ("cm", "length", 0.01),
+    ("mm", "length", 0.001),
+    ("mi", "length", 1609.344),
+    ("yd", "length", 0.9144),
+    ("ft", "length", 0.3048),
+    ("in", "length", 0.0254),
+    ("nmi", "length", 1852.0),
+    ("kg", "mass", 1.0),
+    ("g", "mass", 0.001),
+    ("mg", "mass", 0.000_001),
+    ("t", "mass", 1000.0),
+    ("lb", "mass", 0.453_592_37),
+    ("oz", "mass", 0.028_349_523_125),
+    ("l", "volume", 1.0),
+    ("ml", "volume", 0.001),
+    ("gal", "volume", 3.785_411_784),
+    ("qt", "volume", 0.946_352_946),
+    ("pt", "volume", 0.473_176_473),
+    ("m/s", "speed", 1.0),
+    ("km/h", "speed", 1.0 / 3.6),
+    ("mph", "speed", 0.447_04),
+    ("kn", "speed", 1852.0 / 3600.0),
+    ("s", "time", 1.0),
+    ("min", "time", 60.0),
+    ("h", "time", 3600.0),
+    ("d", "time", 86_400.0),
+    ("b", "data", 1.0),
+    ("kb", "data", 1e3),
+    ("mb", "data", 1e6),
+    ("gb", "data", 1e9),
+    ("tb", "data", 1e12),
+    ("kib", "data", 1024.0),
+    ("mib", "data", 1_048_576.0),
+    ("gib", "data", 1_073_741_824.0),
+];
+
+pub(crate) struct UnitConversion;
+
+impl UnitConversion {
+    pub(crate) fn convert(value: f64, from: &str, to: &str) -> Result<f64> {
+        let (from, to) = (from.to_lowercase(), to.to_lowercase());
+
+        // Temperatures have offsets, so go through kelvin
+        let to_kelvin = |unit: &str, value: f64| match unit {
+            "c" => Some(value + 273.15),
+            "f" => Some((value - 32.0) * 5.0 / 9.0 + 273.15),
+            "k" => Some(value),
+            _ => None,
+        };
+        if let Some(kelvin) = to_kelvin(&from, value) {
+            return match to.as_str() {
+                "c" => Ok(kelvin - 273.15),
+                "f" => Ok((kelvin - 273.15) * 9.0 / 5.0 + 32.0),
+                "k" => Ok(kelvin),
+                _ => Err(Error::InvalidQuery(format!("cannot convert {} to {}", from, to)).into()),
+            };
+        }
+
+        let unit = |name: &str| {
+            UNITS
+                .iter()
+                .find(|(unit, _, _)| *unit == name)
+                .ok_or_else(|| Error::InvalidQuery(format!("unknown unit {}", name)))
+        };
+        let (_, from_dimension, from_factor) = unit(&from)?;
+        let (_, to_dimension, to_factor) = unit(&to)?;
+        if from_dimension != to_dimension {
+            return Err(Error::InvalidQuery(format!("cannot convert {} to {}", from, to)).into());
+        }
+        Ok(value * from_factor / to_factor)
+    }
+}
+
+/// Up to six decimals, without trailing zeros
+pub(crate) fn format_number(value: f64) -> String {
+    let formatted = format!("{:.6}", value);
+    formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[async_trait]
+impl Tool for UnitConversion {
+    fn name(&self) -> &str {
+        "unit_conversion"
+    }
+
+    fn description(&self) -> &str {
+        "Convert a value between units of length, mass, volume, speed, time, data size or temperature (c, f, k)"
+    }
+
+    fn parameters(&self) -> Value {
+        let units: Vec<&str> = UNITS.iter().map(|(unit, _, _)| *unit).chain(["c", "f", "k"]).collect();
+        json!({
+            "type": "object",
+            "properties": {
+                "value": { "type": "number" },
+                "from": { "type": "string", "enum": units },
+                "to": { "type": "string", "enum": units }
+            },
+            "required": ["value", "from", "to"]
+        })
+    }
+
+    async fn call(&self, arguments: &Value) -> Result<String> {
+        let value = arguments
+            .get("value")
+            .and_then(Value::as_f64)
+            .ok_or_else(|| Error::InvalidQuery("missing number argument value".to_string()))?;
+        let from = string_argument(arguments, "from")?;
+        let to = string_argument(arguments, "to")?;
+
+        let converted = Self::convert(value, from, to)?;
+        Ok(format!("{} {} = {} {}", format_number(value), from, format_number(converted), to))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_utc(1_790_000_000), "2026-09-21T14:13:20Z");
+    }
+
+    async fn convert(value: f64, from: &str, to: &str) -> Result<String> {
+        UnitConversion.call(&json!({ "value": value, "from": from, "to": to })).await
+    }
+
+    #[tokio::test]
+    async fn test_unit_conversion() {
+        assert_eq!(convert(5.0, "km", "mi").await.unwrap(), "5 km = 3.106856 mi");
+        assert_eq!(convert(100.0, "C", "F").await.unwrap(), "100 C = 212 F");
+        assert_eq!(convert(1.0, "GiB", "MB").await.unwrap(), "1 GiB = 1073.741824 MB");
+        assert!(convert(1.0, "kg", "m").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_registry_reports_errors_as_text() {
+        let mut registry = ToolRegistry::empty(&ToolsConfig::default());
+        registry.register(Box::new(UnitConversion));
+
+        assert_eq!(
+            registry.call("unit_conversion", r#"{"value": 2, "from": "h", "to": "min"}"#).await,
+            "2 h = 120 min"
+        );
+        assert!(registry.call("unit_conversion", "{not json").await.starts_with("error:"));
+        assert!(registry.call("launch_missiles", "{}").await.starts_with("error: unknown tool"));
+    }
+}
\ No newline at end of file
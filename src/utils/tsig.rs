@@ -0,0 +1,312 @@
+use crate::config::{TsigConfig, TsigKeyConfig};
+use crate::utils::rate_limiter::RateLimiter;
+use crate::Error;
+use anyhow::Result;
+use base64::Engine;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+use trust_dns_proto::op::{Message, ResponseCode};
+use trust_dns_proto::rr::dnssec::rdata::tsig::{make_tsig_record, message_tbs, TsigAlgorithm, TSIG};
+use trust_dns_proto::rr::dnssec::tsig::TSigner;
+use trust_dns_proto::rr::{Name, RecordType};
+use trust_dns_proto::serialize::binary::BinDecodable;
+
+/// A configured shared secret
+struct TsigKey {
+    signer: TSigner,
+    secret: Vec<u8>,
+    algorithm: TsigAlgorithm,
+    rate_limiter: Option<RateLimiter<Name>>,
+}
+
+/// Key and request MAC of a verified query, needed to sign the response
+#[derive(Debug, Clone)]
+pub struct TsigSession {
+    pub key_name: Name,
+    pub request_mac: Vec<u8>,
+    /// TSIG error reported in the signed response, e.g. BADTIME
+    pub error: Option<ResponseCode>,
+}
+
+#[derive(Debug)]
+pub enum TsigVerification {
+    /// The query carried no TSIG record
+    Unsigned,
+    Verified(TsigSession),
+    /// The MAC is right but the query was signed outside the allowed clock
+    /// skew, as a replayed query would be; answered with a signed BADTIME
+    BadTime(TsigSession),
+    /// A TSIG record was present but the key is unknown or the MAC is wrong
+    Failed(String),
+}
+
+/// RFC 2845 TSIG verification and response signing for a set of named keys
+pub struct TsigKeyring {
+    keys: HashMap<Name, TsigKey>,
+    required: bool,
+    fudge: u16,
+}
+
+impl TsigKeyring {
+    pub fn new(config: &TsigConfig) -> Result<Self> {
+        let mut keys = HashMap::new();
+
+        for key_config in &config.keys {
+            let (name, key) = Self::build_key(key_config, config.fudge)?;
+            keys.insert(name, key);
+        }
+
+        if config.required && keys.is_empty() {
+            return Err(Error::Configuration("TSIG is required but no keys are configured".to_string()).into());
+        }
+
+        Ok(Self {
+            keys,
+            required: config.required,
+            fudge: config.fudge,
+        })
+    }
+
+    fn build_key(config: &TsigKeyConfig, fudge: u16) -> Result<(Name, TsigKey)> {
+        let name = Name::from_str(&config.name)
+            .map_err(|e| Error::Configuration(format!("Invalid TSIG key name {}: {}", config.name, e)))?;
+
+        let algorithm = match config.algorithm.to_lowercase().trim_end_matches('.') {
+            "hmac-sha256" => TsigAlgorithm::HmacSha256,
+            "hmac-sha384" => TsigAlgorithm::HmacSha384,
+            "hmac-sha512" => TsigAlgorithm::HmacSha512,
+            other => {
+                return Err(Error::Configuration(format!("Unsupported TSIG algorithm: {}", other)).into());
+            }
+        };
+
+        let secret = base64::engine::general_purpose::STANDARD
+            .decode(config.secret.expose().trim())
+            .map_err(|e| Error::Configuration(format!("Invalid TSIG secret for {}: {}", config.name, e)))?;
+
+        let signer = TSigner::new(secret.clone(), algorithm.clone(), name.clone(), fudge)?;
+
+        let rate_limiter = match (config.requests_per_minute, config.burst_size) {
+            (Some(rpm), burst) => Some(RateLimiter::new(rpm, burst.unwrap_or(rpm.max(1)))),
+            (None, _) => None,
+        };
+
+        Ok((
+            name,
+            TsigKey {
+                signer,
+                secret,
+                algorithm,
+                rate_limiter,
+            },
+        ))
+    }
+
+    /// Whether unsigned queries must be refused
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    /// Verify the TSIG record of a query, given its exact wire bytes
+    pub fn verify(&self, wire: &[u8]) -> TsigVerification {
+        let message = match Message::from_bytes(wire) {
+            Ok(message) => message,
+            Err(e) => return TsigVerification::Failed(e.to_string()),
+        };
+
+        let tsig_record = match message
+            .signature()
+            .iter()
+            .find(|record| record.record_type() == RecordType::TSIG)
+        {
+            Some(record) => record,
+            None => return TsigVerification::Unsigned,
+        };
+
+        let key_name = tsig_record.name().clone();
+        let key = match self.keys.get(&key_name) {
+            Some(key) => key,
+            None => return TsigVerification::Failed(format!("unknown key {}", key_name)),
+        };
+
+        match key.signer.verify_message_byte(None, wire, true) {
+            // The signer only checks the MAC; the time window is up to us
+            Ok((request_mac, valid, signed_at)) => {
+                let now = unix_time();
+                let mut session = TsigSession {
+                    key_name,
+                    request_mac,
+                    error: None,
+                };
+                if now < valid.start || now > valid.end {
+                    debug!(
+                        "TSIG time {} with key {} is outside the allowed skew of the server time {}",
+                        signed_at, session.key_name, now
+                    );
+                    session.error = Some(ResponseCode::BADTIME);
+                    return TsigVerification::BadTime(session);
+                }
+                debug!("TSIG verified with key {}", session.key_name);
+                TsigVerification::Verified(session)
+            }
+            Err(e) => {
+                warn!("TSIG verification failed for key {}: {}", key_name, e);
+                TsigVerification::Failed(e.to_string())
+            }
+        }
+    }
+
+    /// Apply the per-key rate limit. Returns `None` when the key has no
+    /// limit of its own and the per-client limiter should be used instead.
+    pub async fn check_rate_limit(&self, key_name: &Name) -> Option<bool> {
+        let limiter = self.keys.get(key_name)?.rate_limiter.as_ref()?;
+        Some(limiter.allow_request(key_name.clone()).await)
+    }
+
+    /// Append a TSIG record to a response, covering the request MAC per RFC 2845 §4.2.
+    /// A BADTIME response carries the server time as other data (RFC 8945 §5.2.3).
+    pub fn sign(&self, session: &TsigSession, response: &mut Message) -> Result<()> {
+        let key = self
+            .keys
+            .get(&session.key_name)
+            .ok_or_else(|| Error::Dns(format!("TSIG key {} disappeared", session.key_name)))?;
+
+        let now = unix_time();
+        let error = session.error.map_or(0, u16::from);
+        let other = if session.error == Some(ResponseCode::BADTIME) {
+            // 48-bit time signed
+            now.to_be_bytes()[2..].to_vec()
+        } else {
+            Vec::new()
+        };
+
+        let pre_tsig = TSIG::new(
+            key.algorithm.clone(),
+            now,
+            self.fudge,
+            Vec::new(),
+            response.id(),
+            error,
+            other,
+        );
+
+        let tbs = message_tbs(Some(&session.request_mac), response, &pre_tsig, &session.key_name)?;
+        let mac = key.algorithm.mac_data(&key.secret, &tbs)?;
+
+        response.add_tsig(make_tsig_record(session.key_name.clone(), pre_tsig.set_mac(mac)));
+        Ok(())
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trust_dns_proto::op::MessageFinalizer;
+    use trust_dns_proto::serialize::binary::BinEncodable;
+
+    fn key_config(name: &str) -> TsigKeyConfig {
+        TsigKeyConfig {
+            name: name.to_string(),
+            algorithm: "hmac-sha256".to_string(),
+            secret: base64::engine::general_purpose::STANDARD.encode(b"0123456789abcdef0123456789abcdef").into(),
+            requests_per_minute: Some(60),
+            burst_size: Some(2),
+        }
+    }
+
+    #[test]
+    fn test_keyring_rejects_bad_config() {
+        let mut config = TsigConfig {
+            required: true,
+            ..Default::default()
+        };
+        assert!(TsigKeyring::new(&config).is_err());
+
+        let mut bad_key = key_config("client.key.");
+        bad_key.algorithm = "hmac-md4".to_string();
+        config.keys = vec![bad_key];
+        assert!(TsigKeyring::new(&config).is_err());
+
+        config.keys = vec![key_config("client.key.")];
+        assert!(TsigKeyring::new(&config).is_ok());
+    }
+
+    #[test]
+    fn test_unsigned_query() {
+        let config = TsigConfig {
+            keys: vec![key_config("client.key.")],
+            ..Default::default()
+        };
+        let keyring = TsigKeyring::new(&config).unwrap();
+
+        let message = Message::new();
+        let wire = message.to_bytes().unwrap();
+        assert!(matches!(keyring.verify(&wire), TsigVerification::Unsigned));
+    }
+
+    /// A query signed with `key_config("client.key.")` at `signed_at`
+    fn signed_query(signed_at: u64) -> Vec<u8> {
+        let config = key_config("client.key.");
+        let secret = base64::engine::general_purpose::STANDARD.decode(config.secret.expose()).unwrap();
+        let signer = TSigner::new(secret, TsigAlgorithm::HmacSha256, Name::from_str(&config.name).unwrap(), 300).unwrap();
+        let mut message = Message::new();
+        message.set_id(4242);
+        let (records, _) = signer.finalize_message(&message, signed_at as u32).unwrap();
+        for record in records {
+            message.add_tsig(record);
+        }
+        message.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_replayed_query_gets_badtime() {
+        let config = TsigConfig {
+            keys: vec![key_config("client.key.")],
+            ..Default::default()
+        };
+        let keyring = TsigKeyring::new(&config).unwrap();
+        assert!(matches!(keyring.verify(&signed_query(unix_time())), TsigVerification::Verified(_)));
+
+        // Signed an hour ago, well beyond the five minutes of fudge
+        let session = match keyring.verify(&signed_query(unix_time() - 3600)) {
+            TsigVerification::BadTime(session) => session,
+            other => panic!("expected BADTIME, got {:?}", other),
+        };
+        assert_eq!(session.error, Some(ResponseCode::BADTIME));
+
+        // The error response is still signed, so the client can trust it
+        let mut response = Message::new();
+        response.set_id(4242);
+        response.set_response_code(ResponseCode::NotAuth);
+        keyring.sign(&session, &mut response).unwrap();
+        let wire = response.to_bytes().unwrap();
+        let key = keyring.keys.get(&session.key_name).unwrap();
+        assert!(key.signer.verify_message_byte(Some(&session.request_mac), &wire, true).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_per_key_rate_limit() {
+        let config = TsigConfig {
+            keys: vec![key_config("client.key.")],
+            ..Default::default()
+        };
+        let keyring = TsigKeyring::new(&config).unwrap();
+        let name = Name::from_str("client.key.").unwrap();
+
+        assert_eq!(keyring.check_rate_limit(&name).await, Some(true));
+        assert_eq!(keyring.check_rate_limit(&name).await, Some(true));
+        assert_eq!(keyring.check_rate_limit(&name).await, Some(false));
+
+        let unknown = Name::from_str("other.key.").unwrap();
+        assert_eq!(keyring.check_rate_limit(&unknown).await, None);
+    }
+}
\ No newline at end of file
@@ -213,8 +213,7 @@ impl Validator {
                 if let Ok(port) = value.parse::<u16>() {
                     if port == 0 {
                         result.add_error("Port cannot be 0".to_string());
-                    }
-                    if port < 1024 {
+                    } else if port < 1024 {
                         result.add_warning("Using privileged port (< 1024)".to_string());
                     }
                 } else {
@@ -340,19 +339,32 @@ impl Validator {
         result
     }
 
-    /// Comprehensive validation for LLMdig configuration
+    /// Comprehensive validation for LLMdig configuration. Messages start with
+    /// the setting they are about.
     pub fn validate_llmdig_config(config: &crate::config::Config) -> ValidationResult {
         let mut result = ValidationResult::new();
         
-        // Validate server config
-        let port_validation = Self::validate_config_value("port", &config.server.port.to_string());
-        result.merge(port_validation);
+        // Validate server config; the port only matters without listen addresses
+        if config.server.listen.is_empty() {
+            let port_validation = Self::validate_config_value("port", &config.server.port.to_string());
+            result.merge(Self::scoped("server.port", port_validation));
+        }
+        if let Err(e) = config.server.listen_addrs() {
+            let key = if !config.server.listen.is_empty() {
+                "server.listen"
+            } else if !config.server.listen_interfaces.is_empty() {
+                "server.listen_interfaces"
+            } else {
+                "server.host"
+            };
+            result.add_error(format!("{}: {}", key, e));
+        }
         
         let max_conn_validation = Self::validate_config_value("max_connections", &config.server.max_connections.to_string());
-        result.merge(max_conn_validation);
+        result.merge(Self::scoped("server.max_connections", max_conn_validation));
         
         let timeout_validation = Self::validate_config_value("timeout", &config.server.timeout_seconds.to_string());
-        result.merge(timeout_validation);
+        result.merge(Self::scoped("server.timeout_seconds", timeout_validation));
         
         // Validate LLM config
         let llm_validation = Self::validate_llm_config(
@@ -360,18 +372,83 @@ impl Validator {
             config.llm.max_tokens,
             config.llm.temperature,
         );
-        result.merge(llm_validation);
+        result.merge(Self::scoped("llm", llm_validation));
+        
+        // OpenAI needs a key unless a self-hosted server stands in for it
+        match &config.llm.api_key {
+            Some(api_key) => {
+                let api_key_validation = Self::validate_config_value("api_key", api_key.expose());
+                result.merge(Self::scoped("llm.api_key", api_key_validation));
+            }
+            None if matches!(config.llm.backend, crate::config::LlmBackendType::OpenAI) && config.llm.base_url.is_none() => {
+                result.add_error("llm.api_key: OpenAI backend needs an API key (set llm.api_key or OPENAI_API_KEY)".to_string());
+            }
+            None => {}
+        }
+        match (&config.llm.api_key, &config.llm.secondary_api_key) {
+            (None, Some(_)) => result.add_warning("llm.secondary_api_key: set without llm.api_key".to_string()),
+            (Some(primary), Some(secondary)) if primary == secondary => {
+                result.add_warning("llm.secondary_api_key: the same as llm.api_key".to_string());
+            }
+            _ => {}
+        }
         
         // Validate rate limit config
         let rate_limit_validation = Self::validate_rate_limit_config(
             config.rate_limit.requests_per_minute,
             config.rate_limit.burst_size,
         );
-        result.merge(rate_limit_validation);
+        result.merge(Self::scoped("rate_limit", rate_limit_validation));
+
+        // The admin token is given as is or hashed, not both
+        let hashing = &config.password_hashing;
+        if config.admin.token.is_some() && config.admin.token_hash.is_some() {
+            result.add_error("admin.token_hash: cannot be set together with admin.token".to_string());
+        }
+        if let Some(hash) = &config.admin.token_hash {
+            if argon2::password_hash::PasswordHash::new(hash.expose()).is_err() {
+                result.add_error("admin.token_hash: not a hash printed by `llmdig hash-password`".to_string());
+            } else if crate::utils::encryption::HashUtils::needs_rehash(hash.expose(), hashing) {
+                result.add_warning(
+                    "admin.token_hash: weaker than [password_hashing] asks for; regenerate it with `llmdig hash-password`"
+                        .to_string(),
+                );
+            }
+        }
+        if hashing.algorithm == crate::config::PasswordHashAlgorithm::Argon2id {
+            if let Err(e) = argon2::Params::new(hashing.memory_kib, hashing.iterations, hashing.parallelism, None) {
+                result.add_error(format!("password_hashing: {}", e));
+            }
+        }
+
+        // Client certificates are only asked for over HTTPS
+        for (key, tls) in [("admin.tls", &config.admin.tls), ("api.tls", &config.api.tls)] {
+            if tls.client_ca_path.is_none() && !tls.allowed_client_names.is_empty() {
+                result.add_error(format!("{}.allowed_client_names: needs client_ca_path to be set", key));
+            } else if tls.client_ca_path.is_some() && !tls.enabled {
+                result.add_warning(format!("{}.client_ca_path: ignored unless {}.enabled is true", key, key));
+            }
+        }
+        let admin_host_is_local = config.admin.host == "localhost"
+            || config.admin.host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback());
+        if config.admin.enabled && !admin_host_is_local && !(config.admin.tls.enabled && config.admin.tls.client_ca_path.is_some()) {
+            result.add_warning(
+                "admin.host: reachable beyond this machine without client certificates; set admin.tls.client_ca_path"
+                    .to_string(),
+            );
+        }
         
         result
     }
 
+    /// Prefix every message in `result` with the setting it is about
+    fn scoped(key: &str, mut result: ValidationResult) -> ValidationResult {
+        for message in result.errors.iter_mut().chain(result.warnings.iter_mut()) {
+            *message = format!("{}: {}", key, message);
+        }
+        result
+    }
+
     /// Sanitize and validate user input
     pub fn sanitize_and_validate_input(input: &str) -> (String, ValidationResult) {
         let mut result = ValidationResult::new();
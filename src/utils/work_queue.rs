@@ -0,0 +1,110 @@
+use crate::utils::metrics::Metrics;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, Mutex};
+
+/// Bounded queue of jobs drained by a fixed pool of workers.
+///
+/// Pushing never waits: when the queue is full the job is dropped and
+/// counted, so a flood costs at most `capacity` queued jobs of memory and
+/// `workers` jobs of work at a time.
+pub struct WorkQueue<T> {
+    sender: mpsc::Sender<T>,
+    metrics: Arc<Metrics>,
+}
+
+impl<T: Send + 'static> WorkQueue<T> {
+    pub fn spawn<F, Fut>(capacity: usize, workers: usize, metrics: Arc<Metrics>, handler: F) -> Self
+    where
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+
+        for _ in 0..workers.max(1) {
+            let receiver = receiver.clone();
+            let handler = handler.clone();
+            let metrics = metrics.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    // Only one idle worker waits on the channel at a time
+                    let job = receiver.lock().await.recv().await;
+                    let Some(job) = job else { break };
+                    metrics.decrement_request_queue_depth();
+                    handler(job).await;
+                }
+            });
+        }
+
+        Self { sender, metrics }
+    }
+
+    /// Queue `job`, or drop it when the queue is full. Returns whether it
+    /// was queued.
+    pub fn try_push(&self, job: T) -> bool {
+        // Count before sending so a fast worker cannot decrement first
+        self.metrics.increment_request_queue_depth();
+        match self.sender.try_send(job) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => {
+                self.metrics.decrement_request_queue_depth();
+                self.metrics.increment_dropped_requests();
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    #[tokio::test]
+    async fn test_workers_drain_queue() {
+        let metrics = Arc::new(Metrics::new());
+        let handled = Arc::new(AtomicUsize::new(0));
+        let counter = handled.clone();
+        let queue = WorkQueue::spawn(16, 4, metrics.clone(), move |n: usize| {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(n, Ordering::Relaxed);
+            }
+        });
+
+        for _ in 0..10 {
+            assert!(queue.try_push(1));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(handled.load(Ordering::Relaxed), 10);
+        assert_eq!(metrics.request_queue_depth.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops_jobs() {
+        let metrics = Arc::new(Metrics::new());
+        let release = Arc::new(Notify::new());
+        let gate = release.clone();
+        let queue = WorkQueue::spawn(2, 1, metrics.clone(), move |_: ()| {
+            let gate = gate.clone();
+            async move { gate.notified().await }
+        });
+
+        // One job occupies the worker, two fill the queue, the rest are dropped
+        assert!(queue.try_push(()));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(queue.try_push(()));
+        assert!(queue.try_push(()));
+        assert!(!queue.try_push(()));
+
+        assert_eq!(metrics.request_queue_depth.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.dropped_requests.load(Ordering::Relaxed), 1);
+        release.notify_waiters();
+    }
+}
\ No newline at end of file
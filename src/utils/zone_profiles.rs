@@ -0,0 +1,250 @@
+use crate::config::{Config, LlmBackendType, RateLimitConfig, ZoneConfig};
+use crate::llm::{GenerationOptions, LlmClient};
+use crate::utils::llm_keys::LlmKeyRing;
+use crate::utils::metrics::Metrics;
+use crate::utils::rate_limiter::RateLimiter;
+use crate::utils::retention::PurgeFilter;
+use crate::utils::secret::SecretString;
+use crate::Error;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use trust_dns_proto::rr::Name;
+
+/// How long answers are cached in zones that do not say otherwise
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// The settings of one `[[zones]]` entry
+pub struct ZoneProfile {
+    /// Zone apex as a lowercase FQDN
+    pub name: String,
+    /// Client for a zone with a backend of its own
+    llm_client: Option<LlmClient>,
+    model: Option<String>,
+    system_prompt: Option<String>,
+    temperature: Option<f32>,
+    rate_limit: Option<(RateLimitConfig, RateLimiter)>,
+    pub cache_ttl: Duration,
+    /// TTL of TXT answers no TTL rule matches
+    pub answer_ttl: Option<u32>,
+}
+
+impl ZoneProfile {
+    fn new(config: &Config, zone: &ZoneConfig, metrics: &Arc<Metrics>, keys: &Arc<LlmKeyRing>) -> Result<Self> {
+        let llm_client = if zone.backend.is_some() || zone.base_url.is_some() || zone.api_key.is_some() {
+            let config = backend_config(config, zone.backend.as_ref(), zone.base_url.as_ref(), zone.api_key.as_ref());
+            Some(backend_client(config, zone.api_key.as_ref(), keys)?.with_metrics(metrics.clone()))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            name: normalize_zone(&zone.name)?,
+            llm_client,
+            model: zone.model.clone(),
+            system_prompt: zone.system_prompt.clone(),
+            temperature: zone.temperature,
+            rate_limit: zone.rate_limit.clone().map(|limits| {
+                let limiter = RateLimiter::new(limits.requests_per_minute, limits.burst_size);
+                (limits, limiter)
+            }),
+            cache_ttl: zone.cache_ttl_seconds.map(Duration::from_secs).unwrap_or(DEFAULT_CACHE_TTL),
+            answer_ttl: zone.answer_ttl_seconds,
+        })
+    }
+
+    /// Fill in the zone's settings where the request has none, such as a
+    /// model chosen by its access token
+    pub fn apply(&self, options: &mut GenerationOptions) {
+        if options.model.is_none() {
+            options.model = self.model.clone();
+        }
+        if options.system_prompt.is_none() {
+            options.system_prompt = self.system_prompt.clone();
+        }
+        if options.temperature.is_none() {
+            options.temperature = self.temperature;
+        }
+    }
+
+    /// The zone's own backend, if it has one
+    pub fn llm_client(&self) -> Option<&LlmClient> {
+        self.llm_client.as_ref()
+    }
+
+    /// Check the zone's own rate limit for `client_addr`, or `None` when the
+    /// zone uses the global one
+    pub async fn allow_request(&self, client_addr: SocketAddr) -> Option<bool> {
+        let (limits, limiter) = self.rate_limit.as_ref()?;
+        Some(!limits.enabled || limiter.allow_request(client_addr).await)
+    }
+}
+
+/// Per-zone settings, looked up by the served zone a question lies under
+#[derive(Default)]
+pub struct ZoneProfiles {
+    profiles: HashMap<String, ZoneProfile>,
+}
+
+impl ZoneProfiles {
+    /// Zones without an API key of their own call their backend with `keys`
+    pub fn new(config: &Config, metrics: Arc<Metrics>, keys: Arc<LlmKeyRing>) -> Result<Self> {
+        let mut profiles = HashMap::with_capacity(config.zones.len());
+        for zone in &config.zones {
+            let profile = ZoneProfile::new(config, zone, &metrics, &keys)?;
+            if profiles.contains_key(&profile.name) {
+                return Err(Error::Configuration(format!("Duplicate zone {}", zone.name)).into());
+            }
+            profiles.insert(profile.name.clone(), profile);
+        }
+        Ok(Self { profiles })
+    }
+
+    /// The profile of `zone`, a served zone as a lowercase FQDN
+    pub fn get(&self, zone: Option<&str>) -> Option<&ZoneProfile> {
+        self.profiles.get(zone?)
+    }
+
+    /// Drop the rate-limit buckets of the zones' own limits that `filter`
+    /// matches
+    pub async fn forget(&self, filter: &PurgeFilter) -> usize {
+        let mut removed = 0;
+        for (_, limiter) in self.profiles.values().filter_map(|profile| profile.rate_limit.as_ref()) {
+            removed += limiter.forget(|client, idle| filter.matches(Some(client.ip()), idle)).await;
+        }
+        removed
+    }
+
+    /// Run the startup checks of the zones' own backends
+    pub async fn prepare_backends(&self) -> Result<()> {
+        for client in self.profiles.values().filter_map(ZoneProfile::llm_client) {
+            client.prepare_backend().await?;
+        }
+        Ok(())
+    }
+}
+
+/// The global configuration with another backend in place of `llm.backend`
+/// and its endpoints, for zones and tenants with a backend of their own
+pub(crate) fn backend_config(
+    config: &Config,
+    backend: Option<&LlmBackendType>,
+    base_url: Option<&String>,
+    api_key: Option<&SecretString>,
+) -> Config {
+    let mut config = config.clone();
+    if let Some(backend) = backend {
+        config.llm.backend = backend.clone();
+        config.llm.endpoints.clear();
+    }
+    if let Some(base_url) = base_url {
+        match config.llm.backend {
+            LlmBackendType::Ollama => config.llm.ollama.host = base_url.clone(),
+            _ => config.llm.base_url = Some(base_url.clone()),
+        }
+    }
+    if api_key.is_some() {
+        config.llm.api_key = api_key.cloned();
+        config.llm.secondary_api_key = None;
+    }
+    config
+}
+
+/// A client for a backend configured by [`backend_config`], calling the
+/// provider with `api_key` when it has a key of its own and with the shared
+/// `keys` otherwise
+pub(crate) fn backend_client(config: Config, api_key: Option<&SecretString>, keys: &Arc<LlmKeyRing>) -> Result<LlmClient> {
+    match api_key {
+        Some(_) => LlmClient::new(config),
+        None => LlmClient::with_keys(config, keys.clone()),
+    }
+}
+
+/// Spell a zone the way `ServedZones` reports it
+pub(crate) fn normalize_zone(zone: &str) -> Result<String> {
+    if zone.is_empty() {
+        return Err(Error::Configuration("Zone without a name in [[zones]]".to_string()).into());
+    }
+    let mut name = Name::from_ascii(zone)
+        .map_err(|e| Error::Configuration(format!("Invalid zone name {}: {}", zone, e)))?
+        .to_lowercase();
+    name.set_fqdn(true);
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RateLimitBackend;
+
+    fn load(zones: Vec<ZoneConfig>) -> Result<ZoneProfiles> {
+        let config = Config {
+            zones,
+            ..Config::default()
+        };
+        ZoneProfiles::new(&config, Arc::new(Metrics::new()), LlmKeyRing::for_config(&config))
+    }
+
+    #[test]
+    fn test_zone_settings() {
+        let profiles = load(vec![
+            ZoneConfig {
+                name: "Ask.Corp.com".to_string(),
+                backend: Some(LlmBackendType::Mock),
+                model: Some("internal".to_string()),
+                system_prompt: Some("Answer for employees.".to_string()),
+                cache_ttl_seconds: Some(3600),
+                ..ZoneConfig::default()
+            },
+            ZoneConfig {
+                name: "q.public.com".to_string(),
+                model: Some("cheap".to_string()),
+                ..ZoneConfig::default()
+            },
+        ])
+        .unwrap();
+
+        let corp = profiles.get(Some("ask.corp.com.")).unwrap();
+        assert!(corp.llm_client().is_some());
+        assert_eq!(corp.cache_ttl, Duration::from_secs(3600));
+
+        let public = profiles.get(Some("q.public.com.")).unwrap();
+        assert!(public.llm_client().is_none());
+        assert_eq!(public.cache_ttl, DEFAULT_CACHE_TTL);
+        assert!(profiles.get(Some("other.com.")).is_none());
+        assert!(profiles.get(None).is_none());
+
+        // Settings chosen by the request win over the zone's
+        let mut options = GenerationOptions {
+            model: Some("from-token".to_string()),
+            ..GenerationOptions::default()
+        };
+        corp.apply(&mut options);
+        assert_eq!(options.model.as_deref(), Some("from-token"));
+        assert_eq!(options.system_prompt.as_deref(), Some("Answer for employees."));
+    }
+
+    #[tokio::test]
+    async fn test_zone_rate_limit() {
+        let limited = ZoneConfig {
+            name: "q.public.com".to_string(),
+            rate_limit: Some(RateLimitConfig {
+                requests_per_minute: 60,
+                burst_size: 1,
+                enabled: true,
+                backend: RateLimitBackend::Memory,
+            }),
+            ..ZoneConfig::default()
+        };
+        let profiles = load(vec![limited.clone(), ZoneConfig::default()]);
+        assert!(profiles.is_err(), "zones need a name");
+
+        let profiles = load(vec![limited]).unwrap();
+        let zone = profiles.get(Some("q.public.com.")).unwrap();
+        let client = "192.0.2.1:5353".parse().unwrap();
+        assert_eq!(zone.allow_request(client).await, Some(true));
+        assert_eq!(zone.allow_request(client).await, Some(false));
+    }
+}
\ No newline at end of file
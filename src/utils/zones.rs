@@ -0,0 +1,229 @@
+use crate::config::AuthorityConfig;
+use crate::Error;
+use anyhow::Result;
+use trust_dns_proto::rr::rdata::{NS, SOA};
+use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+
+/// The DNS zones whose names are treated as LLM questions, together with
+/// the SOA and NS records LLMdig answers for them as their authority
+#[derive(Debug, Clone, Default)]
+pub struct ServedZones {
+    /// Most specific first
+    zones: Vec<Zone>,
+}
+
+#[derive(Debug, Clone)]
+struct Zone {
+    /// Lowercased zone apex
+    apex: Name,
+    soa: Record,
+    ns: Vec<Record>,
+}
+
+impl ServedZones {
+    pub fn new(zones: &[String], authority: &AuthorityConfig) -> Result<Self> {
+        let mut parsed = Vec::with_capacity(zones.len());
+        for zone in zones {
+            let mut apex = parse_name(zone)?.to_lowercase();
+            apex.set_fqdn(true);
+            parsed.push(Zone::new(apex, authority)?);
+        }
+
+        // Nested zones must match the longest suffix first
+        parsed.sort_by_key(|zone| std::cmp::Reverse(zone.apex.num_labels()));
+        Ok(Self { zones: parsed })
+    }
+
+    /// No zones configured: every name is treated as a question
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+
+    /// The served zone containing `name`, if any
+    pub fn find(&self, name: &Name) -> Option<&Name> {
+        self.find_zone(name).map(|zone| &zone.apex)
+    }
+
+    /// The labels of `name` in front of its served zone, or `None` when the
+    /// name lies outside every zone. The apex itself yields no labels.
+    pub fn strip(&self, name: &Name) -> Option<Vec<String>> {
+        let zone = self.find(name)?;
+        let keep = name.iter().count() - zone.iter().count();
+        Some(
+            name.iter()
+                .take(keep)
+                .map(|label| String::from_utf8_lossy(label).into_owned())
+                .collect(),
+        )
+    }
+
+    /// SOA or NS records when `name` is the apex of a served zone
+    pub fn apex_records(&self, name: &Name, record_type: RecordType) -> Option<Vec<Record>> {
+        if !self.is_apex(name) {
+            return None;
+        }
+        let zone = self.find_zone(name)?;
+
+        let records = match record_type {
+            RecordType::SOA => vec![zone.soa.clone()],
+            RecordType::NS => zone.ns.clone(),
+            _ => return None,
+        };
+        Some(
+            records
+                .into_iter()
+                .map(|mut record| {
+                    record.set_name(name.clone());
+                    record
+                })
+                .collect(),
+        )
+    }
+
+    /// The zone's SOA for the authority section of a negative answer
+    /// (RFC 2308 section 3). Resolvers cache the answer for the SOA's TTL,
+    /// so `ttl` is capped at the SOA MINIMUM like they would. The owner is
+    /// spelled as in `name` so it compresses against the question.
+    pub fn negative_soa(&self, name: &Name, ttl: u32) -> Option<Record> {
+        let zone = self.find_zone(name)?;
+        let mut soa = zone.soa.clone();
+        soa.set_name(name.trim_to(zone.apex.num_labels() as usize));
+        if let Some(RData::SOA(rdata)) = soa.data() {
+            soa.set_ttl(ttl.min(rdata.minimum()));
+        }
+        Some(soa)
+    }
+
+    /// Whether `name` is the apex of a served zone
+    pub fn is_apex(&self, name: &Name) -> bool {
+        self.find_zone(name)
+            .is_some_and(|zone| zone.apex.num_labels() == name.num_labels())
+    }
+
+    fn find_zone(&self, name: &Name) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.apex.zone_of(name))
+    }
+}
+
+impl Zone {
+    fn new(apex: Name, authority: &AuthorityConfig) -> Result<Self> {
+        let nameservers = if authority.nameservers.is_empty() {
+            vec![Name::from_ascii("ns")?.append_domain(&apex)?]
+        } else {
+            authority
+                .nameservers
+                .iter()
+                .map(|ns| parse_name(ns))
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        // Accept the contact as an address and store it in mailbox form
+        let hostmaster = match &authority.hostmaster {
+            Some(contact) => parse_name(&contact.replacen('@', ".", 1))?,
+            None => Name::from_ascii("hostmaster")?.append_domain(&apex)?,
+        };
+
+        let soa = Record::from_rdata(
+            apex.clone(),
+            authority.ttl,
+            RData::SOA(SOA::new(
+                nameservers[0].clone(),
+                hostmaster,
+                authority.serial,
+                authority.refresh,
+                authority.retry,
+                authority.expire,
+                authority.minimum,
+            )),
+        );
+        let ns = nameservers
+            .into_iter()
+            .map(|ns| Record::from_rdata(apex.clone(), authority.ttl, RData::NS(NS(ns))))
+            .collect();
+
+        Ok(Self {
+            apex,
+            soa,
+            ns,
+        })
+    }
+}
+
+fn parse_name(name: &str) -> Result<Name> {
+    let mut parsed =
+        Name::from_ascii(name).map_err(|e| Error::Configuration(format!("Invalid zone name {}: {}", name, e)))?;
+    parsed.set_fqdn(true);
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn zones(zones: &[&str]) -> ServedZones {
+        let zones: Vec<String> = zones.iter().map(|z| z.to_string()).collect();
+        ServedZones::new(&zones, &AuthorityConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn test_strip_zone_suffix() {
+        let zones = zones(&["q.example.com"]);
+
+        let name = Name::from_ascii("What.Is.Rust.Q.Example.com.").unwrap();
+        assert_eq!(zones.strip(&name).unwrap(), vec!["What", "Is", "Rust"]);
+
+        let apex = Name::from_str("q.example.com").unwrap();
+        assert!(zones.strip(&apex).unwrap().is_empty());
+
+        let outside = Name::from_str("www.example.com").unwrap();
+        assert!(zones.strip(&outside).is_none());
+    }
+
+    #[test]
+    fn test_most_specific_zone_wins() {
+        let zones = zones(&["example.com", "ai.example.com"]);
+        let name = Name::from_str("hello.ai.example.com").unwrap();
+
+        assert_eq!(zones.find(&name).unwrap(), &Name::from_str("ai.example.com.").unwrap());
+        assert_eq!(zones.strip(&name).unwrap(), vec!["hello"]);
+    }
+
+    #[test]
+    fn test_invalid_zone() {
+        assert!(ServedZones::new(&["bad..zone".to_string()], &AuthorityConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_apex_records() {
+        let zones = zones(&["q.example.com"]);
+        let apex = Name::from_ascii("Q.example.com.").unwrap();
+
+        let soa = zones.apex_records(&apex, RecordType::SOA).unwrap();
+        assert_eq!(soa[0].name(), &apex);
+        match soa[0].data() {
+            Some(RData::SOA(soa)) => {
+                assert_eq!(soa.mname(), &Name::from_ascii("ns.q.example.com.").unwrap());
+                assert_eq!(soa.rname(), &Name::from_ascii("hostmaster.q.example.com.").unwrap());
+            }
+            other => panic!("expected SOA, got {:?}", other),
+        }
+
+        assert_eq!(zones.apex_records(&apex, RecordType::NS).unwrap().len(), 1);
+        assert!(zones.apex_records(&apex, RecordType::A).is_none());
+
+        let below = Name::from_ascii("what.q.example.com.").unwrap();
+        assert!(zones.apex_records(&below, RecordType::SOA).is_none());
+
+        assert!(zones.is_apex(&apex));
+        assert!(!zones.is_apex(&below));
+
+        // Negative TTLs never exceed the SOA MINIMUM
+        assert_eq!(zones.negative_soa(&below, 60).unwrap().ttl(), 60);
+
+        // The owner keeps the question's spelling so it can be compressed
+        let mixed = Name::from_ascii("What.Q.Example.com.").unwrap();
+        assert_eq!(zones.negative_soa(&mixed, 60).unwrap().name().to_string(), "Q.Example.com.");
+        assert_eq!(zones.negative_soa(&below, 86400).unwrap().ttl(), 300);
+    }
+}
\ No newline at end of file
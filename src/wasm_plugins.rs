@@ -0,0 +1,318 @@
+//! Question and answer hooks in WebAssembly modules.
+//!
+//! A plugin is a core module without imports that exports `memory`,
+//! `alloc(len: i32) -> i32` and one or both hooks:
+//!
+//! - `on_question(ptr: i32, len: i32) -> i64` sees each question before the caches
+//! - `on_answer(ptr: i32, len: i32) -> i64` sees each answer fresh from the backend,
+//!   before it is cut to fit TXT records
+//!
+//! A hook gets UTF-8 text written to memory `alloc` returned, and returns -1 to
+//! leave it alone, -2 (`on_question` only) to refuse the question, or where the
+//! replacement text lies, packed as `ptr << 32 | len`. Every call runs in a fresh
+//! instance with limited fuel and memory.
+
+use crate::config::PluginsConfig;
+use crate::dns::Answer;
+use crate::middleware::{Exchange, Middleware, Next, Pipeline, Stage};
+use crate::Error;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::{debug, warn};
+use trust_dns_proto::op::ResponseCode;
+use wasmtime::{Engine, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+const VETO: i64 = -2;
+
+/// What a hook returned
+#[derive(Debug, PartialEq)]
+enum Output {
+    Code(i64),
+    Text(String),
+}
+
+/// What the question hooks decided
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Keep,
+    Rewrite(String),
+    Veto,
+}
+
+struct Plugin {
+    name: String,
+    instance: InstancePre<StoreLimits>,
+    on_question: bool,
+    on_answer: bool,
+}
+
+/// The configured plugins, sharing one engine
+pub struct WasmPlugins {
+    engine: Engine,
+    plugins: Vec<Plugin>,
+    fuel: u64,
+    max_memory: usize,
+    fail_closed: bool,
+}
+
+impl WasmPlugins {
+    pub fn new(config: &PluginsConfig) -> Result<Self> {
+        let mut modules = Vec::with_capacity(config.wasm.len());
+        for path in &config.wasm {
+            let bytes = std::fs::read(path)
+                .map_err(|e| Error::Configuration(format!("Could not read WASM plugin {}: {}", path, e)))?;
+            modules.push((path.clone(), bytes));
+        }
+        Self::from_modules(config, modules)
+    }
+
+    /// Compile `(name, module)` pairs, in binary or text format
+    fn from_modules(config: &PluginsConfig, modules: Vec<(String, Vec<u8>)>) -> Result<Self> {
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config)?;
+        // Nothing is linked in, so plugins cannot reach the host beyond their arguments
+        let linker = Linker::new(&engine);
+
+        let mut plugins = Vec::with_capacity(modules.len());
+        for (name, bytes) in modules {
+            let invalid = |reason: String| Error::Configuration(format!("Invalid WASM plugin {}: {}", name, reason));
+            let module = Module::new(&engine, &bytes).map_err(|e| invalid(e.to_string()))?;
+            for export in ["memory", "alloc"] {
+                if module.get_export(export).is_none() {
+                    return Err(invalid(format!("missing export {:?}", export)).into());
+                }
+            }
+            let on_question = module.get_export("on_question").is_some();
+            let on_answer = module.get_export("on_answer").is_some();
+            if !on_question && !on_answer {
+                return Err(invalid("exports neither on_question nor on_answer".to_string()).into());
+            }
+            let instance = linker.instantiate_pre(&module).map_err(|e| invalid(e.to_string()))?;
+            plugins.push(Plugin {
+                name,
+                instance,
+                on_question,
+                on_answer,
+            });
+        }
+
+        Ok(Self {
+            engine,
+            plugins,
+            fuel: config.fuel,
+            max_memory: config.max_memory_mb.saturating_mul(1024 * 1024),
+            fail_closed: config.fail_closed,
+        })
+    }
+
+    /// Slot the hooks into `pipeline`: questions after sanitizing, fresh
+    /// answers straight after the backend
+    pub fn install(self, pipeline: &mut Pipeline) {
+        let plugins = Arc::new(self);
+        pipeline.insert_after(Stage::Sanitize, Arc::new(QuestionHooks(plugins.clone())));
+        pipeline.insert_after(Stage::Llm, Arc::new(AnswerHooks(plugins)));
+    }
+
+    /// Run the question hooks in order, each seeing the previous rewrite,
+    /// until one refuses the question
+    fn check_question(&self, question: &str) -> Result<Verdict> {
+        let mut verdict = Verdict::Keep;
+        for plugin in self.plugins.iter().filter(|plugin| plugin.on_question) {
+            let current = match &verdict {
+                Verdict::Rewrite(text) => text.as_str(),
+                _ => question,
+            };
+            match self.call_hook(plugin, "on_question", current)? {
+                Some(Output::Code(VETO)) => {
+                    debug!("WASM plugin {} refused a question", plugin.name);
+                    return Ok(Verdict::Veto);
+                }
+                Some(Output::Text(text)) => verdict = Verdict::Rewrite(text),
+                Some(Output::Code(_)) | None => {}
+            }
+        }
+        Ok(verdict)
+    }
+
+    /// Run the answer hooks in order, each seeing the previous one's output
+    fn transform_answer(&self, answer: &str) -> Result<Option<String>> {
+        let mut transformed = None;
+        for plugin in self.plugins.iter().filter(|plugin| plugin.on_answer) {
+            let current = transformed.as_deref().unwrap_or(answer);
+            if let Some(Output::Text(text)) = self.call_hook(plugin, "on_answer", current)? {
+                transformed = Some(text);
+            }
+        }
+        Ok(transformed)
+    }
+
+    /// Call a hook, skipping a failing plugin unless failures are fatal
+    fn call_hook(&self, plugin: &Plugin, hook: &str, input: &str) -> Result<Option<Output>> {
+        match self.call(plugin, hook, input) {
+            Ok(output) => Ok(Some(output)),
+            Err(e) if self.fail_closed => Err(e.context(format!("WASM plugin {} failed in {}", plugin.name, hook))),
+            Err(e) => {
+                warn!("WASM plugin {} failed in {}, skipping it: {:#}", plugin.name, hook, e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn call(&self, plugin: &Plugin, hook: &str, input: &str) -> Result<Output> {
+        let limits = StoreLimitsBuilder::new().memory_size(self.max_memory).instances(1).build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(self.fuel)?;
+
+        let instance = plugin.instance.instantiate(&mut store)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| anyhow!("export \"memory\" is not a memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let hook = instance.get_typed_func::<(i32, i32), i64>(&mut store, hook)?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, input.as_bytes())?;
+
+        let result = hook.call(&mut store, (ptr, len))?;
+        if result < 0 {
+            return Ok(Output::Code(result));
+        }
+        let (ptr, len) = ((result >> 32) as u32 as usize, result as u32 as usize);
+        if ptr.saturating_add(len) > memory.data_size(&store) {
+            return Err(anyhow!("returned text lies outside memory"));
+        }
+        let mut output = vec![0; len];
+        memory.read(&store, ptr, &mut output)?;
+        Ok(Output::Text(String::from_utf8(output)?))
+    }
+}
+
+/// Rewrites or refuses questions before the caches see them
+struct QuestionHooks(Arc<WasmPlugins>);
+
+#[async_trait]
+impl Middleware for QuestionHooks {
+    async fn handle(&self, exchange: &mut Exchange<'_>, next: Next<'_>) -> Result<()> {
+        for question in exchange.questions.iter_mut().filter(|question| question.is_pending()) {
+            let Some(text) = question.text.as_deref() else {
+                continue;
+            };
+            match self.0.check_question(text) {
+                Ok(Verdict::Keep) => {}
+                Ok(Verdict::Rewrite(text)) => question.text = Some(text),
+                Ok(Verdict::Veto) => question.answer = Some(Answer::Error(ResponseCode::Refused)),
+                Err(e) => {
+                    warn!("{:#}", e);
+                    question.answer = Some(Answer::Error(ResponseCode::ServFail));
+                }
+            }
+        }
+        next.run(exchange).await
+    }
+}
+
+/// Transforms fresh answers, so the caches keep the transformed text
+struct AnswerHooks(Arc<WasmPlugins>);
+
+#[async_trait]
+impl Middleware for AnswerHooks {
+    async fn handle(&self, exchange: &mut Exchange<'_>, next: Next<'_>) -> Result<()> {
+        for question in exchange.questions.iter_mut().filter(|question| question.fresh) {
+            let Some(Answer::Txt(text)) = &mut question.answer else {
+                continue;
+            };
+            match self.0.transform_answer(text) {
+                Ok(Some(transformed)) => *text = transformed,
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("{:#}", e);
+                    question.answer = Some(Answer::Error(ResponseCode::ServFail));
+                    question.fresh = false;
+                }
+            }
+        }
+        next.run(exchange).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Refuses questions starting with `x`, replaces those starting with `r`
+    /// and every answer with "redacted"
+    const REDACTOR: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "redacted")
+          (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "on_question") (param $ptr i32) (param $len i32) (result i64)
+            (if (result i64) (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 120))
+              (then (i64.const -2))
+              (else
+                (if (result i64) (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 114))
+                  (then (i64.const 8))
+                  (else (i64.const -1))))))
+          (func (export "on_answer") (param i32 i32) (result i64) (i64.const 8)))
+    "#;
+
+    const SPINNER: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "on_question") (param i32 i32) (result i64)
+            (loop $spin (br $spin))
+            (i64.const -2)))
+    "#;
+
+    /// Asks for 64 MiB up front
+    const HOG: &str = r#"
+        (module
+          (memory (export "memory") 1024)
+          (func (export "alloc") (param i32) (result i32) (i32.const 0))
+          (func (export "on_question") (param i32 i32) (result i64) (i64.const -2)))
+    "#;
+
+    fn load(config: &PluginsConfig, modules: &[&str]) -> WasmPlugins {
+        let modules = modules
+            .iter()
+            .enumerate()
+            .map(|(i, wat)| (format!("plugin{}", i), wat.as_bytes().to_vec()))
+            .collect();
+        WasmPlugins::from_modules(config, modules).unwrap()
+    }
+
+    #[test]
+    fn test_hooks() {
+        let plugins = load(&PluginsConfig::default(), &[REDACTOR]);
+        assert_eq!(plugins.check_question("what is rust").unwrap(), Verdict::Keep);
+        assert_eq!(plugins.check_question("xyz").unwrap(), Verdict::Veto);
+        assert_eq!(
+            plugins.check_question("rust").unwrap(),
+            Verdict::Rewrite("redacted".to_string())
+        );
+        assert_eq!(plugins.transform_answer("secret").unwrap().as_deref(), Some("redacted"));
+
+        let missing_hooks = r#"(module (memory (export "memory") 1) (func (export "alloc") (param i32) (result i32) (i32.const 0)))"#;
+        let modules = vec![("empty".to_string(), missing_hooks.as_bytes().to_vec())];
+        assert!(WasmPlugins::from_modules(&PluginsConfig::default(), modules).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        // Failing plugins are skipped unless failures are fatal
+        let plugins = load(&PluginsConfig::default(), &[SPINNER, HOG]);
+        assert_eq!(plugins.check_question("what is rust").unwrap(), Verdict::Keep);
+
+        let config = PluginsConfig {
+            fail_closed: true,
+            ..PluginsConfig::default()
+        };
+        assert!(load(&config, &[SPINNER]).check_question("what is rust").is_err());
+        assert!(load(&config, &[HOG]).check_question("what is rust").is_err());
+    }
+}
\ No newline at end of file
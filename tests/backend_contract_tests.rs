@@ -0,0 +1,512 @@
+//! Contract tests for the HTTP LLM backends against a mock server: request
+//! shapes, error statuses, malformed bodies and timeouts.
+
+use llmdig::config::{Config, LlmBackendType, LlmEndpointConfig, OverflowPolicy, ToolKind};
+use llmdig::utils::llm_keys::LlmKeyRing;
+use llmdig::{Error, LlmClient};
+use llmdig::llm::{
+    http_client, CustomBackend, GenerationOptions, LlmBackend, OllamaBackend, OpenAiBackend, TokenUsage,
+};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use wiremock::matchers::{body_partial_json, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn config() -> Config {
+    let mut config = Config::default();
+    config.llm.api_key = Some("sk-test".into());
+    config.llm.timeout_seconds = 1;
+    config
+}
+
+fn client() -> reqwest::Client {
+    http_client(&config()).unwrap()
+}
+
+fn keys() -> Arc<LlmKeyRing> {
+    LlmKeyRing::for_config(&config())
+}
+
+#[tokio::test]
+async fn test_openai_request_shape() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("Authorization", "Bearer sk-test"))
+        .and(body_partial_json(json!({
+            "model": "gpt-3.5-turbo",
+            "messages": [{ "role": "user", "content": "what is dns" }],
+            "max_tokens": 256,
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "role": "assistant", "content": "The Domain Name System." } }]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let backend = OpenAiBackend::with_base_url(config(), client(), &server.uri(), keys()).unwrap();
+    let response = backend.generate_response("what is dns").await.unwrap();
+    assert_eq!(response, "The Domain Name System.");
+}
+
+#[tokio::test]
+async fn test_openai_error_statuses() {
+    for status in [429, 500] {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(status).set_body_string("try again later"))
+            .mount(&server)
+            .await;
+
+        let backend = OpenAiBackend::with_base_url(config(), client(), &server.uri(), keys()).unwrap();
+        let error = backend.generate_response("hello").await.unwrap_err();
+        assert!(error.to_string().contains(&status.to_string()), "{}", error);
+    }
+}
+
+#[tokio::test]
+async fn test_openai_key_failover() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(header("Authorization", "Bearer sk-failover-revoked"))
+        .respond_with(ResponseTemplate::new(401).set_body_string("invalid api key"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(header("Authorization", "Bearer sk-failover-current"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "role": "assistant", "content": "Answered with the secondary key." } }]
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let mut config = config();
+    config.llm.api_key = Some("sk-failover-revoked".into());
+    config.llm.secondary_api_key = Some("sk-failover-current".into());
+    let keys = LlmKeyRing::for_config(&config);
+    let backend = OpenAiBackend::with_base_url(config, client(), &server.uri(), keys).unwrap();
+    assert_eq!(backend.generate_response("hello").await.unwrap(), "Answered with the secondary key.");
+    // Once refused, the primary is not tried again
+    assert_eq!(backend.generate_response("hello").await.unwrap(), "Answered with the secondary key.");
+}
+
+#[tokio::test]
+async fn test_openai_malformed_json() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{\"choices\": ["))
+        .mount(&server)
+        .await;
+
+    let backend = OpenAiBackend::with_base_url(config(), client(), &server.uri(), keys()).unwrap();
+    assert!(backend.generate_response("hello").await.is_err());
+}
+
+#[tokio::test]
+async fn test_openai_timeout() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(3)))
+        .mount(&server)
+        .await;
+
+    let backend = OpenAiBackend::with_base_url(config(), client(), &server.uri(), keys()).unwrap();
+    let started = std::time::Instant::now();
+    assert!(backend.generate_response("hello").await.is_err());
+    assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[tokio::test]
+async fn test_openai_compatible_base_url() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/openai/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "role": "assistant", "content": "Served by a compatible provider." } }]
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    // A self-hosted provider needs no API key, and the full endpoint URL is accepted too
+    for base_url in ["/openai/v1", "/openai/v1/chat/completions/"] {
+        let mut config = config();
+        config.llm.api_key = None;
+        config.llm.base_url = Some(format!("{}{}", server.uri(), base_url));
+
+        let keys = LlmKeyRing::for_config(&config);
+        let backend = OpenAiBackend::new(config, client(), keys).unwrap();
+        let response = backend.generate_response("hello").await.unwrap();
+        assert_eq!(response, "Served by a compatible provider.");
+    }
+
+    let mut config = config();
+    config.llm.base_url = Some("not a url".to_string());
+    assert!(OpenAiBackend::new(config, client(), keys()).is_err());
+}
+
+#[tokio::test]
+async fn test_openai_system_prompt() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(body_partial_json(json!({
+            "messages": [
+                { "role": "system", "content": "Answer like a pirate." },
+                { "role": "user", "content": "what is dns" },
+            ],
+            "temperature": 1.5,
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "role": "assistant", "content": "Arr, names to addresses." } }]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = GenerationOptions {
+        system_prompt: Some("Answer like a pirate.".to_string()),
+        temperature: Some(1.5),
+        ..Default::default()
+    };
+    let backend = OpenAiBackend::with_base_url(config(), client(), &server.uri(), keys()).unwrap();
+    let response = backend.generate_with_options("what is dns", &options).await.unwrap();
+    assert_eq!(response, "Arr, names to addresses.");
+}
+
+#[tokio::test]
+async fn test_deterministic_mode() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(body_partial_json(json!({ "temperature": 0.0, "seed": 42 })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "role": "assistant", "content": "Always the same." } }]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut config = config();
+    config.llm.base_url = Some(server.uri());
+    config.llm.deterministic = true;
+    config.llm.seed = 42;
+    // The temperature asked for is overridden
+    let options = GenerationOptions {
+        temperature: Some(1.5),
+        ..Default::default()
+    };
+    let client = LlmClient::new(config).unwrap();
+    let generation = client.query_detailed("what is dns", &options).await.unwrap();
+    assert_eq!(generation.text, "Always the same.");
+}
+
+#[tokio::test]
+async fn test_openai_tool_calls() {
+    let server = MockServer::start().await;
+    // Second round: the tool output is sent back and the model answers
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(body_partial_json(json!({
+            "messages": [
+                { "role": "user" },
+                { "role": "assistant" },
+                { "role": "tool", "tool_call_id": "call_1", "content": "5 km = 3.106856 mi" },
+            ],
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "role": "assistant", "content": "About 3.1 miles." } }],
+            "usage": { "prompt_tokens": 70, "completion_tokens": 5, "total_tokens": 75 }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    // First round: the model asks for a conversion
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(body_partial_json(json!({
+            "tools": [{ "type": "function", "function": { "name": "unit_conversion" } }],
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": { "name": "unit_conversion", "arguments": "{\"value\": 5, \"from\": \"km\", \"to\": \"mi\"}" }
+                }]
+            } }],
+            "usage": { "prompt_tokens": 50, "completion_tokens": 10, "total_tokens": 60 }
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut config = config();
+    config.llm.base_url = Some(server.uri());
+    config.llm.tools.enabled = true;
+    config.llm.tools.allowed = vec![ToolKind::UnitConversion];
+    let client = LlmClient::new(config).unwrap();
+    let generation = client
+        .query_detailed("how far is 5 km in miles", &GenerationOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(generation.text, "About 3.1 miles.");
+    // Both rounds are counted
+    assert_eq!(generation.usage.map(|usage| usage.total_tokens()), Some(135));
+}
+
+#[tokio::test]
+async fn test_ollama_request_shape() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/generate"))
+        .and(body_partial_json(json!({
+            "model": "gpt-3.5-turbo",
+            "prompt": "what is dns",
+            "stream": false,
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "response": "Names to addresses." })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let backend = OllamaBackend::with_base_url(config(), client(), &server.uri()).unwrap();
+    assert_eq!(backend.generate_response("what is dns").await.unwrap(), "Names to addresses.");
+}
+
+#[tokio::test]
+async fn test_ollama_usage() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "response": "Names to addresses.",
+            "prompt_eval_count": 12,
+            "eval_count": 4,
+        })))
+        .mount(&server)
+        .await;
+
+    let backend = OllamaBackend::with_base_url(config(), client(), &server.uri()).unwrap();
+    let generation = backend
+        .generate_with_usage("what is dns", &GenerationOptions::default(), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        generation.usage,
+        Some(TokenUsage {
+            prompt_tokens: 12,
+            completion_tokens: 4
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_ollama_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/generate"))
+        .respond_with(ResponseTemplate::new(500).set_body_string("model not loaded"))
+        .mount(&server)
+        .await;
+
+    let backend = OllamaBackend::with_base_url(config(), client(), &server.uri()).unwrap();
+    let error = backend.generate_response("hello").await.unwrap_err();
+    assert!(error.to_string().contains("model not loaded"), "{}", error);
+}
+
+fn ollama_config(server: &MockServer, pull: bool) -> Config {
+    let mut config = config();
+    config.llm.backend = LlmBackendType::Ollama;
+    config.llm.model = "llama3".to_string();
+    // Bare host:port, as OLLAMA_HOST is usually written
+    config.llm.ollama.host = server.address().to_string();
+    config.llm.ollama.pull = pull;
+    config
+}
+
+async fn mount_tags(server: &MockServer, models: &[&str]) {
+    let models: Vec<_> = models.iter().map(|name| json!({ "name": name })).collect();
+    Mock::given(method("GET"))
+        .and(path("/api/tags"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "models": models })))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_ollama_model_check() {
+    let server = MockServer::start().await;
+    mount_tags(&server, &["llama3:latest", "mistral:7b"]).await;
+
+    let backend = OllamaBackend::new(ollama_config(&server, false), client()).unwrap();
+    backend.prepare().await.unwrap();
+
+    let mut config = ollama_config(&server, false);
+    config.llm.model = "mistral".to_string();
+    let error = OllamaBackend::new(config, client()).unwrap().prepare().await.unwrap_err();
+    assert!(matches!(error.downcast_ref::<Error>(), Some(Error::Configuration(_))));
+    assert!(error.to_string().contains("ollama pull mistral"));
+}
+
+#[tokio::test]
+async fn test_ollama_pulls_missing_model() {
+    let server = MockServer::start().await;
+    mount_tags(&server, &[]).await;
+    Mock::given(method("POST"))
+        .and(path("/api/pull"))
+        .and(body_partial_json(json!({ "model": "llama3", "stream": false })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "status": "success" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let backend = OllamaBackend::new(ollama_config(&server, true), client()).unwrap();
+    backend.prepare().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_endpoint_pool_skips_failing_endpoint() {
+    let failing = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&failing)
+        .await;
+
+    let healthy = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "role": "assistant", "content": "ok" } }]
+        })))
+        .expect(3)
+        .mount(&healthy)
+        .await;
+
+    let mut config = config();
+    config.llm.endpoints = [&failing, &healthy]
+        .iter()
+        .map(|server| LlmEndpointConfig {
+            url: server.uri(),
+            ..Default::default()
+        })
+        .collect();
+    config.llm.balancing.failure_threshold = 1;
+    let client = LlmClient::new(config).unwrap();
+
+    // The first request lands on the failing endpoint, which then sits out
+    assert!(client.query("hello").await.is_err());
+    for _ in 0..3 {
+        assert_eq!(client.query("hello").await.unwrap(), "ok");
+    }
+}
+
+#[tokio::test]
+async fn test_concurrency_limit_sheds_excess() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_delay(Duration::from_millis(300))
+                .set_body_json(json!({
+                    "choices": [{ "message": { "role": "assistant", "content": "ok" } }]
+                })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut config = config();
+    config.llm.base_url = Some(server.uri());
+    config.llm.max_concurrent_requests = 1;
+    config.llm.overflow.policy = OverflowPolicy::Shed;
+    let client = LlmClient::new(config).unwrap();
+
+    let (first, second) = tokio::join!(client.query("one"), async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.query("two").await
+    });
+    assert_eq!(first.unwrap(), "ok");
+    let error = second.unwrap_err();
+    assert!(matches!(error.downcast_ref::<Error>(), Some(Error::Overloaded(_))));
+}
+
+#[tokio::test]
+async fn test_requests_go_through_proxy() {
+    // A plain HTTP proxy receives the request for the real host and relays it
+    let proxy = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("Host", "llm.internal.example"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "message": { "role": "assistant", "content": "via proxy" } }]
+        })))
+        .expect(1)
+        .mount(&proxy)
+        .await;
+
+    let mut config = config();
+    config.llm.base_url = Some("http://llm.internal.example".to_string());
+    config.llm.proxy_url = Some(proxy.uri());
+    let backend = OpenAiBackend::new(config.clone(), http_client(&config).unwrap(), LlmKeyRing::for_config(&config)).unwrap();
+    assert_eq!(backend.generate_response("hello").await.unwrap(), "via proxy");
+}
+
+#[test]
+fn test_ca_bundle() {
+    let mut config = config();
+    config.llm.ca_bundle_path = Some("tests/fixtures/test_ca_bundle.pem".to_string());
+    assert!(http_client(&config).is_ok());
+
+    config.llm.ca_bundle_path = Some("tests/fixtures/mock_responses.yaml".to_string());
+    assert!(http_client(&config).is_err());
+
+    config.llm.ca_bundle_path = Some("tests/fixtures/missing.pem".to_string());
+    assert!(http_client(&config).is_err());
+}
+
+#[tokio::test]
+async fn test_custom_request_shape() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/generate"))
+        .and(body_partial_json(json!({
+            "prompt": "what is dns",
+            "model": "gpt-3.5-turbo",
+            "max_tokens": 256,
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "response": "A phone book." })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let url = format!("{}/generate", server.uri());
+    let backend = CustomBackend::new(config(), client(), url).unwrap();
+    assert_eq!(backend.generate_response("what is dns").await.unwrap(), "A phone book.");
+}
+
+#[tokio::test]
+async fn test_custom_malformed_json() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/generate"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "text": "wrong field" })))
+        .mount(&server)
+        .await;
+
+    let url = format!("{}/generate", server.uri());
+    let backend = CustomBackend::new(config(), client(), url).unwrap();
+    assert!(backend.generate_response("hello").await.is_err());
+}
\ No newline at end of file
@@ -0,0 +1,55 @@
+use llmdig::dry_run::question_name;
+use llmdig::utils::sanitizer::Sanitizer;
+use proptest::prelude::*;
+
+/// A word as the dns-client tool sends it: lowercase letters and digits,
+/// some of them outside ASCII
+fn word() -> impl Strategy<Value = String> {
+    "[a-z0-9äöüçğışéñ]{1,12}"
+}
+
+/// A label of a hand-written query name, which may be empty or start or
+/// end with a hyphen
+fn label() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9_-]{0,12}"
+}
+
+proptest! {
+    #[test]
+    fn test_sanitized_questions_are_safe(question in "\\PC{0,300}") {
+        // A question is rejected, or what is left of it after sanitizing is safe too
+        prop_assert!(!Sanitizer::is_safe(&question) || Sanitizer::is_safe(&Sanitizer::sanitize_query(&question)));
+    }
+
+    #[test]
+    fn test_sanitizing_is_idempotent(question in "\\PC{0,300}") {
+        let sanitized = Sanitizer::sanitize_query(&question);
+        prop_assert!(sanitized.chars().count() <= 200);
+        prop_assert_eq!(Sanitizer::sanitize_query(&sanitized), sanitized);
+    }
+
+    #[test]
+    fn test_extraction_never_panics(domain in "\\PC{0,100}") {
+        let _ = Sanitizer::extract_question_from_domain(&domain);
+        for label in domain.split('.') {
+            let _ = Sanitizer::decode_label(label);
+        }
+    }
+
+    #[test]
+    fn test_extracted_questions_are_clean(labels in prop::collection::vec(label(), 2..8)) {
+        // Consecutive dots and hyphens at label edges leave no empty words
+        if let Some(question) = Sanitizer::extract_question_from_domain(&labels.join(".")) {
+            prop_assert!(Sanitizer::is_safe(&question));
+            prop_assert!(question.split(' ').all(|word| !word.is_empty()), "{:?}", question);
+        }
+    }
+
+    #[test]
+    fn test_encoded_questions_round_trip(words in prop::collection::vec(word(), 1..8)) {
+        let question = words.join(" ");
+        prop_assume!(Sanitizer::is_safe(&question));
+        let name = question_name(&question, Some("com")).unwrap();
+        prop_assert_eq!(Sanitizer::extract_question_from_domain(&name.to_ascii()), Some(question));
+    }
+}
@@ -1,10 +1,21 @@
-use clap::{Parser, Subcommand};
-use std::net::SocketAddr;
-use std::str::FromStr;
-use tokio::net::UdpSocket;
-use trust_dns_proto::op::{Message, MessageType, OpCode, ResponseCode};
-use trust_dns_proto::rr::{DNSClass, Name, RecordType};
-use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable};
+use clap::{Parser, Subcommand, ValueEnum};
+use data_encoding::BASE32_NOPAD;
+use idna::punycode;
+use stats::LatencyStats;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::task::JoinSet;
+use transport::{Resolver, TlsOptions, Transport};
+use trust_dns_proto::op::{Message, ResponseCode};
+use trust_dns_proto::rr::RData;
+
+mod stats;
+mod transport;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -13,7 +24,7 @@ struct Args {
     command: Commands,
     
     /// DNS server host
-    #[arg(short, long, default_value = "127.0.0.1")]
+    #[arg(short = 'H', long, default_value = "127.0.0.1")]
     host: String,
     
     /// DNS server port
@@ -23,6 +34,27 @@ struct Args {
     /// Timeout in seconds
     #[arg(short, long, default_value = "10")]
     timeout: u64,
+
+    /// How queries reach the server
+    #[arg(long, value_enum, default_value_t = Transport::Udp)]
+    transport: Transport,
+
+    /// Name the server certificate is checked against for `dot` and `doh`
+    /// [default: the host]
+    #[arg(long)]
+    tls_name: Option<String>,
+
+    /// PEM file with an extra trusted root certificate
+    #[arg(long)]
+    ca_cert: Option<PathBuf>,
+
+    /// Accept invalid or self-signed certificates
+    #[arg(long)]
+    insecure: bool,
+
+    /// URL path of the DNS over HTTPS endpoint
+    #[arg(long, default_value = "/dns-query")]
+    doh_path: String,
 }
 
 #[derive(Subcommand)]
@@ -54,6 +86,32 @@ enum Commands {
     /// Health check
     Health,
     
+    /// Ask a question and print the answer
+    Ask {
+        /// The question, quoted or as separate words
+        #[arg(required = true)]
+        question: Vec<String>,
+
+        /// Zone the question is asked under, e.g. `q.example.com`
+        #[arg(short, long, default_value = "com")]
+        zone: String,
+
+        /// How the question is spelled in the query name
+        #[arg(short, long, value_enum, default_value_t = Encoding::Dots)]
+        encoding: Encoding,
+    },
+
+    /// Ask questions interactively, one per line
+    Repl {
+        /// Zone the questions are asked under, e.g. `q.example.com`
+        #[arg(short, long, default_value = "com")]
+        zone: String,
+
+        /// How questions are spelled in the query name
+        #[arg(short, long, value_enum, default_value_t = Encoding::Dots)]
+        encoding: Encoding,
+    },
+
     /// Performance test
     Perf {
         /// Number of requests
@@ -64,27 +122,92 @@ enum Commands {
         #[arg(short, long, default_value = "10")]
         concurrent: usize,
     },
+
+    /// Load test at a fixed query rate, with latency percentiles
+    Load {
+        /// Queries sent per second, whether or not earlier ones were answered
+        #[arg(short, long, default_value = "50")]
+        qps: u32,
+
+        /// Seconds to measure for
+        #[arg(short, long, default_value = "30")]
+        duration: u64,
+
+        /// Seconds to send at the target rate before measuring
+        #[arg(short, long, default_value = "5")]
+        warmup: u64,
+
+        /// File with domains to query, one per line [default: built-in questions]
+        #[arg(long)]
+        domains: Option<String>,
+
+        /// Write every measured query to this CSV file
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+}
+
+/// Questions used by the performance and load tests
+const TEST_DOMAINS: [&str; 5] = [
+    "what.is.the.weather.com",
+    "how.many.stars.are.there.com",
+    "what.is.the.capital.of.france.com",
+    "hello.world.com",
+    "test.query.com",
+];
+
+/// How a question is spelled as a query name
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Encoding {
+    /// One label per word: `what.is.rust`
+    Dots,
+    /// Words joined by hyphens: `what-is-rust`
+    Hyphens,
+    /// The exact question, base32-encoded after a `b32` label. Keeps the
+    /// punctuation and case the other encodings drop.
+    Base32,
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<(), BoxError> {
     let args = Args::parse();
     
-    let server_addr = format!("{}:{}", args.host, args.port);
-    let socket_addr = SocketAddr::from_str(&server_addr)?;
+    let tls = TlsOptions {
+        insecure: args.insecure,
+        ca_cert: args.ca_cert,
+        name: args.tls_name,
+    };
+    let resolver = Resolver::new(
+        &args.host,
+        args.port,
+        args.transport,
+        &tls,
+        &args.doh_path,
+        Duration::from_secs(args.timeout),
+    )
+    .await?;
     
     match args.command {
         Commands::Query { domain, record_type } => {
-            query_domain(&socket_addr, &domain, &record_type, args.timeout).await?;
+            query_domain(&resolver, &domain, &record_type).await?;
         }
         Commands::Batch { file, record_type, concurrent } => {
-            batch_query(&socket_addr, &file, &record_type, concurrent, args.timeout).await?;
+            batch_query(&resolver, &file, &record_type, concurrent).await?;
         }
         Commands::Health => {
-            health_check(&socket_addr, args.timeout).await?;
+            health_check(&resolver).await?;
+        }
+        Commands::Ask { question, zone, encoding } => {
+            ask(&resolver, &question.join(" "), &zone, encoding).await?;
+        }
+        Commands::Repl { zone, encoding } => {
+            repl(&resolver, &zone, encoding).await?;
         }
         Commands::Perf { requests, concurrent } => {
-            performance_test(&socket_addr, requests, concurrent, args.timeout).await?;
+            performance_test(&resolver, requests, concurrent).await?;
+        }
+        Commands::Load { qps, duration, warmup, domains, csv } => {
+            load_test(&resolver, qps, duration, warmup, domains.as_deref(), csv.as_deref()).await?;
         }
     }
     
@@ -92,35 +215,43 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 async fn query_domain(
-    server_addr: &SocketAddr,
+    resolver: &Resolver,
     domain: &str,
     record_type: &str,
-    timeout: u64,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Querying {} {} from {}", domain, record_type, server_addr);
-    
+) -> Result<(), BoxError> {
+    println!(
+        "Querying {} {} from {} over {}",
+        domain,
+        record_type,
+        resolver.addr(),
+        resolver.transport()
+    );
+    
+    // Connection setup, including any TLS handshake, is timed on its own
     let start_time = std::time::Instant::now();
-    let response = send_dns_query(server_addr, domain, record_type, timeout).await?;
+    let mut session = resolver.connect().await?;
+    let connect_time = start_time.elapsed();
+    let response = session.exchange(domain, record_type).await?;
     let duration = start_time.elapsed();
     
+    println!("Connect time: {:?}", connect_time);
     println!("Response time: {:?}", duration);
     println!("Response: {:?}", response);
+
+    if !response.answers().is_empty() {
+        println!("Answer: {}", reassemble_txt(&response));
+    }
     
     Ok(())
 }
 
 async fn batch_query(
-    server_addr: &SocketAddr,
+    resolver: &Resolver,
     file: &str,
     record_type: &str,
     concurrent: usize,
-    timeout: u64,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let domains = std::fs::read_to_string(file)?
-        .lines()
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect::<Vec<_>>();
+) -> Result<(), BoxError> {
+    let domains = read_domains(file)?;
     
     println!("Batch querying {} domains with {} concurrent requests", domains.len(), concurrent);
     
@@ -133,12 +264,12 @@ async fn batch_query(
         let mut handles = vec![];
         
         for domain in chunk {
-            let server_addr = *server_addr;
+            let resolver = resolver.clone();
             let domain = domain.clone();
             let record_type = record_type.to_string();
             
             handles.push(tokio::spawn(async move {
-                send_dns_query(&server_addr, &domain, &record_type, timeout).await
+                resolver.query(&domain, &record_type).await
             }));
         }
         
@@ -160,13 +291,12 @@ async fn batch_query(
 }
 
 async fn health_check(
-    server_addr: &SocketAddr,
-    timeout: u64,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Performing health check on {}", server_addr);
+    resolver: &Resolver,
+) -> Result<(), BoxError> {
+    println!("Performing health check on {} over {}", resolver.addr(), resolver.transport());
     
     let start_time = std::time::Instant::now();
-    let result = send_dns_query(server_addr, "health.check", "TXT", timeout).await;
+    let result = resolver.query("health.check", "TXT").await;
     let duration = start_time.elapsed();
     
     match result {
@@ -183,21 +313,100 @@ async fn health_check(
     Ok(())
 }
 
+async fn ask(
+    resolver: &Resolver,
+    question: &str,
+    zone: &str,
+    encoding: Encoding,
+) -> Result<(), BoxError> {
+    let domain = question_to_domain(question, zone, encoding)?;
+    let response = resolver.query(&domain, "TXT").await?;
+    if response.response_code() != ResponseCode::NoError {
+        return Err(format!("{} for {}", response.response_code(), domain).into());
+    }
+
+    println!("{}", reassemble_txt(&response));
+    Ok(())
+}
+
+async fn repl(resolver: &Resolver, zone: &str, encoding: Encoding) -> Result<(), BoxError> {
+    println!(
+        "Asking {} over {} under {}. Type a question, or `exit` to quit.",
+        resolver.addr(),
+        resolver.transport(),
+        zone
+    );
+
+    // One connection for the whole session, opened again if it fails
+    let mut session = None;
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+
+        let Some(line) = lines.next_line().await? else {
+            println!();
+            break;
+        };
+        let question = line.trim();
+        match question {
+            "" => continue,
+            "exit" | "quit" => break,
+            _ => {}
+        }
+
+        let domain = match question_to_domain(question, zone, encoding) {
+            Ok(domain) => domain,
+            Err(e) => {
+                println!("! {}", e);
+                continue;
+            }
+        };
+
+        let start_time = std::time::Instant::now();
+        if session.is_none() {
+            match resolver.connect().await {
+                Ok(connected) => session = Some(connected),
+                Err(e) => {
+                    println!("! {}", e);
+                    continue;
+                }
+            }
+        }
+        let result = match session.as_mut() {
+            Some(session) => session.exchange(&domain, "TXT").await,
+            None => continue,
+        };
+        match result {
+            Ok(response) if response.response_code() != ResponseCode::NoError => {
+                println!("! {} for {}", response.response_code(), domain);
+            }
+            Ok(response) => {
+                println!("{}", reassemble_txt(&response));
+                println!("  ({:?})", start_time.elapsed());
+            }
+            Err(e) => {
+                println!("! {}", e);
+                session = None;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 async fn performance_test(
-    server_addr: &SocketAddr,
+    resolver: &Resolver,
     requests: usize,
     concurrent: usize,
-    timeout: u64,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Performance test: {} requests, {} concurrent", requests, concurrent);
-    
-    let test_domains = vec![
-        "what.is.the.weather.com",
-        "how.many.stars.are.there.com",
-        "what.is.the.capital.of.france.com",
-        "hello.world.com",
-        "test.query.com",
-    ];
+) -> Result<(), BoxError> {
+    println!(
+        "Performance test: {} requests, {} concurrent, over {}",
+        requests,
+        concurrent,
+        resolver.transport()
+    );
     
     let start_time = std::time::Instant::now();
     let mut success_count = 0;
@@ -210,12 +419,12 @@ async fn performance_test(
         let mut handles = vec![];
         
         for i in chunk_start..chunk_end {
-            let server_addr = *server_addr;
-            let domain = test_domains[i % test_domains.len()].to_string();
+            let resolver = resolver.clone();
+            let domain = TEST_DOMAINS[i % TEST_DOMAINS.len()].to_string();
             
             handles.push(tokio::spawn(async move {
                 let req_start = std::time::Instant::now();
-                let result = send_dns_query(&server_addr, &domain, "TXT", timeout).await;
+                let result = resolver.query(&domain, "TXT").await;
                 let req_duration = req_start.elapsed();
                 (result, req_duration)
             }));
@@ -264,41 +473,310 @@ async fn performance_test(
     Ok(())
 }
 
-async fn send_dns_query(
-    server_addr: &SocketAddr,
-    domain: &str,
-    record_type: &str,
-    timeout: u64,
-) -> Result<Message, Box<dyn std::error::Error>> {
-    let socket = UdpSocket::bind("0.0.0.0:0").await?;
-    socket.connect(server_addr).await?;
-    
-    // Create DNS query
-    let mut message = Message::new();
-    message.set_id(rand::random());
-    message.set_message_type(MessageType::Query);
-    message.set_op_code(OpCode::Query);
-    message.set_response_code(ResponseCode::NoError);
-    
-    let name = Name::from_str(domain)?;
-    let record_type = RecordType::from_str(record_type)?;
-    let query = trust_dns_proto::op::Query::query(name, record_type);
-    message.add_query(query);
-    
-    // Send query
-    let query_bytes = message.to_bytes()?;
-    socket.send(&query_bytes).await?;
-    
-    // Receive response
-    let mut response_buffer = vec![0u8; 512];
-    let len = tokio::time::timeout(
-        std::time::Duration::from_secs(timeout),
-        socket.recv(&mut response_buffer)
-    ).await??;
-    
-    response_buffer.truncate(len);
-    
-    // Parse response
-    let response = Message::from_bytes(&response_buffer)?;
-    Ok(response)
+/// One measured query of a load test
+struct Sample {
+    /// When the query was due, from the start of measurement
+    offset: Duration,
+    latency: Duration,
+    /// Response code, or why there was no response
+    result: String,
+}
+
+async fn load_test(
+    resolver: &Resolver,
+    qps: u32,
+    duration: u64,
+    warmup: u64,
+    domains: Option<&str>,
+    csv: Option<&Path>,
+) -> Result<(), BoxError> {
+    if qps == 0 || duration == 0 {
+        return Err("--qps and --duration must be at least 1".into());
+    }
+    let domains = match domains {
+        Some(file) => read_domains(file)?,
+        None => TEST_DOMAINS.iter().map(|domain| domain.to_string()).collect(),
+    };
+    if domains.is_empty() {
+        return Err("No domains to query".into());
+    }
+
+    println!(
+        "Load test: {} qps for {}s after {}s warm-up, over {}",
+        qps,
+        duration,
+        warmup,
+        resolver.transport()
+    );
+
+    let interval = Duration::from_secs(1) / qps;
+    let warmup_queries = qps as usize * warmup as usize;
+    let total_queries = warmup_queries + qps as usize * duration as usize;
+    let start = tokio::time::Instant::now();
+    let measure_start = start + interval * warmup_queries as u32;
+
+    // Queries go out on schedule even while earlier ones are outstanding,
+    // and each is timed from when it was due, so a slow server cannot hide
+    // its latency by slowing the sender down
+    let mut tasks = JoinSet::new();
+    for i in 0..total_queries {
+        let due = start + interval * i as u32;
+        tokio::time::sleep_until(due).await;
+
+        let resolver = resolver.clone();
+        let domain = domains[i % domains.len()].clone();
+        tasks.spawn(async move {
+            let result = match resolver.query(&domain, "TXT").await {
+                Ok(response) => format!("{:?}", response.response_code()),
+                Err(e) if e.is::<tokio::time::error::Elapsed>() => "timeout".to_string(),
+                Err(_) => "error".to_string(),
+            };
+            (i >= warmup_queries, due, due.elapsed(), result)
+        });
+    }
+
+    let mut samples = Vec::with_capacity(total_queries - warmup_queries);
+    while let Some(joined) = tasks.join_next().await {
+        let (measured, due, latency, result) = joined?;
+        if measured {
+            samples.push(Sample {
+                offset: due - measure_start,
+                latency,
+                result,
+            });
+        }
+    }
+    let elapsed = measure_start.elapsed();
+    samples.sort_by_key(|sample| sample.offset);
+
+    let mut results: BTreeMap<&str, usize> = BTreeMap::new();
+    for sample in &samples {
+        *results.entry(&sample.result).or_default() += 1;
+    }
+    // Only answered queries count towards latency
+    let stats = LatencyStats::new(
+        samples
+            .iter()
+            .filter(|sample| sample.result != "timeout" && sample.result != "error")
+            .map(|sample| sample.latency)
+            .collect(),
+    );
+
+    println!("Load test completed");
+    println!("Measured queries: {}", samples.len());
+    println!("Answered: {}", stats.len());
+    for (result, count) in &results {
+        println!("  {}: {}", result, count);
+    }
+    println!("Achieved rate: {:.2} qps", stats.len() as f64 / elapsed.as_secs_f64());
+    if !stats.is_empty() {
+        println!("Latency p50: {:?}", stats.percentile(50.0));
+        println!("Latency p95: {:?}", stats.percentile(95.0));
+        println!("Latency p99: {:?}", stats.percentile(99.0));
+        println!("Latency mean: {:?}, stddev: {:?}", stats.mean(), stats.stddev());
+        println!("Latency min: {:?}, max: {:?}", stats.min(), stats.max());
+
+        println!("Histogram:");
+        let largest = stats.histogram().iter().map(|(_, count)| *count).max().unwrap_or(1);
+        for (upper, count) in stats.histogram() {
+            let bar = "#".repeat((count * 40).div_ceil(largest));
+            println!("  <= {:>8?} {:>7} {}", upper, count, bar);
+        }
+    }
+
+    if let Some(path) = csv {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "offset_ms,latency_ms,result")?;
+        for sample in &samples {
+            writeln!(
+                file,
+                "{:.3},{:.3},{}",
+                sample.offset.as_secs_f64() * 1000.0,
+                sample.latency.as_secs_f64() * 1000.0,
+                sample.result
+            )?;
+        }
+        file.flush()?;
+        println!("Wrote {} samples to {}", samples.len(), path.display());
+    }
+
+    Ok(())
+}
+
+/// Domains listed in a file, one per line
+fn read_domains(file: &str) -> Result<Vec<String>, BoxError> {
+    Ok(std::fs::read_to_string(file)?
+        .lines()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect())
+}
+
+/// Turn a typed question into a query name under `zone` that the server
+/// reads back as the same question
+fn question_to_domain(question: &str, zone: &str, encoding: Encoding) -> Result<String, String> {
+    let mut labels = match encoding {
+        Encoding::Dots => word_labels(question, false)?,
+        Encoding::Hyphens => word_labels(question, true)?,
+        Encoding::Base32 => base32_labels(question),
+    };
+    if labels.is_empty() {
+        return Err("Question has no words".to_string());
+    }
+    labels.push(zone.trim_matches('.').to_string());
+
+    let domain = labels.join(".");
+    if domain.len() > 253 {
+        return Err("Question too long for a DNS name".to_string());
+    }
+    Ok(domain)
+}
+
+/// One label per word, or with `hyphens` as many words per label as fit.
+/// Punctuation is dropped, and words outside ASCII are punycode encoded in
+/// labels of their own, which the server decodes again.
+fn word_labels(question: &str, hyphens: bool) -> Result<Vec<String>, String> {
+    let mut labels: Vec<String> = Vec::new();
+    for word in question.split_whitespace() {
+        let word: String = word.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect();
+        if word.is_empty() {
+            continue;
+        }
+
+        if !word.is_ascii() {
+            let label = format!("xn--{}", punycode::encode_str(&word).ok_or("Could not encode question")?);
+            if label.len() > 63 {
+                return Err(format!("Word too long for a DNS label: {}", label));
+            }
+            labels.push(label);
+            continue;
+        }
+        if word.len() > 63 {
+            return Err(format!("Word too long for a DNS label: {}", word));
+        }
+
+        match labels.last_mut() {
+            Some(last) if hyphens && !last.starts_with("xn--") && last.len() + 1 + word.len() <= 63 => {
+                last.push('-');
+                last.push_str(&word);
+            }
+            _ => labels.push(word),
+        }
+    }
+    Ok(labels)
+}
+
+fn base32_labels(question: &str) -> Vec<String> {
+    let question = question.trim();
+    if question.is_empty() {
+        return Vec::new();
+    }
+
+    let encoded = BASE32_NOPAD.encode(question.as_bytes()).to_lowercase();
+    let mut labels = vec!["b32".to_string()];
+    // Base32 is ASCII, so byte chunks are whole characters
+    labels.extend(encoded.as_bytes().chunks(63).map(|chunk| String::from_utf8_lossy(chunk).into_owned()));
+    labels
+}
+
+/// Join the TXT answers of an LLMdig response. Multi-part answers carry an
+/// `index/total:` prefix because resolvers may reorder records; anything
+/// else is joined in the order received.
+fn reassemble_txt(response: &Message) -> String {
+    let parts: Vec<String> = response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::TXT(txt)) => Some(
+                txt.txt_data()
+                    .iter()
+                    .map(|string| String::from_utf8_lossy(string).into_owned())
+                    .collect::<String>(),
+            ),
+            _ => None,
+        })
+        .collect();
+
+    let mut indexed = Vec::with_capacity(parts.len());
+    for part in &parts {
+        match parse_chunk(part) {
+            Some((index, total, text)) if total == parts.len() => indexed.push((index, text)),
+            _ => return parts.concat(),
+        }
+    }
+
+    indexed.sort_by_key(|(index, _)| *index);
+    if indexed.iter().enumerate().all(|(position, (index, _))| *index == position + 1) {
+        indexed.into_iter().map(|(_, text)| text).collect()
+    } else {
+        parts.concat()
+    }
+}
+
+/// Split `2/3:text` into its index, total and text
+fn parse_chunk(part: &str) -> Option<(usize, usize, &str)> {
+    let (position, text) = part.split_once(':')?;
+    let (index, total) = position.split_once('/')?;
+    Some((index.parse().ok()?, total.parse().ok()?, text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use trust_dns_proto::rr::rdata::TXT;
+    use trust_dns_proto::rr::{Name, Record};
+
+    fn response(parts: &[&str]) -> Message {
+        let name = Name::from_str("question.q.example.com.").unwrap();
+        let mut message = Message::new();
+        for part in parts {
+            let txt = TXT::new(vec![part.to_string()]);
+            message.add_answer(Record::from_rdata(name.clone(), 300, RData::TXT(txt)));
+        }
+        message
+    }
+
+    #[test]
+    fn test_reassemble_out_of_order_chunks() {
+        let message = response(&["2/3:ğü", "3/3:ş.", "1/3:Merhaba, "]);
+        assert_eq!(reassemble_txt(&message), "Merhaba, ğüş.");
+    }
+
+    #[test]
+    fn test_question_to_domain() {
+        let dots = |question| question_to_domain(question, "com", Encoding::Dots);
+        assert_eq!(dots("What is Rust?").unwrap(), "what.is.rust.com");
+        assert_eq!(
+            question_to_domain("  merhaba dünya ", "q.example.com.", Encoding::Dots).unwrap(),
+            "merhaba.xn--dnya-0ra.q.example.com"
+        );
+        assert!(dots("?!").is_err());
+        assert!(dots(&"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn test_hyphen_and_base32_encodings() {
+        assert_eq!(
+            question_to_domain("What is Rust?", "com", Encoding::Hyphens).unwrap(),
+            "what-is-rust.com"
+        );
+        assert_eq!(
+            question_to_domain("merhaba dünya nasılsın", "com", Encoding::Hyphens).unwrap(),
+            "merhaba.xn--dnya-0ra.xn--naslsn-r9ac.com"
+        );
+        let long = question_to_domain(&"word ".repeat(20), "com", Encoding::Hyphens).unwrap();
+        assert!(long.split('.').all(|label| label.len() <= 63));
+
+        assert_eq!(
+            question_to_domain("What is Rust?", "com", Encoding::Base32).unwrap(),
+            "b32.k5ugc5banfzsautvon2d6.com"
+        );
+    }
+
+    #[test]
+    fn test_unprefixed_answers_are_kept_as_is() {
+        assert_eq!(reassemble_txt(&response(&["ratio 1/2: half"])), "ratio 1/2: half");
+        assert_eq!(reassemble_txt(&response(&["a", "b"])), "ab");
+    }
 } 
\ No newline at end of file
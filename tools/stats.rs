@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+/// Latencies recorded during a load test
+pub struct LatencyStats {
+    /// Sorted, shortest first
+    samples: Vec<Duration>,
+}
+
+impl LatencyStats {
+    pub fn new(mut samples: Vec<Duration>) -> Self {
+        samples.sort();
+        Self { samples }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn min(&self) -> Duration {
+        self.samples.first().copied().unwrap_or_default()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.samples.last().copied().unwrap_or_default()
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.samples.iter().map(Duration::as_secs_f64).sum::<f64>() / self.len() as f64)
+    }
+
+    pub fn stddev(&self) -> Duration {
+        if self.samples.len() < 2 {
+            return Duration::ZERO;
+        }
+        let mean = self.mean().as_secs_f64();
+        let variance = self
+            .samples
+            .iter()
+            .map(|sample| (sample.as_secs_f64() - mean).powi(2))
+            .sum::<f64>()
+            / (self.len() - 1) as f64;
+        Duration::from_secs_f64(variance.sqrt())
+    }
+
+    /// Nearest-rank percentile, `percent` in 0-100
+    pub fn percentile(&self, percent: f64) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percent / 100.0 * self.len() as f64).ceil() as usize;
+        self.samples[rank.clamp(1, self.len()) - 1]
+    }
+
+    /// Sample counts in buckets that double in width, as `(upper bound,
+    /// count)`, up to the bucket holding the slowest sample
+    pub fn histogram(&self) -> Vec<(Duration, usize)> {
+        let mut buckets = Vec::new();
+        let mut upper = Duration::from_millis(1);
+        let mut rest = &self.samples[..];
+        while !rest.is_empty() {
+            let count = rest.partition_point(|sample| *sample <= upper);
+            buckets.push((upper, count));
+            rest = &rest[count..];
+            upper *= 2;
+        }
+        buckets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(values: &[u64]) -> LatencyStats {
+        LatencyStats::new(values.iter().map(|value| Duration::from_millis(*value)).collect())
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let stats = millis(&(1..=100).rev().collect::<Vec<_>>());
+        assert_eq!(stats.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(stats.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(stats.percentile(100.0), Duration::from_millis(100));
+        assert_eq!(stats.min(), Duration::from_millis(1));
+
+        let stats = millis(&[2, 4, 4, 4, 5, 5, 7, 9]);
+        assert_eq!(stats.mean(), Duration::from_millis(5));
+        assert_eq!(stats.stddev().as_micros(), 2138);
+
+        assert_eq!(millis(&[]).percentile(95.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let stats = millis(&[0, 1, 3, 3, 12]);
+        let counts: Vec<(u128, usize)> = stats
+            .histogram()
+            .into_iter()
+            .map(|(upper, count)| (upper.as_millis(), count))
+            .collect();
+        assert_eq!(counts, vec![(1, 2), (2, 0), (4, 2), (8, 0), (16, 1)]);
+    }
+}
\ No newline at end of file
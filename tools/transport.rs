@@ -0,0 +1,254 @@
+use crate::BoxError;
+use clap::ValueEnum;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio_native_tls::{native_tls, TlsConnector, TlsStream};
+use trust_dns_proto::op::{Message, MessageType, OpCode, ResponseCode};
+use trust_dns_proto::rr::{Name, RecordType};
+use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable};
+
+/// How queries reach the server
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Transport {
+    Udp,
+    Tcp,
+    /// DNS over TLS
+    Dot,
+    /// DNS over HTTPS
+    Doh,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+            Transport::Dot => "dot",
+            Transport::Doh => "doh",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Certificate checks for `dot` and `doh`
+pub struct TlsOptions {
+    /// Accept any certificate, for self-signed test setups
+    pub insecure: bool,
+    /// PEM file with an extra trusted root
+    pub ca_cert: Option<PathBuf>,
+    /// Name the certificate must match; defaults to the host
+    pub name: Option<String>,
+}
+
+/// Everything needed to send queries to one server. Cheap to clone, so
+/// concurrent tasks can each hold one.
+#[derive(Clone)]
+pub struct Resolver {
+    addr: SocketAddr,
+    transport: Transport,
+    tls_name: String,
+    tls: Option<TlsConnector>,
+    /// Client and URL for `doh`
+    http: Option<(reqwest::Client, String)>,
+    timeout: Duration,
+}
+
+impl Resolver {
+    pub async fn new(
+        host: &str,
+        port: u16,
+        transport: Transport,
+        tls: &TlsOptions,
+        doh_path: &str,
+        timeout: Duration,
+    ) -> Result<Self, BoxError> {
+        let addr = lookup_host((host, port))
+            .await?
+            .next()
+            .ok_or_else(|| format!("{} has no addresses", host))?;
+        let tls_name = tls.name.clone().unwrap_or_else(|| host.to_string());
+
+        let ca_cert = match &tls.ca_cert {
+            Some(path) => Some(std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?),
+            None => None,
+        };
+
+        let mut connector = None;
+        let mut http = None;
+        match transport {
+            Transport::Udp | Transport::Tcp => {}
+            Transport::Dot => {
+                let mut builder = native_tls::TlsConnector::builder();
+                builder.danger_accept_invalid_certs(tls.insecure);
+                if let Some(pem) = &ca_cert {
+                    builder.add_root_certificate(native_tls::Certificate::from_pem(pem)?);
+                }
+                connector = Some(TlsConnector::from(builder.build()?));
+            }
+            Transport::Doh => {
+                // Connect to the given address while checking the certificate
+                // against the TLS name
+                let mut builder = reqwest::Client::builder()
+                    .timeout(timeout)
+                    .danger_accept_invalid_certs(tls.insecure)
+                    .resolve(&tls_name, addr);
+                if let Some(pem) = &ca_cert {
+                    builder = builder.add_root_certificate(reqwest::Certificate::from_pem(pem)?);
+                }
+                let url = format!("https://{}:{}{}", tls_name, port, doh_path);
+                http = Some((builder.build()?, url));
+            }
+        }
+
+        Ok(Self {
+            addr,
+            transport,
+            tls_name,
+            tls: connector,
+            http,
+            timeout,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    /// Open a connection that several queries can be sent over. For `doh`
+    /// the connection is made by the first query.
+    pub async fn connect(&self) -> Result<Session, BoxError> {
+        let connection = tokio::time::timeout(self.timeout, async {
+            let connection = match self.transport {
+                Transport::Udp => {
+                    let bind = if self.addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+                    let socket = UdpSocket::bind(bind).await?;
+                    socket.connect(self.addr).await?;
+                    Connection::Udp(socket)
+                }
+                Transport::Tcp => Connection::Tcp(TcpStream::connect(self.addr).await?),
+                Transport::Dot => {
+                    let stream = TcpStream::connect(self.addr).await?;
+                    let connector = self.tls.as_ref().ok_or("TLS is not set up")?;
+                    Connection::Tls(Box::new(connector.connect(&self.tls_name, stream).await?))
+                }
+                Transport::Doh => {
+                    let (client, url) = self.http.clone().ok_or("HTTPS is not set up")?;
+                    Connection::Https { client, url }
+                }
+            };
+            Ok::<_, BoxError>(connection)
+        })
+        .await??;
+
+        Ok(Session {
+            connection,
+            timeout: self.timeout,
+        })
+    }
+
+    /// Send one query over a fresh connection
+    pub async fn query(&self, domain: &str, record_type: &str) -> Result<Message, BoxError> {
+        self.connect().await?.exchange(domain, record_type).await
+    }
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    Https { client: reqwest::Client, url: String },
+}
+
+/// An open connection to the server
+pub struct Session {
+    connection: Connection,
+    timeout: Duration,
+}
+
+impl Session {
+    /// Send one query and wait for its response
+    pub async fn exchange(&mut self, domain: &str, record_type: &str) -> Result<Message, BoxError> {
+        let query = build_query(domain, record_type)?;
+        let bytes = query.to_bytes()?;
+        let deadline = tokio::time::Instant::now() + self.timeout;
+
+        match &mut self.connection {
+            Connection::Udp(socket) => {
+                socket.send(&bytes).await?;
+                // Skip late answers to earlier queries on the same socket
+                loop {
+                    let mut response_buffer = vec![0u8; 4096];
+                    let len = tokio::time::timeout_at(deadline, socket.recv(&mut response_buffer)).await??;
+                    response_buffer.truncate(len);
+                    let response = Message::from_bytes(&response_buffer)?;
+                    if response.id() == query.id() {
+                        return Ok(response);
+                    }
+                }
+            }
+            Connection::Tcp(stream) => {
+                tokio::time::timeout_at(deadline, exchange_stream(stream, &bytes, query.id())).await?
+            }
+            Connection::Tls(stream) => {
+                tokio::time::timeout_at(deadline, exchange_stream(stream, &bytes, query.id())).await?
+            }
+            Connection::Https { client, url } => {
+                let response = client
+                    .post(url.as_str())
+                    .header("content-type", "application/dns-message")
+                    .header("accept", "application/dns-message")
+                    .body(bytes)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok(Message::from_bytes(&response.bytes().await?)?)
+            }
+        }
+    }
+}
+
+fn build_query(domain: &str, record_type: &str) -> Result<Message, BoxError> {
+    let mut message = Message::new();
+    message.set_id(rand::random());
+    message.set_message_type(MessageType::Query);
+    message.set_op_code(OpCode::Query);
+    message.set_response_code(ResponseCode::NoError);
+    message.set_recursion_desired(true);
+
+    let name = Name::from_str(domain)?;
+    let record_type = RecordType::from_str(record_type)?;
+    message.add_query(trust_dns_proto::op::Query::query(name, record_type));
+    Ok(message)
+}
+
+/// One query on a TCP or TLS stream, each message prefixed with its length
+async fn exchange_stream<S>(stream: &mut S, query: &[u8], id: u16) -> Result<Message, BoxError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut framed = Vec::with_capacity(query.len() + 2);
+    framed.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    framed.extend_from_slice(query);
+    stream.write_all(&framed).await?;
+
+    loop {
+        let mut length = [0u8; 2];
+        stream.read_exact(&mut length).await?;
+        let mut response = vec![0u8; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut response).await?;
+
+        let response = Message::from_bytes(&response)?;
+        if response.id() == id {
+            return Ok(response);
+        }
+    }
+}
\ No newline at end of file